@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD CONSTRAINT users_authority_check CHECK (authority IN ('user', 'moderator', 'admin'));
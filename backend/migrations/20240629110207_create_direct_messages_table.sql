@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS direct_messages (
+    id BIGSERIAL PRIMARY KEY,
+    sender_uuid VARCHAR(255) NOT NULL,
+    sender_username VARCHAR(255) NOT NULL,
+    recipient_uuid VARCHAR(255) NOT NULL,
+    recipient_username VARCHAR(255) NOT NULL,
+    text TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS direct_messages_undelivered_idx ON direct_messages (recipient_uuid) WHERE NOT delivered;
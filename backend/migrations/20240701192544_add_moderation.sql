@@ -0,0 +1,17 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN IF NOT EXISTS banned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS muted_until TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS moderation_actions (
+    id BIGSERIAL PRIMARY KEY,
+    action VARCHAR(32) NOT NULL,
+    moderator_uuid VARCHAR(255),
+    moderator_username VARCHAR(255),
+    target_uuid VARCHAR(255) NOT NULL,
+    target_username VARCHAR(255) NOT NULL,
+    reason TEXT,
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS moderation_actions_target_uuid_idx ON moderation_actions (target_uuid);
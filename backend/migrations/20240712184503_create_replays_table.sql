@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS replays (
+    match_id VARCHAR(255) PRIMARY KEY,
+    level VARCHAR(255) NOT NULL,
+    players VARCHAR(255)[] NOT NULL,
+    public BOOLEAN NOT NULL,
+    outcome VARCHAR(32),
+    ticks BIGINT NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS replays_recorded_at_idx ON replays (recorded_at);
@@ -0,0 +1,26 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS ratings (
+    uuid VARCHAR(255) NOT NULL,
+    mode VARCHAR(32) NOT NULL,
+    rating DOUBLE PRECISION NOT NULL,
+    deviation DOUBLE PRECISION NOT NULL,
+    volatility DOUBLE PRECISION NOT NULL,
+    games_played INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (uuid, mode)
+);
+
+CREATE TABLE IF NOT EXISTS rating_history (
+    id SERIAL PRIMARY KEY,
+    uuid VARCHAR(255) NOT NULL,
+    mode VARCHAR(32) NOT NULL,
+    match_id VARCHAR(255) NOT NULL,
+    rating DOUBLE PRECISION NOT NULL,
+    deviation DOUBLE PRECISION NOT NULL,
+    volatility DOUBLE PRECISION NOT NULL,
+    rating_change DOUBLE PRECISION NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (uuid, match_id)
+);
+
+CREATE INDEX IF NOT EXISTS rating_history_uuid_idx ON rating_history (uuid, mode, recorded_at);
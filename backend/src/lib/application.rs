@@ -1,3 +1,4 @@
+use actix::{Actor, Addr};
 use actix_cors::Cors;
 use actix_web::dev::Server;
 use actix_web::http::header;
@@ -6,12 +7,18 @@ use std::net::TcpListener;
 use std::sync::Arc;
 
 use crate::configuration::Settings;
-use crate::database::db::DatabaseClient;
+use crate::database::db::{ArcDb, DatabaseClient};
+use crate::game::level;
+use crate::matches::manager::MatchManager;
 use crate::routes::config_server;
+use crate::websocket::rate_limit::RateLimiter;
+use crate::websocket::server::ChatServer;
 
 pub struct Application {
     server: Server,
     port: u16,
+    chat_server: Addr<ChatServer>,
+    match_manager: Addr<MatchManager>,
 }
 
 impl Application {
@@ -23,24 +30,73 @@ impl Application {
         let listener = TcpListener::bind(address).expect("Failed to bind to random port");
         let port = listener.local_addr().unwrap().port();
 
-        let db = DatabaseClient::new().await;
+        // Broken level files keep the server from starting rather than a match
+        let levels = level::load(&settings.matches.levels)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        log::info!("Loaded {} levels", levels.len());
 
-        let server = run(listener, db)?;
+        let db = Arc::new(DatabaseClient::new().await);
 
-        Ok(Self { server, port })
+        // Needs a running actix system, shared by every worker
+        let chat_server = ChatServer::new(db.clone(), settings.chat, settings.session).start();
+
+        let match_manager = MatchManager::new(
+            db.clone(),
+            chat_server.clone(),
+            settings.matches,
+            settings.matchmaking,
+            levels,
+        )
+        .start();
+
+        let rate_limiter = RateLimiter::new(settings.rate_limit);
+
+        let server = run(
+            listener,
+            db,
+            chat_server.clone(),
+            match_manager.clone(),
+            rate_limiter,
+        )?;
+
+        Ok(Self {
+            server,
+            port,
+            chat_server,
+            match_manager,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The chat server, used to open and close match and lobby rooms
+    pub fn chat_server(&self) -> Addr<ChatServer> {
+        self.chat_server.clone()
+    }
+
+    /// The match manager, used to look at the running matches
+    pub fn match_manager(&self) -> Addr<MatchManager> {
+        self.match_manager.clone()
+    }
+
     pub async fn start(self) -> Result<(), std::io::Error> {
         self.server.await
     }
 }
 
-fn run(listener: TcpListener, db_client: DatabaseClient) -> Result<Server, std::io::Error> {
-    let db_client = web::Data::new(Arc::new(db_client));
+fn run(
+    listener: TcpListener,
+    db_client: ArcDb,
+    chat_server: Addr<ChatServer>,
+    match_manager: Addr<MatchManager>,
+    rate_limiter: RateLimiter,
+) -> Result<Server, std::io::Error> {
+    let db_client = web::Data::new(db_client);
+    let chat_server = web::Data::new(chat_server);
+    let match_manager = web::Data::new(match_manager);
+    let rate_limiter = web::Data::new(rate_limiter);
 
     let server = HttpServer::new(move || {
         let cors = Cors::default()
@@ -53,6 +109,9 @@ fn run(listener: TcpListener, db_client: DatabaseClient) -> Result<Server, std::
         App::new()
             .wrap(cors)
             .app_data(db_client.clone())
+            .app_data(chat_server.clone())
+            .app_data(match_manager.clone())
+            .app_data(rate_limiter.clone())
             .configure(config_server)
     })
     .listen(listener)?
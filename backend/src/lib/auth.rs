@@ -0,0 +1,203 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use serde_json::json;
+use thiserror::Error;
+
+use crate::claims::{Claims, TokenError};
+use crate::database::db::ArcDb;
+use crate::types::Authority;
+
+/// Rejection returned by the authentication extractors, rendered as `{"error": ...}`
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Unauthorized")]
+    Unauthorized(#[source] TokenError),
+
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Internal server error")]
+    Internal,
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+            AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({"error": self.to_string()}))
+    }
+}
+
+/// Decodes the JWT in the `Authorization` header and makes sure it has not been revoked
+pub async fn authorize(req: &HttpRequest, db: &ArcDb) -> Result<Claims, TokenError> {
+    let header_value = req
+        .headers()
+        .get("authorization")
+        .ok_or(TokenError::MissingHeader)?;
+
+    let claims = Claims::from_header_value(header_value)?;
+    db.check_revocation(&claims).await?;
+
+    Ok(claims)
+}
+
+/// Prefix of the `Sec-WebSocket-Protocol` entry that carries the JWT
+const WS_TOKEN_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// Like `authorize`, but for the websocket handshake.
+///
+/// Browsers can't set headers on a websocket upgrade, so besides the `Authorization` header the
+/// JWT is also accepted as a `bearer.<jwt>` entry in `Sec-WebSocket-Protocol` or as a `token`
+/// query parameter.
+pub async fn authorize_websocket(req: &HttpRequest, db: &ArcDb) -> Result<Claims, TokenError> {
+    if req.headers().contains_key("authorization") {
+        return authorize(req, db).await;
+    }
+
+    let from_protocol = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .map(|protocol| protocol.trim())
+                .find_map(|protocol| protocol.strip_prefix(WS_TOKEN_PROTOCOL_PREFIX))
+                .map(|jwt| jwt.to_string())
+        });
+
+    let jwt = match from_protocol {
+        Some(jwt) => jwt,
+        None => web::Query::<WebsocketTokenQuery>::from_query(req.query_string())
+            .map_err(|_| TokenError::ParseError)?
+            .into_inner()
+            .token
+            .ok_or(TokenError::MissingHeader)?,
+    };
+
+    let claims = Claims::decode(&jwt)?;
+    db.check_revocation(&claims).await?;
+
+    Ok(claims)
+}
+
+#[derive(Deserialize)]
+struct WebsocketTokenQuery {
+    token: Option<String>,
+}
+
+/// Extractor for handlers that need a logged in user.
+///
+/// Rejects the request with a 401 if the JWT is missing, invalid, expired or revoked, or with a
+/// 500 if it could not be checked against the database.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub claims: Claims,
+}
+
+impl Deref for AuthenticatedUser {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claims
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let db = req
+                .app_data::<web::Data<ArcDb>>()
+                .expect("DatabaseClient is not registered as app data");
+
+            match authorize(&req, db).await {
+                Ok(claims) => Ok(AuthenticatedUser { claims }),
+                // Already logged by `check_revocation`
+                Err(TokenError::Database) => Err(AuthError::Internal),
+                Err(e) => {
+                    log::info!("Rejected request to {}: {}", req.path(), e);
+                    Err(AuthError::Unauthorized(e))
+                }
+            }
+        })
+    }
+}
+
+/// The minimum authority a `RequireAuthority` extractor accepts
+pub trait AuthorityLevel {
+    const MINIMUM: Authority;
+}
+
+pub struct Moderator;
+
+impl AuthorityLevel for Moderator {
+    const MINIMUM: Authority = Authority::Moderator;
+}
+
+pub struct Admin;
+
+impl AuthorityLevel for Admin {
+    const MINIMUM: Authority = Authority::Admin;
+}
+
+/// Extractor for handlers that need a logged in user of at least authority `A`.
+///
+/// Rejects the request with a 401 like `AuthenticatedUser`, or with a 403 if the user's authority
+/// is too low. Higher authorities are always accepted, an admin passes `RequireAuthority<Moderator>`.
+pub struct RequireAuthority<A: AuthorityLevel> {
+    pub user: AuthenticatedUser,
+    _level: PhantomData<A>,
+}
+
+impl<A: AuthorityLevel> Deref for RequireAuthority<A> {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user.claims
+    }
+}
+
+impl<A: AuthorityLevel + 'static> FromRequest for RequireAuthority<A> {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::from_request(req, payload);
+
+        Box::pin(async move {
+            let user = user.await?;
+
+            if user.authority_level < A::MINIMUM {
+                log::info!(
+                    "User {} ({}) attempted to access a {} route",
+                    user.username,
+                    user.authority_level,
+                    A::MINIMUM
+                );
+                return Err(AuthError::Forbidden);
+            }
+
+            Ok(RequireAuthority {
+                user,
+                _level: PhantomData,
+            })
+        })
+    }
+}
@@ -2,19 +2,24 @@ use anyhow::anyhow;
 use jsonwebtoken::{decode, DecodingKey, EncodingKey, Header, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
 use warp::{http::header::HeaderValue, reject::Reject};
 
-use crate::types::UserRecord;
+use crate::types::{Authority, UserRecord};
 
 const JWT_EXPIRY: Option<chrono::TimeDelta> = chrono::TimeDelta::try_minutes(30);
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub username: String,
-    pub authority_level: String,
+    pub authority_level: Authority,
     pub uuid: String,
+    /// Unique id of this token, used to revoke it before it expires
+    pub jti: String,
+    /// The user's token generation at the time of issuing, see `DatabaseClient::logout_all`
+    pub generation: i32,
 }
 
 impl Reject for TokenError {}
@@ -32,6 +37,16 @@ pub enum TokenError {
 
     #[error("Token is expired")]
     Expired,
+
+    #[error("Token has been revoked")]
+    Revoked,
+
+    #[error("Authorization header not found")]
+    MissingHeader,
+
+    /// The token could not be checked against the database, this is not the client's fault
+    #[error("Could not check the token")]
+    Database,
 }
 
 impl Claims {
@@ -61,7 +76,7 @@ impl Claims {
             .to_str()
             .map_err(|_| TokenError::ValueError)?
             .split(' ')
-            .last()
+            .next_back()
             .ok_or(TokenError::ParseError)?
             .to_string();
 
@@ -81,6 +96,8 @@ impl Claims {
             username: user_details.username,
             authority_level: user_details.authority, // level of authorization that user has
             uuid: user_details.uuid,                 // unique uuid for this player
+            jti: Uuid::new_v4().to_string(),
+            generation: user_details.token_generation,
         };
 
         let secret = Self::get_jwt_secret();
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use serde::Deserialize;
 use sqlx::postgres::PgConnectOptions;
 
@@ -5,6 +7,21 @@ use sqlx::postgres::PgConnectOptions;
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
+
+    #[serde(default)]
+    pub chat: ChatSettings,
+
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+
+    #[serde(default)]
+    pub session: SessionSettings,
+
+    #[serde(default)]
+    pub matches: MatchSettings,
+
+    #[serde(default)]
+    pub matchmaking: MatchmakingSettings,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -22,6 +39,202 @@ pub struct DatabaseSettings {
     pub database_name: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// How many of the latest messages of a room are sent to a session joining it
+    pub backlog_length: i64,
+
+    /// Words the word filter looks for, matched as whole words and ignoring case
+    pub filtered_words: Vec<String>,
+
+    /// What happens to messages containing a filtered word
+    pub filter_mode: FilterMode,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            backlog_length: 50,
+            filtered_words: Vec::new(),
+            filter_mode: FilterMode::Mask,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    /// Filtered words are replaced with asterisks
+    Mask,
+
+    /// Messages with a filtered word are not sent at all
+    Reject,
+}
+
+/// Limits on what a single websocket client may send, see `websocket::rate_limit`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Messages a session can send in a burst
+    pub session_burst: f64,
+
+    /// Messages per second a session can keep sending
+    pub session_rate: f64,
+
+    /// Messages all sessions of an account together can send in a burst
+    pub account_burst: f64,
+
+    /// Messages per second all sessions of an account together can keep sending
+    pub account_rate: f64,
+
+    /// Match inputs and snapshot acknowledgements a session can send in a burst
+    pub input_burst: f64,
+
+    /// Match inputs and snapshot acknowledgements per second a session can keep sending. Clients
+    /// send an input every tick of `game::TICK_RATE` and acknowledge every snapshot.
+    pub input_rate: f64,
+
+    /// Largest websocket frame in bytes, bigger frames close the connection
+    pub max_frame_size: usize,
+
+    /// Longest chat message or whisper in characters
+    pub max_message_length: usize,
+
+    /// Strikes that only get a warning
+    pub warnings: u32,
+
+    /// How long every message is dropped once the warnings are used up
+    pub throttle_seconds: u64,
+
+    /// Strikes after which the client is disconnected
+    pub max_strikes: u32,
+
+    /// Strikes are forgiven after this long without one
+    pub strike_decay_seconds: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            session_burst: 20.0,
+            session_rate: 10.0,
+            account_burst: 40.0,
+            account_rate: 20.0,
+            input_burst: 40.0,
+            input_rate: 120.0,
+            max_frame_size: 64 * 1024,
+            max_message_length: 500,
+            warnings: 3,
+            throttle_seconds: 10,
+            max_strikes: 20,
+            strike_decay_seconds: 60,
+        }
+    }
+}
+
+/// How dropped websocket connections are resumed, see `websocket::server::ChatServer`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// How long a session whose connection dropped is kept around for the client to resume it
+    pub resume_grace_seconds: u64,
+
+    /// Most messages kept for a session while its connection is gone, older ones are dropped
+    pub resume_buffer_length: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            resume_grace_seconds: 30,
+            resume_buffer_length: 256,
+        }
+    }
+}
+
+/// How matches are hosted, see `matches::manager::MatchManager`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MatchSettings {
+    /// How often per second a match sends its state to the players. The simulation itself runs
+    /// at `game::TICK_RATE`, as many steps as needed are taken between two updates.
+    pub tick_rate: u32,
+
+    /// Most players in a single match
+    pub max_players: usize,
+
+    /// Most spectators watching a single match
+    pub max_spectators: usize,
+
+    /// How far behind the match spectators are, so they can't tell the players what is about to
+    /// happen
+    pub spectator_delay_seconds: u64,
+
+    /// Threads the matches are spread over, separate from the http workers
+    pub threads: usize,
+
+    /// Directory of the level files, see `game::level`. Read once when the server starts.
+    pub levels: PathBuf,
+
+    /// Directory the replay of every match is written to, see `matches::replay`
+    pub replays: PathBuf,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 30,
+            max_players: 4,
+            max_spectators: 8,
+            spectator_delay_seconds: 0,
+            threads: 2,
+            levels: PathBuf::from("levels"),
+            replays: PathBuf::from("replays"),
+        }
+    }
+}
+
+/// How players waiting for a match are paired up, see `matches::matchmaking::Matchmaker`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MatchmakingSettings {
+    /// Region tags players can queue in, players are only paired within a region
+    pub regions: Vec<String>,
+
+    /// Players of a co-op match found by matchmaking, versus is always one against one
+    pub coop_players: usize,
+
+    /// Largest rating difference between two players that just started waiting
+    pub initial_tolerance: f64,
+
+    /// How much the tolerance widens every second a player waits
+    pub tolerance_per_second: f64,
+
+    /// Widest the tolerance gets, however long players wait
+    pub max_tolerance: f64,
+
+    /// How long the players of a found match have to accept it
+    pub accept_timeout_seconds: u64,
+
+    /// How often the queue is searched for matches, in milliseconds
+    pub search_interval_ms: u64,
+}
+
+impl Default for MatchmakingSettings {
+    fn default() -> Self {
+        Self {
+            regions: vec!["eu".to_string(), "na".to_string(), "asia".to_string()],
+            coop_players: 2,
+            initial_tolerance: 50.0,
+            tolerance_per_second: 10.0,
+            max_tolerance: 400.0,
+            accept_timeout_seconds: 15,
+            search_interval_ms: 1000,
+        }
+    }
+}
+
 impl DatabaseSettings {
     pub fn connection_string_env(&self) -> String {
         std::env::var("DATABASE_URL").expect("DATABASE_URL is not set.")
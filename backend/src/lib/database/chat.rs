@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    database::db::DatabaseClient,
+    types::{ChatMessageRecord, DirectMessageRecord},
+};
+
+impl DatabaseClient {
+    /// Stores a chat message that was relayed to a room
+    pub async fn insert_chat_message(
+        &self,
+        room: &str,
+        uuid: &str,
+        username: &str,
+        text: &str,
+        sent_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO chat_messages (room, uuid, username, text, sent_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(room)
+        .bind(uuid)
+        .bind(username)
+        .bind(text)
+        .bind(sent_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns at most `limit` messages of a room sent before `before`, oldest first.
+    ///
+    /// Without `before` these are the latest messages of the room.
+    pub async fn chat_history(
+        &self,
+        room: &str,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessageRecord>, sqlx::Error> {
+        let mut messages = sqlx::query_as!(
+            ChatMessageRecord,
+            r#"SELECT id, room, uuid, username, text, sent_at FROM chat_messages
+            WHERE room = $1 AND ($2::timestamptz IS NULL OR sent_at < $2)
+            ORDER BY sent_at DESC, id DESC
+            LIMIT $3"#,
+            room,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    /// Returns the uuid of the user with this username, if there is one
+    pub async fn uuid_by_username(&self, username: &str) -> Result<Option<String>, sqlx::Error> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT uuid FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(uuid,)| uuid))
+    }
+
+    /// Stores a direct message, `delivered` is false if the recipient was offline
+    pub async fn insert_direct_message(
+        &self,
+        message: &DirectMessageRecord,
+        delivered: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO direct_messages (sender_uuid, sender_username, recipient_uuid, recipient_username, text, sent_at, delivered) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&message.sender_uuid)
+        .bind(&message.sender_username)
+        .bind(&message.recipient_uuid)
+        .bind(&message.recipient_username)
+        .bind(&message.text)
+        .bind(message.sent_at)
+        .bind(delivered)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks every undelivered direct message to `uuid` as delivered and returns them, oldest first.
+    ///
+    /// A message is only ever returned once, even if the recipient connects twice at the same time.
+    pub async fn take_undelivered_direct_messages(
+        &self,
+        uuid: &str,
+    ) -> Result<Vec<DirectMessageRecord>, sqlx::Error> {
+        let mut messages = sqlx::query_as!(
+            DirectMessageRecord,
+            r#"UPDATE direct_messages SET delivered = TRUE
+            WHERE recipient_uuid = $1 AND NOT delivered
+            RETURNING id, sender_uuid, sender_username, recipient_uuid, recipient_username, text, sent_at"#,
+            uuid
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        messages.sort_by_key(|message| (message.sent_at, message.id));
+
+        Ok(messages)
+    }
+}
@@ -5,7 +5,9 @@ use uuid::Uuid;
 
 use crate::{
     claims::Claims,
-    types::{LoginDetails, LoginError, LoginMethod, SignupError, User, UserRecord},
+    types::{
+        AuthTokens, Authority, LoginDetails, LoginError, LoginMethod, SignupError, User, UserRecord,
+    },
 };
 use sqlx::{postgres::PgPool, Postgres, Transaction};
 
@@ -97,7 +99,8 @@ impl DatabaseClient {
 
         let games_played = 0;
 
-        let authority = "user";
+        // Everyone starts out as a regular user, the authority in the request is ignored
+        let authority = Authority::User;
 
         let hashed_password = hash_password(&user.password).map_err(|e| {
             log::error!("Failed to hash password: {}", e);
@@ -138,11 +141,11 @@ impl DatabaseClient {
     ///
     /// Login is possible with either username or email.
     ///
-    /// Returns a boolean indicating success.
+    /// Returns a fresh access token and the first refresh token of a new token family.
     pub async fn check_login_details(
         &self,
         login_details: &LoginDetails,
-    ) -> Result<String, LoginError> {
+    ) -> Result<AuthTokens, LoginError> {
         // Determine if username or password was used
         // Right now the Ok below is unused, only really checking the error
         let login_method = match (&login_details.email, &login_details.username) {
@@ -173,12 +176,24 @@ impl DatabaseClient {
             return Err(LoginError::InvalidPassword);
         }
 
+        // Only checked once the password is known to be right, so it doesn't leak who is banned
+        if self.is_banned(&user_details.uuid).await? {
+            return Err(LoginError::Banned);
+        }
+
+        let refresh_token = self.issue_refresh_token(&user_details.uuid).await?;
+
         // Since we early return in the case of a wrong password,
         // we should create a JWT cuz the password seems valid
         let jwt = Claims::generate_jwt(user_details);
 
         // Convert the error to a LoginError
-        jwt.map_err(|e| LoginError::Catchall(e.to_string()))
+        let access_token = jwt.map_err(|e| LoginError::Catchall(e.to_string()))?;
+
+        Ok(AuthTokens {
+            access_token,
+            refresh_token,
+        })
     }
 
     /// Searches the database for a password matching the provided login method (email or username) , returns all detail
@@ -191,7 +206,7 @@ impl DatabaseClient {
             LoginMethod::Email(email) => {
                 sqlx::query_as!(
                     UserRecord,
-                    "SELECT email, username, password, uuid, authority FROM users WHERE email = $1",
+                    r#"SELECT email, username, password, uuid, authority as "authority: Authority", token_generation FROM users WHERE email = $1"#,
                     email,
                 )
                 .fetch_one(&self.pool)
@@ -202,7 +217,7 @@ impl DatabaseClient {
             LoginMethod::Username(username) => {
                 sqlx::query_as!(
                 UserRecord,
-                "SELECT email, username, password, uuid, authority FROM users WHERE username = $1",
+                r#"SELECT email, username, password, uuid, authority as "authority: Authority", token_generation FROM users WHERE username = $1"#,
                 username,
             )
                 .fetch_one(&self.pool)
@@ -212,4 +227,23 @@ impl DatabaseClient {
 
         user_data.map_err(|_| LoginError::UserDoesntExist)
     }
+
+    /// Changes the authority of a user and invalidates their current access tokens.
+    ///
+    /// Returns whether a user with this username exists.
+    pub async fn set_authority(
+        &self,
+        username: &str,
+        authority: Authority,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE users SET authority = $1, token_generation = token_generation + 1 WHERE username = $2",
+        )
+        .bind(authority)
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
 }
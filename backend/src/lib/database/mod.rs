@@ -1 +1,6 @@
+pub mod chat;
 pub mod db;
+pub mod moderation;
+pub mod ratings;
+pub mod replays;
+pub mod tokens;
@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    database::db::DatabaseClient,
+    types::{Authority, ModerationAction, ModerationRecord},
+};
+
+/// The user a moderation action is aimed at
+#[derive(Debug)]
+pub struct ModerationTarget {
+    pub uuid: String,
+    pub username: String,
+    pub authority: Authority,
+}
+
+/// Who did what to whom, see `DatabaseClient::record_moderation_action`
+#[derive(Debug)]
+pub struct NewModerationAction<'a> {
+    pub action: ModerationAction,
+
+    /// `(uuid, username)` of the moderator, `None` for actions of the server itself
+    pub moderator: Option<(&'a str, &'a str)>,
+    pub target_uuid: &'a str,
+    pub target_username: &'a str,
+    pub reason: Option<&'a str>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl DatabaseClient {
+    /// Looks up the user a moderation action is aimed at
+    pub async fn moderation_target(
+        &self,
+        username: &str,
+    ) -> Result<Option<ModerationTarget>, sqlx::Error> {
+        let row: Option<(String, String, Authority)> =
+            sqlx::query_as("SELECT uuid, username, authority FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row.map(|(uuid, username, authority)| ModerationTarget {
+            uuid,
+            username,
+            authority,
+        }))
+    }
+
+    /// Appends an entry to the audit table
+    pub async fn record_moderation_action(
+        &self,
+        action: &NewModerationAction<'_>,
+    ) -> Result<(), sqlx::Error> {
+        let (moderator_uuid, moderator_username) = action.moderator.unzip();
+
+        sqlx::query(
+            "INSERT INTO moderation_actions (action, moderator_uuid, moderator_username, target_uuid, target_username, reason, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(action.action)
+        .bind(moderator_uuid)
+        .bind(moderator_username)
+        .bind(action.target_uuid)
+        .bind(action.target_username)
+        .bind(action.reason)
+        .bind(action.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The latest entries of the audit table, newest first, optionally only those against one user
+    pub async fn moderation_log(
+        &self,
+        target_username: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<ModerationRecord>, sqlx::Error> {
+        sqlx::query_as!(
+            ModerationRecord,
+            r#"SELECT id, action as "action: ModerationAction", moderator_uuid, moderator_username,
+            target_uuid, target_username, reason, expires_at, created_at
+            FROM moderation_actions
+            WHERE $1::varchar IS NULL OR target_username = $1
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2"#,
+            target_username,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Mutes a user until `until`, or unmutes them with `None`
+    pub async fn set_muted_until(
+        &self,
+        uuid: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET muted_until = $1 WHERE uuid = $2")
+            .bind(until)
+            .bind(uuid)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns until when the user is muted, `None` if they are not (or no longer) muted
+    pub async fn muted_until(&self, uuid: &str) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        let row: Option<(Option<DateTime<Utc>>,)> =
+            sqlx::query_as("SELECT muted_until FROM users WHERE uuid = $1")
+                .bind(uuid)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row
+            .and_then(|(until,)| until)
+            .filter(|until| *until > Utc::now()))
+    }
+
+    /// Bans or unbans a user. A ban also invalidates every token the user has.
+    pub async fn set_banned(&self, uuid: &str, banned: bool) -> Result<(), sqlx::Error> {
+        let mut transaction: Transaction<'_, Postgres> = self.pool.begin().await?;
+
+        sqlx::query("UPDATE users SET banned = $1 WHERE uuid = $2")
+            .bind(banned)
+            .bind(uuid)
+            .execute(&mut *transaction)
+            .await?;
+
+        if banned {
+            // Same as `logout_all`, the ban has to take effect on open sessions as well
+            sqlx::query("UPDATE users SET token_generation = token_generation + 1 WHERE uuid = $1")
+                .bind(uuid)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE uuid = $1")
+                .bind(uuid)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    pub async fn is_banned(&self, uuid: &str) -> Result<bool, sqlx::Error> {
+        let row: Option<(bool,)> = sqlx::query_as("SELECT banned FROM users WHERE uuid = $1")
+            .bind(uuid)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.is_some_and(|(banned,)| banned))
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::{
+    database::db::DatabaseClient,
+    game::world::Outcome,
+    matches::{
+        matchmaking::GameMode,
+        rating::{self, Rating, Standing},
+    },
+    types::{RatingHistoryRecord, RatingRecord},
+};
+
+impl DatabaseClient {
+    /// Returns the ratings of a player in every mode they played a rated match of
+    pub async fn ratings(&self, uuid: &str) -> Result<Vec<RatingRecord>, sqlx::Error> {
+        sqlx::query_as!(
+            RatingRecord,
+            r#"SELECT mode as "mode: GameMode", rating, deviation, volatility, games_played, updated_at
+            FROM ratings
+            WHERE uuid = $1
+            ORDER BY mode"#,
+            uuid
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the rating of a player in a mode, the default one if they haven't played it yet
+    pub async fn rating(&self, uuid: &str, mode: GameMode) -> Result<Rating, sqlx::Error> {
+        let rating = sqlx::query_as!(
+            Rating,
+            "SELECT rating, deviation, volatility FROM ratings WHERE uuid = $1 AND mode = $2",
+            uuid,
+            mode as _
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(rating.unwrap_or_default())
+    }
+
+    /// Rates the players of a match found by matchmaking that ended in `outcome`, see
+    /// `rating::rate_match`, and returns their new ratings.
+    ///
+    /// The ratings are read, updated and added to the history in a single transaction. The rows
+    /// are locked while the match is rated, a player in two matches that end at once is rated
+    /// by one after the other. A match can only be rated once.
+    pub async fn rate_match(
+        &self,
+        match_id: &str,
+        mode: GameMode,
+        outcome: Outcome,
+        standings: &[Standing],
+    ) -> Result<HashMap<String, Rating>, sqlx::Error> {
+        // Always locked in the same order, so two matches never wait for each other
+        let mut uuids: Vec<String> = standings
+            .iter()
+            .map(|standing| standing.uuid.clone())
+            .collect();
+        uuids.sort();
+
+        let mut transaction = self.pool.begin().await?;
+
+        // Players new to the mode get a row first, to be locked like everyone else's
+        let default = Rating::default();
+        sqlx::query(
+            "INSERT INTO ratings (uuid, mode, rating, deviation, volatility) SELECT uuid, $2, $3, $4, $5 FROM UNNEST($1::varchar[]) AS uuid ORDER BY uuid ON CONFLICT (uuid, mode) DO NOTHING",
+        )
+        .bind(&uuids)
+        .bind(mode)
+        .bind(default.rating)
+        .bind(default.deviation)
+        .bind(default.volatility)
+        .execute(&mut *transaction)
+        .await?;
+
+        let rows: Vec<(String, f64, f64, f64)> = sqlx::query_as(
+            "SELECT uuid, rating, deviation, volatility FROM ratings WHERE mode = $1 AND uuid = ANY($2) ORDER BY uuid FOR UPDATE",
+        )
+        .bind(mode)
+        .bind(&uuids)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let before: HashMap<String, Rating> = rows
+            .into_iter()
+            .map(|(uuid, rating, deviation, volatility)| {
+                let rating = Rating {
+                    rating,
+                    deviation,
+                    volatility,
+                };
+                (uuid, rating)
+            })
+            .collect();
+
+        let after = rating::rate_match(mode, outcome, standings, &before);
+        for uuid in &uuids {
+            let (Some(old), Some(new)) = (before.get(uuid), after.get(uuid)) else {
+                continue;
+            };
+
+            sqlx::query(
+                "UPDATE ratings SET rating = $3, deviation = $4, volatility = $5, games_played = games_played + 1, updated_at = CURRENT_TIMESTAMP WHERE uuid = $1 AND mode = $2",
+            )
+            .bind(uuid)
+            .bind(mode)
+            .bind(new.rating)
+            .bind(new.deviation)
+            .bind(new.volatility)
+            .execute(&mut *transaction)
+            .await?;
+
+            sqlx::query(
+                "INSERT INTO rating_history (uuid, mode, match_id, rating, deviation, volatility, rating_change) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(uuid)
+            .bind(mode)
+            .bind(match_id)
+            .bind(new.rating)
+            .bind(new.deviation)
+            .bind(new.volatility)
+            .bind(new.rating - old.rating)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(after)
+    }
+
+    /// Returns the last `limit` ratings matches of a mode left a player with, newest first
+    pub async fn rating_history(
+        &self,
+        uuid: &str,
+        mode: GameMode,
+        limit: i64,
+    ) -> Result<Vec<RatingHistoryRecord>, sqlx::Error> {
+        sqlx::query_as!(
+            RatingHistoryRecord,
+            r#"SELECT match_id, mode as "mode: GameMode", rating, deviation, volatility, rating_change, recorded_at
+            FROM rating_history
+            WHERE uuid = $1 AND mode = $2
+            ORDER BY recorded_at DESC, id DESC
+            LIMIT $3"#,
+            uuid,
+            mode as _,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
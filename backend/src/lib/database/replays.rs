@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+
+use crate::{database::db::DatabaseClient, game::world::Outcome, types::ReplayRecord};
+
+impl DatabaseClient {
+    /// Stores the details of a replay that was written to disk
+    pub async fn insert_replay(&self, replay: &ReplayRecord) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO replays (match_id, level, players, public, outcome, ticks, recorded_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&replay.match_id)
+        .bind(&replay.level)
+        .bind(&replay.players)
+        .bind(replay.public)
+        .bind(replay.outcome)
+        .bind(replay.ticks)
+        .bind(replay.recorded_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns at most `limit` replays recorded before `before` that `uuid` may watch, newest
+    /// first. With `everything` private replays of other players are included as well.
+    pub async fn replays_visible_to(
+        &self,
+        uuid: &str,
+        everything: bool,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<ReplayRecord>, sqlx::Error> {
+        sqlx::query_as!(
+            ReplayRecord,
+            r#"SELECT match_id, level, players, public, outcome as "outcome: Outcome", ticks, recorded_at
+            FROM replays
+            WHERE (public OR $2 OR $1 = ANY(players))
+            AND ($3::timestamptz IS NULL OR recorded_at < $3)
+            ORDER BY recorded_at DESC, match_id
+            LIMIT $4"#,
+            uuid,
+            everything,
+            before,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns the replay of a match, if one was stored
+    pub async fn replay(&self, match_id: &str) -> Result<Option<ReplayRecord>, sqlx::Error> {
+        sqlx::query_as!(
+            ReplayRecord,
+            r#"SELECT match_id, level, players, public, outcome as "outcome: Outcome", ticks, recorded_at
+            FROM replays
+            WHERE match_id = $1"#,
+            match_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,240 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    claims::{Claims, TokenError},
+    database::db::DatabaseClient,
+    types::{AuthTokens, Authority, RefreshError, RefreshTokenRecord, UserRecord},
+};
+
+/// How long a refresh token can be exchanged for a new token pair
+const REFRESH_TOKEN_EXPIRY: Option<chrono::TimeDelta> = chrono::TimeDelta::try_days(30);
+
+/// Returns a new random refresh token, hex encoded
+fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Refresh tokens are only ever stored as their sha256 hash
+fn hash_refresh_token(refresh_token: &str) -> String {
+    hex::encode(Sha256::digest(refresh_token.as_bytes()))
+}
+
+/// Stores a new refresh token for `uuid` in the provided family and returns the plain token
+async fn insert_refresh_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    uuid: &str,
+    family_id: &str,
+) -> Result<String, sqlx::Error> {
+    let refresh_token = generate_refresh_token();
+
+    let expires_at = chrono::Utc::now()
+        .naive_utc()
+        .checked_add_signed(REFRESH_TOKEN_EXPIRY.expect("TimeDelta is none!"))
+        .expect("valid timestamp");
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, family_id, uuid, expires_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(hash_refresh_token(&refresh_token))
+    .bind(family_id)
+    .bind(uuid)
+    .bind(expires_at)
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(refresh_token)
+}
+
+impl DatabaseClient {
+    /// Starts a new refresh token family for the user and returns the first token of it
+    pub async fn issue_refresh_token(&self, uuid: &str) -> Result<String, sqlx::Error> {
+        let family_id = Uuid::new_v4().to_string();
+
+        let mut transaction: Transaction<'_, Postgres> = self.pool.begin().await?;
+        let refresh_token = insert_refresh_token(&mut transaction, uuid, &family_id).await?;
+        transaction.commit().await?;
+
+        Ok(refresh_token)
+    }
+
+    /// Exchanges a refresh token for a new access token and a new refresh token.
+    ///
+    /// Every refresh token can only be used once. Presenting a token that was already used means
+    /// it was (most likely) stolen, in which case every token of its family is revoked and both
+    /// the thief and the legitimate user have to log in again.
+    pub async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<AuthTokens, RefreshError> {
+        let mut transaction: Transaction<'_, Postgres> = self.pool.begin().await?;
+
+        // Lock the row so two concurrent refreshes with the same token can't both succeed
+        let record = sqlx::query_as!(
+            RefreshTokenRecord,
+            "SELECT family_id, uuid, expires_at, used, revoked FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+            hash_refresh_token(refresh_token),
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or(RefreshError::Invalid)?;
+
+        if record.revoked {
+            return Err(RefreshError::Revoked);
+        }
+
+        if record.used {
+            log::warn!(
+                "Refresh token reuse detected for user {}, revoking family {}",
+                record.uuid,
+                record.family_id
+            );
+
+            sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = $1")
+                .bind(&record.family_id)
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+
+            return Err(RefreshError::Reused);
+        }
+
+        if record.expires_at < chrono::Utc::now().naive_utc() {
+            return Err(RefreshError::Expired);
+        }
+
+        sqlx::query("UPDATE refresh_tokens SET used = TRUE WHERE token_hash = $1")
+            .bind(hash_refresh_token(refresh_token))
+            .execute(&mut *transaction)
+            .await?;
+
+        let new_refresh_token =
+            insert_refresh_token(&mut transaction, &record.uuid, &record.family_id).await?;
+
+        let user_details = sqlx::query_as!(
+            UserRecord,
+            r#"SELECT email, username, password, uuid, authority as "authority: Authority", token_generation FROM users WHERE uuid = $1"#,
+            record.uuid,
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or(RefreshError::Invalid)?;
+
+        transaction.commit().await?;
+
+        Ok(AuthTokens {
+            access_token: Claims::generate_jwt(user_details)?,
+            refresh_token: new_refresh_token,
+        })
+    }
+
+    /// Checks a decoded access token against the revocation list and the user's token generation.
+    ///
+    /// `Claims::decode` only checks the signature and expiry, every authenticated route should
+    /// call this as well.
+    pub async fn check_revocation(&self, claims: &Claims) -> Result<(), TokenError> {
+        let revoked: Option<(String,)> =
+            sqlx::query_as("SELECT jti FROM revoked_tokens WHERE jti = $1")
+                .bind(&claims.jti)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| {
+                    log::error!(
+                        "Failed to check the revocation of token {}: {}",
+                        claims.jti,
+                        e
+                    );
+                    TokenError::Database
+                })?;
+
+        if revoked.is_some() {
+            return Err(TokenError::Revoked);
+        }
+
+        let generation: Option<(i32,)> =
+            sqlx::query_as("SELECT token_generation FROM users WHERE uuid = $1")
+                .bind(&claims.uuid)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| {
+                    log::error!(
+                        "Failed to check the revocation of token {}: {}",
+                        claims.jti,
+                        e
+                    );
+                    TokenError::Database
+                })?;
+
+        match generation {
+            Some((generation,)) if generation == claims.generation => Ok(()),
+            // Either `logout_all` was called since the token was issued or the user is gone
+            _ => Err(TokenError::Revoked),
+        }
+    }
+
+    /// Revokes a single access token and, if provided, the refresh token family it came with
+    pub async fn logout(
+        &self,
+        claims: &Claims,
+        refresh_token: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0)
+            .unwrap_or_default()
+            .naive_utc();
+
+        let mut transaction: Transaction<'_, Postgres> = self.pool.begin().await?;
+
+        // Tokens that are expired anyway no longer need to be on the list
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1")
+            .bind(chrono::Utc::now().naive_utc())
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, uuid, expires_at) VALUES ($1, $2, $3) ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(&claims.jti)
+        .bind(&claims.uuid)
+        .bind(expires_at)
+        .execute(&mut *transaction)
+        .await?;
+
+        if let Some(refresh_token) = refresh_token {
+            // Only revoke the family if it actually belongs to the user logging out
+            sqlx::query(
+                "UPDATE refresh_tokens SET revoked = TRUE WHERE uuid = $1 AND family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $2)",
+            )
+            .bind(&claims.uuid)
+            .bind(hash_refresh_token(refresh_token))
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await
+    }
+
+    /// Invalidates every access and refresh token the user currently has.
+    ///
+    /// Bumping the token generation makes `check_revocation` reject all access tokens issued
+    /// before this call, without having to know their ids.
+    pub async fn logout_all(&self, uuid: &str) -> Result<(), sqlx::Error> {
+        let mut transaction: Transaction<'_, Postgres> = self.pool.begin().await?;
+
+        sqlx::query("UPDATE users SET token_generation = token_generation + 1 WHERE uuid = $1")
+            .bind(uuid)
+            .execute(&mut *transaction)
+            .await?;
+
+        sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE uuid = $1")
+            .bind(uuid)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use super::entity::{EntityId, Movement};
+
+/// How a boss shoots when its fire rate comes around
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "pattern", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Attack {
+    /// A single bullet, like every other shooting alien
+    #[default]
+    Straight,
+
+    /// `bullets` bullets side by side, `spacing` pixels apart
+    Volley { bullets: u32, spacing: f64 },
+}
+
+/// A part of a boss fight, lasts from `health` hit points down to the next phase
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phase {
+    pub health: u32,
+    pub attack: Attack,
+
+    /// Ticks between two attacks, `None` for a phase without any
+    pub fire_rate: Option<u64>,
+
+    /// Chance that an attack goes off when the fire rate comes around, rolled with the world's
+    /// seeded random numbers
+    pub fire_chance: f64,
+
+    /// Pixels per tick of the bullets
+    pub bullet_speed: f64,
+
+    pub speed: f64,
+    pub movement: Movement,
+}
+
+/// The fight of a boss alien, built from the boss of a level
+#[derive(Debug, Clone, PartialEq)]
+pub struct Boss {
+    pub name: String,
+
+    /// Sorted by `health` from the full health of the boss down, there is at least one
+    pub phases: Vec<Phase>,
+}
+
+impl Boss {
+    pub fn max_health(&self) -> u32 {
+        self.phases[0].health
+    }
+
+    /// Index of the phase the boss is in at `health`
+    pub fn phase_at(&self, health: u32) -> usize {
+        self.phases
+            .iter()
+            .rposition(|phase| phase.health >= health)
+            .unwrap_or(0)
+    }
+}
+
+/// The health bar of a boss, sent to the players whenever it changes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BossHealth {
+    /// The boss alien
+    pub id: EntityId,
+    pub name: String,
+    pub health: u32,
+    pub max_health: u32,
+
+    /// The phase the boss is in and how many it has, counting from 1
+    pub phase: usize,
+    pub phases: usize,
+}
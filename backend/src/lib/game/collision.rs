@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+/// A point on the playing field, `y` grows downwards like on the canvas
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Position {
+    pub fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+}
+
+/// An axis aligned rectangle, `pos` is its top left corner
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub pos: Position,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn new(pos: Position, width: f64, height: f64) -> Self {
+        Self { pos, width, height }
+    }
+}
+
+/// A circle, `pos` is the top left corner of its bounding box like in `collisionManager.ts`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub pos: Position,
+    pub radius: f64,
+}
+
+impl Circle {
+    pub fn new(pos: Position, radius: f64) -> Self {
+        Self { pos, radius }
+    }
+}
+
+/// Whether a circle and a rectangle overlap, `circleRectCollision` in the frontend
+pub fn circle_rect_collision(circle: Circle, rect: Rect) -> bool {
+    let closest_x = rect.pos.x.max(circle.pos.x.min(rect.pos.x + rect.width));
+    let closest_y = rect.pos.y.max(circle.pos.y.min(rect.pos.y + rect.height));
+
+    let distance_x = circle.pos.x + circle.radius - closest_x;
+    let distance_y = circle.pos.y + circle.radius - closest_y;
+
+    distance_x * distance_x + distance_y * distance_y < circle.radius * circle.radius
+}
+
+/// Whether two rectangles overlap, touching edges count. `rectRectCollision` in the frontend.
+pub fn rect_rect_collision(a: Rect, b: Rect) -> bool {
+    let x_overlap = a.pos.x + a.width >= b.pos.x && b.pos.x + b.width >= a.pos.x;
+    let y_overlap = a.pos.y + a.height >= b.pos.y && b.pos.y + b.height >= a.pos.y;
+
+    x_overlap && y_overlap
+}
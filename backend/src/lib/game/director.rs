@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::level::{Level, Stage};
+use super::world::{Outcome, World};
+
+/// A stage of a level started, levels and waves count from 1
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StageStarted {
+    pub level: usize,
+    pub name: String,
+    pub wave: usize,
+    pub boss: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    /// The stage starts on tick `until`
+    Waiting { until: u64 },
+
+    /// The stage started on tick `started`, `spawned` of its aliens are out
+    Playing { started: u64, spawned: usize },
+
+    /// Every level was played
+    Finished,
+}
+
+/// Plays levels into a `World`, one stage after another.
+///
+/// A stage starts its `delay` after every alien of the previous one was destroyed and spawns its
+/// aliens at their tick, the next level starts once the last stage of a level was cleared. The
+/// world only has a `Victory` once the last level is done.
+#[derive(Debug, Clone)]
+pub struct Director {
+    levels: Arc<[Level]>,
+    level: usize,
+    stage: usize,
+    state: State,
+}
+
+impl Director {
+    pub fn new(levels: Arc<[Level]>) -> Self {
+        let state = match levels.first().and_then(|level| level.stages.first()) {
+            Some(stage) => State::Waiting { until: stage.delay },
+            None => State::Finished,
+        };
+
+        Self {
+            levels,
+            level: 0,
+            stage: 0,
+            state,
+        }
+    }
+
+    fn current(&self) -> &Stage {
+        &self.levels[self.level].stages[self.stage]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// Moves on to the next stage once the current one is cleared on `tick`
+    fn advance(&mut self, tick: u64) {
+        self.stage += 1;
+        if self.stage == self.levels[self.level].stages.len() {
+            self.level += 1;
+            self.stage = 0;
+        }
+
+        self.state = if self.level == self.levels.len() {
+            State::Finished
+        } else {
+            State::Waiting {
+                until: tick + self.current().delay,
+            }
+        };
+    }
+
+    /// Spawns the aliens that are due, to be called before every step of the world. Returns the
+    /// stage that started on this tick, if any.
+    pub fn step(&mut self, world: &mut World) -> Option<StageStarted> {
+        let mut started = None;
+
+        if let State::Waiting { until } = self.state {
+            if world.tick < until {
+                return None;
+            }
+
+            self.state = State::Playing {
+                started: world.tick,
+                spawned: 0,
+            };
+            started = Some(StageStarted {
+                level: self.level + 1,
+                name: self.levels[self.level].name.clone(),
+                wave: self.stage + 1,
+                boss: self.current().boss,
+            });
+        }
+
+        if let State::Playing {
+            started: start,
+            spawned,
+        } = self.state
+        {
+            let tick = world.tick;
+            let spawns = &self.levels[self.level].stages[self.stage].spawns;
+            let due = spawns[spawned..]
+                .iter()
+                .take_while(|spawn| start + spawn.at <= tick);
+
+            let mut count = spawned;
+            for spawn in due {
+                world.spawn(spawn);
+                count += 1;
+            }
+
+            if count == spawns.len() && world.aliens.is_empty() {
+                self.advance(world.tick);
+            } else {
+                self.state = State::Playing {
+                    started: start,
+                    spawned: count,
+                };
+            }
+        }
+
+        started
+    }
+
+    /// How the game ended, like `World::outcome` but there is no victory while levels are left
+    pub fn outcome(&self, world: &World) -> Option<Outcome> {
+        match world.outcome() {
+            Some(Outcome::Victory) if !self.is_finished() => None,
+            outcome => outcome,
+        }
+    }
+}
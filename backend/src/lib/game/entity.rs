@@ -0,0 +1,453 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::boss::{Attack, Boss, BossHealth, Phase};
+use super::collision::{circle_rect_collision, rect_rect_collision, Circle, Position, Rect};
+use super::input::InputQueue;
+use super::{FIELD_HEIGHT, FIELD_WIDTH};
+
+/// Identifies an entity within a single `World`
+pub type EntityId = u32;
+
+/// Keys a player is holding down, kept until the next input is applied
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct PlayerInput {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+/// A ship controlled by a client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Player {
+    pub id: EntityId,
+    pub uuid: String,
+    pub position: Position,
+    pub alive: bool,
+    pub score: u64,
+
+    /// Ticks between two shots
+    pub fire_rate: u64,
+
+    /// Bullets of this player that can be in flight at once
+    pub max_bullets: usize,
+
+    /// Sequence of the last input the simulation applied, the acknowledgement clients reconcile
+    /// their prediction against
+    pub last_input: Option<u32>,
+
+    #[serde(skip)]
+    pub input: PlayerInput,
+
+    /// Inputs waiting for their tick
+    #[serde(skip)]
+    pub inputs: InputQueue,
+
+    /// Tick of the last shot
+    #[serde(skip)]
+    pub last_shot: Option<u64>,
+}
+
+impl Player {
+    pub const WIDTH: f64 = 20.0;
+    pub const HEIGHT: f64 = 20.0;
+
+    /// Pixels per tick
+    pub const SPEED: f64 = 1.0;
+
+    pub fn new(id: EntityId, uuid: &str, position: Position) -> Self {
+        Self {
+            id,
+            uuid: uuid.to_string(),
+            position,
+            alive: true,
+            score: 0,
+            fire_rate: 5,
+            max_bullets: 1,
+            last_input: None,
+            input: PlayerInput::default(),
+            inputs: InputQueue::new(),
+            last_shot: None,
+        }
+    }
+
+    pub fn shape(&self) -> Rect {
+        Rect::new(self.position, Self::WIDTH, Self::HEIGHT)
+    }
+
+    /// Moves along the held direction keys, the ship can't leave the field
+    pub fn update(&mut self) {
+        let mut dx = 0.0;
+        let mut dy = 0.0;
+
+        if self.input.up {
+            dy -= Self::SPEED;
+        }
+        if self.input.down {
+            dy += Self::SPEED;
+        }
+        if self.input.left {
+            dx -= Self::SPEED;
+        }
+        if self.input.right {
+            dx += Self::SPEED;
+        }
+
+        self.position.x = (self.position.x + dx).clamp(0.0, FIELD_WIDTH - Self::WIDTH);
+        self.position.y = (self.position.y + dy).clamp(0.0, FIELD_HEIGHT - Self::HEIGHT);
+    }
+
+    /// Whether the fire rate allows a shot at `tick`
+    pub fn can_fire(&self, tick: u64) -> bool {
+        self.alive
+            && self.input.fire
+            && self
+                .last_shot
+                .is_none_or(|last| tick - last >= self.fire_rate)
+    }
+
+    pub fn new_bullet(&self, id: EntityId) -> Bullet {
+        Bullet::new(id, self.id, self.position, Bullet::PLAYER_SPEED, true)
+    }
+
+    /// Whether a bullet hits this player
+    pub fn is_hit_by(&self, bullet: &Bullet) -> bool {
+        rect_rect_collision(self.shape(), bullet.swept())
+    }
+}
+
+/// The kinds of aliens, named after the entities of the frontend
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlienKind {
+    /// `Alien`, a small alien that sweeps across the field and never shoots
+    Alien,
+
+    /// `slowStraightShootingAlien`, the rock boss that shoots straight down
+    SlowStraightShootingAlien,
+}
+
+impl AlienKind {
+    pub fn radius(self) -> f64 {
+        match self {
+            AlienKind::Alien => 10.0,
+            AlienKind::SlowStraightShootingAlien => 136.0,
+        }
+    }
+
+    /// Horizontal step per tick before the alien's speed is applied
+    fn x_velocity(self) -> f64 {
+        match self {
+            AlienKind::Alien => 30.0,
+            AlienKind::SlowStraightShootingAlien => 10.0,
+        }
+    }
+
+    /// Ticks between two shots, `None` for aliens that don't shoot
+    pub fn fire_rate(self) -> Option<u64> {
+        match self {
+            AlienKind::Alien => None,
+            AlienKind::SlowStraightShootingAlien => Some(30),
+        }
+    }
+
+    pub fn max_bullets(self) -> usize {
+        match self {
+            AlienKind::Alien => 0,
+            AlienKind::SlowStraightShootingAlien => 10,
+        }
+    }
+
+    pub fn bullet_speed(self) -> f64 {
+        match self {
+            AlienKind::Alien => 0.0,
+            AlienKind::SlowStraightShootingAlien => 100.0,
+        }
+    }
+
+    /// Hits it takes to destroy the alien
+    pub fn health(self) -> u32 {
+        match self {
+            AlienKind::Alien => 1,
+            AlienKind::SlowStraightShootingAlien => 20,
+        }
+    }
+
+    /// Added to the score of the player that destroys the alien
+    pub fn points(self) -> u64 {
+        match self {
+            AlienKind::Alien => 10,
+            AlienKind::SlowStraightShootingAlien => 500,
+        }
+    }
+}
+
+/// How an alien moves across the field
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Movement {
+    /// Sideways, down a row whenever it reaches an edge of the field
+    #[default]
+    Sweep,
+
+    /// Sideways, turning around at the edges without ever coming closer
+    Strafe,
+}
+
+/// An enemy, sweeps sideways and moves down a row whenever it reaches an edge of the field
+/// unless its `movement` says otherwise
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Alien {
+    pub id: EntityId,
+    pub kind: AlienKind,
+    pub position: Position,
+    pub health: u32,
+    pub max_health: u32,
+
+    /// Whether the alien shows its damaged sprite: a boss from its second phase on, other aliens
+    /// once they lost half their health
+    pub damaged: bool,
+    pub movement: Movement,
+
+    /// Multiplier of the kind's horizontal velocity
+    pub speed: f64,
+
+    /// Horizontal step per tick before `speed` is applied, the sign is the direction. Sent along
+    /// with `move_down` so clients can move the alien themselves between snapshots.
+    pub x_velocity: f64,
+
+    /// Whether the alien moves down a row on its next tick
+    pub move_down: bool,
+
+    /// The fight of a boss, `None` for every other alien
+    #[serde(skip)]
+    pub boss: Option<Arc<Boss>>,
+
+    /// Ticks the alien has been alive, its fire rate counts from its spawn
+    #[serde(skip)]
+    cycle: u64,
+}
+
+impl Alien {
+    /// How far an alien moves down when it reaches an edge
+    pub const ROW_HEIGHT: f64 = 30.0;
+
+    pub fn new(id: EntityId, kind: AlienKind, position: Position, speed: f64) -> Self {
+        Self {
+            id,
+            kind,
+            position,
+            health: kind.health(),
+            max_health: kind.health(),
+            damaged: false,
+            movement: Movement::Sweep,
+            speed,
+            x_velocity: kind.x_velocity(),
+            move_down: false,
+            boss: None,
+            cycle: 0,
+        }
+    }
+
+    /// Turns the alien into a boss at the start of its first phase
+    pub fn with_boss(mut self, boss: Arc<Boss>) -> Self {
+        self.health = boss.max_health();
+        self.max_health = boss.max_health();
+        self.enter(&boss.phases[0]);
+        self.boss = Some(boss);
+        self
+    }
+
+    fn enter(&mut self, phase: &Phase) {
+        self.speed = phase.speed;
+        self.movement = phase.movement;
+    }
+
+    /// The phase of a boss, `None` for other aliens
+    pub fn phase(&self) -> Option<&Phase> {
+        let boss = self.boss.as_ref()?;
+        Some(&boss.phases[boss.phase_at(self.health)])
+    }
+
+    /// The health bar of a boss, `None` for other aliens
+    pub fn boss_health(&self) -> Option<BossHealth> {
+        let boss = self.boss.as_ref()?;
+        Some(BossHealth {
+            id: self.id,
+            name: boss.name.clone(),
+            health: self.health,
+            max_health: self.max_health,
+            phase: boss.phase_at(self.health) + 1,
+            phases: boss.phases.len(),
+        })
+    }
+
+    /// Ticks between two attacks, `None` for aliens that don't shoot
+    pub fn fire_rate(&self) -> Option<u64> {
+        match self.phase() {
+            Some(phase) => phase.fire_rate,
+            None => self.kind.fire_rate(),
+        }
+    }
+
+    pub fn shape(&self) -> Circle {
+        Circle::new(self.position, self.kind.radius())
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.health > 0
+    }
+
+    /// Moves the alien, returns whether its fire rate has it shoot on this tick
+    pub fn update(&mut self) -> bool {
+        if self.move_down {
+            self.position.y += Self::ROW_HEIGHT;
+            self.x_velocity *= -1.0;
+            self.move_down = false;
+        } else {
+            self.position.x += self.speed * self.x_velocity;
+        }
+
+        let width = 2.0 * self.kind.radius();
+        if (self.position.x <= 0.0 && self.x_velocity < 0.0)
+            || (self.position.x + width >= FIELD_WIDTH && self.x_velocity > 0.0)
+        {
+            match self.movement {
+                Movement::Sweep => self.move_down = true,
+                Movement::Strafe => self.x_velocity *= -1.0,
+            }
+        }
+
+        let fires = self
+            .fire_rate()
+            .is_some_and(|rate| self.cycle.is_multiple_of(rate));
+        self.cycle += 1;
+
+        fires
+    }
+
+    /// Chance that the alien shoots when its fire rate comes around
+    pub fn fire_chance(&self) -> f64 {
+        self.phase().map_or(1.0, |phase| phase.fire_chance)
+    }
+
+    /// Where the bullets of an attack start, a volley is spread evenly around the alien's
+    /// position
+    pub fn muzzles(&self) -> Vec<Position> {
+        let attack = self.phase().map(|phase| phase.attack).unwrap_or_default();
+        match attack {
+            Attack::Straight => vec![self.position],
+            Attack::Volley { bullets, spacing } => {
+                let first = self.position.x - spacing * (bullets - 1) as f64 / 2.0;
+                (0..bullets)
+                    .map(|i| Position::new(first + spacing * i as f64, self.position.y))
+                    .collect()
+            }
+        }
+    }
+
+    pub fn new_bullet(&self, id: EntityId, muzzle: Position) -> Bullet {
+        let speed = match self.phase() {
+            Some(phase) => phase.bullet_speed,
+            None => self.kind.bullet_speed(),
+        };
+        Bullet::new(id, self.id, muzzle, speed, false)
+    }
+
+    /// Whether a bullet hits this alien
+    pub fn is_hit_by(&self, bullet: &Bullet) -> bool {
+        circle_rect_collision(self.shape(), bullet.swept())
+    }
+
+    /// Takes a hit, returns whether it destroyed the alien. A boss that falls into its next
+    /// phase changes its movement on the spot.
+    pub fn take_damage(&mut self) -> bool {
+        let before = self.boss.as_ref().map(|boss| boss.phase_at(self.health));
+        self.health = self.health.saturating_sub(1);
+
+        match self.boss.clone() {
+            Some(boss) => {
+                let phase = boss.phase_at(self.health);
+                if before != Some(phase) && self.is_alive() {
+                    self.enter(&boss.phases[phase]);
+                }
+                self.damaged = phase > 0;
+            }
+            None => self.damaged = self.health * 2 <= self.max_health,
+        }
+
+        self.health == 0
+    }
+}
+
+/// A bullet flying straight up (fired by a player) or down (fired by an alien)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bullet {
+    pub id: EntityId,
+
+    /// The player or alien that fired it
+    pub shooter: EntityId,
+    pub position: Position,
+
+    /// Player bullets fly up and only hit aliens, alien bullets fly down and only hit players
+    pub from_player: bool,
+
+    /// Pixels per tick, positive is downwards
+    pub velocity: f64,
+    pub active: bool,
+}
+
+impl Bullet {
+    pub const WIDTH: f64 = 5.0;
+    pub const HEIGHT: f64 = 10.0;
+    pub const PLAYER_SPEED: f64 = 10.0;
+
+    pub fn new(
+        id: EntityId,
+        shooter: EntityId,
+        position: Position,
+        speed: f64,
+        from_player: bool,
+    ) -> Self {
+        Self {
+            id,
+            shooter,
+            position,
+            from_player,
+            velocity: if from_player { -speed } else { speed },
+            active: true,
+        }
+    }
+
+    pub fn shape(&self) -> Rect {
+        Rect::new(self.position, Self::WIDTH, Self::HEIGHT)
+    }
+
+    /// The area the bullet covered during the last tick.
+    ///
+    /// Collisions are checked against this rather than `shape`, alien bullets move further per
+    /// tick than a ship is tall and would otherwise fly straight through it.
+    pub fn swept(&self) -> Rect {
+        let previous_y = self.position.y - self.velocity;
+        let top = previous_y.min(self.position.y);
+
+        Rect::new(
+            Position::new(self.position.x, top),
+            Self::WIDTH,
+            Self::HEIGHT + self.velocity.abs(),
+        )
+    }
+
+    /// Moves the bullet, it is gone once it leaves the field
+    pub fn update(&mut self) {
+        self.position.y += self.velocity;
+
+        if self.position.y < 0.0 || self.position.y > FIELD_HEIGHT {
+            self.active = false;
+        }
+    }
+}
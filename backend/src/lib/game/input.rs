@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::entity::PlayerInput;
+
+/// Inputs a player can be ahead of the simulation by. Inputs arrive in bursts when the network
+/// hiccups, more than this in too few ticks means the client runs faster than the server.
+pub const MAX_INPUT_BURST: u32 = 8;
+
+/// The keys a client held during one of its ticks.
+///
+/// Clients number their inputs and keep the ones the server hasn't acknowledged yet. Every
+/// snapshot carries the last sequence the simulation applied for each player, the client resets
+/// its ship to the snapshot and replays the newer inputs on top of it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SequencedInput {
+    /// Increases by one with every input of the client
+    pub sequence: u32,
+
+    /// Tick of the client's own simulation the input was recorded on
+    pub client_tick: u64,
+
+    pub input: PlayerInput,
+}
+
+/// Why an input was not queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputRejection {
+    /// Its sequence or client tick is not newer than the last queued input, a duplicate or an
+    /// input that arrived out of order
+    Stale,
+
+    /// The client sends inputs faster than the simulation ticks
+    TooFast,
+}
+
+/// Inputs of a player waiting for the simulation, exactly one is applied per tick.
+///
+/// Applying one input per tick is what keeps clients from speeding up their ship by sending
+/// more inputs: a flood just waits in the queue. The queue takes one input per tick that went by
+/// on top of `MAX_INPUT_BURST`, so it can't grow without bound either.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputQueue {
+    pending: VecDeque<SequencedInput>,
+    last_queued: Option<SequencedInput>,
+
+    /// Inputs that may still be queued, grows by one per tick up to `MAX_INPUT_BURST`
+    credit: u32,
+    credited_at: u64,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            last_queued: None,
+            credit: MAX_INPUT_BURST,
+            credited_at: 0,
+        }
+    }
+
+    /// Queues an input that arrived while the simulation is at `tick`
+    pub fn push(&mut self, input: SequencedInput, tick: u64) -> Result<(), InputRejection> {
+        let stale = self.last_queued.is_some_and(|last| {
+            input.sequence <= last.sequence || input.client_tick < last.client_tick
+        });
+        if stale {
+            return Err(InputRejection::Stale);
+        }
+
+        let elapsed = tick.saturating_sub(self.credited_at);
+        let credit = u64::from(self.credit) + elapsed;
+        self.credit = credit.min(u64::from(MAX_INPUT_BURST)) as u32;
+        self.credited_at = tick;
+
+        if self.credit == 0 {
+            return Err(InputRejection::TooFast);
+        }
+
+        self.credit -= 1;
+        self.last_queued = Some(input);
+        self.pending.push_back(input);
+        Ok(())
+    }
+
+    /// The input the next tick applies, without taking it
+    pub fn peek(&self) -> Option<&SequencedInput> {
+        self.pending.front()
+    }
+
+    /// The input for the next tick, `None` if the client hasn't sent it yet
+    pub fn pop(&mut self) -> Option<SequencedInput> {
+        self.pending.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
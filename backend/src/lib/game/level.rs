@@ -0,0 +1,541 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::boss::{Attack, Boss, Phase};
+use super::collision::Position;
+use super::entity::{AlienKind, Movement};
+use super::{FIELD_HEIGHT, FIELD_WIDTH, TICK_RATE};
+
+#[derive(Error, Debug)]
+pub enum LevelError {
+    #[error("Could not read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{path} is not a level: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("{path} is not a valid level: {reason}")]
+    Invalid { path: PathBuf, reason: String },
+
+    #[error("There are no levels in {0}")]
+    NoLevels(PathBuf),
+}
+
+/// How the aliens of a group are laid out, `origin` of the group is the top left one
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "shape", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Formation {
+    /// A single alien
+    #[default]
+    Single,
+
+    /// `count` aliens next to each other, `spacing` pixels apart
+    Row { count: u32, spacing: f64 },
+
+    /// `count` aliens below each other, `spacing` pixels apart
+    Column { count: u32, spacing: f64 },
+
+    /// `rows` rows of `columns` aliens
+    Grid {
+        rows: u32,
+        columns: u32,
+        spacing_x: f64,
+        spacing_y: f64,
+    },
+
+    /// A V pointing down with `count` aliens on each arm and one at the tip, the arms start
+    /// at the top
+    Wedge {
+        count: u32,
+        spacing_x: f64,
+        spacing_y: f64,
+    },
+}
+
+impl Formation {
+    /// Where the aliens are relative to the origin, in spawn order
+    fn offsets(&self) -> Vec<Position> {
+        match *self {
+            Formation::Single => vec![Position::default()],
+            Formation::Row { count, spacing } => (0..count)
+                .map(|i| Position::new(spacing * i as f64, 0.0))
+                .collect(),
+            Formation::Column { count, spacing } => (0..count)
+                .map(|i| Position::new(0.0, spacing * i as f64))
+                .collect(),
+            Formation::Grid {
+                rows,
+                columns,
+                spacing_x,
+                spacing_y,
+            } => (0..rows)
+                .flat_map(|row| {
+                    (0..columns).map(move |column| {
+                        Position::new(spacing_x * column as f64, spacing_y * row as f64)
+                    })
+                })
+                .collect(),
+            Formation::Wedge {
+                count,
+                spacing_x,
+                spacing_y,
+            } => {
+                let tip = count as f64;
+                (0..count)
+                    .flat_map(|i| {
+                        let y = spacing_y * i as f64;
+                        [
+                            Position::new(spacing_x * i as f64, y),
+                            Position::new(spacing_x * (2.0 * tip - i as f64), y),
+                        ]
+                    })
+                    .chain([Position::new(spacing_x * tip, spacing_y * tip)])
+                    .collect()
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let (counts, spacings): (&[u32], &[f64]) = match self {
+            Formation::Single => (&[], &[]),
+            Formation::Row { count, spacing } | Formation::Column { count, spacing } => {
+                (std::slice::from_ref(count), std::slice::from_ref(spacing))
+            }
+            Formation::Grid {
+                rows,
+                columns,
+                spacing_x,
+                spacing_y,
+            } => (&[*rows, *columns], &[*spacing_x, *spacing_y]),
+            Formation::Wedge {
+                count,
+                spacing_x,
+                spacing_y,
+            } => (std::slice::from_ref(count), &[*spacing_x, *spacing_y]),
+        };
+
+        if counts.contains(&0) {
+            return Err("a formation has no aliens".to_string());
+        }
+        if spacings.iter().any(|spacing| !spacing.is_finite()) {
+            return Err("a formation spacing is not a number".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Aliens of one kind spawned together
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GroupDefinition {
+    pub kind: AlienKind,
+    pub origin: Position,
+
+    #[serde(default)]
+    pub formation: Formation,
+
+    /// Multiplier of the kind's horizontal velocity
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+
+    #[serde(default)]
+    pub movement: Movement,
+
+    /// Seconds after the start of the wave the first alien of the group appears
+    #[serde(default)]
+    pub delay: f64,
+
+    /// Seconds between two aliens of the group, they appear all at once by default
+    #[serde(default)]
+    pub interval: f64,
+}
+
+/// Aliens that come together, the next wave starts once all of them are destroyed
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WaveDefinition {
+    /// Seconds between clearing the previous wave and the start of this one
+    #[serde(default)]
+    pub delay: f64,
+
+    pub groups: Vec<GroupDefinition>,
+}
+
+/// A part of a boss fight, the attack and movement of the boss change when it starts
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseDefinition {
+    /// The phase starts once the boss is down to this many hit points
+    pub health: u32,
+
+    #[serde(default)]
+    pub attack: Attack,
+
+    /// Seconds between two attacks, the kind's fire rate by default
+    pub fire_rate: Option<f64>,
+
+    /// Chance that an attack goes off when the fire rate comes around, every time by default
+    pub fire_chance: Option<f64>,
+
+    /// Pixels per tick of the bullets, the kind's bullet speed by default
+    pub bullet_speed: Option<f64>,
+
+    /// The boss's own speed by default
+    pub speed: Option<f64>,
+
+    /// The boss's own movement by default
+    pub movement: Option<Movement>,
+}
+
+/// A single big alien that ends the level
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BossDefinition {
+    pub kind: AlienKind,
+    pub origin: Position,
+
+    /// Shown on the health bar, the name of the level by default
+    pub name: Option<String>,
+
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+
+    #[serde(default)]
+    pub movement: Movement,
+
+    /// Hits it takes to destroy the boss, the kind's own health by default
+    pub health: Option<u32>,
+
+    /// Seconds between clearing the last wave and the boss appearing
+    #[serde(default)]
+    pub delay: f64,
+
+    /// The phases from full health down, a single phase that shoots like the kind by default
+    #[serde(default)]
+    pub phases: Vec<PhaseDefinition>,
+}
+
+/// A level file as written by designers
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LevelDefinition {
+    pub name: String,
+
+    #[serde(default)]
+    pub waves: Vec<WaveDefinition>,
+
+    pub boss: Option<BossDefinition>,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+/// A single alien of a stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spawn {
+    /// Ticks after the start of the stage
+    pub at: u64,
+    pub kind: AlienKind,
+    pub position: Position,
+    pub speed: f64,
+    pub movement: Movement,
+
+    /// Makes the alien a boss
+    pub boss: Option<Arc<Boss>>,
+}
+
+/// A wave or the boss of a level, ready to be played
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    /// Ticks between clearing the previous stage and the start of this one
+    pub delay: u64,
+    pub boss: bool,
+
+    /// Sorted by `at`
+    pub spawns: Vec<Spawn>,
+}
+
+/// A validated level, its waves and boss turned into stages timed in ticks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    /// The name of the level's file without the extension, replays refer to levels by it
+    pub id: String,
+    pub name: String,
+    pub stages: Vec<Stage>,
+}
+
+/// Seconds of a level file in ticks of `TICK_RATE`
+fn ticks(seconds: f64, what: &str) -> Result<u64, String> {
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("{what} must be zero or more seconds"));
+    }
+    Ok((seconds * TICK_RATE as f64).round() as u64)
+}
+
+fn check_speed(speed: f64) -> Result<(), String> {
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("speed {speed} must be more than zero"));
+    }
+    Ok(())
+}
+
+/// Aliens have to appear entirely on the field
+fn check_position(kind: AlienKind, position: Position) -> Result<(), String> {
+    let size = 2.0 * kind.radius();
+    let fits = (0.0..=FIELD_WIDTH - size).contains(&position.x)
+        && (0.0..=FIELD_HEIGHT - size).contains(&position.y);
+
+    if !fits {
+        return Err(format!(
+            "a {kind:?} at ({}, {}) is not on the field",
+            position.x, position.y
+        ));
+    }
+    Ok(())
+}
+
+impl GroupDefinition {
+    fn spawns(&self) -> Result<Vec<Spawn>, String> {
+        self.formation.validate()?;
+        check_speed(self.speed)?;
+        let delay = ticks(self.delay, "a group delay")?;
+        let interval = ticks(self.interval, "a group interval")?;
+
+        self.formation
+            .offsets()
+            .into_iter()
+            .enumerate()
+            .map(|(i, offset)| {
+                let position = Position::new(self.origin.x + offset.x, self.origin.y + offset.y);
+                check_position(self.kind, position)?;
+
+                Ok(Spawn {
+                    at: delay + interval * i as u64,
+                    kind: self.kind,
+                    position,
+                    speed: self.speed,
+                    movement: self.movement,
+                    boss: None,
+                })
+            })
+            .collect()
+    }
+}
+
+impl WaveDefinition {
+    fn stage(&self) -> Result<Stage, String> {
+        if self.groups.is_empty() {
+            return Err("a wave has no groups".to_string());
+        }
+
+        let mut spawns = Vec::new();
+        for group in &self.groups {
+            spawns.extend(group.spawns()?);
+        }
+        spawns.sort_by_key(|spawn| spawn.at);
+
+        Ok(Stage {
+            delay: ticks(self.delay, "a wave delay")?,
+            boss: false,
+            spawns,
+        })
+    }
+}
+
+impl PhaseDefinition {
+    fn phase(&self, boss: &BossDefinition) -> Result<Phase, String> {
+        if let Attack::Volley { bullets, spacing } = self.attack {
+            if bullets == 0 || !spacing.is_finite() {
+                return Err("a volley needs bullets and a spacing".to_string());
+            }
+        }
+
+        let fire_rate = match self.fire_rate {
+            Some(seconds) if seconds <= 0.0 => {
+                return Err("a phase fire rate must be more than zero seconds".to_string())
+            }
+            Some(seconds) => Some(ticks(seconds, "a phase fire rate")?.max(1)),
+            None => boss.kind.fire_rate(),
+        };
+
+        let fire_chance = self.fire_chance.unwrap_or(1.0);
+        if !(fire_chance > 0.0 && fire_chance <= 1.0) {
+            return Err(format!(
+                "fire chance {fire_chance} must be above 0 and at most 1"
+            ));
+        }
+
+        let bullet_speed = self.bullet_speed.unwrap_or(boss.kind.bullet_speed());
+        let speed = self.speed.unwrap_or(boss.speed);
+        check_speed(bullet_speed)?;
+        check_speed(speed)?;
+
+        Ok(Phase {
+            health: self.health,
+            attack: self.attack,
+            fire_rate,
+            fire_chance,
+            bullet_speed,
+            speed,
+            movement: self.movement.unwrap_or(boss.movement),
+        })
+    }
+}
+
+impl BossDefinition {
+    fn boss(&self, level: &str) -> Result<Boss, String> {
+        let health = self.health.unwrap_or(self.kind.health());
+        if health == 0 {
+            return Err("the boss has no health".to_string());
+        }
+
+        let phases = if self.phases.is_empty() {
+            vec![Phase {
+                health,
+                attack: Attack::Straight,
+                fire_rate: self.kind.fire_rate(),
+                fire_chance: 1.0,
+                bullet_speed: self.kind.bullet_speed(),
+                speed: self.speed,
+                movement: self.movement,
+            }]
+        } else {
+            self.phases
+                .iter()
+                .map(|phase| phase.phase(self))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        if phases[0].health != health {
+            return Err(format!(
+                "the first phase of the boss has to start at its full health of {health}"
+            ));
+        }
+        if phases
+            .windows(2)
+            .any(|pair| pair[1].health >= pair[0].health)
+        {
+            return Err("the phases of the boss have to start at less and less health".to_string());
+        }
+        if phases.iter().any(|phase| phase.health == 0) {
+            return Err("a phase of the boss starts after it is destroyed".to_string());
+        }
+
+        Ok(Boss {
+            name: self.name.clone().unwrap_or_else(|| level.to_string()),
+            phases,
+        })
+    }
+
+    fn stage(&self, level: &str) -> Result<Stage, String> {
+        check_speed(self.speed)?;
+        check_position(self.kind, self.origin)?;
+        let boss = self.boss(level)?;
+
+        Ok(Stage {
+            delay: ticks(self.delay, "the boss delay")?,
+            boss: true,
+            spawns: vec![Spawn {
+                at: 0,
+                kind: self.kind,
+                position: self.origin,
+                speed: boss.phases[0].speed,
+                movement: boss.phases[0].movement,
+                boss: Some(Arc::new(boss)),
+            }],
+        })
+    }
+}
+
+impl LevelDefinition {
+    /// Checks the level and times its stages
+    pub fn compile(&self, id: &str) -> Result<Level, String> {
+        if self.name.trim().is_empty() {
+            return Err("the level has no name".to_string());
+        }
+        if self.waves.is_empty() && self.boss.is_none() {
+            return Err("the level has neither waves nor a boss".to_string());
+        }
+
+        let mut stages = self
+            .waves
+            .iter()
+            .map(WaveDefinition::stage)
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(boss) = &self.boss {
+            stages.push(boss.stage(&self.name)?);
+        }
+
+        Ok(Level {
+            id: id.to_string(),
+            name: self.name.clone(),
+            stages,
+        })
+    }
+}
+
+/// Parses and validates the level file at `path`
+pub fn parse(path: &Path, text: &str) -> Result<Level, LevelError> {
+    let definition: LevelDefinition = toml::from_str(text).map_err(|source| LevelError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let id = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    definition
+        .compile(&id)
+        .map_err(|reason| LevelError::Invalid {
+            path: path.to_path_buf(),
+            reason,
+        })
+}
+
+/// Loads every `.toml` file in `directory` as a level. Matches play them in the order of their
+/// file names, a single broken file fails the whole set.
+pub fn load(directory: &Path) -> Result<Arc<[Level]>, LevelError> {
+    let io_error = |source| LevelError::Io {
+        path: directory.to_path_buf(),
+        source,
+    };
+
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(LevelError::NoLevels(directory.to_path_buf()));
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let text = std::fs::read_to_string(path).map_err(|source| LevelError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            parse(path, &text)
+        })
+        .collect()
+}
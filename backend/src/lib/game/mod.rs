@@ -0,0 +1,22 @@
+pub mod boss;
+pub mod collision;
+pub mod director;
+pub mod entity;
+pub mod input;
+pub mod level;
+pub mod snapshot;
+pub mod timestep;
+pub mod world;
+
+/// Width of the playing field, the canvas of the frontend
+pub const FIELD_WIDTH: f64 = 1280.0;
+
+/// Height of the playing field, bullets are gone once they leave it
+pub const FIELD_HEIGHT: f64 = 800.0;
+
+/// Ticks per second of the simulation. The frontend draws at 60 fps and its entities move per
+/// frame, so the speeds of the entities are per tick at this rate.
+pub const TICK_RATE: u32 = 60;
+
+/// Most bullets in flight at once, across every shooter. `MAX_BULLETS` in the frontend.
+pub const MAX_BULLETS: usize = 50;
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::collision::Position;
+use super::entity::{Alien, Bullet, EntityId, Movement, Player};
+use super::world::World;
+
+/// Bytes of snapshots per second a player may receive, the tests hold a busy match to it
+pub const BANDWIDTH_BUDGET: usize = 20 * 1024;
+
+/// An entity that can be sent as the fields that changed since an earlier snapshot
+pub trait Delta: Clone {
+    /// The fields of the entity that can change, each set only if it did
+    type Change;
+
+    fn id(&self) -> EntityId;
+
+    /// Where the entity is `ticks` later if nothing but its own motion changes it. Clients do the
+    /// same, so predictable motion doesn't have to be sent.
+    fn predict(&self, _ticks: u64) -> Self {
+        self.clone()
+    }
+
+    /// What changed compared to `base`, `None` if nothing did
+    fn diff(&self, base: &Self) -> Option<Self::Change>;
+
+    fn apply(&mut self, change: &Self::Change);
+}
+
+/// How the entities of one kind changed between two snapshots
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(bound(deserialize = "E: Deserialize<'de>, C: Deserialize<'de>"))]
+pub struct EntityDelta<E, C> {
+    /// Entities that weren't there before, in full
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spawned: Vec<E>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<C>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub despawned: Vec<EntityId>,
+}
+
+impl<E: Delta<Change = C>, C> EntityDelta<E, C> {
+    fn between(base: &[E], entities: &[E], ticks: u64) -> Self {
+        let base: HashMap<EntityId, &E> = base.iter().map(|entity| (entity.id(), entity)).collect();
+        let ids: HashSet<EntityId> = entities.iter().map(Delta::id).collect();
+
+        let mut spawned = Vec::new();
+        let mut changed = Vec::new();
+        for entity in entities {
+            match base.get(&entity.id()) {
+                Some(before) => changed.extend(entity.diff(&before.predict(ticks))),
+                None => spawned.push(entity.clone()),
+            }
+        }
+
+        let mut despawned: Vec<EntityId> = base
+            .keys()
+            .filter(|id| !ids.contains(id))
+            .copied()
+            .collect();
+        despawned.sort_unstable();
+
+        Self {
+            spawned,
+            changed,
+            despawned,
+        }
+    }
+
+    fn apply(&self, base: &[E], ticks: u64, change_id: impl Fn(&C) -> EntityId) -> Vec<E> {
+        let changes: HashMap<EntityId, &C> = self
+            .changed
+            .iter()
+            .map(|change| (change_id(change), change))
+            .collect();
+
+        base.iter()
+            .filter(|entity| !self.despawned.contains(&entity.id()))
+            .map(|entity| {
+                let mut entity = entity.predict(ticks);
+                if let Some(change) = changes.get(&entity.id()) {
+                    entity.apply(change);
+                }
+                entity
+            })
+            .chain(self.spawned.iter().cloned())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.changed.is_empty() && self.despawned.is_empty()
+    }
+}
+
+/// `Some(new)` if it differs from `base`
+fn changed<T: PartialEq + Clone>(new: &T, base: &T) -> Option<T> {
+    (new != base).then(|| new.clone())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PlayerChange {
+    pub id: EntityId,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alive: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fire_rate: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bullets: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_input: Option<u32>,
+}
+
+impl Delta for Player {
+    type Change = PlayerChange;
+
+    fn id(&self) -> EntityId {
+        self.id
+    }
+
+    fn diff(&self, base: &Self) -> Option<PlayerChange> {
+        let change = PlayerChange {
+            id: self.id,
+            position: changed(&self.position, &base.position),
+            alive: changed(&self.alive, &base.alive),
+            score: changed(&self.score, &base.score),
+            fire_rate: changed(&self.fire_rate, &base.fire_rate),
+            max_bullets: changed(&self.max_bullets, &base.max_bullets),
+            // Acknowledgements only ever go up
+            last_input: changed(&self.last_input, &base.last_input).flatten(),
+        };
+
+        (change
+            != PlayerChange {
+                id: self.id,
+                ..PlayerChange::default()
+            })
+        .then_some(change)
+    }
+
+    fn apply(&mut self, change: &PlayerChange) {
+        if let Some(position) = change.position {
+            self.position = position;
+        }
+        if let Some(alive) = change.alive {
+            self.alive = alive;
+        }
+        if let Some(score) = change.score {
+            self.score = score;
+        }
+        if let Some(fire_rate) = change.fire_rate {
+            self.fire_rate = fire_rate;
+        }
+        if let Some(max_bullets) = change.max_bullets {
+            self.max_bullets = max_bullets;
+        }
+        if let Some(last_input) = change.last_input {
+            self.last_input = Some(last_input);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AlienChange {
+    pub id: EntityId,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub damaged: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub movement: Option<Movement>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_velocity: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub move_down: Option<bool>,
+}
+
+impl Delta for Alien {
+    type Change = AlienChange;
+
+    fn id(&self) -> EntityId {
+        self.id
+    }
+
+    /// Aliens move the same way on every client, only hits and the changes of a boss's phase are
+    /// sent
+    fn predict(&self, ticks: u64) -> Self {
+        let mut alien = self.clone();
+        for _ in 0..ticks {
+            alien.update();
+        }
+        alien
+    }
+
+    fn diff(&self, base: &Self) -> Option<AlienChange> {
+        let change = AlienChange {
+            id: self.id,
+            position: changed(&self.position, &base.position),
+            health: changed(&self.health, &base.health),
+            damaged: changed(&self.damaged, &base.damaged),
+            movement: changed(&self.movement, &base.movement),
+            speed: changed(&self.speed, &base.speed),
+            x_velocity: changed(&self.x_velocity, &base.x_velocity),
+            move_down: changed(&self.move_down, &base.move_down),
+        };
+
+        (change
+            != AlienChange {
+                id: self.id,
+                ..AlienChange::default()
+            })
+        .then_some(change)
+    }
+
+    fn apply(&mut self, change: &AlienChange) {
+        if let Some(position) = change.position {
+            self.position = position;
+        }
+        if let Some(health) = change.health {
+            self.health = health;
+        }
+        if let Some(damaged) = change.damaged {
+            self.damaged = damaged;
+        }
+        if let Some(movement) = change.movement {
+            self.movement = movement;
+        }
+        if let Some(speed) = change.speed {
+            self.speed = speed;
+        }
+        if let Some(x_velocity) = change.x_velocity {
+            self.x_velocity = x_velocity;
+        }
+        if let Some(move_down) = change.move_down {
+            self.move_down = move_down;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct BulletChange {
+    pub id: EntityId,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<f64>,
+}
+
+impl Delta for Bullet {
+    type Change = BulletChange;
+
+    fn id(&self) -> EntityId {
+        self.id
+    }
+
+    /// Bullets fly in a straight line, they only show up in a delta when they spawn or despawn
+    fn predict(&self, ticks: u64) -> Self {
+        let mut bullet = self.clone();
+        bullet.position.y += self.velocity * ticks as f64;
+        bullet
+    }
+
+    fn diff(&self, base: &Self) -> Option<BulletChange> {
+        let change = BulletChange {
+            id: self.id,
+            position: changed(&self.position, &base.position),
+            velocity: changed(&self.velocity, &base.velocity),
+        };
+
+        (change
+            != BulletChange {
+                id: self.id,
+                ..BulletChange::default()
+            })
+        .then_some(change)
+    }
+
+    fn apply(&mut self, change: &BulletChange) {
+        if let Some(position) = change.position {
+            self.position = position;
+        }
+        if let Some(velocity) = change.velocity {
+            self.velocity = velocity;
+        }
+    }
+}
+
+/// How a `World` changed since a snapshot the client acknowledged.
+///
+/// A client that has the snapshot of `base_tick` applies the delta to it and ends up with the
+/// snapshot of `tick`. Deltas against anything else are useless to it, it keeps acknowledging the
+/// last snapshot it does have and the server falls back to a full snapshot once that one is too
+/// old to diff against.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorldDelta {
+    pub base_tick: u64,
+    pub tick: u64,
+    pub players: EntityDelta<Player, PlayerChange>,
+    pub aliens: EntityDelta<Alien, AlienChange>,
+    pub bullets: EntityDelta<Bullet, BulletChange>,
+}
+
+impl WorldDelta {
+    pub fn between(base: &World, world: &World) -> Self {
+        let ticks = world.tick.saturating_sub(base.tick);
+
+        Self {
+            base_tick: base.tick,
+            tick: world.tick,
+            players: EntityDelta::between(&base.players, &world.players, ticks),
+            aliens: EntityDelta::between(&base.aliens, &world.aliens, ticks),
+            bullets: EntityDelta::between(&base.bullets, &world.bullets, ticks),
+        }
+    }
+
+    /// The snapshot of `tick`, `None` if `base` is not the snapshot the delta was made against
+    pub fn apply(&self, base: &World) -> Option<World> {
+        if base.tick != self.base_tick {
+            return None;
+        }
+        let ticks = self.tick - self.base_tick;
+
+        let mut world = base.clone();
+        world.tick = self.tick;
+        world.players = self.players.apply(&base.players, ticks, |c| c.id);
+        world.aliens = self.aliens.apply(&base.aliens, ticks, |c| c.id);
+        world.bullets = self.bullets.apply(&base.bullets, ticks, |c| c.id);
+
+        Some(world)
+    }
+}
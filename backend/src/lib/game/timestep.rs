@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+/// Turns elapsed time into a whole number of fixed ticks.
+///
+/// Time that doesn't add up to a full tick is carried over to the next call, so the simulation
+/// runs at the same speed however unevenly it is driven. Lagging far behind doesn't make it run
+/// an unbounded number of ticks at once, at most `max_ticks` are returned per call.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulated: Duration,
+    max_ticks: u32,
+}
+
+impl FixedTimestep {
+    /// A timestep of `tick_rate` ticks per second
+    pub fn new(tick_rate: u32, max_ticks: u32) -> Self {
+        Self {
+            tick: Duration::from_secs(1) / tick_rate,
+            accumulated: Duration::ZERO,
+            max_ticks,
+        }
+    }
+
+    /// Length of a single tick
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    /// Adds `elapsed` and returns how many ticks to run
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulated += elapsed;
+
+        let mut ticks = 0;
+        while self.accumulated >= self.tick && ticks < self.max_ticks {
+            self.accumulated -= self.tick;
+            ticks += 1;
+        }
+
+        // Whatever couldn't be caught up with is dropped
+        if ticks == self.max_ticks {
+            self.accumulated = self.accumulated.min(self.tick);
+        }
+
+        ticks
+    }
+}
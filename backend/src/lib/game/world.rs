@@ -0,0 +1,267 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::collision::Position;
+use super::entity::{Alien, AlienKind, Bullet, EntityId, Player, PlayerInput};
+use super::input::{InputRejection, SequencedInput};
+use super::level::Spawn;
+use super::MAX_BULLETS;
+
+/// Where players appear, the spawn point of the frontend
+pub const PLAYER_SPAWN: Position = Position { x: 640.0, y: 730.0 };
+
+/// How a game ended
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Every alien was destroyed
+    Victory,
+
+    /// Every player was shot down
+    Defeat,
+}
+
+/// The random numbers of a world. ChaCha8 gives the same numbers for a seed on every machine and
+/// with every version of `rand`, so a game can be played again from its seed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRng(ChaCha8Rng);
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self(ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    /// `true` with a probability of `chance`
+    pub fn chance(&mut self, chance: f64) -> bool {
+        self.0.gen::<f64>() < chance
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Everything in a single game, advanced one fixed tick at a time with `step`.
+///
+/// Follows the frontend's `SpaceInvadersGame`: every tick each player's next queued input is
+/// applied, the entities move and shoot, then the bullets are checked against the entities they
+/// can hit using the rules of `collisionManager.ts`. A bullet is used up by the first entity it
+/// hits, a destroyed alien adds to the score of the player that shot it.
+///
+/// The game only depends on its seed, its inputs and the ticks, never on the clock or on random
+/// numbers from elsewhere. That makes it reproducible, see `matches::replay`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct World {
+    pub tick: u64,
+    pub players: Vec<Player>,
+    pub aliens: Vec<Alien>,
+    pub bullets: Vec<Bullet>,
+
+    #[serde(skip)]
+    next_id: EntityId,
+
+    #[serde(skip)]
+    rng: GameRng,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A world whose random numbers come from `seed`
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: GameRng::new(seed),
+            ..Self::default()
+        }
+    }
+
+    /// Hash of everything clients can see of the world, equal for two worlds that played the same
+    /// game
+    pub fn state_hash(&self) -> String {
+        let state = rmp_serde::to_vec(self).expect("Worlds always serialize");
+        hex::encode(Sha256::digest(state))
+    }
+
+    fn next_id(&mut self) -> EntityId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Adds a player at the spawn point, returns its id
+    pub fn add_player(&mut self, uuid: &str) -> EntityId {
+        let id = self.next_id();
+        self.players.push(Player::new(id, uuid, PLAYER_SPAWN));
+        id
+    }
+
+    /// Adds an alien, returns its id
+    pub fn spawn_alien(&mut self, kind: AlienKind, position: Position, speed: f64) -> EntityId {
+        let id = self.next_id();
+        self.aliens.push(Alien::new(id, kind, position, speed));
+        id
+    }
+
+    /// Adds an alien of a level, returns its id
+    pub fn spawn(&mut self, spawn: &Spawn) -> EntityId {
+        let id = self.next_id();
+        let mut alien = Alien::new(id, spawn.kind, spawn.position, spawn.speed);
+        alien.movement = spawn.movement;
+        if let Some(boss) = &spawn.boss {
+            alien = alien.with_boss(boss.clone());
+        }
+        self.aliens.push(alien);
+        id
+    }
+
+    /// Takes a player out of the game, its bullets keep flying. Returns `false` if there is no
+    /// such player.
+    pub fn remove_player(&mut self, uuid: &str) -> bool {
+        let before = self.players.len();
+        self.players.retain(|player| player.uuid != uuid);
+        self.players.len() != before
+    }
+
+    pub fn player(&self, uuid: &str) -> Option<&Player> {
+        self.players.iter().find(|player| player.uuid == uuid)
+    }
+
+    /// Replaces the keys a player is holding, returns `false` if there is no such player
+    pub fn set_input(&mut self, uuid: &str, input: PlayerInput) -> bool {
+        match self.players.iter_mut().find(|player| player.uuid == uuid) {
+            Some(player) => {
+                player.input = input;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues an input of a player for the coming ticks, `None` if there is no such player
+    pub fn queue_input(
+        &mut self,
+        uuid: &str,
+        input: SequencedInput,
+    ) -> Option<Result<(), InputRejection>> {
+        let tick = self.tick;
+        let player = self.players.iter_mut().find(|player| player.uuid == uuid)?;
+        Some(player.inputs.push(input, tick))
+    }
+
+    fn bullets_of(&self, shooter: EntityId) -> usize {
+        self.bullets
+            .iter()
+            .filter(|bullet| bullet.shooter == shooter)
+            .count()
+    }
+
+    /// Whether another bullet fits on the field and in the shooter's own limit
+    fn has_room_for_bullet(&self, shooter: EntityId, max_bullets: usize) -> bool {
+        self.bullets.len() < MAX_BULLETS && self.bullets_of(shooter) < max_bullets
+    }
+
+    /// Advances the game by one tick
+    pub fn step(&mut self) {
+        let tick = self.tick;
+
+        for player in &mut self.players {
+            if let Some(next) = player.inputs.pop() {
+                player.input = next.input;
+                player.last_input = Some(next.sequence);
+            }
+        }
+
+        for i in 0..self.players.len() {
+            if !self.players[i].alive {
+                continue;
+            }
+            self.players[i].update();
+
+            let player = &self.players[i];
+            if player.can_fire(tick) && self.has_room_for_bullet(player.id, player.max_bullets) {
+                let id = self.next_id();
+                let bullet = self.players[i].new_bullet(id);
+                self.bullets.push(bullet);
+                self.players[i].last_shot = Some(tick);
+            }
+        }
+
+        for i in 0..self.aliens.len() {
+            if !self.aliens[i].update() {
+                continue;
+            }
+            let chance = self.aliens[i].fire_chance();
+            if !self.rng.chance(chance) {
+                continue;
+            }
+
+            for muzzle in self.aliens[i].muzzles() {
+                let alien = &self.aliens[i];
+                if !self.has_room_for_bullet(alien.id, alien.kind.max_bullets()) {
+                    break;
+                }
+                let id = self.next_id();
+                let bullet = self.aliens[i].new_bullet(id, muzzle);
+                self.bullets.push(bullet);
+            }
+        }
+
+        for bullet in &mut self.bullets {
+            bullet.update();
+        }
+
+        self.collisions();
+
+        self.bullets.retain(|bullet| bullet.active);
+        self.aliens.retain(Alien::is_alive);
+        self.tick += 1;
+    }
+
+    fn collisions(&mut self) {
+        for bullet in self.bullets.iter_mut().filter(|bullet| bullet.active) {
+            if bullet.from_player {
+                let Some(alien) = self
+                    .aliens
+                    .iter_mut()
+                    .find(|alien| alien.is_alive() && alien.is_hit_by(bullet))
+                else {
+                    continue;
+                };
+
+                bullet.active = false;
+                if alien.take_damage() {
+                    let points = alien.kind.points();
+                    if let Some(shooter) = self.players.iter_mut().find(|p| p.id == bullet.shooter)
+                    {
+                        shooter.score += points;
+                    }
+                }
+            } else if let Some(player) = self
+                .players
+                .iter_mut()
+                .find(|player| player.alive && player.is_hit_by(bullet))
+            {
+                bullet.active = false;
+                player.alive = false;
+            }
+        }
+    }
+
+    /// How the game ended, `None` while it is still going
+    pub fn outcome(&self) -> Option<Outcome> {
+        if !self.players.is_empty() && self.players.iter().all(|player| !player.alive) {
+            Some(Outcome::Defeat)
+        } else if self.aliens.is_empty() {
+            Some(Outcome::Victory)
+        } else {
+            None
+        }
+    }
+}
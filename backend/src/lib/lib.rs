@@ -1,8 +1,11 @@
 pub mod application;
+pub mod auth;
 pub mod claims;
 pub mod cli;
 pub mod configuration;
 pub mod database;
+pub mod game;
+pub mod matches;
 pub mod routes;
 pub mod types;
 pub mod websocket;
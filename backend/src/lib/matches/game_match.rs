@@ -0,0 +1,630 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use chrono::Utc;
+
+use crate::configuration::MatchSettings;
+use crate::game::boss::BossHealth;
+use crate::game::director::Director;
+use crate::game::entity::{Alien, EntityId};
+use crate::game::input::{InputRejection, SequencedInput};
+use crate::game::level::Level;
+use crate::game::snapshot::WorldDelta;
+use crate::game::timestep::FixedTimestep;
+use crate::game::world::{Outcome, World};
+use crate::game::TICK_RATE;
+use crate::types::ReplayRecord;
+use crate::websocket::protocol::{ErrorCode, ServerMessage, PROTOCOL_VERSION};
+use crate::websocket::server::{ChatServer, SendToPlayers, SendToPlayersExcept, SendToSessions};
+
+use super::manager::{MatchFinished, MatchManager, MatchStopped, StoreReplay};
+use super::replay::{Recorder, ReplayHeader};
+use super::spectator_room;
+
+/// Most simulation steps taken between two updates, a match that fell further behind than this
+/// skips ahead instead of catching up
+const MAX_STEPS_PER_UPDATE: u32 = 10;
+
+/// Snapshots kept to diff against, a client that hasn't acknowledged any of them gets a full one
+const SNAPSHOT_HISTORY: usize = 32;
+
+/// Puts a player in the match
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AddPlayer {
+    pub uuid: String,
+    pub username: String,
+}
+
+/// Takes a player out of the match
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemovePlayer {
+    pub uuid: String,
+}
+
+/// Lets a player watch the match
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AddSpectator {
+    pub uuid: String,
+}
+
+/// Stops a player from watching the match
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemoveSpectator {
+    pub uuid: String,
+}
+
+/// Input of a player, applied on the first tick that has no input of theirs yet
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueueInput {
+    pub uuid: String,
+    pub input: SequencedInput,
+}
+
+/// A session of a player received the snapshot of `tick`, later snapshots are sent to that
+/// session as deltas against it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RecordAck {
+    pub uuid: String,
+    pub session: usize,
+    pub tick: u64,
+}
+
+/// A session of a player was resumed on a new connection, which may not have the snapshot the
+/// session acknowledged, or is gone for good. A resumed session gets a full snapshot next.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ForgetAck {
+    pub uuid: String,
+    pub session: usize,
+}
+
+/// Stops the match without an outcome, sent when the last player left
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopMatch;
+
+/// A single running match, hosts the `World` of the game and plays the levels in it.
+///
+/// Every update the simulation is advanced by the time that passed since the previous one, in
+/// fixed steps of `game::TICK_RATE`, and the players get a snapshot of the world: a delta against
+/// the last snapshot they acknowledged, or the full world if there is none to diff against.
+/// Acknowledgements are kept per session, a player that is connected more than once gets every
+/// session its own snapshots.
+/// Updates run `MatchSettings::tick_rate` times per second. Inputs go straight into the world's
+/// input queues as they arrive, see `game::input::InputQueue`. Once the game has an outcome the
+/// players are told and the manager is notified.
+///
+/// Spectators get snapshots the same way, of the world as it was
+/// `MatchSettings::spectator_delay_seconds` ago, and nothing else the players are told. After the
+/// game has an outcome the match keeps running until the spectators were shown the rest of it,
+/// only then are they told how it ended and the actor stops.
+pub struct GameMatch {
+    id: String,
+    world: World,
+    director: Director,
+    timestep: FixedTimestep,
+    update_interval: Duration,
+    last_update: Instant,
+
+    /// The snapshots sent last, oldest first
+    history: VecDeque<World>,
+
+    /// Tick of the last snapshot each session acknowledged, by player
+    acks: HashMap<String, HashMap<usize, u64>>,
+
+    /// Players that were told they send inputs too fast, they are told again once they sent
+    /// one that was accepted
+    too_fast: HashSet<String>,
+
+    /// Usernames of the players, by uuid
+    players: HashMap<String, String>,
+
+    spectators: HashSet<String>,
+
+    /// The worlds spectators are shown next, oldest first. Empty without a spectator delay.
+    delayed: VecDeque<World>,
+
+    /// Ticks spectators are behind the players
+    spectator_delay: u64,
+
+    /// How the game ended, set once it has an outcome
+    ended: Option<Outcome>,
+
+    /// Ticks that passed since the game ended, the spectators catch up on the end meanwhile
+    overtime: u64,
+
+    /// Health bars of the bosses as the players last saw them, by alien
+    bosses: HashMap<EntityId, BossHealth>,
+
+    recorder: Recorder,
+
+    /// Whether the replay is listed for everyone, or only for the players
+    public: bool,
+    settings: MatchSettings,
+
+    chat_server: Addr<ChatServer>,
+    manager: Addr<MatchManager>,
+}
+
+impl GameMatch {
+    pub fn new(
+        id: String,
+        seed: u64,
+        public: bool,
+        settings: MatchSettings,
+        levels: Arc<[Level]>,
+        chat_server: Addr<ChatServer>,
+        manager: Addr<MatchManager>,
+    ) -> Self {
+        let recorder = Recorder::new(ReplayHeader {
+            match_id: id.clone(),
+            seed,
+            level: levels
+                .first()
+                .map(|level| level.id.clone())
+                .unwrap_or_default(),
+            protocol_version: PROTOCOL_VERSION,
+        });
+
+        Self {
+            id,
+            world: World::with_seed(seed),
+            director: Director::new(levels),
+            timestep: FixedTimestep::new(TICK_RATE, MAX_STEPS_PER_UPDATE),
+            update_interval: Duration::from_secs(1) / settings.tick_rate.max(1),
+            last_update: Instant::now(),
+            history: VecDeque::new(),
+            acks: HashMap::new(),
+            too_fast: HashSet::new(),
+            players: HashMap::new(),
+            spectators: HashSet::new(),
+            delayed: VecDeque::new(),
+            spectator_delay: settings.spectator_delay_seconds * u64::from(TICK_RATE),
+            ended: None,
+            overtime: 0,
+            bosses: HashMap::new(),
+            recorder,
+            public,
+            settings,
+            chat_server,
+            manager,
+        }
+    }
+
+    /// Writes the replay of the match to `MatchSettings::replays` and has the manager store its
+    /// details
+    fn save_replay(&mut self) {
+        let replay = self.recorder.finish(&self.world);
+
+        match replay.save(&self.settings.replays) {
+            Ok(path) => log::info!("Saved the replay of match {} to {:?}", self.id, path),
+            Err(e) => {
+                log::error!("Could not save the replay of match {}: {}", self.id, e);
+                return;
+            }
+        }
+
+        self.manager.do_send(StoreReplay(ReplayRecord {
+            match_id: self.id.clone(),
+            level: replay.header.level.clone(),
+            players: replay.players(),
+            public: self.public,
+            outcome: self.outcome(),
+            ticks: i64::try_from(replay.final_tick).unwrap_or(i64::MAX),
+            recorded_at: Utc::now(),
+        }));
+    }
+
+    /// Sends a message to every player of the match
+    fn broadcast(&self, message: ServerMessage) {
+        self.chat_server.do_send(SendToPlayers {
+            uuids: self.players.keys().cloned().collect(),
+            message,
+        });
+    }
+
+    /// Keeps a snapshot that was sent to diff later snapshots against
+    fn remember(&mut self, world: &World) {
+        if self.history.iter().any(|sent| sent.tick == world.tick) {
+            return;
+        }
+
+        self.history.push_back(world.clone());
+        if self.history.len() > SNAPSHOT_HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    /// Sends `world` to every session of `uuids`, as a delta against the last snapshot the
+    /// session acknowledged where possible
+    fn send_world(&self, uuids: Vec<String>, world: &World) {
+        if uuids.is_empty() {
+            return;
+        }
+
+        // Sessions that acknowledged the same snapshot get the same delta
+        let mut by_base: HashMap<u64, Vec<usize>> = HashMap::new();
+        for acks in uuids.iter().filter_map(|uuid| self.acks.get(uuid)) {
+            for (session, tick) in acks {
+                by_base.entry(*tick).or_default().push(*session);
+            }
+        }
+
+        let mut sent_delta = HashSet::new();
+        for (tick, ids) in by_base {
+            let Some(base) = self.history.iter().find(|sent| sent.tick == tick) else {
+                continue;
+            };
+
+            sent_delta.extend(ids.iter().copied());
+            self.chat_server.do_send(SendToSessions {
+                ids,
+                message: ServerMessage::SnapshotDelta {
+                    match_id: self.id.clone(),
+                    delta: WorldDelta::between(base, world),
+                },
+            });
+        }
+
+        self.chat_server.do_send(SendToPlayersExcept {
+            uuids,
+            except: sent_delta,
+            message: ServerMessage::Snapshot {
+                match_id: self.id.clone(),
+                world: world.clone(),
+            },
+        });
+    }
+
+    /// Sends every player the state of the world, and every spectator the state they are shown
+    fn send_snapshots(&mut self) {
+        let world = self.world.clone();
+        self.remember(&world);
+
+        let mut uuids: Vec<String> = self.players.keys().cloned().collect();
+        if self.spectator_delay == 0 {
+            uuids.extend(self.spectators.iter().cloned());
+        } else {
+            self.send_delayed_snapshots();
+        }
+        self.send_world(uuids, &world);
+    }
+
+    /// Sends the spectators the latest world that is at least `spectator_delay` ticks old. The
+    /// worlds are kept without spectators too, to show new ones the match right away.
+    fn send_delayed_snapshots(&mut self) {
+        if self.delayed.back().map(|world| world.tick) != Some(self.world.tick) {
+            self.delayed.push_back(self.world.clone());
+        }
+
+        // The world stands still once the game ended, the spectators' clock doesn't
+        let tick = self.world.tick + self.overtime;
+
+        let delay = self.spectator_delay;
+        while self
+            .delayed
+            .get(1)
+            .is_some_and(|next| next.tick + delay <= tick)
+        {
+            self.delayed.pop_front();
+        }
+
+        let Some(world) = self
+            .delayed
+            .front()
+            .filter(|world| world.tick + delay <= tick)
+            .cloned()
+        else {
+            return;
+        };
+        if self.spectators.is_empty() {
+            return;
+        }
+
+        self.remember(&world);
+        self.send_world(self.spectators.iter().cloned().collect(), &world);
+    }
+
+    /// Tells the players about bosses that appeared, took a hit or were destroyed
+    fn send_boss_health(&mut self) {
+        let bosses: HashMap<EntityId, BossHealth> = self
+            .world
+            .aliens
+            .iter()
+            .filter_map(Alien::boss_health)
+            .map(|health| (health.id, health))
+            .collect();
+
+        let destroyed = self
+            .bosses
+            .values()
+            .filter(|last| !bosses.contains_key(&last.id))
+            .map(|last| BossHealth {
+                health: 0,
+                phase: last.phases,
+                ..last.clone()
+            });
+        let changed = bosses
+            .values()
+            .filter(|health| self.bosses.get(&health.id) != Some(health))
+            .cloned();
+
+        for health in changed.chain(destroyed).collect::<Vec<_>>() {
+            self.broadcast(ServerMessage::BossHealth {
+                match_id: self.id.clone(),
+                health,
+            });
+        }
+        self.bosses = bosses;
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        self.director.outcome(&self.world)
+    }
+
+    fn update(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let steps = self.timestep.advance(now - self.last_update);
+        self.last_update = now;
+
+        if self.ended.is_some() {
+            self.overtime += u64::from(steps);
+            self.send_delayed_snapshots();
+            self.play_out(ctx);
+            return;
+        }
+
+        for _ in 0..steps {
+            if let Some(stage) = self.director.step(&mut self.world) {
+                self.broadcast(ServerMessage::WaveStarted {
+                    match_id: self.id.clone(),
+                    stage,
+                });
+            }
+
+            self.recorder.inputs(&self.world);
+            self.world.step();
+            if self.outcome().is_some() {
+                break;
+            }
+        }
+
+        self.send_snapshots();
+        self.send_boss_health();
+
+        if let Some(outcome) = self.outcome() {
+            log::info!("Match {} ended in {:?}", self.id, outcome);
+            self.broadcast(ServerMessage::MatchEnded {
+                match_id: self.id.clone(),
+                outcome,
+            });
+            self.manager.do_send(MatchFinished {
+                match_id: self.id.clone(),
+                outcome,
+                scores: self
+                    .world
+                    .players
+                    .iter()
+                    .map(|player| (player.uuid.clone(), player.score))
+                    .collect(),
+            });
+            self.ended = Some(outcome);
+            self.play_out(ctx);
+        }
+    }
+
+    /// Shows the spectators what is left of an ended match, once they saw the end they are told
+    /// how it ended and the actor stops
+    fn play_out(&mut self, ctx: &mut Context<Self>) {
+        let Some(outcome) = self.ended else {
+            return;
+        };
+
+        if !self.spectators.is_empty() && self.overtime < self.spectator_delay {
+            return;
+        }
+
+        self.chat_server.do_send(SendToPlayers {
+            uuids: self.spectators.iter().cloned().collect(),
+            message: ServerMessage::MatchEnded {
+                match_id: self.id.clone(),
+                outcome,
+            },
+        });
+        ctx.stop();
+    }
+}
+
+impl Actor for GameMatch {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("Match {} started", self.id);
+
+        self.last_update = Instant::now();
+        ctx.run_interval(self.update_interval, |act, ctx| act.update(ctx));
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.save_replay();
+        self.manager.do_send(MatchStopped {
+            match_id: self.id.clone(),
+        });
+
+        self.chat_server.do_send(SendToPlayers {
+            uuids: self.spectators.drain().collect(),
+            message: ServerMessage::StoppedSpectating {
+                match_id: self.id.clone(),
+            },
+        });
+    }
+}
+
+impl Handler<AddPlayer> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddPlayer, _: &mut Context<Self>) {
+        if self.players.contains_key(&msg.uuid) {
+            return;
+        }
+
+        self.world.add_player(&msg.uuid);
+        self.recorder.join(&self.world, &msg.uuid);
+        self.players.insert(msg.uuid.clone(), msg.username.clone());
+
+        self.broadcast(ServerMessage::MatchJoined {
+            match_id: self.id.clone(),
+            uuid: msg.uuid.clone(),
+            username: msg.username,
+        });
+
+        // A boss fight that is already going on
+        for health in self.bosses.values() {
+            self.chat_server.do_send(SendToPlayers {
+                uuids: vec![msg.uuid.clone()],
+                message: ServerMessage::BossHealth {
+                    match_id: self.id.clone(),
+                    health: health.clone(),
+                },
+            });
+        }
+    }
+}
+
+impl Handler<RemovePlayer> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemovePlayer, _: &mut Context<Self>) {
+        let Some(username) = self.players.get(&msg.uuid).cloned() else {
+            return;
+        };
+
+        // The leaving player is told as well
+        self.broadcast(ServerMessage::MatchLeft {
+            match_id: self.id.clone(),
+            uuid: msg.uuid.clone(),
+            username,
+        });
+
+        self.players.remove(&msg.uuid);
+        self.acks.remove(&msg.uuid);
+        self.too_fast.remove(&msg.uuid);
+        self.world.remove_player(&msg.uuid);
+        self.recorder.leave(&self.world, &msg.uuid);
+    }
+}
+
+impl Handler<AddSpectator> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddSpectator, _: &mut Context<Self>) {
+        if !self.spectators.insert(msg.uuid.clone()) {
+            return;
+        }
+
+        self.chat_server.do_send(SendToPlayers {
+            uuids: vec![msg.uuid],
+            message: ServerMessage::Spectating {
+                match_id: self.id.clone(),
+                room: spectator_room(&self.id),
+                delay_seconds: self.settings.spectator_delay_seconds,
+            },
+        });
+    }
+}
+
+impl Handler<RemoveSpectator> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveSpectator, _: &mut Context<Self>) {
+        if !self.spectators.remove(&msg.uuid) {
+            return;
+        }
+
+        self.acks.remove(&msg.uuid);
+        self.chat_server.do_send(SendToPlayers {
+            uuids: vec![msg.uuid],
+            message: ServerMessage::StoppedSpectating {
+                match_id: self.id.clone(),
+            },
+        });
+    }
+}
+
+impl Handler<QueueInput> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueInput, _: &mut Context<Self>) {
+        match self.world.queue_input(&msg.uuid, msg.input) {
+            Some(Ok(())) => {
+                self.too_fast.remove(&msg.uuid);
+            }
+            Some(Err(InputRejection::TooFast)) if !self.too_fast.contains(&msg.uuid) => {
+                log::info!(
+                    "{} sends inputs faster than match {} ticks",
+                    msg.uuid,
+                    self.id
+                );
+                self.too_fast.insert(msg.uuid.clone());
+                self.chat_server.do_send(SendToPlayers {
+                    uuids: vec![msg.uuid],
+                    message: ServerMessage::error(
+                        ErrorCode::RateLimited,
+                        "Inputs are sent faster than the match ticks, they are dropped",
+                    ),
+                });
+            }
+            // Stale inputs are duplicates or were overtaken, a newer input is already queued
+            Some(Err(_)) | None => {}
+        }
+    }
+}
+
+impl Handler<RecordAck> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordAck, _: &mut Context<Self>) {
+        let watching = self.players.contains_key(&msg.uuid) || self.spectators.contains(&msg.uuid);
+        if !watching || msg.tick > self.world.tick {
+            return;
+        }
+
+        let ack = self
+            .acks
+            .entry(msg.uuid)
+            .or_default()
+            .entry(msg.session)
+            .or_insert(msg.tick);
+        *ack = (*ack).max(msg.tick);
+    }
+}
+
+impl Handler<ForgetAck> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, msg: ForgetAck, _: &mut Context<Self>) {
+        if let Some(acks) = self.acks.get_mut(&msg.uuid) {
+            acks.remove(&msg.session);
+            if acks.is_empty() {
+                self.acks.remove(&msg.uuid);
+            }
+        }
+    }
+}
+
+impl Handler<StopMatch> for GameMatch {
+    type Result = ();
+
+    fn handle(&mut self, _: StopMatch, ctx: &mut Context<Self>) {
+        log::info!("Match {} stopped", self.id);
+        ctx.stop();
+    }
+}
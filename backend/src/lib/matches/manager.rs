@@ -0,0 +1,1005 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::configuration::{MatchSettings, MatchmakingSettings};
+use crate::database::db::ArcDb;
+use crate::game::input::SequencedInput;
+use crate::game::level::Level;
+use crate::game::world::Outcome;
+use crate::types::{Authority, ReplayRecord};
+use crate::websocket::presence::Activity;
+use crate::websocket::protocol::{Member, ServerMessage};
+use crate::websocket::server::{
+    AddToRoom, ChatServer, CloseRoom, Deliver, OpenRoom, PresenceUpdate, RemoveFromRoom,
+    SendToPlayers, SessionRemoved, SetPlayerActivity, WatchPresence, WatchSessions,
+};
+
+use super::game_match::{
+    AddPlayer, AddSpectator, ForgetAck, GameMatch, QueueInput, RecordAck, RemovePlayer,
+    RemoveSpectator, StopMatch,
+};
+use super::matchmaking::{Cancelled, GameMode, Matchmaker, Proposal, Ticket};
+use super::playback::{ReplayPlayback, Timeline};
+use super::rating::{Rating, Standing};
+use super::replay::Replay;
+use super::{lobby_room, match_room, spectator_room, MatchError};
+
+/// A player creates a new match and joins it, returns the id of the match
+#[derive(Message)]
+#[rtype(result = "Result<String, MatchError>")]
+pub struct CreateMatch {
+    pub uuid: String,
+    pub username: String,
+
+    /// Whether the replay of the match is listed for everyone
+    pub public: bool,
+}
+
+/// A player joins a running match
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct JoinMatch {
+    pub uuid: String,
+    pub username: String,
+    pub match_id: String,
+}
+
+/// A player starts spectating a running match. Private matches can only be spectated by
+/// moderators.
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct SpectateMatch {
+    pub uuid: String,
+    pub authority: Authority,
+    pub match_id: String,
+}
+
+/// A player leaves the match they are in, or stops spectating
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct LeaveMatch {
+    pub uuid: String,
+}
+
+/// A player starts waiting for a match, see `matchmaking::Matchmaker`. A player that waits
+/// already is told again where they stand, which is how a client that reconnected finds out.
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct JoinQueue {
+    pub uuid: String,
+    pub username: String,
+    pub mode: GameMode,
+    pub region: String,
+}
+
+/// A player stops waiting for a match, declining the one that was found
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct LeaveQueue {
+    pub uuid: String,
+}
+
+/// A player accepts or declines the match that was found for them
+#[derive(Message)]
+#[rtype(result = "Result<(), MatchError>")]
+pub struct AnswerMatch {
+    pub uuid: String,
+    pub match_id: String,
+    pub accept: bool,
+}
+
+/// Input of a player for the match they are in, ignored if they are not in one
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubmitInput {
+    pub uuid: String,
+    pub input: SequencedInput,
+}
+
+/// A session of a player received the snapshot of `tick` of the match they are in or watch
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AcknowledgeSnapshot {
+    pub uuid: String,
+    pub session: usize,
+    pub tick: u64,
+}
+
+/// A session of a player was resumed on a new connection, the snapshot it acknowledged on the
+/// old one is forgotten
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionResumed {
+    pub uuid: String,
+    pub session: usize,
+}
+
+/// A match has an outcome, it only runs on until its spectators saw the end
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MatchFinished {
+    pub match_id: String,
+    pub outcome: Outcome,
+
+    /// Final score of every player that was still in the match, by uuid
+    pub scores: HashMap<String, u64>,
+}
+
+/// A match stopped, on its own or because it was told to
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MatchStopped {
+    pub match_id: String,
+}
+
+/// A match stopped and its replay was written to `MatchSettings::replays`, the details go to
+/// the database
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StoreReplay(pub ReplayRecord);
+
+/// A player wants to watch the replay of a match. Returns the playback, which sends the replay
+/// to `session`.
+#[derive(Message)]
+#[rtype(result = "Result<Addr<ReplayPlayback>, MatchError>")]
+pub struct WatchReplay {
+    pub uuid: String,
+    pub authority: Authority,
+    pub match_id: String,
+    pub session: Recipient<Deliver>,
+}
+
+/// Every running match with its players
+#[derive(Message)]
+#[rtype(result = "Vec<MatchSummary>")]
+pub struct ListMatches;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchSummary {
+    pub match_id: String,
+
+    /// Uuids of the players, sorted
+    pub players: Vec<String>,
+
+    /// Uuids of the spectators, sorted
+    pub spectators: Vec<String>,
+}
+
+struct RunningMatch {
+    addr: Addr<GameMatch>,
+    players: HashSet<String>,
+    spectators: HashSet<String>,
+
+    /// Whether everyone may spectate the match and watch its replay
+    public: bool,
+
+    /// How the match is rated, only matches found by matchmaking are
+    rated: Option<RatedMatch>,
+
+    /// Whether the match has an outcome, nobody can join or spectate it anymore
+    ended: bool,
+}
+
+struct RatedMatch {
+    mode: GameMode,
+
+    /// Uuids of the players the match was found for, they are rated even if they left
+    players: Vec<String>,
+
+    /// Uuids of the players the match was found for that left it, in the order they left
+    left: Vec<String>,
+}
+
+/// Creates matches and keeps track of who plays in which.
+///
+/// Every match is its own `GameMatch` actor, started on one of a few dedicated arbiters so
+/// ticking matches never compete with the HTTP workers or the chat server. The manager only
+/// routes players and their inputs to the right match. A player is in at most one match, a match
+/// is torn down when its last player leaves or goes offline. Spectators don't count as players,
+/// they watch at most one match and never play in it. Replays are played back on the same
+/// arbiters.
+///
+/// Players that want to be paired with others wait in the matchmaking queue. It is searched
+/// every `search_interval_ms`, the players of a match that was found share a lobby room until
+/// they all accepted and the match starts. Like matches, the queue is left when a player goes
+/// offline, not when their connection drops.
+pub struct MatchManager {
+    settings: MatchSettings,
+    db: ArcDb,
+
+    /// The levels every match plays, in order
+    levels: Arc<[Level]>,
+    chat_server: Addr<ChatServer>,
+    arbiters: Vec<Arbiter>,
+    next_arbiter: usize,
+    matches: HashMap<String, RunningMatch>,
+
+    /// The match every player is in, by uuid
+    players: HashMap<String, String>,
+
+    /// The match every spectator watches, by uuid
+    spectators: HashMap<String, String>,
+
+    /// The players waiting for a match and the matches found for them
+    matchmaker: Matchmaker,
+}
+
+impl MatchManager {
+    pub fn new(
+        db: ArcDb,
+        chat_server: Addr<ChatServer>,
+        settings: MatchSettings,
+        matchmaking: MatchmakingSettings,
+        levels: Arc<[Level]>,
+    ) -> Self {
+        let arbiters = (0..settings.threads.max(1))
+            .map(|_| Arbiter::new())
+            .collect();
+
+        Self {
+            settings,
+            db,
+            levels,
+            chat_server,
+            arbiters,
+            next_arbiter: 0,
+            matches: HashMap::new(),
+            players: HashMap::new(),
+            spectators: HashMap::new(),
+            matchmaker: Matchmaker::new(matchmaking),
+        }
+    }
+
+    /// Picks the arbiter of the next match, round robin
+    fn arbiter(&mut self) -> ArbiterHandle {
+        let arbiter = self.arbiters[self.next_arbiter].handle();
+        self.next_arbiter = (self.next_arbiter + 1) % self.arbiters.len();
+        arbiter
+    }
+
+    /// Starts a match with nobody in it yet
+    fn start_match(&mut self, match_id: &str, public: bool, ctx: &mut Context<Self>) {
+        let settings = self.settings.clone();
+        let levels = self.levels.clone();
+        let chat_server = self.chat_server.clone();
+        let manager = ctx.address();
+        let id = match_id.to_string();
+        let addr = GameMatch::start_in_arbiter(&self.arbiter(), move |_| {
+            GameMatch::new(
+                id,
+                rand::random(),
+                public,
+                settings,
+                levels,
+                chat_server,
+                manager,
+            )
+        });
+
+        self.matches.insert(
+            match_id.to_string(),
+            RunningMatch {
+                addr,
+                players: HashSet::new(),
+                spectators: HashSet::new(),
+                public,
+                rated: None,
+                ended: false,
+            },
+        );
+        self.chat_server.do_send(OpenRoom {
+            room: match_room(match_id),
+            uuids: Vec::new(),
+        });
+        self.chat_server.do_send(OpenRoom {
+            room: spectator_room(match_id),
+            uuids: Vec::new(),
+        });
+    }
+
+    fn match_of(&self, uuid: &str) -> Option<&RunningMatch> {
+        self.players
+            .get(uuid)
+            .and_then(|match_id| self.matches.get(match_id))
+    }
+
+    fn spectated_by(&self, uuid: &str) -> Option<&RunningMatch> {
+        self.spectators
+            .get(uuid)
+            .and_then(|match_id| self.matches.get(match_id))
+    }
+
+    fn add_player(&mut self, match_id: &str, uuid: String, username: String) {
+        let Some(running) = self.matches.get_mut(match_id) else {
+            return;
+        };
+
+        running.players.insert(uuid.clone());
+        if let Some(rated) = &mut running.rated {
+            rated.left.retain(|left| *left != uuid);
+        }
+        running.addr.do_send(AddPlayer {
+            uuid: uuid.clone(),
+            username,
+        });
+        self.players.insert(uuid.clone(), match_id.to_string());
+
+        self.chat_server.do_send(AddToRoom {
+            room: match_room(match_id),
+            uuid: uuid.clone(),
+        });
+        self.chat_server.do_send(SetPlayerActivity {
+            uuid,
+            activity: Some(Activity::InMatch),
+        });
+    }
+
+    fn remove_player(&mut self, uuid: &str) -> Result<(), MatchError> {
+        let match_id = self.players.remove(uuid).ok_or(MatchError::NotInMatch)?;
+
+        self.chat_server.do_send(RemoveFromRoom {
+            room: match_room(&match_id),
+            uuid: uuid.to_string(),
+        });
+        self.chat_server.do_send(SetPlayerActivity {
+            uuid: uuid.to_string(),
+            activity: None,
+        });
+
+        let Some(running) = self.matches.get_mut(&match_id) else {
+            return Ok(());
+        };
+
+        running.players.remove(uuid);
+        if let Some(rated) = &mut running.rated {
+            if rated.players.iter().any(|player| player == uuid) {
+                rated.left.push(uuid.to_string());
+            }
+        }
+        running.addr.do_send(RemovePlayer {
+            uuid: uuid.to_string(),
+        });
+
+        if running.players.is_empty() {
+            self.tear_down(&match_id);
+        }
+
+        Ok(())
+    }
+
+    fn add_spectator(&mut self, match_id: &str, uuid: String) {
+        let Some(running) = self.matches.get_mut(match_id) else {
+            return;
+        };
+
+        running.spectators.insert(uuid.clone());
+        running.addr.do_send(AddSpectator { uuid: uuid.clone() });
+        self.spectators.insert(uuid.clone(), match_id.to_string());
+
+        self.chat_server.do_send(AddToRoom {
+            room: spectator_room(match_id),
+            uuid: uuid.clone(),
+        });
+        self.chat_server.do_send(SetPlayerActivity {
+            uuid,
+            activity: Some(Activity::Spectating),
+        });
+    }
+
+    /// Stops a spectator from watching their match, returns whether they watched one
+    fn remove_spectator(&mut self, uuid: &str) -> bool {
+        let Some(match_id) = self.spectators.remove(uuid) else {
+            return false;
+        };
+
+        self.chat_server.do_send(RemoveFromRoom {
+            room: spectator_room(&match_id),
+            uuid: uuid.to_string(),
+        });
+        self.chat_server.do_send(SetPlayerActivity {
+            uuid: uuid.to_string(),
+            activity: None,
+        });
+
+        if let Some(running) = self.matches.get_mut(&match_id) {
+            running.spectators.remove(uuid);
+            running.addr.do_send(RemoveSpectator {
+                uuid: uuid.to_string(),
+            });
+        }
+
+        true
+    }
+
+    /// Offers the match that was found to some of its players
+    fn offer(&self, proposal: &Proposal, uuids: Vec<String>) {
+        let timeout = proposal
+            .expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+            .ceil() as u64;
+        let players = proposal
+            .tickets
+            .iter()
+            .map(|ticket| Member {
+                username: ticket.username.clone(),
+                uuid: ticket.uuid.clone(),
+            })
+            .collect();
+
+        self.chat_server.do_send(SendToPlayers {
+            uuids,
+            message: ServerMessage::MatchFound {
+                match_id: proposal.match_id.clone(),
+                mode: proposal.mode,
+                region: proposal.region.clone(),
+                lobby: lobby_room(&proposal.match_id),
+                players,
+                timeout_seconds: timeout,
+            },
+        });
+    }
+
+    /// Cancels the proposals that timed out and offers the matches found in the queue
+    fn search(&mut self) {
+        let now = Instant::now();
+        for cancelled in self.matchmaker.expire(now) {
+            self.fall_through(cancelled);
+        }
+
+        for proposal in self.matchmaker.find_matches(now) {
+            log::info!(
+                "Found {:?} match {} in {}",
+                proposal.mode,
+                proposal.match_id,
+                proposal.region
+            );
+            self.offer(&proposal, proposal.uuids());
+            self.chat_server.do_send(OpenRoom {
+                room: lobby_room(&proposal.match_id),
+                uuids: proposal.uuids(),
+            });
+        }
+    }
+
+    /// Tells the players of a proposal that fell through whether they are back in the queue
+    fn fall_through(&self, cancelled: Cancelled) {
+        let match_id = cancelled.proposal.match_id;
+        self.chat_server.do_send(CloseRoom {
+            room: lobby_room(&match_id),
+        });
+
+        for (uuids, requeued) in [(cancelled.requeued, true), (cancelled.dropped, false)] {
+            self.chat_server.do_send(SendToPlayers {
+                uuids,
+                message: ServerMessage::MatchCancelled {
+                    match_id: match_id.clone(),
+                    requeued,
+                },
+            });
+        }
+    }
+
+    /// Starts a match every player accepted and puts them in it
+    fn start_found_match(&mut self, proposal: Proposal, ctx: &mut Context<Self>) {
+        self.chat_server.do_send(CloseRoom {
+            room: lobby_room(&proposal.match_id),
+        });
+        self.start_match(&proposal.match_id, true, ctx);
+        if let Some(running) = self.matches.get_mut(&proposal.match_id) {
+            running.rated = Some(RatedMatch {
+                mode: proposal.mode,
+                players: proposal.uuids(),
+                left: Vec::new(),
+            });
+        }
+
+        for ticket in proposal.tickets {
+            self.remove_spectator(&ticket.uuid);
+            self.add_player(&proposal.match_id, ticket.uuid, ticket.username);
+        }
+    }
+
+    /// Puts a player in the queue with their rating in the mode they queue for
+    fn enqueue(&mut self, msg: JoinQueue, rating: Rating) -> Result<(), MatchError> {
+        // The player may have started playing while the rating was looked up
+        if self.players.contains_key(&msg.uuid) {
+            return Err(MatchError::AlreadyInMatch);
+        }
+        if let Some(proposal) = self.matchmaker.proposal_of(&msg.uuid) {
+            self.offer(proposal, vec![msg.uuid]);
+            return Ok(());
+        }
+
+        self.matchmaker.enqueue(Ticket {
+            uuid: msg.uuid.clone(),
+            username: msg.username,
+            mode: msg.mode,
+            region: msg.region,
+            rating: rating.rating,
+            queued_at: Instant::now(),
+        })?;
+
+        if let Some(ticket) = self.matchmaker.ticket(&msg.uuid) {
+            self.chat_server.do_send(SendToPlayers {
+                uuids: vec![msg.uuid.clone()],
+                message: ServerMessage::Queued {
+                    mode: ticket.mode,
+                    region: ticket.region.clone(),
+                    rating: ticket.rating,
+                },
+            });
+        }
+        Ok(())
+    }
+
+    /// Rates the players of a match found by matchmaking, `scores` has the final score of
+    /// every player that was still in the match
+    fn rate(
+        &self,
+        match_id: &str,
+        rated: RatedMatch,
+        outcome: Outcome,
+        scores: &HashMap<String, u64>,
+    ) {
+        let standings: Vec<Standing> = rated
+            .players
+            .into_iter()
+            .map(|uuid| Standing {
+                score: scores.get(&uuid).copied(),
+                left: rated.left.iter().position(|left| *left == uuid),
+                uuid,
+            })
+            .collect();
+
+        let db = self.db.clone();
+        let match_id = match_id.to_string();
+        actix::spawn(async move {
+            match db
+                .rate_match(&match_id, rated.mode, outcome, &standings)
+                .await
+            {
+                Ok(ratings) => log::info!("Rated match {}: {:?}", match_id, ratings),
+                Err(e) => log::error!("Failed to rate match {}: {}", match_id, e),
+            }
+        });
+    }
+
+    /// Releases the players of a match that has an outcome, the spectators stay until it stops
+    fn finish(&mut self, match_id: &str) {
+        let Some(running) = self.matches.get_mut(match_id) else {
+            return;
+        };
+
+        running.ended = true;
+        self.chat_server.do_send(CloseRoom {
+            room: match_room(match_id),
+        });
+
+        for uuid in running.players.drain() {
+            self.players.remove(&uuid);
+            self.chat_server.do_send(SetPlayerActivity {
+                uuid,
+                activity: None,
+            });
+        }
+    }
+
+    /// Stops a match and releases whoever is still in it.
+    ///
+    /// A rated match that is torn down before it ended was abandoned by all of its players. They
+    /// are rated as if they lost it, in versus the players that left first lose to the others.
+    fn tear_down(&mut self, match_id: &str) {
+        let Some(mut running) = self.matches.remove(match_id) else {
+            return;
+        };
+
+        if let Some(rated) = running.rated.take() {
+            log::info!("Match {} was abandoned, rating it as a defeat", match_id);
+            self.rate(match_id, rated, Outcome::Defeat, &HashMap::new());
+        }
+
+        running.addr.do_send(StopMatch);
+        self.chat_server.do_send(CloseRoom {
+            room: match_room(match_id),
+        });
+        self.chat_server.do_send(CloseRoom {
+            room: spectator_room(match_id),
+        });
+
+        for uuid in running.spectators {
+            self.spectators.remove(&uuid);
+            self.chat_server.do_send(SetPlayerActivity {
+                uuid,
+                activity: None,
+            });
+        }
+
+        for uuid in running.players {
+            self.players.remove(&uuid);
+            self.chat_server.do_send(SetPlayerActivity {
+                uuid,
+                activity: None,
+            });
+        }
+
+        log::info!(
+            "Match {} torn down, {} running",
+            match_id,
+            self.matches.len()
+        );
+    }
+}
+
+impl Actor for MatchManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.chat_server
+            .do_send(WatchPresence(ctx.address().recipient()));
+        self.chat_server
+            .do_send(WatchSessions(ctx.address().recipient()));
+
+        let interval = Duration::from_millis(self.matchmaker.settings().search_interval_ms.max(1));
+        ctx.run_interval(interval, |act, _| act.search());
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        for arbiter in &self.arbiters {
+            arbiter.stop();
+        }
+    }
+}
+
+impl Handler<CreateMatch> for MatchManager {
+    type Result = Result<String, MatchError>;
+
+    fn handle(&mut self, msg: CreateMatch, ctx: &mut Context<Self>) -> Self::Result {
+        if self.players.contains_key(&msg.uuid) {
+            return Err(MatchError::AlreadyInMatch);
+        }
+        if self.matchmaker.is_waiting(&msg.uuid) {
+            return Err(MatchError::Queued);
+        }
+
+        let match_id = Uuid::new_v4().to_string();
+        self.start_match(&match_id, msg.public, ctx);
+
+        // Spectators that start playing stop watching
+        self.remove_spectator(&msg.uuid);
+        self.add_player(&match_id, msg.uuid, msg.username);
+
+        Ok(match_id)
+    }
+}
+
+impl Handler<JoinMatch> for MatchManager {
+    type Result = Result<(), MatchError>;
+
+    fn handle(&mut self, msg: JoinMatch, _: &mut Context<Self>) -> Self::Result {
+        if self.players.contains_key(&msg.uuid) {
+            return Err(MatchError::AlreadyInMatch);
+        }
+        if self.matchmaker.is_waiting(&msg.uuid) {
+            return Err(MatchError::Queued);
+        }
+
+        let running = self
+            .matches
+            .get(&msg.match_id)
+            .filter(|running| !running.ended)
+            .ok_or_else(|| MatchError::NotFound(msg.match_id.clone()))?;
+        // Matches found by matchmaking are for the players they were found for
+        if running.players.len() >= self.settings.max_players || running.rated.is_some() {
+            return Err(MatchError::Full(msg.match_id));
+        }
+
+        self.remove_spectator(&msg.uuid);
+        self.add_player(&msg.match_id, msg.uuid, msg.username);
+        Ok(())
+    }
+}
+
+impl Handler<SpectateMatch> for MatchManager {
+    type Result = Result<(), MatchError>;
+
+    fn handle(&mut self, msg: SpectateMatch, _: &mut Context<Self>) -> Self::Result {
+        if self.players.contains_key(&msg.uuid) {
+            return Err(MatchError::AlreadyInMatch);
+        }
+        if self.spectators.get(&msg.uuid) == Some(&msg.match_id) {
+            return Ok(());
+        }
+
+        // Private matches are as good as missing for everyone else
+        let running = self
+            .matches
+            .get(&msg.match_id)
+            .filter(|running| !running.ended)
+            .filter(|running| running.public || msg.authority >= Authority::Moderator)
+            .ok_or_else(|| MatchError::NotFound(msg.match_id.clone()))?;
+        if running.spectators.len() >= self.settings.max_spectators {
+            return Err(MatchError::NoSpectatorSeats(msg.match_id));
+        }
+
+        self.remove_spectator(&msg.uuid);
+        self.add_spectator(&msg.match_id, msg.uuid);
+        Ok(())
+    }
+}
+
+impl Handler<LeaveMatch> for MatchManager {
+    type Result = Result<(), MatchError>;
+
+    fn handle(&mut self, msg: LeaveMatch, _: &mut Context<Self>) -> Self::Result {
+        if self.remove_spectator(&msg.uuid) {
+            return Ok(());
+        }
+
+        self.remove_player(&msg.uuid)
+    }
+}
+
+impl Handler<JoinQueue> for MatchManager {
+    type Result = ResponseActFuture<Self, Result<(), MatchError>>;
+
+    fn handle(&mut self, msg: JoinQueue, _: &mut Context<Self>) -> Self::Result {
+        if self.players.contains_key(&msg.uuid) {
+            return Box::pin(fut::ready(Err(MatchError::AlreadyInMatch)));
+        }
+
+        let db = self.db.clone();
+        let (uuid, mode) = (msg.uuid.clone(), msg.mode);
+        Box::pin(
+            async move { db.rating(&uuid, mode).await }
+                .into_actor(self)
+                .map(|rating, act, _| {
+                    let rating = rating.unwrap_or_else(|e| {
+                        log::error!("Failed to load the rating of {}: {}", msg.uuid, e);
+                        Rating::default()
+                    });
+                    act.enqueue(msg, rating)
+                }),
+        )
+    }
+}
+
+impl Handler<LeaveQueue> for MatchManager {
+    type Result = Result<(), MatchError>;
+
+    fn handle(&mut self, msg: LeaveQueue, _: &mut Context<Self>) -> Self::Result {
+        if let Some(cancelled) = self.matchmaker.leave(&msg.uuid)? {
+            self.fall_through(cancelled);
+        }
+
+        self.chat_server.do_send(SendToPlayers {
+            uuids: vec![msg.uuid],
+            message: ServerMessage::LeftQueue,
+        });
+        Ok(())
+    }
+}
+
+impl Handler<AnswerMatch> for MatchManager {
+    type Result = Result<(), MatchError>;
+
+    fn handle(&mut self, msg: AnswerMatch, ctx: &mut Context<Self>) -> Self::Result {
+        if !msg.accept {
+            let cancelled = self.matchmaker.decline(&msg.uuid, &msg.match_id)?;
+            self.fall_through(cancelled);
+            return Ok(());
+        }
+
+        if let Some(proposal) = self.matchmaker.proposal_of(&msg.uuid) {
+            self.chat_server.do_send(SendToPlayers {
+                uuids: proposal.uuids(),
+                message: ServerMessage::MatchAccepted {
+                    match_id: msg.match_id.clone(),
+                    uuid: msg.uuid.clone(),
+                },
+            });
+        }
+
+        if let Some(proposal) = self.matchmaker.accept(&msg.uuid, &msg.match_id)? {
+            self.start_found_match(proposal, ctx);
+        }
+        Ok(())
+    }
+}
+
+impl Handler<SubmitInput> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubmitInput, _: &mut Context<Self>) {
+        if self.spectators.contains_key(&msg.uuid) {
+            self.chat_server.do_send(SendToPlayers {
+                uuids: vec![msg.uuid],
+                message: MatchError::Spectating.to_server_message(),
+            });
+            return;
+        }
+
+        let Some(running) = self.match_of(&msg.uuid) else {
+            return;
+        };
+
+        running.addr.do_send(QueueInput {
+            uuid: msg.uuid,
+            input: msg.input,
+        });
+    }
+}
+
+impl Handler<AcknowledgeSnapshot> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcknowledgeSnapshot, _: &mut Context<Self>) {
+        let running = self
+            .match_of(&msg.uuid)
+            .or_else(|| self.spectated_by(&msg.uuid));
+        if let Some(running) = running {
+            running.addr.do_send(RecordAck {
+                uuid: msg.uuid,
+                session: msg.session,
+                tick: msg.tick,
+            });
+        }
+    }
+}
+
+impl Handler<SessionResumed> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SessionResumed, _: &mut Context<Self>) {
+        let running = self
+            .match_of(&msg.uuid)
+            .or_else(|| self.spectated_by(&msg.uuid));
+        if let Some(running) = running {
+            running.addr.do_send(ForgetAck {
+                uuid: msg.uuid,
+                session: msg.session,
+            });
+        }
+    }
+}
+
+impl Handler<MatchFinished> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: MatchFinished, _: &mut Context<Self>) {
+        if let Some(rated) = self
+            .matches
+            .get_mut(&msg.match_id)
+            .and_then(|running| running.rated.take())
+        {
+            self.rate(&msg.match_id, rated, msg.outcome, &msg.scores);
+        }
+
+        self.finish(&msg.match_id);
+    }
+}
+
+impl Handler<MatchStopped> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: MatchStopped, _: &mut Context<Self>) {
+        self.tear_down(&msg.match_id);
+    }
+}
+
+impl Handler<StoreReplay> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: StoreReplay, _: &mut Context<Self>) {
+        let db = self.db.clone();
+        actix::spawn(async move {
+            if let Err(e) = db.insert_replay(&msg.0).await {
+                log::error!(
+                    "Failed to store the replay of match {}: {}",
+                    msg.0.match_id,
+                    e
+                );
+            }
+        });
+    }
+}
+
+impl Handler<WatchReplay> for MatchManager {
+    type Result = ResponseFuture<Result<Addr<ReplayPlayback>, MatchError>>;
+
+    fn handle(&mut self, msg: WatchReplay, _: &mut Context<Self>) -> Self::Result {
+        let db = self.db.clone();
+        let settings = self.settings.clone();
+        let levels = self.levels.clone();
+        let arbiter = self.arbiter();
+
+        Box::pin(async move {
+            let unavailable = |e: &dyn std::fmt::Display| {
+                log::error!("Failed to load the replay of match {}: {}", msg.match_id, e);
+                MatchError::ReplayUnavailable(msg.match_id.clone())
+            };
+
+            // Private replays are as good as missing for everyone else
+            let record = db
+                .replay(&msg.match_id)
+                .await
+                .map_err(|e| unavailable(&e))?
+                .filter(|record| record.visible_to(&msg.uuid, msg.authority))
+                .ok_or_else(|| MatchError::ReplayNotFound(msg.match_id.clone()))?;
+
+            let timeline = Replay::load(&settings.replays, &record.match_id)
+                .and_then(|replay| Timeline::new(replay, &levels))
+                .map_err(|e| unavailable(&e))?;
+
+            let session = msg.session;
+            Ok(ReplayPlayback::start_in_arbiter(&arbiter, move |_| {
+                ReplayPlayback::new(timeline, &settings, session)
+            }))
+        })
+    }
+}
+
+impl Handler<ListMatches> for MatchManager {
+    type Result = MessageResult<ListMatches>;
+
+    fn handle(&mut self, _: ListMatches, _: &mut Context<Self>) -> Self::Result {
+        let mut matches: Vec<MatchSummary> = self
+            .matches
+            .iter()
+            .filter(|(_, running)| !running.ended)
+            .map(|(match_id, running)| {
+                let mut players: Vec<String> = running.players.iter().cloned().collect();
+                players.sort();
+                let mut spectators: Vec<String> = running.spectators.iter().cloned().collect();
+                spectators.sort();
+                MatchSummary {
+                    match_id: match_id.clone(),
+                    players,
+                    spectators,
+                }
+            })
+            .collect();
+        matches.sort_by(|a, b| a.match_id.cmp(&b.match_id));
+
+        MessageResult(matches)
+    }
+}
+
+impl Handler<SessionRemoved> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: SessionRemoved, _: &mut Context<Self>) {
+        // The player may still be in their match on another session
+        let running = self
+            .match_of(&msg.uuid)
+            .or_else(|| self.spectated_by(&msg.uuid));
+        if let Some(running) = running {
+            running.addr.do_send(ForgetAck {
+                uuid: msg.uuid,
+                session: msg.session,
+            });
+        }
+    }
+}
+
+impl Handler<PresenceUpdate> for MatchManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: PresenceUpdate, _: &mut Context<Self>) {
+        // A dropped player stays in their match while their session can still be resumed
+        if msg.0.activity == Activity::Offline {
+            if let Ok(Some(cancelled)) = self.matchmaker.leave(&msg.0.uuid) {
+                self.fall_through(cancelled);
+            }
+            self.remove_spectator(&msg.0.uuid);
+            if self.players.contains_key(&msg.0.uuid) {
+                let _ = self.remove_player(&msg.0.uuid);
+            }
+        }
+    }
+}
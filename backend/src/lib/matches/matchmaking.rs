@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::configuration::MatchmakingSettings;
+
+use super::MatchError;
+
+/// What a match found by matchmaking is played as, players have a rating for each
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum GameMode {
+    /// The players fight the waves of the levels together
+    Coop,
+
+    /// One player against another
+    Versus,
+}
+
+/// A player waiting for a match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ticket {
+    pub uuid: String,
+    pub username: String,
+    pub mode: GameMode,
+    pub region: String,
+    pub rating: f64,
+
+    /// When the player started waiting, kept when a match that was found falls through
+    pub queued_at: Instant,
+}
+
+/// A match that was found, waiting for its players to accept it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    /// Id the match gets once everyone accepted
+    pub match_id: String,
+    pub mode: GameMode,
+    pub region: String,
+
+    /// The players, the one that waited longest first
+    pub tickets: Vec<Ticket>,
+
+    /// Uuids of the players that accepted
+    pub accepted: HashSet<String>,
+    pub expires_at: Instant,
+}
+
+impl Proposal {
+    pub fn uuids(&self) -> Vec<String> {
+        self.tickets
+            .iter()
+            .map(|ticket| ticket.uuid.clone())
+            .collect()
+    }
+
+    fn contains(&self, uuid: &str) -> bool {
+        self.tickets.iter().any(|ticket| ticket.uuid == uuid)
+    }
+}
+
+/// A proposal that fell through because a player declined or didn't answer in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cancelled {
+    pub proposal: Proposal,
+
+    /// Uuids of the players that are back in the queue, at the place they had
+    pub requeued: Vec<String>,
+
+    /// Uuids of the players that declined or didn't answer, they are out of the queue
+    pub dropped: Vec<String>,
+}
+
+/// The matchmaking queue and the matches found in it.
+///
+/// Players wait for a mode in a region with their rating. Whoever waited longest picks first,
+/// from the players of the same mode and region, the ones with the closest ratings. Two players
+/// can only be paired if their ratings are within the tolerance of both, which starts at
+/// `initial_tolerance` and widens the longer a player waits. A match that was found is a
+/// `Proposal` until every player accepted it. Players that accepted a proposal that falls through
+/// go back to the queue without losing their place.
+///
+/// Nothing here knows about sessions, the `MatchManager` tells the players what happens.
+pub struct Matchmaker {
+    settings: MatchmakingSettings,
+
+    /// The waiting players, the one that waited longest first
+    queue: Vec<Ticket>,
+    proposals: Vec<Proposal>,
+}
+
+impl Matchmaker {
+    pub fn new(settings: MatchmakingSettings) -> Self {
+        Self {
+            settings,
+            queue: Vec::new(),
+            proposals: Vec::new(),
+        }
+    }
+
+    pub fn settings(&self) -> &MatchmakingSettings {
+        &self.settings
+    }
+
+    /// Players a match of `mode` is found for
+    pub fn players_per_match(&self, mode: GameMode) -> usize {
+        match mode {
+            GameMode::Coop => self.settings.coop_players.max(1),
+            GameMode::Versus => 2,
+        }
+    }
+
+    /// Number of players in the queue, players with a match to accept excluded
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn ticket(&self, uuid: &str) -> Option<&Ticket> {
+        self.queue.iter().find(|ticket| ticket.uuid == uuid)
+    }
+
+    /// The match a player was offered and has yet to be accepted by everyone
+    pub fn proposal_of(&self, uuid: &str) -> Option<&Proposal> {
+        self.proposals
+            .iter()
+            .find(|proposal| proposal.contains(uuid))
+    }
+
+    /// Whether a player is in the queue or has a match to accept
+    pub fn is_waiting(&self, uuid: &str) -> bool {
+        self.ticket(uuid).is_some() || self.proposal_of(uuid).is_some()
+    }
+
+    /// Puts a player in the queue. Queueing again for the same mode and region keeps the place
+    /// in the queue, queueing for another one starts over.
+    pub fn enqueue(&mut self, ticket: Ticket) -> Result<(), MatchError> {
+        if !self.settings.regions.contains(&ticket.region) {
+            return Err(MatchError::UnknownRegion(ticket.region));
+        }
+        if self.proposal_of(&ticket.uuid).is_some() {
+            return Err(MatchError::Queued);
+        }
+
+        let index = self
+            .queue
+            .iter()
+            .position(|queued| queued.uuid == ticket.uuid);
+        match index.map(|index| &mut self.queue[index]) {
+            Some(queued) if queued.mode == ticket.mode && queued.region == ticket.region => {
+                queued.username = ticket.username;
+                queued.rating = ticket.rating;
+            }
+            Some(_) => {
+                self.queue.retain(|queued| queued.uuid != ticket.uuid);
+                self.insert(ticket);
+            }
+            None => self.insert(ticket),
+        }
+        Ok(())
+    }
+
+    /// Puts a ticket at its place in the queue
+    fn insert(&mut self, ticket: Ticket) {
+        let index = self
+            .queue
+            .partition_point(|queued| queued.queued_at <= ticket.queued_at);
+        self.queue.insert(index, ticket);
+    }
+
+    /// Takes a player out of the queue, declining the match they were offered if there is one
+    pub fn leave(&mut self, uuid: &str) -> Result<Option<Cancelled>, MatchError> {
+        if let Some(index) = self.queue.iter().position(|ticket| ticket.uuid == uuid) {
+            self.queue.remove(index);
+            return Ok(None);
+        }
+
+        let index = self
+            .proposals
+            .iter()
+            .position(|proposal| proposal.contains(uuid))
+            .ok_or(MatchError::NotQueued)?;
+        Ok(Some(self.cancel(index, |ticket| ticket.uuid != uuid)))
+    }
+
+    /// Largest rating difference a player accepts at `now`
+    pub fn tolerance(&self, ticket: &Ticket, now: Instant) -> f64 {
+        let waited = now
+            .saturating_duration_since(ticket.queued_at)
+            .as_secs_f64();
+        let tolerance =
+            self.settings.initial_tolerance + waited * self.settings.tolerance_per_second;
+        tolerance.min(self.settings.max_tolerance)
+    }
+
+    /// Pairs up the players that can play together at `now`, returns the matches that were
+    /// found. Their players are out of the queue until the match is accepted or falls through.
+    pub fn find_matches(&mut self, now: Instant) -> Vec<Proposal> {
+        let mut found = Vec::new();
+
+        let mut index = 0;
+        while index < self.queue.len() {
+            let oldest = &self.queue[index];
+            let (mode, region) = (oldest.mode, oldest.region.clone());
+            let tolerance = self.tolerance(oldest, now);
+
+            let mut candidates: Vec<(usize, f64)> = self
+                .queue
+                .iter()
+                .enumerate()
+                .skip(index + 1)
+                .filter(|(_, ticket)| ticket.mode == mode && ticket.region == region)
+                .map(|(i, ticket)| (i, (ticket.rating - oldest.rating).abs()))
+                .filter(|&(i, difference)| {
+                    difference <= tolerance.min(self.tolerance(&self.queue[i], now))
+                })
+                .collect();
+
+            let size = self.players_per_match(mode);
+            if candidates.len() + 1 < size {
+                index += 1;
+                continue;
+            }
+
+            // The closest ratings, taken out of the queue from the back to keep the indices valid
+            candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+            let mut picked: Vec<usize> = candidates
+                .iter()
+                .take(size - 1)
+                .map(|&(i, _)| i)
+                .chain([index])
+                .collect();
+            picked.sort_unstable_by(|a, b| b.cmp(a));
+            let mut tickets: Vec<Ticket> =
+                picked.into_iter().map(|i| self.queue.remove(i)).collect();
+            tickets.reverse();
+
+            let proposal = Proposal {
+                match_id: Uuid::new_v4().to_string(),
+                mode,
+                region,
+                tickets,
+                accepted: HashSet::new(),
+                expires_at: now + Duration::from_secs(self.settings.accept_timeout_seconds),
+            };
+            self.proposals.push(proposal.clone());
+            found.push(proposal);
+        }
+
+        found
+    }
+
+    /// The proposal of `match_id` if the player was offered it
+    fn offered(&self, uuid: &str, match_id: &str) -> Result<usize, MatchError> {
+        self.proposals
+            .iter()
+            .position(|proposal| proposal.match_id == match_id && proposal.contains(uuid))
+            .ok_or_else(|| MatchError::NotFound(match_id.to_string()))
+    }
+
+    /// A player accepts the match they were offered. Returns the proposal once every player
+    /// accepted it, matchmaking is done with them then.
+    pub fn accept(&mut self, uuid: &str, match_id: &str) -> Result<Option<Proposal>, MatchError> {
+        let index = self.offered(uuid, match_id)?;
+
+        let proposal = &mut self.proposals[index];
+        proposal.accepted.insert(uuid.to_string());
+        if proposal.accepted.len() < proposal.tickets.len() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.proposals.swap_remove(index)))
+    }
+
+    /// A player declines the match they were offered and leaves the queue, the other players
+    /// go back to it
+    pub fn decline(&mut self, uuid: &str, match_id: &str) -> Result<Cancelled, MatchError> {
+        let index = self.offered(uuid, match_id)?;
+        Ok(self.cancel(index, |ticket| ticket.uuid != uuid))
+    }
+
+    /// Cancels the proposals that weren't accepted by everyone before `now`. The players that
+    /// accepted go back to the queue, the others leave it.
+    pub fn expire(&mut self, now: Instant) -> Vec<Cancelled> {
+        let mut cancelled = Vec::new();
+        while let Some(index) = self
+            .proposals
+            .iter()
+            .position(|proposal| proposal.expires_at <= now)
+        {
+            let accepted = self.proposals[index].accepted.clone();
+            cancelled.push(self.cancel(index, |ticket| accepted.contains(&ticket.uuid)));
+        }
+        cancelled
+    }
+
+    /// Drops a proposal, the players `requeue` picks go back to the queue
+    fn cancel(&mut self, index: usize, requeue: impl Fn(&Ticket) -> bool) -> Cancelled {
+        let proposal = self.proposals.swap_remove(index);
+
+        let mut requeued = Vec::new();
+        let mut dropped = Vec::new();
+        for ticket in &proposal.tickets {
+            if requeue(ticket) {
+                requeued.push(ticket.uuid.clone());
+                self.insert(ticket.clone());
+            } else {
+                dropped.push(ticket.uuid.clone());
+            }
+        }
+
+        Cancelled {
+            proposal,
+            requeued,
+            dropped,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use thiserror::Error;
+
+use crate::websocket::protocol::{ErrorCode, ServerMessage};
+
+pub mod game_match;
+pub mod manager;
+pub mod matchmaking;
+pub mod playback;
+pub mod rating;
+pub mod replay;
+
+/// Why a request to the `MatchManager` was refused
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MatchError {
+    #[error("There is no match {0}")]
+    NotFound(String),
+
+    #[error("Match {0} is full")]
+    Full(String),
+
+    #[error("You are already in a match")]
+    AlreadyInMatch,
+
+    #[error("You are not in a match")]
+    NotInMatch,
+
+    #[error("Match {0} has no room for more spectators")]
+    NoSpectatorSeats(String),
+
+    #[error("You are waiting for a match, leave the queue first")]
+    Queued,
+
+    #[error("You are not waiting for a match")]
+    NotQueued,
+
+    #[error("There is no region {0}")]
+    UnknownRegion(String),
+
+    #[error("Spectators can't play along")]
+    Spectating,
+
+    #[error("There is no replay of match {0}")]
+    ReplayNotFound(String),
+
+    #[error("The replay of match {0} can't be played back")]
+    ReplayUnavailable(String),
+
+    #[error("You are not watching a replay")]
+    NotWatching,
+}
+
+impl MatchError {
+    /// The error message sent back to the client
+    pub fn to_server_message(&self) -> ServerMessage {
+        let code = match self {
+            MatchError::NotFound(_) => ErrorCode::MatchNotFound,
+            MatchError::ReplayNotFound(_) => ErrorCode::ReplayNotFound,
+            _ => ErrorCode::Rejected,
+        };
+
+        ServerMessage::error(code, self)
+    }
+}
+
+/// Name of the chat room of a match
+pub fn match_room(match_id: &str) -> String {
+    format!("match:{}", match_id)
+}
+
+/// Name of the chat room of the spectators of a match, the players don't read along
+pub fn spectator_room(match_id: &str) -> String {
+    format!("spectators:{}", match_id)
+}
+
+/// Name of the chat room of the players a match was found for, until they accepted it
+pub fn lobby_room(match_id: &str) -> String {
+    format!("lobby:{}", match_id)
+}
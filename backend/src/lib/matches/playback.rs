@@ -0,0 +1,333 @@
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::MatchSettings;
+use crate::game::level::Level;
+use crate::game::timestep::FixedTimestep;
+use crate::game::world::World;
+use crate::game::TICK_RATE;
+use crate::websocket::protocol::{ErrorCode, ServerMessage};
+use crate::websocket::server::Deliver;
+
+use super::replay::{Playthrough, Replay, ReplayError};
+
+/// Ticks between two keyframes, seeking never simulates more than this many ticks past one
+pub const KEYFRAME_INTERVAL: u64 = 5 * TICK_RATE as u64;
+
+/// Most simulation steps taken between two updates of a playback, enough for 4x with some lag.
+/// Seeking takes as many per update, a far seek is spread over several.
+const MAX_STEPS_PER_UPDATE: u32 = 40;
+
+/// How fast a replay is played back
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaybackSpeed {
+    #[serde(rename = "paused")]
+    Paused,
+
+    #[default]
+    #[serde(rename = "1x")]
+    Normal,
+
+    #[serde(rename = "2x")]
+    Double,
+
+    #[serde(rename = "4x")]
+    Quadruple,
+}
+
+impl PlaybackSpeed {
+    /// Ticks played for every tick of real time
+    pub fn factor(self) -> u32 {
+        match self {
+            PlaybackSpeed::Paused => 0,
+            PlaybackSpeed::Normal => 1,
+            PlaybackSpeed::Double => 2,
+            PlaybackSpeed::Quadruple => 4,
+        }
+    }
+}
+
+/// A replay that is played forwards and jumped around in.
+///
+/// Every `KEYFRAME_INTERVAL` ticks the first time they are played, the playthrough is kept as a
+/// keyframe. Seeking starts over from the keyframe right before the tick it seeks to and
+/// simulates the rest, the same way the match got there, a bounded number of ticks at a time.
+pub struct Timeline {
+    replay: Replay,
+    playthrough: Playthrough,
+
+    /// The playthrough at every `KEYFRAME_INTERVAL`th tick played so far, starting at tick 0
+    keyframes: Vec<Playthrough>,
+
+    /// The tick a seek is on its way to, see `catch_up`
+    target: Option<u64>,
+}
+
+impl Timeline {
+    pub fn new(replay: Replay, levels: &[Level]) -> Result<Self, ReplayError> {
+        let playthrough = replay.start(levels)?;
+
+        Ok(Self {
+            replay,
+            keyframes: vec![playthrough.clone()],
+            playthrough,
+            target: None,
+        })
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn world(&self) -> &World {
+        self.playthrough.world()
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.playthrough.tick()
+    }
+
+    pub fn final_tick(&self) -> u64 {
+        self.replay.final_tick
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick() >= self.final_tick()
+    }
+
+    /// Number of keyframes kept so far
+    pub fn keyframes(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    /// Plays the next tick, returns false if the replay is over
+    pub fn step(&mut self) -> Result<bool, ReplayError> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+
+        self.playthrough.step(&self.replay)?;
+
+        let tick = self.tick();
+        if tick.is_multiple_of(KEYFRAME_INTERVAL)
+            && tick / KEYFRAME_INTERVAL == self.keyframes.len() as u64
+        {
+            self.keyframes.push(self.playthrough.clone());
+        }
+        Ok(true)
+    }
+
+    /// Whether a seek still has ticks to simulate
+    pub fn is_seeking(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Jumps to `tick`, or to the end of the replay if it is past it. Only keyframes are jumped
+    /// to, the ticks after one are simulated by `catch_up`.
+    pub fn seek(&mut self, tick: u64) {
+        let tick = tick.min(self.final_tick());
+
+        // Playing on is cheaper than starting over if the playthrough is past the keyframe
+        let index = usize::try_from(tick / KEYFRAME_INTERVAL)
+            .unwrap_or(usize::MAX)
+            .min(self.keyframes.len() - 1);
+        let keyframe = &self.keyframes[index];
+        if self.tick() > tick || keyframe.tick() > self.tick() {
+            self.playthrough = keyframe.clone();
+        }
+        self.target = (self.tick() < tick).then_some(tick);
+    }
+
+    /// Simulates at most `max_steps` ticks towards the tick that is sought
+    pub fn catch_up(&mut self, max_steps: u32) -> Result<(), ReplayError> {
+        let Some(target) = self.target else {
+            return Ok(());
+        };
+
+        for _ in 0..max_steps {
+            if self.tick() >= target {
+                break;
+            }
+            self.step()?;
+        }
+        if self.tick() >= target {
+            self.target = None;
+        }
+        Ok(())
+    }
+}
+
+/// Changes the speed of a playback
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetPlaybackSpeed(pub PlaybackSpeed);
+
+/// Jumps to a tick of the replay
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SeekPlayback(pub u64);
+
+/// Stops a playback, sent when the watcher stops watching or disconnects
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopPlayback;
+
+/// A replay played back to a single session.
+///
+/// Runs on the arbiters of the matches, like a `GameMatch` without players: the timeline is
+/// advanced by the time that passed times the speed, and the session gets the reconstructed
+/// world as a full `Snapshot` every update. Seeks are simulated over as many updates as they
+/// take, the playback stands still meanwhile. A `Playback` message tells the session where the
+/// playback is whenever it starts, changes speed, lands a seek or reaches the end, where it
+/// pauses.
+pub struct ReplayPlayback {
+    timeline: Timeline,
+    speed: PlaybackSpeed,
+    timestep: FixedTimestep,
+    update_interval: Duration,
+    last_update: Instant,
+    session: Recipient<Deliver>,
+}
+
+impl ReplayPlayback {
+    pub fn new(timeline: Timeline, settings: &MatchSettings, session: Recipient<Deliver>) -> Self {
+        Self {
+            timeline,
+            speed: PlaybackSpeed::default(),
+            timestep: FixedTimestep::new(TICK_RATE, MAX_STEPS_PER_UPDATE),
+            update_interval: Duration::from_secs(1) / settings.tick_rate.max(1),
+            last_update: Instant::now(),
+            session,
+        }
+    }
+
+    fn match_id(&self) -> String {
+        self.timeline.replay().header.match_id.clone()
+    }
+
+    fn send(&self, message: ServerMessage) {
+        self.session.do_send(Deliver(message));
+    }
+
+    fn send_state(&self) {
+        self.send(ServerMessage::Playback {
+            match_id: self.match_id(),
+            tick: self.timeline.tick(),
+            final_tick: self.timeline.final_tick(),
+            speed: self.speed,
+        });
+    }
+
+    fn send_snapshot(&self) {
+        self.send(ServerMessage::Snapshot {
+            match_id: self.match_id(),
+            world: self.timeline.world().clone(),
+        });
+    }
+
+    /// Tells the session that the replay can't be played on and stops
+    fn fail(&self, error: ReplayError, ctx: &mut Context<Self>) {
+        log::error!("Playback of replay {} failed: {}", self.match_id(), error);
+        self.send(ServerMessage::error(
+            ErrorCode::Rejected,
+            format!("The replay can't be played back: {}", error),
+        ));
+        ctx.stop();
+    }
+
+    /// Simulates the next part of a seek, once it landed the session is told where it is
+    fn catch_up(&mut self, ctx: &mut Context<Self>) {
+        if let Err(e) = self.timeline.catch_up(MAX_STEPS_PER_UPDATE) {
+            return self.fail(e, ctx);
+        }
+        if self.timeline.is_seeking() {
+            return;
+        }
+
+        if self.timeline.is_finished() {
+            self.speed = PlaybackSpeed::Paused;
+        }
+        self.send_state();
+        self.send_snapshot();
+    }
+
+    fn update(&mut self, ctx: &mut Context<Self>) {
+        if !self.session.connected() {
+            ctx.stop();
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = (now - self.last_update) * self.speed.factor();
+        self.last_update = now;
+        // The time spent seeking isn't played, the playback goes on from where the seek landed
+        if self.timeline.is_seeking() {
+            return self.catch_up(ctx);
+        }
+        if self.speed == PlaybackSpeed::Paused {
+            return;
+        }
+
+        for _ in 0..self.timestep.advance(elapsed) {
+            match self.timeline.step() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return self.fail(e, ctx),
+            }
+        }
+        self.send_snapshot();
+
+        if self.timeline.is_finished() {
+            self.speed = PlaybackSpeed::Paused;
+            self.send_state();
+        }
+    }
+}
+
+impl Actor for ReplayPlayback {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("Playback of replay {} started", self.match_id());
+
+        self.send_state();
+        self.send_snapshot();
+
+        self.last_update = Instant::now();
+        ctx.run_interval(self.update_interval, |act, ctx| act.update(ctx));
+    }
+}
+
+impl Handler<SetPlaybackSpeed> for ReplayPlayback {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetPlaybackSpeed, _: &mut Context<Self>) {
+        // A replay that is over can only be paused, until it is rewound
+        self.speed = match msg.0 {
+            _ if self.timeline.is_finished() => PlaybackSpeed::Paused,
+            speed => speed,
+        };
+        self.timestep = FixedTimestep::new(TICK_RATE, MAX_STEPS_PER_UPDATE);
+        self.send_state();
+    }
+}
+
+impl Handler<SeekPlayback> for ReplayPlayback {
+    type Result = ();
+
+    fn handle(&mut self, msg: SeekPlayback, ctx: &mut Context<Self>) {
+        self.timeline.seek(msg.0);
+        self.catch_up(ctx);
+    }
+}
+
+impl Handler<StopPlayback> for ReplayPlayback {
+    type Result = ();
+
+    fn handle(&mut self, _: StopPlayback, ctx: &mut Context<Self>) {
+        log::info!("Playback of replay {} stopped", self.match_id());
+        ctx.stop();
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::Outcome;
+
+use super::matchmaking::GameMode;
+
+/// Rating of players that haven't played a rated match yet
+pub const DEFAULT_RATING: f64 = 1500.0;
+
+/// Deviation of players that haven't played a rated match yet, also the largest it gets
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// How much the volatility may change in one rating period, Glicko-2's τ
+const TAU: f64 = 0.5;
+
+/// Converts ratings to the Glicko-2 scale and back
+const SCALE: f64 = 173.7178;
+
+/// Precision the volatility is computed to
+const CONVERGENCE: f64 = 0.000001;
+
+/// The opponent of co-op players, rated like a player that played a lot at the default rating.
+/// Players that keep beating the levels end up above it, players that lose to them below.
+pub const LEVELS: Rating = Rating {
+    rating: DEFAULT_RATING,
+    deviation: 50.0,
+    volatility: DEFAULT_VOLATILITY,
+};
+
+/// A Glicko-2 rating. The rating is how good a player is thought to be, the deviation how sure
+/// that is and the volatility how erratic their results are.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+/// A game against one opponent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Game {
+    pub opponent: Rating,
+
+    /// 1 for a win, 0.5 for a draw and 0 for a loss
+    pub score: f64,
+}
+
+/// Weighs a game by how uncertain the rating of the opponent is
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+/// Score a player of `mu` is expected to get against one of `mu_j` and `phi_j`
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+    1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+impl Rating {
+    /// The rating after a rating period with `games`, as described in Glickman's "Example of
+    /// the Glicko-2 system". Without games only the deviation grows.
+    pub fn update(self, games: &[Game]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let sigma = self.volatility;
+
+        if games.is_empty() {
+            let deviation = (phi * phi + sigma * sigma).sqrt() * SCALE;
+            return Rating {
+                deviation: deviation.min(DEFAULT_DEVIATION),
+                ..self
+            };
+        }
+
+        // Estimated variance of the rating and the improvement the games suggest
+        let mut variance = 0.0;
+        let mut improvement = 0.0;
+        for game in games {
+            let mu_j = (game.opponent.rating - DEFAULT_RATING) / SCALE;
+            let phi_j = game.opponent.deviation / SCALE;
+            let expected = expected(mu, mu_j, phi_j);
+            variance += g(phi_j).powi(2) * expected * (1.0 - expected);
+            improvement += g(phi_j) * (game.score - expected);
+        }
+        let v = 1.0 / variance;
+        let delta = v * improvement;
+
+        let volatility = volatility(phi, sigma, v, delta);
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+
+        Rating {
+            rating: mu * SCALE + DEFAULT_RATING,
+            deviation: (phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+}
+
+/// The new volatility, found with the Illinois algorithm
+fn volatility(phi: f64, sigma: f64, v: f64, delta: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2))
+            - (x - a) / (TAU * TAU)
+    };
+
+    let mut lower = a;
+    let mut upper = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+    while (upper - lower).abs() > CONVERGENCE {
+        let next = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_next = f(next);
+        if f_next * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+        upper = next;
+        f_upper = f_next;
+    }
+
+    (lower / 2.0).exp()
+}
+
+/// How a player of a rated match finished it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Standing {
+    pub uuid: String,
+
+    /// The score the player ended with, empty if they left before the end
+    pub score: Option<u64>,
+
+    /// How many of the players that left did so before this one, empty if they stayed until the
+    /// end
+    pub left: Option<usize>,
+}
+
+/// The ratings of the players of a match found by matchmaking after it ended in `outcome`.
+///
+/// Every match is a rating period of its own. In versus every player played a game against each
+/// of the others, won by the higher score. Players that left lose to everyone that stayed longer.
+/// In co-op every player played a game against the `LEVELS`, won if the match was won and the
+/// player stayed until the end.
+///
+/// `ratings` has the ratings the players had before, players without one start at the default.
+pub fn rate_match(
+    mode: GameMode,
+    outcome: Outcome,
+    standings: &[Standing],
+    ratings: &HashMap<String, Rating>,
+) -> HashMap<String, Rating> {
+    let rating = |uuid: &str| ratings.get(uuid).copied().unwrap_or_default();
+
+    standings
+        .iter()
+        .map(|standing| {
+            let games: Vec<Game> = match mode {
+                GameMode::Versus => standings
+                    .iter()
+                    .filter(|other| other.uuid != standing.uuid)
+                    .map(|other| Game {
+                        opponent: rating(&other.uuid),
+                        score: match standing
+                            .score
+                            .cmp(&other.score)
+                            .then(standing.left.cmp(&other.left))
+                        {
+                            std::cmp::Ordering::Greater => 1.0,
+                            std::cmp::Ordering::Equal => 0.5,
+                            std::cmp::Ordering::Less => 0.0,
+                        },
+                    })
+                    .collect(),
+                GameMode::Coop => vec![Game {
+                    opponent: LEVELS,
+                    score: match (outcome, standing.score) {
+                        (Outcome::Victory, Some(_)) => 1.0,
+                        _ => 0.0,
+                    },
+                }],
+            };
+
+            (standing.uuid.clone(), rating(&standing.uuid).update(&games))
+        })
+        .collect()
+}
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::game::director::Director;
+use crate::game::entity::PlayerInput;
+use crate::game::input::SequencedInput;
+use crate::game::level::Level;
+use crate::game::world::World;
+use crate::websocket::protocol::PROTOCOL_VERSION;
+
+/// First bytes of every replay file
+pub const REPLAY_MAGIC: &[u8; 4] = b"SBRP";
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("Not a replay file")]
+    NotAReplay,
+
+    #[error("Could not read or write the replay: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Could not decode the replay: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+
+    #[error("Could not encode the replay: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+
+    #[error("The replay was recorded with protocol version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("The replay starts with level {0}, which is not loaded")]
+    UnknownLevel(String),
+
+    #[error("The replay refers to player {0} before they joined")]
+    UnknownPlayer(u16),
+
+    #[error("The replay ended in state {actual} rather than {expected}")]
+    HashMismatch { expected: String, actual: String },
+}
+
+/// What a replay needs to set up the game it recorded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReplayHeader {
+    pub match_id: String,
+
+    /// Seed of the world's random numbers
+    pub seed: u64,
+
+    /// Id of the first level the match played, the levels after it followed in order
+    pub level: String,
+
+    pub protocol_version: u32,
+}
+
+/// A player joined or left before a tick was simulated. Players are numbered in the order they
+/// joined, to keep their uuids out of every input.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ReplayEvent {
+    Join { uuid: String },
+    Leave { player: u16 },
+}
+
+/// An input a tick applied
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedInput {
+    pub player: u16,
+
+    /// The held keys as bits, in the order of the fields of `PlayerInput`
+    pub keys: u8,
+
+    /// How far the sequence moved on from the player's previous input, 1 unless inputs were
+    /// dropped on the way
+    pub sequence_step: u32,
+}
+
+/// What happened before one tick, ticks without anything are left out
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReplayTick {
+    pub tick: u64,
+    pub events: Vec<ReplayEvent>,
+    pub inputs: Vec<RecordedInput>,
+}
+
+/// A recorded match: its header, the inputs of every tick and the state it ended in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub ticks: Vec<ReplayTick>,
+
+    /// Tick the match ended on
+    pub final_tick: u64,
+
+    /// `World::state_hash` of the world the match ended with
+    pub final_hash: String,
+}
+
+fn keys(input: PlayerInput) -> u8 {
+    [input.up, input.down, input.left, input.right, input.fire]
+        .iter()
+        .enumerate()
+        .fold(0, |keys, (bit, held)| keys | (u8::from(*held) << bit))
+}
+
+fn input(keys: u8) -> PlayerInput {
+    let held = |bit: u8| keys & (1 << bit) != 0;
+    PlayerInput {
+        up: held(0),
+        down: held(1),
+        left: held(2),
+        right: held(3),
+        fire: held(4),
+    }
+}
+
+impl Replay {
+    /// The replay file, `REPLAY_MAGIC` followed by the replay as MessagePack
+    pub fn encode(&self) -> Result<Vec<u8>, ReplayError> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        rmp_serde::encode::write(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let body = bytes
+            .strip_prefix(REPLAY_MAGIC.as_slice())
+            .ok_or(ReplayError::NotAReplay)?;
+        Ok(rmp_serde::from_slice(body)?)
+    }
+
+    /// Writes the replay to `<dir>/<match id>.replay`
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, ReplayError> {
+        let bytes = self.encode()?;
+        let path = replay_path(dir, &self.header.match_id);
+
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, bytes)?;
+        Ok(path)
+    }
+
+    /// Reads the replay of a match that `save` wrote to `dir`
+    pub fn load(dir: &Path, match_id: &str) -> Result<Self, ReplayError> {
+        Self::decode(&std::fs::read(replay_path(dir, match_id))?)
+    }
+
+    /// Uuids of everyone who played in the match, in the order they first joined
+    pub fn players(&self) -> Vec<String> {
+        let mut players: Vec<String> = Vec::new();
+        for event in self.ticks.iter().flat_map(|tick| &tick.events) {
+            if let ReplayEvent::Join { uuid } = event {
+                if !players.contains(uuid) {
+                    players.push(uuid.clone());
+                }
+            }
+        }
+        players
+    }
+
+    /// Sets the match up again with `levels`, the levels the server had loaded, to play it
+    /// through tick by tick
+    pub fn start(&self, levels: &[Level]) -> Result<Playthrough, ReplayError> {
+        if self.header.protocol_version != PROTOCOL_VERSION {
+            return Err(ReplayError::UnsupportedVersion(
+                self.header.protocol_version,
+            ));
+        }
+
+        let first = levels
+            .iter()
+            .position(|level| level.id == self.header.level)
+            .ok_or_else(|| ReplayError::UnknownLevel(self.header.level.clone()))?;
+
+        let mut playthrough = Playthrough {
+            world: World::with_seed(self.header.seed),
+            director: Director::new(Arc::from(&levels[first..])),
+            players: Players::default(),
+            next: 0,
+        };
+        playthrough.apply(self)?;
+        Ok(playthrough)
+    }
+
+    /// Plays the match again with `levels`. Returns the hash of the state it ends in, which has
+    /// to be the recorded one.
+    pub fn run(&self, levels: &[Level]) -> Result<String, ReplayError> {
+        let mut playthrough = self.start(levels)?;
+        while playthrough.tick() < self.final_tick {
+            playthrough.step(self)?;
+        }
+
+        let actual = playthrough.world.state_hash();
+        if actual != self.final_hash {
+            return Err(ReplayError::HashMismatch {
+                expected: self.final_hash.clone(),
+                actual,
+            });
+        }
+        Ok(actual)
+    }
+}
+
+/// Where `Replay::save` puts the replay of a match
+pub fn replay_path(dir: &Path, match_id: &str) -> PathBuf {
+    dir.join(format!("{match_id}.replay"))
+}
+
+/// A replay that is played back, see `Replay::start`. It is cheap enough to clone to keep a copy
+/// to return to later.
+#[derive(Debug, Clone)]
+pub struct Playthrough {
+    world: World,
+    director: Director,
+    players: Players,
+
+    /// Index of the next recorded tick to apply
+    next: usize,
+}
+
+impl Playthrough {
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.world.tick
+    }
+
+    /// Simulates the next tick of `replay`, the replay the playthrough was started from
+    pub fn step(&mut self, replay: &Replay) -> Result<(), ReplayError> {
+        self.director.step(&mut self.world);
+        self.world.step();
+        self.apply(replay)
+    }
+
+    /// Joins and leaves the players recorded for the current tick and queues their inputs, the
+    /// match did so before it simulated the tick
+    fn apply(&mut self, replay: &Replay) -> Result<(), ReplayError> {
+        while let Some(recorded) = replay
+            .ticks
+            .get(self.next)
+            .filter(|recorded| recorded.tick <= self.world.tick)
+        {
+            self.players.apply(&mut self.world, recorded)?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+/// The players of a replay that is played back, by number
+#[derive(Debug, Clone, Default)]
+struct Players {
+    uuids: Vec<String>,
+
+    /// Sequence of the last input of every player
+    sequences: Vec<u32>,
+}
+
+impl Players {
+    fn uuid(&self, player: u16) -> Result<&str, ReplayError> {
+        self.uuids
+            .get(usize::from(player))
+            .map(String::as_str)
+            .ok_or(ReplayError::UnknownPlayer(player))
+    }
+
+    /// Joins and leaves the players of a recorded tick and queues their inputs
+    fn apply(&mut self, world: &mut World, recorded: &ReplayTick) -> Result<(), ReplayError> {
+        for event in &recorded.events {
+            match event {
+                ReplayEvent::Join { uuid } => {
+                    world.add_player(uuid);
+                    self.uuids.push(uuid.clone());
+                    self.sequences.push(0);
+                }
+                ReplayEvent::Leave { player } => {
+                    world.remove_player(self.uuid(*player)?);
+                }
+            }
+        }
+
+        for recorded in &recorded.inputs {
+            let uuid = self.uuid(recorded.player)?.to_string();
+            let sequence = &mut self.sequences[usize::from(recorded.player)];
+            *sequence += recorded.sequence_step;
+
+            let input = SequencedInput {
+                sequence: *sequence,
+                client_tick: world.tick,
+                input: input(recorded.keys),
+            };
+            world.queue_input(&uuid, input);
+        }
+        Ok(())
+    }
+}
+
+/// Writes down what happens in a match as it is played
+#[derive(Debug)]
+pub struct Recorder {
+    header: ReplayHeader,
+    ticks: Vec<ReplayTick>,
+
+    /// Number of every player, by uuid
+    players: HashMap<String, u16>,
+    joined: u16,
+
+    /// Sequence of the last input of every player, by number
+    sequences: HashMap<u16, u32>,
+}
+
+impl Recorder {
+    pub fn new(header: ReplayHeader) -> Self {
+        Self {
+            header,
+            ticks: Vec::new(),
+            players: HashMap::new(),
+            joined: 0,
+            sequences: HashMap::new(),
+        }
+    }
+
+    /// The recorded tick `tick`, added if there is none yet
+    fn tick(&mut self, tick: u64) -> &mut ReplayTick {
+        if self.ticks.last().is_none_or(|last| last.tick != tick) {
+            self.ticks.push(ReplayTick {
+                tick,
+                events: Vec::new(),
+                inputs: Vec::new(),
+            });
+        }
+        self.ticks.last_mut().expect("The tick was just added")
+    }
+
+    /// A player was added to the world
+    pub fn join(&mut self, world: &World, uuid: &str) {
+        self.players.insert(uuid.to_string(), self.joined);
+        self.joined += 1;
+        self.tick(world.tick).events.push(ReplayEvent::Join {
+            uuid: uuid.to_string(),
+        });
+    }
+
+    /// A player was taken out of the world
+    pub fn leave(&mut self, world: &World, uuid: &str) {
+        if let Some(player) = self.players.remove(uuid) {
+            self.tick(world.tick)
+                .events
+                .push(ReplayEvent::Leave { player });
+        }
+    }
+
+    /// Records the inputs the next step of `world` applies, to be called right before it
+    pub fn inputs(&mut self, world: &World) {
+        let mut inputs = Vec::new();
+        for player in &world.players {
+            let (Some(&number), Some(next)) =
+                (self.players.get(&player.uuid), player.inputs.peek())
+            else {
+                continue;
+            };
+
+            let previous = self.sequences.insert(number, next.sequence).unwrap_or(0);
+            inputs.push(RecordedInput {
+                player: number,
+                keys: keys(next.input),
+                sequence_step: next.sequence - previous,
+            });
+        }
+
+        if !inputs.is_empty() {
+            self.tick(world.tick).inputs.extend(inputs);
+        }
+    }
+
+    /// The replay of the match, which ended with `world`
+    pub fn finish(&mut self, world: &World) -> Replay {
+        Replay {
+            header: self.header.clone(),
+            ticks: std::mem::take(&mut self.ticks),
+            final_tick: world.tick,
+            final_hash: world.state_hash(),
+        }
+    }
+}
@@ -1,8 +1,21 @@
-use crate::claims::Claims;
-use crate::types::{LoginDetails, LoginMethod, Player, PublicUserRecord, User};
+use crate::auth::{authorize_websocket, Admin, AuthenticatedUser, Moderator, RequireAuthority};
+use crate::claims::{Claims, TokenError};
+use crate::database::moderation::{ModerationTarget, NewModerationAction};
+use crate::matches::manager::MatchManager;
+use crate::types::{
+    AuthTokens, Authority, AuthorityChange, ChatHistoryQuery, LoginDetails, LoginError,
+    LoginMethod, LogoutRequest, ModerationAction, ModerationLogQuery, ModerationRequest, Player,
+    PlayerDetails, PresenceQuery, PublicUserRecord, RefreshError, RefreshRequest, ReplaysQuery,
+    User, WebsocketQuery,
+};
+use crate::websocket::protocol::Encoding;
+use crate::websocket::rate_limit::{FloodGuard, RateLimiter};
+use crate::websocket::room::{RoomKind, GLOBAL_ROOM};
+use crate::websocket::server::{ChatServer, GetPresence, InRoom, Kick, Mute};
 use crate::websocket::MyWebSocket;
 use crate::{database::db::ArcDb, websocket::INDEX_HTML};
-use actix_web::http::header::{ContentType, HeaderValue};
+use actix::Addr;
+use actix_web::http::header::{self, ContentType};
 use actix_web::http::StatusCode;
 use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
@@ -13,28 +26,107 @@ use serde_json::json;
 /// POST /players/create - players_create - Create a new player
 /// POST /database/resettable - database_resettable - Drop the provided table
 /// POST /auth/login - login - start authenticating the login request
+/// POST /auth/refresh - refresh - exchange a refresh token for a new token pair
 /// GET /helloworld - helloworld - for sanity checks / testing warp things
 /// GET /auth/verify_jwt
-
+/// POST /auth/logout - logout - revoke the current access token (and optionally refresh token)
+/// POST /auth/logout_all - logout_all - revoke every token of the current user
+/// POST /users/authority - set_authority - change the authority of a user (admin)
+/// GET /chat/{room}/history - chat_history - older messages of a chat room
+/// POST /moderation/{mute,unmute,kick,ban,unban} - moderate a user (moderator)
+/// GET /moderation/audit - moderation_audit - the moderation audit log (moderator)
+/// GET /presence - presence - who is online and what they are doing
+/// GET /replays - replays - replays of finished matches the player may watch
+/// GET /replays/{match_id} - replay - a single replay
+///
 /// Configure the server services
 pub fn config_server(cfg: &mut web::ServiceConfig) {
     cfg.service(index)
         //.service(chat)
-        .service(web::resource("/ws").route(web::get().to(echo_websocket)))
+        .service(web::resource("/ws").route(web::get().to(websocket)))
         .service(players_all)
         .service(users_all)
+        .service(set_authority)
         .service(login)
+        .service(refresh)
         .service(sign_up)
         .service(hello_world)
         .service(verify_jwt)
+        .service(logout)
+        .service(logout_all)
+        .service(chat_history)
+        .service(mute)
+        .service(unmute)
+        .service(kick)
+        .service(ban)
+        .service(unban)
+        .service(moderation_audit)
+        .service(presence)
+        .service(replays)
+        .service(replay)
         .service(player_info);
 }
 
-async fn echo_websocket(
+/// GET /ws?resume= -> Upgrade to a websocket session bound to the authenticated player
+///
+/// Returns a 401 before upgrading if no valid JWT was provided, see `authorize_websocket`.
+/// `resume` takes the resume token of a session whose connection dropped. Offering the
+/// `starblazers.msgpack` subprotocol instead of `starblazers` switches game traffic to binary
+/// frames.
+async fn websocket(
     req: HttpRequest,
     stream: web::Payload,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    match_manager: web::Data<Addr<MatchManager>>,
+    rate_limiter: web::Data<RateLimiter>,
+    query: web::Query<WebsocketQuery>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    ws::start(MyWebSocket::new(), &req, stream)
+    let claims = match authorize_websocket(&req, &db).await {
+        Ok(claims) => claims,
+        Err(TokenError::Database) => {
+            return json_with_status(
+                &json!({"error": "Internal server error"}),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+        Err(e) => {
+            log::info!("Rejected websocket handshake: {}", e);
+            return json_with_status(&json!({"error": "Unauthorized"}), StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    let muted_until = db.muted_until(&claims.uuid).await.unwrap_or_else(|e| {
+        log::error!("Failed to look up the mute of {}: {}", claims.username, e);
+        None
+    });
+
+    let encoding = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|offered| offered.to_str().ok())
+        .and_then(Encoding::negotiate)
+        .unwrap_or_default();
+
+    let flood_guard = FloodGuard::new(rate_limiter.into_inner(), &claims.uuid);
+    let max_frame_size = flood_guard.max_frame_size();
+
+    ws::WsResponseBuilder::new(
+        MyWebSocket::new(
+            claims,
+            chat_server.get_ref().clone(),
+            match_manager.get_ref().clone(),
+            muted_until,
+            flood_guard,
+            encoding,
+            query.into_inner().resume,
+        ),
+        &req,
+        stream,
+    )
+    .protocols(&Encoding::SUBPROTOCOLS)
+    .frame_size(max_frame_size)
+    .start()
 }
 
 /// POST /auth/signup -> Returns TODO
@@ -77,12 +169,18 @@ async fn players_all(db: web::Data<ArcDb>) -> Result<HttpResponse, actix_web::Er
     }
 }
 
-/// Returns all users from the Users table
+/// Returns all users from the Users table, admins only
 #[get("/users/all")]
-async fn users_all(db: web::Data<ArcDb>) -> Result<HttpResponse, actix_web::Error> {
-    let result: Result<Vec<User>, sqlx::Error> = sqlx::query_as!(User, "SELECT * FROM users",)
-        .fetch_all(&db.pool)
-        .await;
+async fn users_all(
+    _admin: RequireAuthority<Admin>,
+    db: web::Data<ArcDb>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let result: Result<Vec<User>, sqlx::Error> = sqlx::query_as!(
+        User,
+        r#"SELECT id, email, username, password, creation_date, uuid, authority as "authority: Authority" FROM users"#,
+    )
+    .fetch_all(&db.pool)
+    .await;
 
     match result {
         Ok(users) => Ok(HttpResponse::Ok().json(users)),
@@ -91,16 +189,19 @@ async fn users_all(db: web::Data<ArcDb>) -> Result<HttpResponse, actix_web::Erro
 }
 
 /// POST /auth/login -> Try to login
+///
+/// The access token is returned in the `Authorization` header, the refresh token in the body.
 #[post("/auth/login")]
 async fn login(db: web::Data<ArcDb>, body: web::Bytes) -> Result<HttpResponse, actix_web::Error> {
     let login_details = serde_json::from_slice::<LoginDetails>(&body)?;
     match db.check_login_details(&login_details).await {
-        Ok(jwt) => {
+        Ok(tokens) => {
             log::info!("Logged in!");
 
-            Ok(HttpResponse::Ok()
-                .insert_header(("Authorization", "Bearer ".to_owned() + &jwt))
-                .finish())
+            Ok(token_response(tokens))
+        }
+        Err(LoginError::Banned) => {
+            json_with_status(&json!({"error": "Banned"}), StatusCode::FORBIDDEN)
         }
         Err(e) => {
             log::error!("Error during login: {}", e);
@@ -109,83 +210,567 @@ async fn login(db: web::Data<ArcDb>, body: web::Bytes) -> Result<HttpResponse, a
     }
 }
 
+/// POST /auth/refresh -> Exchange a refresh token for a new access token and refresh token
+///
+/// Refresh tokens are single use, the one that was sent is no longer valid afterwards.
+#[post("/auth/refresh")]
+async fn refresh(db: web::Data<ArcDb>, body: web::Bytes) -> Result<HttpResponse, actix_web::Error> {
+    let refresh_request = serde_json::from_slice::<RefreshRequest>(&body)?;
+    match db
+        .rotate_refresh_token(&refresh_request.refresh_token)
+        .await
+    {
+        Ok(tokens) => Ok(token_response(tokens)),
+        Err(RefreshError::SqlError(e)) => {
+            log::error!("Error during refresh: {}", e);
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
+        Err(e) => {
+            log::info!("Refresh token rejected: {}", e);
+            json_with_status(&json!({"error": e.to_string()}), StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Puts the access token in the `Authorization` header and the refresh token in the body
+fn token_response(tokens: AuthTokens) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Authorization", "Bearer ".to_owned() + &tokens.access_token))
+        .json(json!({"refresh_token": tokens.refresh_token}))
+}
+
 /// GET /auth/verify_jwt
 #[get("/auth/verify_jwt")]
-async fn verify_jwt(req: HttpRequest) -> Result<HttpResponse, actix_web::Error> {
-    let header_value: Option<&HeaderValue> = req.headers().get("authorization");
+async fn verify_jwt(_user: AuthenticatedUser) -> Result<HttpResponse, actix_web::Error> {
+    // TODO: Maybe add the succesful login to the database?
+    Ok(HttpResponse::Ok().body("JWT Valid"))
+}
 
-    match header_value {
-        Some(header_value) => {
-            match Claims::from_header_value(header_value) {
-                Ok(_) => {
-                    // TODO: Maybe add the succesful login to the database?
-                    Ok(HttpResponse::Ok().body("JWT Valid"))
-                }
-                Err(e) => {
-                    log::error!("Error validating jwt: {}", e);
+/// POST /auth/logout -> Revoke the access token that was used for this request
+///
+/// The refresh token can be sent in the body to revoke it (and its family) as well.
+#[post("/auth/logout")]
+async fn logout(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let logout_request = if body.is_empty() {
+        LogoutRequest::default()
+    } else {
+        serde_json::from_slice::<LogoutRequest>(&body)?
+    };
 
-                    Ok(HttpResponse::Unauthorized().finish())
-                }
-            }
+    match db
+        .logout(&user.claims, logout_request.refresh_token.as_deref())
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => {
+            log::error!("Error during logout: {}", e);
+            Err(actix_web::error::ErrorInternalServerError(e))
         }
-        None => {
-            log::error!("Authorization header not found!");
-            Ok(HttpResponse::Unauthorized().finish())
+    }
+}
+
+/// POST /auth/logout_all -> Revoke every access and refresh token of the user
+#[post("/auth/logout_all")]
+async fn logout_all(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match db.logout_all(&user.uuid).await {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => {
+            log::error!("Error during logout_all: {}", e);
+            Err(actix_web::error::ErrorInternalServerError(e))
         }
     }
 }
 
-/// Creates the warp filter for the GET /players/player endpoint.
+/// POST /users/authority -> Change the authority of a user, admins only
 ///
-/// This filter extracts the authorization header, decodes it into claims, and retrieves player
-/// information based on those claims from the database.
-#[get("/players/player")]
-async fn player_info(
-    req: HttpRequest,
+/// The user's current access tokens are invalidated so the new authority is picked up on their
+/// next refresh.
+#[post("/users/authority")]
+async fn set_authority(
+    admin: RequireAuthority<Admin>,
     db: web::Data<ArcDb>,
+    body: web::Bytes,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let header_value: Option<&HeaderValue> = req.headers().get("authorization");
+    let change = serde_json::from_slice::<AuthorityChange>(&body)
+        .map_err(actix_web::error::ErrorBadRequest)?;
 
-    // Token is not in the headers
-    if header_value.is_none() {
-        log::error!("Authorization header not found!");
-        return Ok(HttpResponse::Unauthorized().finish());
+    match db.set_authority(&change.username, change.authority).await {
+        Ok(true) => {
+            log::info!(
+                "{} changed the authority of {} to {}",
+                admin.username,
+                change.username,
+                change.authority
+            );
+            Ok(HttpResponse::Ok().finish())
+        }
+        Ok(false) => json_with_status(&json!({"error": "User not found"}), StatusCode::NOT_FOUND),
+        Err(e) => {
+            log::error!("Error changing authority: {}", e);
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
     }
+}
+
+/// Most messages returned by a single GET /chat/{room}/history
+const MAX_HISTORY_PAGE: i64 = 100;
 
-    let claims = Claims::from_header_value(header_value.unwrap());
+/// GET /chat/{room}/history?before=&limit= -> Messages of a room, oldest first
+///
+/// Pages backwards through the history: pass the timestamp of the oldest message received so
+/// far as `before` to get the page before it. Everyone can read `global`, other rooms only by
+/// their members and by moderators.
+#[get("/chat/{room}/history")]
+async fn chat_history(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    room: web::Path<String>,
+    query: web::Query<ChatHistoryQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let room = room.into_inner();
 
-    let (email, uuid) = match claims {
-        Ok(claims) => (claims.sub, claims.uuid),
+    if RoomKind::of(&room).is_none() {
+        return json_with_status(&json!({"error": "Invalid room"}), StatusCode::BAD_REQUEST);
+    }
+
+    let allowed = room == GLOBAL_ROOM
+        || user.authority_level >= Authority::Moderator
+        || chat_server
+            .send(InRoom {
+                room: room.clone(),
+                uuid: user.uuid.clone(),
+            })
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if !allowed {
+        return json_with_status(&json!({"error": "Forbidden"}), StatusCode::FORBIDDEN);
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(MAX_HISTORY_PAGE)
+        .clamp(1, MAX_HISTORY_PAGE);
+
+    match db.chat_history(&room, query.before, limit).await {
+        Ok(messages) => {
+            json_with_status(&json!({"room": room, "messages": messages}), StatusCode::OK)
+        }
         Err(e) => {
-            log::info!("Invalid JWT attempted to access user information: {}", e);
-            return json_with_status(&json!({"error": "Unauthorized"}), StatusCode::UNAUTHORIZED);
+            log::error!("Error loading the chat history of {}: {}", room, e);
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
+    }
+}
+
+/// GET /presence?uuids= -> Online players and what they are doing, sorted by username
+///
+/// `uuids` is a comma separated list of players to look up, players that are offline are left
+/// out of the answer.
+#[get("/presence")]
+async fn presence(
+    _user: AuthenticatedUser,
+    chat_server: web::Data<Addr<ChatServer>>,
+    query: web::Query<PresenceQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let uuids = query.uuids.as_ref().map(|uuids| {
+        uuids
+            .split(',')
+            .map(str::trim)
+            .filter(|uuid| !uuid.is_empty())
+            .map(str::to_string)
+            .collect()
+    });
+
+    let players = chat_server
+        .send(GetPresence { uuids })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    json_with_status(&json!({ "players": players }), StatusCode::OK)
+}
+
+/// Most replays returned by a single GET /replays
+const MAX_REPLAYS_PAGE: i64 = 50;
+
+/// GET /replays?before=&limit= -> Replays the player may watch, newest first
+///
+/// Public replays are listed for everyone, private ones for the players of the match and for
+/// moderators. Pass `recorded_at` of the oldest replay received so far as `before` for the page
+/// before it. Replays are watched over the websocket, see `ClientMessage::WatchReplay`.
+#[get("/replays")]
+async fn replays(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+    query: web::Query<ReplaysQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_REPLAYS_PAGE)
+        .clamp(1, MAX_REPLAYS_PAGE);
+    let everything = user.authority_level >= Authority::Moderator;
+
+    match db
+        .replays_visible_to(&user.uuid, everything, query.before, limit)
+        .await
+    {
+        Ok(replays) => json_with_status(&json!({ "replays": replays }), StatusCode::OK),
+        Err(e) => {
+            log::error!("Error loading the replays of {}: {}", user.username, e);
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
+    }
+}
+
+/// GET /replays/{match_id} -> The replay of a match
+///
+/// Returns a 404 status code for private replays of other players as well, their matches are
+/// nobody else's business.
+#[get("/replays/{match_id}")]
+async fn replay(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+    match_id: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let replay = db.replay(&match_id).await.map_err(|e| {
+        log::error!("Error loading the replay of {}: {}", match_id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    match replay.filter(|replay| replay.visible_to(&user.uuid, user.authority_level)) {
+        Some(replay) => json_with_status(&json!(replay), StatusCode::OK),
+        None => json_with_status(&json!({"error": "Replay not found"}), StatusCode::NOT_FOUND),
+    }
+}
+
+/// Parses the body of a POST /moderation/* route and looks up the user it is aimed at.
+///
+/// Users can only be moderated by someone of a higher authority: moderators can't act on other
+/// moderators, and nobody can act on an admin. The error is the response to send instead.
+async fn moderation_target(
+    moderator: &Claims,
+    db: &ArcDb,
+    body: &[u8],
+) -> Result<(ModerationRequest, ModerationTarget), HttpResponse> {
+    let request = serde_json::from_slice::<ModerationRequest>(body)
+        .map_err(|e| HttpResponse::BadRequest().json(json!({"error": e.to_string()})))?;
+
+    let target = match db.moderation_target(&request.username).await {
+        Ok(Some(target)) => target,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(json!({"error": "User not found"})));
         }
+        Err(e) => {
+            log::error!("Error looking up {}: {}", request.username, e);
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    if target.authority >= moderator.authority_level {
+        log::info!(
+            "{} ({}) attempted to moderate {} ({})",
+            moderator.username,
+            moderator.authority_level,
+            target.username,
+            target.authority
+        );
+        return Err(HttpResponse::Forbidden().json(json!({"error": "Forbidden"})));
+    }
+
+    Ok((request, target))
+}
+
+/// Records a moderation action in the audit table
+async fn audit(
+    db: &ArcDb,
+    action: ModerationAction,
+    moderator: &Claims,
+    target: &ModerationTarget,
+    reason: Option<&str>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), actix_web::Error> {
+    log::info!(
+        "{} performed {} on {}",
+        moderator.username,
+        action,
+        target.username
+    );
+
+    db.record_moderation_action(&NewModerationAction {
+        action,
+        moderator: Some((&moderator.uuid, &moderator.username)),
+        target_uuid: &target.uuid,
+        target_username: &target.username,
+        reason,
+        expires_at,
+    })
+    .await
+    .map_err(|e| {
+        log::error!("Error recording a moderation action: {}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })
+}
+
+/// POST /moderation/mute -> Mute a user for `duration_seconds`, moderators only
+#[post("/moderation/mute")]
+async fn mute(
+    moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (request, target) = match moderation_target(&moderator, &db, &body).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
     };
 
+    let Some(duration) = request
+        .duration_seconds
+        .filter(|seconds| *seconds > 0)
+        .and_then(chrono::TimeDelta::try_seconds)
+    else {
+        return json_with_status(
+            &json!({"error": "duration_seconds must be a positive number"}),
+            StatusCode::BAD_REQUEST,
+        );
+    };
+    let until = chrono::Utc::now() + duration;
+
+    db.set_muted_until(&target.uuid, Some(until))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    chat_server.do_send(Mute {
+        uuid: target.uuid.clone(),
+        until: Some(until),
+    });
+
+    let reason = request.reason.as_deref();
+    audit(
+        &db,
+        ModerationAction::Mute,
+        &moderator,
+        &target,
+        reason,
+        Some(until),
+    )
+    .await?;
+
+    json_with_status(&json!({"muted_until": until}), StatusCode::OK)
+}
+
+/// POST /moderation/unmute -> Lift the mute of a user, moderators only
+#[post("/moderation/unmute")]
+async fn unmute(
+    moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (request, target) = match moderation_target(&moderator, &db, &body).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    db.set_muted_until(&target.uuid, None)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    chat_server.do_send(Mute {
+        uuid: target.uuid.clone(),
+        until: None,
+    });
+
+    let reason = request.reason.as_deref();
+    audit(
+        &db,
+        ModerationAction::Unmute,
+        &moderator,
+        &target,
+        reason,
+        None,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /moderation/kick -> Disconnect every websocket session of a user, moderators only
+///
+/// Returns how many sessions were kicked, the user can connect again right away.
+#[post("/moderation/kick")]
+async fn kick(
+    moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (request, target) = match moderation_target(&moderator, &db, &body).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let sessions = chat_server
+        .send(Kick {
+            uuid: target.uuid.clone(),
+            reason: request.reason.clone(),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let reason = request.reason.as_deref();
+    audit(
+        &db,
+        ModerationAction::Kick,
+        &moderator,
+        &target,
+        reason,
+        None,
+    )
+    .await?;
+
+    json_with_status(&json!({"sessions": sessions}), StatusCode::OK)
+}
+
+/// POST /moderation/ban -> Ban a user, moderators only
+///
+/// Banned users can't log in, their tokens are revoked and their sessions kicked.
+#[post("/moderation/ban")]
+async fn ban(
+    moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (request, target) = match moderation_target(&moderator, &db, &body).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    db.set_banned(&target.uuid, true)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    chat_server.do_send(Kick {
+        uuid: target.uuid.clone(),
+        reason: Some("Banned".to_string()),
+    });
+
+    let reason = request.reason.as_deref();
+    audit(
+        &db,
+        ModerationAction::Ban,
+        &moderator,
+        &target,
+        reason,
+        None,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// POST /moderation/unban -> Lift the ban of a user, moderators only
+#[post("/moderation/unban")]
+async fn unban(
+    moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    body: web::Bytes,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (request, target) = match moderation_target(&moderator, &db, &body).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    db.set_banned(&target.uuid, false)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let reason = request.reason.as_deref();
+    audit(
+        &db,
+        ModerationAction::Unban,
+        &moderator,
+        &target,
+        reason,
+        None,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Most entries returned by a single GET /moderation/audit
+const MAX_AUDIT_PAGE: i64 = 100;
+
+/// GET /moderation/audit?username=&limit= -> The latest moderation actions, newest first
+#[get("/moderation/audit")]
+async fn moderation_audit(
+    _moderator: RequireAuthority<Moderator>,
+    db: web::Data<ArcDb>,
+    query: web::Query<ModerationLogQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_AUDIT_PAGE)
+        .clamp(1, MAX_AUDIT_PAGE);
+
+    match db.moderation_log(query.username.as_deref(), limit).await {
+        Ok(actions) => json_with_status(&json!(actions), StatusCode::OK),
+        Err(e) => {
+            log::error!("Error loading the moderation log: {}", e);
+            Err(actix_web::error::ErrorInternalServerError(e))
+        }
+    }
+}
+
+/// Creates the warp filter for the GET /players/player endpoint.
+///
+/// This filter extracts the authorization header, decodes it into claims, and retrieves player
+/// information based on those claims from the database.
+///
+/// The player's rating in every mode they played a rated match of is included.
+///
+/// Returns a 401 status code if the JWT is invalid or expired.
+/// Returns a 404 status code if the user cannot be found in the database.
+#[get("/players/player")]
+async fn player_info(
+    user: AuthenticatedUser,
+    db: web::Data<ArcDb>,
+) -> Result<HttpResponse, actix_web::Error> {
     let query_result = db
-        .get_details_by_login_method(&LoginMethod::Email(email))
+        .get_details_by_login_method(&LoginMethod::Email(user.sub.clone()))
         .await;
 
     match query_result {
         Ok(user_record) => {
             // Happy path: Found the UserRecord and removed the password.
             let public_user_record: PublicUserRecord = user_record.into();
-            json_with_status(&json!(public_user_record), StatusCode::OK)
+            let ratings = db.ratings(&public_user_record.uuid).await.map_err(|e| {
+                log::error!("Error loading the ratings of {}: {}", user.uuid, e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?;
+
+            let details = PlayerDetails {
+                user: public_user_record,
+                ratings,
+            };
+            json_with_status(&json!(details), StatusCode::OK)
         }
         Err(_) => {
             // Could not find a user for this email in the database. Should not happen unless the
             // token contains a faked email.
-            log::info!("Could not find user with UUID: {}", uuid);
+            log::info!("Could not find user with UUID: {}", user.uuid);
             json_with_status(&json!({"error": "User not found"}), StatusCode::NOT_FOUND)
         }
     }
 }
 
-/// Retrieves player information based on the provided JWT in the header.
-///
-/// Returns a 401 status code if the JWT is invalid or expired.
-/// Returns a 404 status code if the user cannot be found in the database.
-
 /// Constructs a JSON response with a specific status code.
 #[inline(always)]
 fn json_with_status(
@@ -5,11 +5,37 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use warp::reject::Reject;
 
+use crate::game::world::Outcome;
+use crate::matches::matchmaking::GameMode;
+
 #[derive(Debug)]
 pub struct DatabaseError(pub sqlx::Error);
 
 impl Reject for DatabaseError {}
 
+/// Level of authorization a user has, ordered from least to most privileged
+#[derive(
+    Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum Authority {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl fmt::Display for Authority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Authority::User => write!(f, "user"),
+            Authority::Moderator => write!(f, "moderator"),
+            Authority::Admin => write!(f, "admin"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct User {
     pub id: Option<i32>,
@@ -18,7 +44,7 @@ pub struct User {
     pub password: String,
     pub creation_date: Option<chrono::NaiveDateTime>,
     pub uuid: Option<String>,
-    pub authority: Option<String>,
+    pub authority: Option<Authority>,
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
@@ -57,7 +83,8 @@ pub struct UserRecord {
     pub password: String,
     pub username: String,
     pub uuid: String,
-    pub authority: String,
+    pub authority: Authority,
+    pub token_generation: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -65,7 +92,7 @@ pub struct PublicUserRecord {
     pub email: String,
     pub username: String,
     pub uuid: String,
-    pub authority: String,
+    pub authority: Authority,
 }
 
 impl From<UserRecord> for PublicUserRecord {
@@ -79,6 +106,193 @@ impl From<UserRecord> for PublicUserRecord {
     }
 }
 
+/// Body of GET /players/player
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlayerDetails {
+    #[serde(flatten)]
+    pub user: PublicUserRecord,
+
+    /// The rating of the player in every mode they played a rated match of
+    pub ratings: Vec<RatingRecord>,
+}
+
+/// Body of POST /users/authority
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthorityChange {
+    pub username: String,
+    pub authority: Authority,
+}
+
+/// The pair of tokens handed out on login and on every refresh
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Body of POST /auth/logout, the refresh token is optional so clients that lost it can still log out
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+/// A row of the `refresh_tokens` table, minus the hash we looked it up by
+#[derive(Debug)]
+pub struct RefreshTokenRecord {
+    pub family_id: String,
+    pub uuid: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used: bool,
+    pub revoked: bool,
+}
+
+/// A row of the `chat_messages` table
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatMessageRecord {
+    pub id: i64,
+    pub room: String,
+    pub uuid: String,
+    pub username: String,
+    pub text: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A row of the `direct_messages` table
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirectMessageRecord {
+    pub id: i64,
+    pub sender_uuid: String,
+    pub sender_username: String,
+    pub recipient_uuid: String,
+    pub recipient_username: String,
+    pub text: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What a moderator (or the word filter) did to a user
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum ModerationAction {
+    Mute,
+    Unmute,
+    Kick,
+    Ban,
+    Unban,
+
+    /// A chat message was masked or rejected by the word filter
+    Filter,
+}
+
+impl fmt::Display for ModerationAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationAction::Mute => write!(f, "mute"),
+            ModerationAction::Unmute => write!(f, "unmute"),
+            ModerationAction::Kick => write!(f, "kick"),
+            ModerationAction::Ban => write!(f, "ban"),
+            ModerationAction::Unban => write!(f, "unban"),
+            ModerationAction::Filter => write!(f, "filter"),
+        }
+    }
+}
+
+/// Body of the POST /moderation/* routes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModerationRequest {
+    pub username: String,
+    pub reason: Option<String>,
+
+    /// How long a mute lasts, required for POST /moderation/mute
+    pub duration_seconds: Option<i64>,
+}
+
+/// A row of the `moderation_actions` table, the moderator is empty for actions of the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModerationRecord {
+    pub id: i64,
+    pub action: ModerationAction,
+    pub moderator_uuid: Option<String>,
+    pub moderator_username: Option<String>,
+    pub target_uuid: String,
+    pub target_username: String,
+    pub reason: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A row of the `replays` table, the replay itself is a file in `MatchSettings::replays`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayRecord {
+    pub match_id: String,
+
+    /// Id of the first level of the match
+    pub level: String,
+
+    /// Uuids of everyone who played in the match
+    pub players: Vec<String>,
+
+    /// Private replays are only listed for the players of the match and for moderators
+    pub public: bool,
+
+    /// Empty if the match was stopped before it had an outcome
+    pub outcome: Option<Outcome>,
+
+    /// Length of the match in ticks of `game::TICK_RATE`
+    pub ticks: i64,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ReplayRecord {
+    /// Whether the player `uuid` may list and watch the replay
+    pub fn visible_to(&self, uuid: &str, authority: Authority) -> bool {
+        self.public || authority >= Authority::Moderator || self.players.iter().any(|p| p == uuid)
+    }
+}
+
+/// Query of GET /moderation/audit
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModerationLogQuery {
+    /// Only actions against this user
+    pub username: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Query of GET /ws
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebsocketQuery {
+    /// Resume token of a session whose connection dropped
+    pub resume: Option<String>,
+}
+
+/// Query of GET /presence
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PresenceQuery {
+    /// Comma separated uuids, every online player if not set
+    pub uuids: Option<String>,
+}
+
+/// Query of GET /chat/{room}/history
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatHistoryQuery {
+    /// Only messages sent before this moment, the latest messages if not set
+    pub before: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<i64>,
+}
+
+/// Query of GET /replays
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReplaysQuery {
+    /// Only replays recorded before this moment, the latest replays if not set
+    pub before: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<i64>,
+}
+
 #[derive(Error, Debug)]
 pub enum SignupError {
     #[error("Username already in use")]
@@ -107,6 +321,9 @@ pub enum LoginError {
     #[error("The password entered was incorrect")]
     InvalidPassword,
 
+    #[error("This account is banned")]
+    Banned,
+
     #[error("Failed to hash the password: {0}")]
     PasswordHashingError(String),
 
@@ -122,3 +339,50 @@ pub enum LoginError {
     #[error(transparent)]
     SqlError(#[from] sqlx::Error),
 }
+
+#[derive(Error, Debug)]
+pub enum RefreshError {
+    #[error("Refresh token is not known")]
+    Invalid,
+
+    #[error("Refresh token is expired")]
+    Expired,
+
+    #[error("Refresh token has been revoked")]
+    Revoked,
+
+    #[error("Refresh token was already used, the token family has been revoked")]
+    Reused,
+
+    #[error("Failed to create a jwt: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+
+    #[error(transparent)]
+    SqlError(#[from] sqlx::Error),
+}
+
+/// A row of the `ratings` table, the Glicko-2 rating of a player in a mode, see
+/// `matches::rating`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RatingRecord {
+    pub mode: GameMode,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games_played: i32,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A row of the `rating_history` table, the rating a match left a player with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RatingHistoryRecord {
+    pub match_id: String,
+    pub mode: GameMode,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+
+    /// How much the rating went up or down in the match
+    pub rating_change: f64,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+}
@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+
+use crate::configuration::{ChatSettings, FilterMode};
+
+/// Outcome of running a chat message through the `WordFilter`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filtered {
+    /// No filtered words, the text is unchanged
+    Clean(String),
+
+    /// Filtered words were replaced with asterisks
+    Masked(String),
+
+    /// The message contains a filtered word and must not be sent
+    Rejected,
+}
+
+/// Masks or rejects chat messages containing words from the `[chat]` settings.
+///
+/// Words are matched as a whole and ignoring case, "heck" matches "HECK!" but not "checkers".
+#[derive(Debug, Clone)]
+pub struct WordFilter {
+    words: HashSet<String>,
+    mode: FilterMode,
+}
+
+impl WordFilter {
+    pub fn new(settings: &ChatSettings) -> Self {
+        Self {
+            words: settings
+                .filtered_words
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect(),
+            mode: settings.filter_mode,
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> Filtered {
+        let mut output = String::with_capacity(text.len());
+        let mut matched = false;
+        let mut word = String::new();
+
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+
+            if !word.is_empty() {
+                if self.words.contains(&word.to_lowercase()) {
+                    matched = true;
+                    output.extend(word.chars().map(|_| '*'));
+                } else {
+                    output.push_str(&word);
+                }
+                word.clear();
+            }
+            output.push(c);
+        }
+
+        // Drop the space that was chained on to flush the last word
+        output.pop();
+
+        match (matched, self.mode) {
+            (false, _) => Filtered::Clean(output),
+            (true, FilterMode::Mask) => Filtered::Masked(output),
+            (true, FilterMode::Reject) => Filtered::Rejected,
+        }
+    }
+}
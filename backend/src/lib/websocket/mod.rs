@@ -0,0 +1,595 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+use actix_web_actors::ws;
+use chrono::{DateTime, Utc};
+
+use crate::claims::Claims;
+use crate::game::input::SequencedInput;
+use crate::matches::manager::{
+    AcknowledgeSnapshot, AnswerMatch, CreateMatch, JoinMatch, JoinQueue, LeaveMatch, LeaveQueue,
+    MatchManager, SessionResumed, SpectateMatch, SubmitInput, WatchReplay,
+};
+use crate::matches::playback::{ReplayPlayback, SeekPlayback, SetPlaybackSpeed, StopPlayback};
+use crate::matches::MatchError;
+
+pub mod filter;
+pub mod presence;
+pub mod protocol;
+pub mod rate_limit;
+pub mod room;
+pub mod server;
+
+use protocol::{
+    ClientMessage, Encoding, ErrorCode, Frame, GameEvent, GameState, ProtocolError, ServerMessage,
+};
+use rate_limit::{FloodGuard, Verdict};
+use server::{
+    ChatServer, ClientChat, ClientWhisper, Connect, Deliver, Disconnect, JoinRoom, LeaveRoom,
+    ListMembers, SetActivity, SubscribePresence, UnsubscribePresence,
+};
+
+/// How often heartbeat pings are sent
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long before lack of client response causes a timeout
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Subprotocol the server selects during the handshake.
+///
+/// Browsers offer it next to the `bearer.<jwt>` entry, a server that doesn't echo one of the
+/// offered protocols back makes the browser abort the connection.
+pub const WS_PROTOCOL: &str = "starblazers";
+
+/// Subprotocol of clients that want game traffic as MessagePack, see `protocol::Encoding`
+pub const WS_BINARY_PROTOCOL: &str = "starblazers.msgpack";
+
+/// websocket connection is long running connection, it easier
+/// to handle with an actor
+pub struct MyWebSocket {
+    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
+    /// otherwise we drop connection.
+    hb: Instant,
+
+    /// The decoded JWT the session was opened with
+    claims: Claims,
+
+    /// Id assigned by the chat server once the session is registered
+    id: usize,
+
+    /// The chat server this session relays messages through
+    server: Addr<ChatServer>,
+
+    /// Hosts the matches, match requests and inputs go here
+    matches: Addr<MatchManager>,
+
+    /// The replay the client watches
+    playback: Option<Addr<ReplayPlayback>>,
+
+    /// Last state the client reported for its game
+    game_state: Option<GameState>,
+
+    /// Until when the player was muted when the session started, handed to the chat server
+    muted_until: Option<DateTime<Utc>>,
+
+    /// Rate limits every message the client sends
+    flood_guard: FloodGuard,
+
+    /// How game traffic is encoded, negotiated during the handshake
+    encoding: Encoding,
+
+    /// Token of the session the client wants to resume, see `server::Connect`
+    resume_token: Option<String>,
+
+    /// Whether the session is kept for the client to resume when this connection stops. Unset
+    /// when the client closed the connection itself or was thrown out.
+    resumable: bool,
+}
+
+impl MyWebSocket {
+    pub fn new(
+        claims: Claims,
+        server: Addr<ChatServer>,
+        matches: Addr<MatchManager>,
+        muted_until: Option<DateTime<Utc>>,
+        flood_guard: FloodGuard,
+        encoding: Encoding,
+        resume_token: Option<String>,
+    ) -> Self {
+        Self {
+            hb: Instant::now(),
+            claims,
+            id: 0,
+            server,
+            matches,
+            playback: None,
+            game_state: None,
+            muted_until,
+            flood_guard,
+            encoding,
+            resume_token,
+            resumable: true,
+        }
+    }
+
+    /// The decoded JWT of the player on the other end of this session
+    pub fn claims(&self) -> &Claims {
+        &self.claims
+    }
+
+    /// Serializes a `ServerMessage` in the session's encoding and sends it to the client
+    fn send(&self, message: &ServerMessage, ctx: &mut <Self as Actor>::Context) {
+        match protocol::encode_frame(message, self.encoding) {
+            Ok(Frame::Text(text)) => ctx.text(text),
+            Ok(Frame::Binary(bytes)) => ctx.binary(bytes),
+            Err(e) => log::error!("Failed to serialize {:?}: {}", message, e),
+        }
+    }
+
+    /// Runs a frame past the flood guard, returns whether it should be handled
+    fn admit(&mut self, ctx: &mut <Self as Actor>::Context) -> bool {
+        let verdict = self.flood_guard.check(Instant::now());
+        self.enforce(verdict, ctx)
+    }
+
+    /// Like `admit`, for match inputs
+    fn admit_input(&mut self, ctx: &mut <Self as Actor>::Context) -> bool {
+        let verdict = self.flood_guard.check_input(Instant::now());
+        self.enforce(verdict, ctx)
+    }
+
+    /// Acts on the flood guard's verdict, returns whether the frame should be handled
+    fn enforce(&mut self, verdict: Verdict, ctx: &mut <Self as Actor>::Context) -> bool {
+        match verdict {
+            Verdict::Allow => true,
+            Verdict::Warn => {
+                self.send(
+                    &ServerMessage::error(ErrorCode::RateLimited, "Slow down"),
+                    ctx,
+                );
+                false
+            }
+            Verdict::Drop { first } => {
+                if first {
+                    log::info!("Throttling {} ({})", self.claims.username, self.id);
+                    self.send(
+                        &ServerMessage::error(
+                            ErrorCode::RateLimited,
+                            "Too many messages, they are dropped for a while",
+                        ),
+                        ctx,
+                    );
+                }
+                false
+            }
+            Verdict::Disconnect => {
+                log::info!("Disconnecting {} for flooding", self.claims.username);
+                self.resumable = false;
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Flooding".to_string()),
+                }));
+                ctx.stop();
+                false
+            }
+        }
+    }
+
+    /// Rejects chat messages and whispers that are empty or too long, returns the trimmed text
+    fn check_text<'a>(&self, text: &'a str, ctx: &mut <Self as Actor>::Context) -> Option<&'a str> {
+        let text = text.trim();
+
+        let problem = if text.is_empty() {
+            "Message is empty"
+        } else if text.chars().count() > self.flood_guard.max_message_length() {
+            "Message is too long"
+        } else {
+            return Some(text);
+        };
+
+        self.send(&ServerMessage::error(ErrorCode::Rejected, problem), ctx);
+        None
+    }
+
+    /// Waits for the match manager to answer a request, the client is told if it was refused
+    fn await_match_request<T: 'static>(
+        &self,
+        request: impl Future<Output = Result<Result<T, MatchError>, MailboxError>> + 'static,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        request
+            .into_actor(self)
+            .map(|res, act, ctx| match res {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => act.send(&e.to_server_message(), ctx),
+                Err(e) => log::error!("The match manager is unavailable: {}", e),
+            })
+            .spawn(ctx);
+    }
+
+    /// The playback of the replay the client watches, the client is told if there is none
+    fn playback(&mut self, ctx: &mut <Self as Actor>::Context) -> Option<Addr<ReplayPlayback>> {
+        // A playback stops by itself if the replay turns out to be broken
+        self.playback = self.playback.take().filter(Addr::connected);
+        if self.playback.is_none() {
+            self.send(&MatchError::NotWatching.to_server_message(), ctx);
+        }
+        self.playback.clone()
+    }
+
+    fn stop_playback(&mut self) {
+        if let Some(playback) = self.playback.take() {
+            playback.do_send(StopPlayback);
+        }
+    }
+
+    /// Runs a decoded frame past the flood guard and handles it
+    fn receive(
+        &mut self,
+        decoded: Result<ClientMessage, ProtocolError>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        match decoded {
+            // Inputs and acknowledgements come every tick, they have a limit of their own
+            Ok(message @ (ClientMessage::Input { .. } | ClientMessage::AckSnapshot { .. })) => {
+                if self.admit_input(ctx) {
+                    self.handle_client_message(message, ctx);
+                }
+            }
+            Ok(message) => {
+                if self.admit(ctx) {
+                    self.handle_client_message(message, ctx);
+                }
+            }
+            Err(e) => {
+                if self.admit(ctx) {
+                    log::info!("Malformed message from {}: {}", self.claims.username, e);
+                    self.send(&e.to_server_message(), ctx);
+                }
+            }
+        }
+    }
+
+    /// Handles a message the client sent
+    fn handle_client_message(
+        &mut self,
+        message: ClientMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        match message {
+            ClientMessage::Chat { room, text } => {
+                let Some(text) = self.check_text(&text, ctx) else {
+                    return;
+                };
+
+                self.server.do_send(ClientChat {
+                    id: self.id,
+                    room,
+                    text: text.to_string(),
+                });
+            }
+            ClientMessage::Whisper { to, text } => {
+                let Some(text) = self.check_text(&text, ctx) else {
+                    return;
+                };
+
+                self.server.do_send(ClientWhisper {
+                    id: self.id,
+                    to,
+                    text: text.to_string(),
+                });
+            }
+            ClientMessage::JoinRoom { room } => self.server.do_send(JoinRoom { id: self.id, room }),
+            ClientMessage::LeaveRoom { room } => {
+                self.server.do_send(LeaveRoom { id: self.id, room })
+            }
+            ClientMessage::ListMembers { room } => {
+                self.server.do_send(ListMembers { id: self.id, room })
+            }
+            ClientMessage::SubscribePresence => {
+                self.server.do_send(SubscribePresence { id: self.id })
+            }
+            ClientMessage::UnsubscribePresence => {
+                self.server.do_send(UnsubscribePresence { id: self.id })
+            }
+            ClientMessage::CreateMatch { private } => {
+                let request = self.matches.send(CreateMatch {
+                    uuid: self.claims.uuid.clone(),
+                    username: self.claims.username.clone(),
+                    public: !private,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::JoinMatch { match_id } => {
+                let request = self.matches.send(JoinMatch {
+                    uuid: self.claims.uuid.clone(),
+                    username: self.claims.username.clone(),
+                    match_id,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::SpectateMatch { match_id } => {
+                let request = self.matches.send(SpectateMatch {
+                    uuid: self.claims.uuid.clone(),
+                    authority: self.claims.authority_level,
+                    match_id,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::LeaveMatch => {
+                let request = self.matches.send(LeaveMatch {
+                    uuid: self.claims.uuid.clone(),
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::JoinQueue { mode, region } => {
+                let request = self.matches.send(JoinQueue {
+                    uuid: self.claims.uuid.clone(),
+                    username: self.claims.username.clone(),
+                    mode,
+                    region,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::LeaveQueue => {
+                let request = self.matches.send(LeaveQueue {
+                    uuid: self.claims.uuid.clone(),
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::AcceptMatch { match_id } => {
+                let request = self.matches.send(AnswerMatch {
+                    uuid: self.claims.uuid.clone(),
+                    match_id,
+                    accept: true,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::DeclineMatch { match_id } => {
+                let request = self.matches.send(AnswerMatch {
+                    uuid: self.claims.uuid.clone(),
+                    match_id,
+                    accept: false,
+                });
+                self.await_match_request(request, ctx);
+            }
+            ClientMessage::Input {
+                sequence,
+                client_tick,
+                input,
+            } => self.matches.do_send(SubmitInput {
+                uuid: self.claims.uuid.clone(),
+                input: SequencedInput {
+                    sequence,
+                    client_tick,
+                    input,
+                },
+            }),
+            ClientMessage::AckSnapshot { tick } => self.matches.do_send(AcknowledgeSnapshot {
+                uuid: self.claims.uuid.clone(),
+                session: self.id,
+                tick,
+            }),
+            ClientMessage::WatchReplay { match_id } => {
+                self.stop_playback();
+                self.matches
+                    .send(WatchReplay {
+                        uuid: self.claims.uuid.clone(),
+                        authority: self.claims.authority_level,
+                        match_id,
+                        session: ctx.address().recipient(),
+                    })
+                    .into_actor(self)
+                    .map(|res, act, ctx| match res {
+                        Ok(Ok(playback)) => {
+                            // Only the replay asked for last is watched
+                            act.stop_playback();
+                            act.playback = Some(playback);
+                        }
+                        Ok(Err(e)) => act.send(&e.to_server_message(), ctx),
+                        Err(e) => log::error!("The match manager is unavailable: {}", e),
+                    })
+                    .spawn(ctx);
+            }
+            ClientMessage::SetPlaybackSpeed { speed } => {
+                if let Some(playback) = self.playback(ctx) {
+                    playback.do_send(SetPlaybackSpeed(speed));
+                }
+            }
+            ClientMessage::SeekReplay { tick } => {
+                if let Some(playback) = self.playback(ctx) {
+                    playback.do_send(SeekPlayback(tick));
+                }
+            }
+            ClientMessage::StopReplay => {
+                if self.playback(ctx).is_some() {
+                    self.stop_playback();
+                }
+            }
+            ClientMessage::Ping { nonce } => self.send(&ServerMessage::Pong { nonce }, ctx),
+            ClientMessage::GameEvent { event } => match event {
+                GameEvent::StateChanged { state } => {
+                    if self.game_state != Some(state) {
+                        self.server.do_send(SetActivity {
+                            id: self.id,
+                            activity: state.into(),
+                        });
+                    }
+                    self.game_state = Some(state);
+                }
+            },
+        }
+    }
+
+    /// helper method that sends ping to client every 5 seconds (HEARTBEAT_INTERVAL).
+    ///
+    /// also this method checks heartbeats from client
+    fn hb(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            // check client heartbeats
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                // heartbeat timed out, the session can still be resumed
+                log::info!(
+                    "Heartbeat of {} ({}) failed, detaching the session for resume",
+                    act.claims.username,
+                    act.id
+                );
+
+                // stop actor
+                ctx.stop();
+
+                // don't try to send a ping
+                return;
+            }
+
+            ctx.ping(b"");
+        });
+    }
+}
+
+impl Actor for MyWebSocket {
+    type Context = ws::WebsocketContext<Self>;
+
+    /// Method is called on actor start. We start the heartbeat process here.
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+
+        log::info!(
+            "Websocket session started for {} ({})",
+            self.claims.username,
+            self.claims.uuid
+        );
+
+        let welcome = ServerMessage::Welcome {
+            username: self.claims.username.clone(),
+            uuid: self.claims.uuid.clone(),
+            authority: self.claims.authority_level,
+        };
+        self.send(&welcome, ctx);
+
+        // Wait for the id before handling any frames, chat messages need it
+        let resuming = self.resume_token.is_some();
+        self.server
+            .send(Connect {
+                addr: ctx.address().recipient(),
+                username: self.claims.username.clone(),
+                uuid: self.claims.uuid.clone(),
+                muted_until: self.muted_until,
+                resume_token: self.resume_token.take(),
+            })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(id) => {
+                        act.id = id;
+                        // This connection has none of the snapshots the session acknowledged
+                        if resuming {
+                            act.matches.do_send(SessionResumed {
+                                uuid: act.claims.uuid.clone(),
+                                session: id,
+                            });
+                        }
+                    }
+                    // Something is wrong with the chat server
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    /// Unregister from the chat server, this also runs when the heartbeat times out
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.stop_playback();
+        self.server.do_send(Disconnect {
+            id: self.id,
+            addr: ctx.address().recipient(),
+            resumable: self.resumable,
+        });
+        Running::Stop
+    }
+}
+
+/// Handler for messages the chat server routes to this session
+impl Handler<Deliver> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, ctx: &mut Self::Context) {
+        self.send(&msg.0, ctx);
+
+        if let ServerMessage::Kicked { reason } = msg.0 {
+            log::info!("Kicking {} ({})", self.claims.username, self.claims.uuid);
+            self.resumable = false;
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: reason,
+            }));
+            ctx.stop();
+        }
+    }
+}
+
+/// Handler for `ws::Message`
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        // process websocket messages
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                self.hb = Instant::now();
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.hb = Instant::now();
+            }
+            Ok(ws::Message::Text(text)) => self.receive(protocol::decode(&text), ctx),
+            Ok(ws::Message::Binary(bytes)) if self.encoding == Encoding::MessagePack => {
+                self.receive(protocol::decode_frame(&bytes), ctx)
+            }
+            Ok(ws::Message::Binary(_)) if !self.admit(ctx) => {}
+            Ok(ws::Message::Binary(_)) => self.send(
+                &ServerMessage::error(
+                    ErrorCode::MalformedMessage,
+                    "Binary frames need the starblazers.msgpack subprotocol",
+                ),
+                ctx,
+            ),
+            Err(ws::ProtocolError::Overflow) => {
+                log::info!("{} sent a frame that is too big", self.claims.username);
+                self.resumable = false;
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Size,
+                    description: Some(format!(
+                        "Frames are limited to {} bytes",
+                        self.flood_guard.max_frame_size()
+                    )),
+                }));
+                ctx.stop();
+            }
+            Ok(ws::Message::Close(reason)) => {
+                self.resumable = false;
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => ctx.stop(),
+        }
+    }
+}
+
+pub static INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html lang="en">
+    <head>
+        <title>Starblazers Sanity Check</title>
+    </head>
+
+    <br>
+
+    <body>
+    Welcome to StarBlazers index.html! <br>
+
+    The server is working I guess...<br>
+
+    Now what?
+    </body>
+</html>
+"#;
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::protocol::GameState;
+
+/// What a player is doing, ordered so that a player with several sessions shows the most
+/// interesting activity of them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    /// No sessions, only ever sent in a `PresenceChanged`
+    Offline,
+
+    /// Connected, but the client hasn't reported what it is doing yet
+    Online,
+
+    Menu,
+    Spectating,
+    InMatch,
+}
+
+impl From<GameState> for Activity {
+    /// A paused game is still a game in progress
+    fn from(state: GameState) -> Self {
+        match state {
+            GameState::Menu => Activity::Menu,
+            GameState::Run | GameState::Pause => Activity::InMatch,
+        }
+    }
+}
+
+/// A player and what they are doing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerPresence {
+    pub uuid: String,
+    pub username: String,
+    pub activity: Activity,
+}
+
+#[derive(Debug)]
+struct PlayerSessions {
+    username: String,
+    sessions: HashMap<usize, Activity>,
+
+    /// What the server knows the player is doing, like playing a match, whatever the clients say
+    server_activity: Option<Activity>,
+}
+
+impl PlayerSessions {
+    fn activity(&self) -> Activity {
+        let reported = self.sessions.values().copied().max();
+
+        match reported {
+            Some(reported) => reported.max(self.server_activity.unwrap_or(Activity::Online)),
+            None => Activity::Offline,
+        }
+    }
+}
+
+/// Who is online and what they are doing, built from the websocket sessions.
+///
+/// Every method that changes a session returns the player's new presence if what others see
+/// of the player changed, so the caller knows when to tell the subscribers.
+#[derive(Debug, Default)]
+pub struct PresenceRegistry {
+    players: HashMap<String, PlayerSessions>,
+}
+
+impl PresenceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(&mut self, id: usize, uuid: &str, username: &str) -> Option<PlayerPresence> {
+        self.update(uuid, username, |sessions| {
+            sessions.insert(id, Activity::Online);
+        })
+    }
+
+    pub fn set_activity(
+        &mut self,
+        id: usize,
+        uuid: &str,
+        activity: Activity,
+    ) -> Option<PlayerPresence> {
+        let username = self.players.get(uuid)?.username.clone();
+
+        self.update(uuid, &username, |sessions| {
+            if let Some(current) = sessions.get_mut(&id) {
+                *current = activity;
+            }
+        })
+    }
+
+    /// Sets or clears the activity the server imposes on all sessions of an online player
+    pub fn set_server_activity(
+        &mut self,
+        uuid: &str,
+        activity: Option<Activity>,
+    ) -> Option<PlayerPresence> {
+        let player = self.players.get_mut(uuid)?;
+
+        let before = player.activity();
+        player.server_activity = activity;
+        let after = player.activity();
+
+        (before != after).then(|| PlayerPresence {
+            uuid: uuid.to_string(),
+            username: player.username.clone(),
+            activity: after,
+        })
+    }
+
+    pub fn disconnect(&mut self, id: usize, uuid: &str) -> Option<PlayerPresence> {
+        let username = self.players.get(uuid)?.username.clone();
+
+        let change = self.update(uuid, &username, |sessions| {
+            sessions.remove(&id);
+        });
+
+        if self
+            .players
+            .get(uuid)
+            .is_some_and(|player| player.sessions.is_empty())
+        {
+            self.players.remove(uuid);
+        }
+
+        change
+    }
+
+    /// The presence of a player, `None` if they are offline
+    pub fn get(&self, uuid: &str) -> Option<PlayerPresence> {
+        self.players.get(uuid).map(|player| PlayerPresence {
+            uuid: uuid.to_string(),
+            username: player.username.clone(),
+            activity: player.activity(),
+        })
+    }
+
+    /// Every online player, sorted by username
+    pub fn all(&self) -> Vec<PlayerPresence> {
+        let mut players: Vec<_> = self
+            .players
+            .keys()
+            .filter_map(|uuid| self.get(uuid))
+            .collect();
+        players.sort_by(|a, b| a.username.cmp(&b.username));
+
+        players
+    }
+
+    fn update(
+        &mut self,
+        uuid: &str,
+        username: &str,
+        change: impl FnOnce(&mut HashMap<usize, Activity>),
+    ) -> Option<PlayerPresence> {
+        let player = self
+            .players
+            .entry(uuid.to_string())
+            .or_insert_with(|| PlayerSessions {
+                username: username.to_string(),
+                sessions: HashMap::new(),
+                server_activity: None,
+            });
+
+        let before = player.activity();
+        change(&mut player.sessions);
+        let after = player.activity();
+
+        (before != after).then(|| PlayerPresence {
+            uuid: uuid.to_string(),
+            username: player.username.clone(),
+            activity: after,
+        })
+    }
+}
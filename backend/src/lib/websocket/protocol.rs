@@ -0,0 +1,600 @@
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::game::boss::BossHealth;
+use crate::game::director::StageStarted;
+use crate::game::entity::PlayerInput;
+use crate::game::snapshot::WorldDelta;
+use crate::game::world::{Outcome, World};
+use crate::matches::matchmaking::GameMode;
+use crate::matches::playback::PlaybackSpeed;
+use crate::types::{Authority, ChatMessageRecord, DirectMessageRecord};
+
+use super::presence::PlayerPresence;
+use super::room::GLOBAL_ROOM;
+use super::{WS_BINARY_PROTOCOL, WS_PROTOCOL};
+
+/// Version of the websocket protocol, bump it on every breaking change to the messages below
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Every frame on the websocket is a message wrapped in this envelope.
+///
+/// On the wire the message's fields sit next to `protocol_version`:
+/// `{"protocol_version": 1, "type": "chat", "text": "hi"}`. Binary frames carry the same map
+/// encoded as MessagePack.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope<T> {
+    pub protocol_version: u32,
+
+    #[serde(flatten)]
+    pub message: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(message: T) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
+/// Messages a client sends to the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Say something in chat, in the global room unless another room is given
+    Chat {
+        #[serde(default = "global_room")]
+        room: String,
+        text: String,
+    },
+
+    /// Send a private message to a player, stored until they connect if they are offline
+    Whisper { to: String, text: String },
+
+    /// Join a party room, it is created if it doesn't exist yet
+    JoinRoom { room: String },
+
+    /// Leave a party room
+    LeaveRoom { room: String },
+
+    /// Ask for the members of a room the client is in
+    ListMembers { room: String },
+
+    /// Ask to be told whenever a player comes online, goes offline or starts doing something else
+    SubscribePresence,
+
+    /// Stop the presence changes
+    UnsubscribePresence,
+
+    /// Start a new match and join it. The replay of a private match is only listed for its
+    /// players, see `GET /replays`.
+    CreateMatch {
+        #[serde(default)]
+        private: bool,
+    },
+
+    /// Join a match that is already running
+    JoinMatch { match_id: String },
+
+    /// Watch a running match as a spectator. Spectators get the snapshots of the match,
+    /// possibly some seconds late, and chat in a room of their own.
+    SpectateMatch { match_id: String },
+
+    /// Leave the current match, or stop spectating it
+    LeaveMatch,
+
+    /// Wait for a match of `mode` with players of a similar rating in `region`. Sending it
+    /// again while waiting confirms the place in the queue, or offers the match that was found
+    /// once more.
+    JoinQueue { mode: GameMode, region: String },
+
+    /// Stop waiting for a match, this declines the match that was found if there is one
+    LeaveQueue,
+
+    /// Accept the match that was found, see `ServerMessage::MatchFound`
+    AcceptMatch { match_id: String },
+
+    /// Decline the match that was found, this leaves the queue as well
+    DeclineMatch { match_id: String },
+
+    /// The keys the player held during one tick of its game, see `game::input::SequencedInput`.
+    /// Sent every tick while in a match.
+    Input {
+        sequence: u32,
+        client_tick: u64,
+        input: PlayerInput,
+    },
+
+    /// The client has the snapshot of `tick`, either in full or by applying a delta. Later
+    /// snapshots come as deltas against it.
+    AckSnapshot { tick: u64 },
+
+    /// Watch the replay of a match, it is played back from the start at 1x. Watching another
+    /// replay stops the current one.
+    WatchReplay { match_id: String },
+
+    /// Change the speed of the replay the client watches
+    SetPlaybackSpeed { speed: PlaybackSpeed },
+
+    /// Jump to a tick of the replay the client watches
+    SeekReplay { tick: u64 },
+
+    /// Stop watching the replay
+    StopReplay,
+
+    /// Application level ping, answered with a `Pong` carrying the same nonce
+    Ping { nonce: Option<u64> },
+
+    /// Something happened in the client's game
+    GameEvent { event: GameEvent },
+}
+
+impl ClientMessage {
+    /// Messages about the client's match, the only ones that may come in binary frames
+    pub fn is_game_traffic(&self) -> bool {
+        matches!(
+            self,
+            ClientMessage::CreateMatch { .. }
+                | ClientMessage::JoinMatch { .. }
+                | ClientMessage::SpectateMatch { .. }
+                | ClientMessage::LeaveMatch
+                | ClientMessage::Input { .. }
+                | ClientMessage::AckSnapshot { .. }
+                | ClientMessage::WatchReplay { .. }
+                | ClientMessage::SetPlaybackSpeed { .. }
+                | ClientMessage::SeekReplay { .. }
+                | ClientMessage::StopReplay
+        )
+    }
+}
+
+/// Messages the server sends to a client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message of every session, tells the client who the server thinks it is
+    Welcome {
+        username: String,
+        uuid: String,
+        authority: Authority,
+    },
+
+    /// A chat message from a player, relayed by the `ChatServer`
+    Chat {
+        room: String,
+        username: String,
+        uuid: String,
+        text: String,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// A private message, sent to every session of both the sender and the recipient
+    Whisper {
+        from: Member,
+        to: Member,
+        text: String,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// The server registered the session, sent right after the `Welcome`.
+    ///
+    /// If the connection drops, reconnecting to `/ws?resume=<resume_token>` within
+    /// `resume_grace_seconds` gets the same session back.
+    SessionStarted {
+        resume_token: String,
+        resume_grace_seconds: u64,
+    },
+
+    /// The session was resumed, sent instead of `SessionStarted`.
+    ///
+    /// The `missed` messages that arrived while the client was gone follow right after. If
+    /// `truncated` is set even older ones were lost and the client should fetch what it needs.
+    /// The old token is spent, `resume_token` is the one for the next time.
+    SessionResumed {
+        resume_token: String,
+        missed: usize,
+        truncated: bool,
+    },
+
+    /// Another player joined a room the client is in, for `global` this means they connected
+    Joined {
+        room: String,
+        username: String,
+        uuid: String,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// A player left a room the client is in, for `global` this means they disconnected
+    Left {
+        room: String,
+        username: String,
+        uuid: String,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// The members of a room, sent when the client joins a room or asks for them
+    RoomMembers { room: String, members: Vec<Member> },
+
+    /// The latest messages of a room, sent after joining it.
+    ///
+    /// Older messages are available from `GET /chat/{room}/history`.
+    ChatHistory {
+        room: String,
+        messages: Vec<ChatMessageRecord>,
+    },
+
+    /// The server closed a room the client was in
+    RoomClosed { room: String },
+
+    /// Every online player and what they are doing, sent after `SubscribePresence`
+    Presence { players: Vec<PlayerPresence> },
+
+    /// A player came online, went offline or started doing something else
+    PresenceChanged(PlayerPresence),
+
+    /// The client waits for a match of `mode` in `region`, against players with a rating close
+    /// to its own `rating` in the mode
+    Queued {
+        mode: GameMode,
+        region: String,
+        rating: f64,
+    },
+
+    /// The client no longer waits for a match
+    LeftQueue,
+
+    /// A match was found for the client. It starts once every player accepted it within
+    /// `timeout_seconds`, until then the players can talk in `lobby`.
+    MatchFound {
+        match_id: String,
+        mode: GameMode,
+        region: String,
+        lobby: String,
+        players: Vec<Member>,
+        timeout_seconds: u64,
+    },
+
+    /// A player accepted the match that was found, the client itself included
+    MatchAccepted { match_id: String, uuid: String },
+
+    /// The match that was found fell through because a player declined it or didn't answer in
+    /// time. If `requeued` is set the client waits for the next one at the place it had.
+    MatchCancelled { match_id: String, requeued: bool },
+
+    /// A player joined the client's match, the client itself included
+    MatchJoined {
+        match_id: String,
+        uuid: String,
+        username: String,
+    },
+
+    /// A player left the client's match, the client itself included
+    MatchLeft {
+        match_id: String,
+        uuid: String,
+        username: String,
+    },
+
+    /// The client spectates a match, its snapshots are `delay_seconds` behind the players' and
+    /// the spectators chat in `room`
+    Spectating {
+        match_id: String,
+        room: String,
+        delay_seconds: u64,
+    },
+
+    /// The client stopped spectating a match, because it left or the match is over
+    StoppedSpectating { match_id: String },
+
+    /// The state of the client's match, sent every time the match ticks. `last_input` of every
+    /// player acknowledges the last of its inputs the state includes.
+    Snapshot { match_id: String, world: World },
+
+    /// The state of the client's match as a delta against a snapshot it acknowledged
+    SnapshotDelta { match_id: String, delta: WorldDelta },
+
+    /// A wave or the boss of a level started in the client's match
+    WaveStarted {
+        match_id: String,
+        #[serde(flatten)]
+        stage: StageStarted,
+    },
+
+    /// The health bar of a boss in the client's match changed, sent when the boss appears, on
+    /// every hit and once more with no health left when it is destroyed
+    BossHealth {
+        match_id: String,
+        #[serde(flatten)]
+        health: BossHealth,
+    },
+
+    /// The client's match is over
+    MatchEnded { match_id: String, outcome: Outcome },
+
+    /// Where the replay the client watches is, sent when the playback starts, changes speed,
+    /// seeks and when it reaches `final_tick` and pauses. The worlds of the replay come as
+    /// `Snapshot`s with the `match_id` of the replay.
+    Playback {
+        match_id: String,
+        tick: u64,
+        final_tick: u64,
+        speed: PlaybackSpeed,
+    },
+
+    /// A moderator muted the client until `until`, or unmuted it if `until` is empty
+    Muted { until: Option<DateTime<Utc>> },
+
+    /// A moderator kicked the client, the server closes the connection after this message
+    Kicked { reason: Option<String> },
+
+    /// A notice from the server itself, not from a player
+    System {
+        text: String,
+        timestamp: DateTime<Utc>,
+    },
+
+    /// The last client message could not be handled
+    Error { code: ErrorCode, message: String },
+
+    /// Answer to a `ClientMessage::Ping`
+    Pong { nonce: Option<u64> },
+
+    /// Something happened in a game the client takes part in
+    GameEvent { uuid: String, event: GameEvent },
+}
+
+/// A player in a room
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub username: String,
+    pub uuid: String,
+}
+
+impl From<DirectMessageRecord> for ServerMessage {
+    fn from(message: DirectMessageRecord) -> Self {
+        ServerMessage::Whisper {
+            from: Member {
+                username: message.sender_username,
+                uuid: message.sender_uuid,
+            },
+            to: Member {
+                username: message.recipient_username,
+                uuid: message.recipient_uuid,
+            },
+            text: message.text,
+            timestamp: message.sent_at,
+        }
+    }
+}
+
+fn global_room() -> String {
+    GLOBAL_ROOM.to_string()
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl ToString) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// Messages about the client's match, sent in binary frames to sessions that negotiated
+    /// `Encoding::MessagePack`
+    pub fn is_game_traffic(&self) -> bool {
+        matches!(
+            self,
+            ServerMessage::MatchJoined { .. }
+                | ServerMessage::MatchLeft { .. }
+                | ServerMessage::Spectating { .. }
+                | ServerMessage::StoppedSpectating { .. }
+                | ServerMessage::Snapshot { .. }
+                | ServerMessage::SnapshotDelta { .. }
+                | ServerMessage::WaveStarted { .. }
+                | ServerMessage::BossHealth { .. }
+                | ServerMessage::MatchEnded { .. }
+                | ServerMessage::Playback { .. }
+        )
+    }
+
+    /// Messages that are outdated as soon as the next one arrives, they are not kept for
+    /// sessions that wait to be resumed
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ServerMessage::Snapshot { .. } | ServerMessage::SnapshotDelta { .. }
+        )
+    }
+}
+
+/// Machine readable reason of a `ServerMessage::Error`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame is not a valid message
+    MalformedMessage,
+
+    /// The client speaks a different protocol version
+    UnsupportedVersion,
+
+    /// The message is valid, but not accepted in this context
+    Rejected,
+
+    /// The player the message was meant for doesn't exist
+    UserNotFound,
+
+    /// The client is muted and can't chat
+    Muted,
+
+    /// The client sends too many messages, see `rate_limit::FloodGuard`
+    RateLimited,
+
+    /// The match the client asked for doesn't exist (anymore)
+    MatchNotFound,
+
+    /// There is no replay of the match the client asked for, or it is private
+    ReplayNotFound,
+}
+
+/// What the client's game is currently doing, mirrors `GameState` in the frontend
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameState {
+    Menu,
+    Run,
+    Pause,
+}
+
+/// Events from and about a game
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GameEvent {
+    /// The client's game switched between menu, running and paused, this sets the player's
+    /// presence as well
+    StateChanged { state: GameState },
+}
+
+/// How game traffic is encoded on a session, picked with the subprotocol during the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// `WS_PROTOCOL`, every message is JSON in a text frame
+    #[default]
+    Json,
+
+    /// `WS_BINARY_PROTOCOL`, game traffic is MessagePack in binary frames. Chat and everything
+    /// else stays JSON.
+    MessagePack,
+}
+
+impl Encoding {
+    /// The subprotocols the server knows, in the order it prefers them
+    pub const SUBPROTOCOLS: [&'static str; 2] = [WS_PROTOCOL, WS_BINARY_PROTOCOL];
+
+    /// The encoding of the first subprotocol in the `Sec-WebSocket-Protocol` header that the
+    /// server knows, which is the one the handshake selects
+    pub fn negotiate(offered: &str) -> Option<Encoding> {
+        offered
+            .split(',')
+            .map(str::trim)
+            .find_map(|protocol| match protocol {
+                WS_PROTOCOL => Some(Encoding::Json),
+                WS_BINARY_PROTOCOL => Some(Encoding::MessagePack),
+                _ => None,
+            })
+    }
+}
+
+/// A websocket frame carrying a message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error("Failed to encode as JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Failed to encode as MessagePack: {0}")]
+    MessagePack(#[from] rmp_serde::encode::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ProtocolError {
+    #[error("Message is not valid: {0}")]
+    Malformed(#[from] serde_json::Error),
+
+    #[error("Binary message is not valid: {0}")]
+    MalformedBinary(#[from] rmp_serde::decode::Error),
+
+    #[error("Only match messages can be sent in binary frames, chat stays JSON")]
+    NotGameTraffic,
+
+    #[error("Protocol version {0} is not supported, the server speaks version {PROTOCOL_VERSION}")]
+    UnsupportedVersion(u32),
+}
+
+impl ProtocolError {
+    /// The error message sent back to the client
+    pub fn to_server_message(&self) -> ServerMessage {
+        let code = match self {
+            ProtocolError::Malformed(_)
+            | ProtocolError::MalformedBinary(_)
+            | ProtocolError::NotGameTraffic => ErrorCode::MalformedMessage,
+            ProtocolError::UnsupportedVersion(_) => ErrorCode::UnsupportedVersion,
+        };
+
+        ServerMessage::error(code, self)
+    }
+}
+
+#[derive(Deserialize)]
+struct Version {
+    protocol_version: u32,
+}
+
+fn check_version(version: Version) -> Result<(), ProtocolError> {
+    match version.protocol_version {
+        PROTOCOL_VERSION => Ok(()),
+        version => Err(ProtocolError::UnsupportedVersion(version)),
+    }
+}
+
+/// Serializes a message in its envelope as JSON
+pub fn encode_json<T: Serialize>(message: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&Envelope::new(message))
+}
+
+/// Parses a message in its envelope from JSON, checking the protocol version before the message
+/// itself
+pub fn decode_json<T: DeserializeOwned>(text: &str) -> Result<T, ProtocolError> {
+    check_version(serde_json::from_str(text)?)?;
+    Ok(serde_json::from_str::<Envelope<T>>(text)?.message)
+}
+
+/// Serializes a message in its envelope as MessagePack. Fields are kept by name, the tags and
+/// optional fields of the messages rely on it.
+pub fn encode_binary<T: Serialize>(message: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(&Envelope::new(message))
+}
+
+/// Parses a message in its envelope from MessagePack, checking the protocol version first
+pub fn decode_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ProtocolError> {
+    check_version(rmp_serde::from_slice(bytes)?)?;
+    Ok(rmp_serde::from_slice::<Envelope<T>>(bytes)?.message)
+}
+
+/// Serializes a server message in its envelope
+pub fn encode(message: &ServerMessage) -> Result<String, serde_json::Error> {
+    encode_json(message)
+}
+
+/// Serializes a server message for a session, game traffic goes out in binary frames if the
+/// session negotiated `Encoding::MessagePack`
+pub fn encode_frame(message: &ServerMessage, encoding: Encoding) -> Result<Frame, EncodeError> {
+    if encoding == Encoding::MessagePack && message.is_game_traffic() {
+        return Ok(Frame::Binary(encode_binary(message)?));
+    }
+
+    Ok(Frame::Text(encode_json(message)?))
+}
+
+/// Parses a text frame from a client, checking the protocol version before the message itself
+pub fn decode(text: &str) -> Result<ClientMessage, ProtocolError> {
+    decode_json(text)
+}
+
+/// Parses a binary frame from a client, only game traffic may come in one
+pub fn decode_frame(bytes: &[u8]) -> Result<ClientMessage, ProtocolError> {
+    let message: ClientMessage = decode_binary(bytes)?;
+    if !message.is_game_traffic() {
+        return Err(ProtocolError::NotGameTraffic);
+    }
+
+    Ok(message)
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::configuration::RateLimitSettings;
+
+/// Classic token bucket: holds up to `capacity` tokens and refills `rate` tokens per second
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket
+    pub fn new(capacity: f64, rate: f64, now: Instant) -> Self {
+        Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Takes a token if there is one
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Puts a token back, for when a later check rejected the message after all
+    fn give_back(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.capacity);
+    }
+
+    /// Whether the bucket has refilled completely, a full bucket carries no state worth keeping
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+/// Rate limits shared by every session, holds the per account buckets
+#[derive(Debug)]
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    accounts: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            accounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn settings(&self) -> &RateLimitSettings {
+        &self.settings
+    }
+
+    fn try_take(&self, uuid: &str, now: Instant) -> bool {
+        let mut accounts = self.accounts.lock().expect("Rate limiter lock is poisoned");
+
+        accounts
+            .entry(uuid.to_string())
+            .or_insert_with(|| {
+                TokenBucket::new(self.settings.account_burst, self.settings.account_rate, now)
+            })
+            .try_take(now)
+    }
+
+    /// Forgets the buckets that refilled completely, their accounts are not sending anything
+    fn prune(&self, now: Instant) {
+        let mut accounts = self.accounts.lock().expect("Rate limiter lock is poisoned");
+        accounts.retain(|_, bucket| !bucket.is_full(now));
+    }
+}
+
+/// What to do with a message from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Handle the message
+    Allow,
+
+    /// Over the limit, drop the message and warn the client
+    Warn,
+
+    /// Over the limit too often, the client is throttled and the message is dropped.
+    ///
+    /// `first` is set for the message that started the throttle, so the client is told once.
+    Drop { first: bool },
+
+    /// Still flooding while throttled, close the connection
+    Disconnect,
+}
+
+/// Per session flood protection.
+///
+/// Every message takes a token from the session's bucket and from the account's bucket, the
+/// latter is shared by every session of the player. Match inputs have a bucket of their own. A
+/// message that finds any of its buckets empty is a strike: the first strikes are warnings, after
+/// that the session is throttled and every message is dropped for a while, and a client that keeps
+/// going gets disconnected. Strikes are forgiven after a quiet period.
+#[derive(Debug)]
+pub struct FloodGuard {
+    limiter: Arc<RateLimiter>,
+    uuid: String,
+    bucket: TokenBucket,
+    inputs: TokenBucket,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    throttled_until: Option<Instant>,
+}
+
+impl FloodGuard {
+    pub fn new(limiter: Arc<RateLimiter>, uuid: &str) -> Self {
+        let settings = limiter.settings();
+        let now = Instant::now();
+        let bucket = TokenBucket::new(settings.session_burst, settings.session_rate, now);
+        let inputs = TokenBucket::new(settings.input_burst, settings.input_rate, now);
+
+        Self {
+            limiter,
+            uuid: uuid.to_string(),
+            bucket,
+            inputs,
+            strikes: 0,
+            last_strike: None,
+            throttled_until: None,
+        }
+    }
+
+    /// Largest frame the session accepts, in bytes
+    pub fn max_frame_size(&self) -> usize {
+        self.limiter.settings().max_frame_size
+    }
+
+    /// Longest chat message or whisper the session accepts, in characters
+    pub fn max_message_length(&self) -> usize {
+        self.limiter.settings().max_message_length
+    }
+
+    /// Decides what to do with a message that arrived at `now`
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        if !self.is_throttled(now) && self.bucket.try_take(now) {
+            if self.limiter.try_take(&self.uuid, now) {
+                return Verdict::Allow;
+            }
+            self.bucket.give_back();
+        }
+
+        self.strike(now)
+    }
+
+    /// Decides what to do with a match input that arrived at `now`.
+    ///
+    /// Inputs are sent every tick, they take from their own bucket instead of the message
+    /// buckets. Whether they outpace the simulation is up to the match.
+    pub fn check_input(&mut self, now: Instant) -> Verdict {
+        if !self.is_throttled(now) && self.inputs.try_take(now) {
+            return Verdict::Allow;
+        }
+
+        self.strike(now)
+    }
+
+    fn is_throttled(&mut self, now: Instant) -> bool {
+        let throttled = self.throttled_until.is_some_and(|until| now < until);
+        if !throttled {
+            self.throttled_until = None;
+        }
+        throttled
+    }
+
+    /// Counts a message over the limit
+    fn strike(&mut self, now: Instant) -> Verdict {
+        let settings = self.limiter.settings();
+        let throttled = self.throttled_until.is_some();
+
+        let forgiven = self.last_strike.is_some_and(|last| {
+            now.saturating_duration_since(last) > Duration::from_secs(settings.strike_decay_seconds)
+        });
+        if forgiven {
+            self.strikes = 0;
+        }
+
+        self.strikes += 1;
+        self.last_strike = Some(now);
+
+        if self.strikes <= settings.warnings {
+            Verdict::Warn
+        } else if self.strikes > settings.max_strikes {
+            Verdict::Disconnect
+        } else if throttled {
+            Verdict::Drop { first: false }
+        } else {
+            self.throttled_until = Some(now + Duration::from_secs(settings.throttle_seconds));
+            Verdict::Drop { first: true }
+        }
+    }
+}
+
+impl Drop for FloodGuard {
+    fn drop(&mut self) {
+        self.limiter.prune(Instant::now());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+/// Every session is a member of this room for as long as it is connected
+pub const GLOBAL_ROOM: &str = "global";
+
+/// Longest a room name may be, prefix excluded
+const MAX_ROOM_NAME_LENGTH: usize = 64;
+
+/// What a room is used for, derived from the prefix of its name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomKind {
+    /// `global`, everyone
+    Global,
+
+    /// `party:<name>`, joined and left by clients themselves
+    Party,
+
+    /// `match:<id>`, managed by the server for the players of a match
+    Match,
+
+    /// `spectators:<id>`, managed by the server for the spectators of a match
+    Spectators,
+
+    /// `lobby:<id>`, managed by the server for players waiting for a match
+    Lobby,
+}
+
+impl RoomKind {
+    /// Returns the kind of a room name, or `None` if the name is not valid
+    pub fn of(name: &str) -> Option<RoomKind> {
+        if name == GLOBAL_ROOM {
+            return Some(RoomKind::Global);
+        }
+
+        let (prefix, rest) = name.split_once(':')?;
+        let kind = match prefix {
+            "party" => RoomKind::Party,
+            "match" => RoomKind::Match,
+            "spectators" => RoomKind::Spectators,
+            "lobby" => RoomKind::Lobby,
+            _ => return None,
+        };
+
+        let valid = !rest.is_empty()
+            && rest.len() <= MAX_ROOM_NAME_LENGTH
+            && rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        valid.then_some(kind)
+    }
+
+    /// Whether clients may join and leave rooms of this kind on their own
+    pub fn is_client_managed(&self) -> bool {
+        matches!(self, RoomKind::Party)
+    }
+}
+
+/// A named group of sessions that chat messages are scoped to
+#[derive(Debug)]
+pub struct Room {
+    pub kind: RoomKind,
+
+    /// Ids of the member sessions
+    pub members: HashSet<usize>,
+}
+
+impl Room {
+    pub fn new(kind: RoomKind) -> Self {
+        Self {
+            kind,
+            members: HashSet::new(),
+        }
+    }
+}
@@ -0,0 +1,1210 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+
+use chrono::{DateTime, Utc};
+
+use crate::configuration::{ChatSettings, SessionSettings};
+use crate::database::db::ArcDb;
+use crate::database::moderation::NewModerationAction;
+use crate::types::{DirectMessageRecord, ModerationAction};
+
+use super::filter::{Filtered, WordFilter};
+use super::presence::{Activity, PlayerPresence, PresenceRegistry};
+use super::protocol::{ErrorCode, Member, ServerMessage};
+use super::room::{Room, RoomKind, GLOBAL_ROOM};
+
+/// A message for a single session, the session serializes it and writes it to the client
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct Deliver(pub ServerMessage);
+
+/// Registers a new session with the chat server, or hands an existing one to a new connection
+/// if a valid `resume_token` is given. Returns the id of the session.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Connect {
+    pub addr: Recipient<Deliver>,
+    pub username: String,
+    pub uuid: String,
+
+    /// Until when the player is muted, looked up before the session was started
+    pub muted_until: Option<DateTime<Utc>>,
+
+    /// Token from the `SessionStarted` or `SessionResumed` of a session the client lost
+    pub resume_token: Option<String>,
+}
+
+/// Sent when the connection of a session closes.
+///
+/// A connection that dropped without the client saying goodbye is `resumable`, its session is
+/// kept for a while so the client can pick it up again. Otherwise the session is unregistered.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub id: usize,
+
+    /// The connection that closed, the session may already have moved to another one
+    pub addr: Recipient<Deliver>,
+    pub resumable: bool,
+}
+
+/// A chat message a client sent through its session
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientChat {
+    pub id: usize,
+    pub room: String,
+    pub text: String,
+}
+
+/// A private message a client sent through its session, `to` is a username
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientWhisper {
+    pub id: usize,
+    pub to: String,
+    pub text: String,
+}
+
+/// Delivers the stored direct messages of a player to their sessions, if they are connected
+#[derive(Message)]
+#[rtype(result = "()")]
+struct FlushWhispers {
+    uuid: String,
+}
+
+/// A client asks to join a party room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinRoom {
+    pub id: usize,
+    pub room: String,
+}
+
+/// A client asks to leave a party room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveRoom {
+    pub id: usize,
+    pub room: String,
+}
+
+/// A client asks for the members of a room it is in
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ListMembers {
+    pub id: usize,
+    pub room: String,
+}
+
+/// Opens a match or lobby room and puts every session of the given players in it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OpenRoom {
+    pub room: String,
+    pub uuids: Vec<String>,
+}
+
+/// Puts every session of a player in a room opened with `OpenRoom`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AddToRoom {
+    pub room: String,
+    pub uuid: String,
+}
+
+/// Takes every session of a player out of a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemoveFromRoom {
+    pub room: String,
+    pub uuid: String,
+}
+
+/// Whether any session of a player is in a room
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct InRoom {
+    pub room: String,
+    pub uuid: String,
+}
+
+/// Mutes every session of a player until `until`, or unmutes them with `None`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Mute {
+    pub uuid: String,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Disconnects every session of a player, returns how many sessions were kicked
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Kick {
+    pub uuid: String,
+    pub reason: Option<String>,
+}
+
+/// Closes a room, its members receive a `RoomClosed`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseRoom {
+    pub room: String,
+}
+
+/// A session reports what its game is doing
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetActivity {
+    pub id: usize,
+    pub activity: Activity,
+}
+
+/// A client asks to be told about every presence change, it gets the current presence of
+/// every online player first
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribePresence {
+    pub id: usize,
+}
+
+/// A client no longer wants presence changes
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnsubscribePresence {
+    pub id: usize,
+}
+
+/// The presence of the given players, or of every online player without `uuids`.
+///
+/// Players that are offline are left out.
+#[derive(Message)]
+#[rtype(result = "Vec<PlayerPresence>")]
+pub struct GetPresence {
+    pub uuids: Option<Vec<String>>,
+}
+
+/// Sets or clears what the server knows a player is doing, like playing a match. Overrides
+/// what the player's clients report while set.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetPlayerActivity {
+    pub uuid: String,
+    pub activity: Option<Activity>,
+}
+
+/// A presence change, for actors that registered with `WatchPresence`
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct PresenceUpdate(pub PlayerPresence);
+
+/// Registers an actor that wants every presence change
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchPresence(pub Recipient<PresenceUpdate>);
+
+/// A session was unregistered for good and can't be resumed anymore, for actors that
+/// registered with `WatchSessions`
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct SessionRemoved {
+    pub uuid: String,
+    pub session: usize,
+}
+
+/// Registers an actor that wants to know when sessions are gone
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WatchSessions(pub Recipient<SessionRemoved>);
+
+/// Sends a message to every session of the given players, for actors that only know players by
+/// their uuid
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendToPlayers {
+    pub uuids: Vec<String>,
+    pub message: ServerMessage,
+}
+
+/// Sends a message to single sessions, for actors that keep track of the sessions of a player
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendToSessions {
+    pub ids: Vec<usize>,
+    pub message: ServerMessage,
+}
+
+/// Like `SendToPlayers`, but leaves out the sessions in `except`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendToPlayersExcept {
+    pub uuids: Vec<String>,
+    pub except: HashSet<usize>,
+    pub message: ServerMessage,
+}
+
+/// Messages for a session whose connection is gone, replayed when it is resumed
+#[derive(Default)]
+struct Missed {
+    messages: VecDeque<ServerMessage>,
+
+    /// Whether older messages were dropped to stay within the buffer length
+    truncated: bool,
+}
+
+/// A session as far as the chat server is concerned
+struct Session {
+    /// The connection of the session, `None` while it waits to be resumed
+    addr: Option<Recipient<Deliver>>,
+    username: String,
+    uuid: String,
+    rooms: HashSet<String>,
+    muted_until: Option<DateTime<Utc>>,
+
+    /// Secret the client presents to resume the session, replaced every time it is used
+    resume_token: String,
+
+    /// When the connection dropped, identifies the grace period that is running
+    detached_at: Option<Instant>,
+
+    /// Filled while detached. Sending only needs `&self`, hence the cell.
+    missed: RefCell<Missed>,
+}
+
+impl Session {
+    fn member(&self) -> Member {
+        Member {
+            username: self.username.clone(),
+            uuid: self.uuid.clone(),
+        }
+    }
+}
+
+/// Central actor every websocket session registers with.
+///
+/// Chat messages are relayed through here so they reach every member of the room they were sent
+/// to, stamped with the sender's username and the server's clock. Every message is stored, a
+/// session joining a room gets the latest ones as a `ChatHistory`. Whispers to players that are
+/// offline are stored and delivered when they connect.
+///
+/// Muted players can't chat or whisper, and every message goes through the `WordFilter` first.
+///
+/// Every session is in the `global` room while it is connected. Party rooms are joined and left
+/// by clients, match and lobby rooms are opened and closed by the server with `OpenRoom` and
+/// `CloseRoom`. Rooms other than `global` are dropped as soon as the last member leaves.
+///
+/// A session outlives a connection that drops: it stays in its rooms and keeps its presence for
+/// a grace period, collecting the messages meant for it. A client that reconnects with the
+/// session's resume token in time gets the same session back and the messages it missed.
+///
+/// The sessions double as the presence of the players: what each player is doing is tracked in
+/// a `PresenceRegistry` and changes are pushed to the sessions that subscribed to them.
+pub struct ChatServer {
+    sessions: HashMap<usize, Session>,
+    rooms: HashMap<String, Room>,
+    presence: PresenceRegistry,
+    presence_subscribers: HashSet<usize>,
+    presence_watchers: Vec<Recipient<PresenceUpdate>>,
+    session_watchers: Vec<Recipient<SessionRemoved>>,
+    next_id: usize,
+    db: ArcDb,
+    filter: WordFilter,
+    settings: ChatSettings,
+    session_settings: SessionSettings,
+}
+
+impl ChatServer {
+    pub fn new(db: ArcDb, settings: ChatSettings, session_settings: SessionSettings) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(GLOBAL_ROOM.to_string(), Room::new(RoomKind::Global));
+
+        Self {
+            sessions: HashMap::new(),
+            rooms,
+            presence: PresenceRegistry::new(),
+            presence_subscribers: HashSet::new(),
+            presence_watchers: Vec::new(),
+            session_watchers: Vec::new(),
+            next_id: 0,
+            db,
+            filter: WordFilter::new(&settings),
+            settings,
+            session_settings,
+        }
+    }
+
+    /// Sends a message to a single session, or keeps it for later if the session is detached
+    fn send_to(&self, id: usize, message: ServerMessage) {
+        let Some(session) = self.sessions.get(&id) else {
+            return;
+        };
+
+        match &session.addr {
+            Some(addr) => addr.do_send(Deliver(message)),
+            None if message.is_transient() => {}
+            None => {
+                let mut missed = session.missed.borrow_mut();
+                missed.messages.push_back(message);
+                if missed.messages.len() > self.session_settings.resume_buffer_length {
+                    missed.messages.pop_front();
+                    missed.truncated = true;
+                }
+            }
+        }
+    }
+
+    /// Connections of a player's sessions, detached sessions have none
+    fn connections_of(&self, uuid: &str) -> Vec<Recipient<Deliver>> {
+        self.sessions_of(uuid)
+            .into_iter()
+            .filter_map(|id| self.sessions[&id].addr.clone())
+            .collect()
+    }
+
+    /// Sends a message to every member of a room, except `skip`
+    fn send_to_room(&self, room: &str, message: ServerMessage, skip: Option<usize>) {
+        let Some(room) = self.rooms.get(room) else {
+            return;
+        };
+
+        for id in room.members.iter().filter(|id| Some(**id) != skip) {
+            self.send_to(*id, message.clone());
+        }
+    }
+
+    /// Tells every presence subscriber and watcher about a change, if there was one
+    fn publish_presence(&self, change: Option<PlayerPresence>) {
+        let Some(change) = change else {
+            return;
+        };
+
+        for id in &self.presence_subscribers {
+            self.send_to(*id, ServerMessage::PresenceChanged(change.clone()));
+        }
+        for watcher in &self.presence_watchers {
+            watcher.do_send(PresenceUpdate(change.clone()));
+        }
+    }
+
+    fn reject(&self, id: usize, message: impl ToString) {
+        self.send_to(id, ServerMessage::error(ErrorCode::Rejected, message));
+    }
+
+    /// Checks that a session may send a message and runs it through the word filter.
+    ///
+    /// Returns the text to send, or `None` after telling the sender why the message was dropped.
+    fn screen(&self, id: usize, text: String) -> Option<String> {
+        let session = self.sessions.get(&id)?;
+
+        if let Some(until) = session.muted_until.filter(|until| *until > Utc::now()) {
+            self.send_to(
+                id,
+                ServerMessage::error(ErrorCode::Muted, format!("You are muted until {}", until)),
+            );
+            return None;
+        }
+
+        let screened = match self.filter.apply(&text) {
+            Filtered::Clean(text) => return Some(text),
+            Filtered::Masked(masked) => Some(masked),
+            Filtered::Rejected => {
+                self.reject(id, "Your message contains a filtered word");
+                None
+            }
+        };
+
+        // The original text is kept in the audit log for moderators
+        let db = self.db.clone();
+        let (uuid, username) = (session.uuid.clone(), session.username.clone());
+        actix::spawn(async move {
+            let action = NewModerationAction {
+                action: ModerationAction::Filter,
+                moderator: None,
+                target_uuid: &uuid,
+                target_username: &username,
+                reason: Some(&text),
+                expires_at: None,
+            };
+            if let Err(e) = db.record_moderation_action(&action).await {
+                log::error!("Failed to record a filtered message: {}", e);
+            }
+        });
+
+        screened
+    }
+
+    fn is_member(&self, id: usize, room: &str) -> bool {
+        self.rooms
+            .get(room)
+            .is_some_and(|room| room.members.contains(&id))
+    }
+
+    /// Sends a message to every session of a player
+    fn send_to_player(&self, uuid: &str, message: ServerMessage) {
+        for id in self.sessions_of(uuid) {
+            self.send_to(id, message.clone());
+        }
+    }
+
+    /// Sessions of a player, a player can be connected more than once
+    fn sessions_of(&self, uuid: &str) -> Vec<usize> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| session.uuid == uuid)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn members(&self, room: &str) -> Vec<Member> {
+        let Some(room) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+
+        room.members
+            .iter()
+            .filter_map(|id| self.sessions.get(id))
+            .map(Session::member)
+            .collect()
+    }
+
+    /// Loads the latest messages of a room and sends them to a session
+    fn send_backlog(&self, id: usize, room: &str) {
+        let Some(addr) = self.sessions.get(&id).and_then(|s| s.addr.clone()) else {
+            return;
+        };
+
+        let db = self.db.clone();
+        let room = room.to_string();
+        let limit = self.settings.backlog_length;
+
+        actix::spawn(async move {
+            match db.chat_history(&room, None, limit).await {
+                Ok(messages) => {
+                    addr.do_send(Deliver(ServerMessage::ChatHistory { room, messages }))
+                }
+                Err(e) => log::error!("Failed to load the chat history of {}: {}", room, e),
+            }
+        });
+    }
+
+    /// Adds a session to an existing room.
+    ///
+    /// The other members are told with a `Joined`, the new member gets the member list and the
+    /// backlog of the room.
+    fn join(&mut self, id: usize, room: &str) {
+        let (Some(session), Some(entry)) = (self.sessions.get_mut(&id), self.rooms.get_mut(room))
+        else {
+            return;
+        };
+
+        if !entry.members.insert(id) {
+            return;
+        }
+        session.rooms.insert(room.to_string());
+
+        let joined = ServerMessage::Joined {
+            room: room.to_string(),
+            username: session.username.clone(),
+            uuid: session.uuid.clone(),
+            timestamp: chrono::Utc::now(),
+        };
+        self.send_to_room(room, joined, Some(id));
+
+        self.send_to(
+            id,
+            ServerMessage::RoomMembers {
+                room: room.to_string(),
+                members: self.members(room),
+            },
+        );
+        self.send_backlog(id, room);
+    }
+
+    /// Removes a session from a room, the remaining members are told with a `Left`
+    fn leave(&mut self, id: usize, room: &str) {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        session.rooms.remove(room);
+
+        let was_member = self
+            .rooms
+            .get_mut(room)
+            .is_some_and(|entry| entry.members.remove(&id));
+        if !was_member {
+            return;
+        }
+
+        let session = &self.sessions[&id];
+        let left = ServerMessage::Left {
+            room: room.to_string(),
+            username: session.username.clone(),
+            uuid: session.uuid.clone(),
+            timestamp: chrono::Utc::now(),
+        };
+        self.send_to_room(room, left, None);
+
+        let empty = self
+            .rooms
+            .get(room)
+            .is_some_and(|entry| entry.kind != RoomKind::Global && entry.members.is_empty());
+        if empty {
+            log::debug!("Dropping empty room {}", room);
+            self.rooms.remove(room);
+        }
+    }
+
+    /// Hands a detached session to a new connection and replays what it missed.
+    ///
+    /// The client may come back before its old connection was noticed to be dead, that
+    /// connection is closed.
+    fn resume(&mut self, id: usize, addr: Recipient<Deliver>, ctx: &mut Context<Self>) {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+
+        if let Some(old) = session.addr.replace(addr) {
+            old.do_send(Deliver(ServerMessage::Kicked {
+                reason: Some("The session was resumed on another connection".to_string()),
+            }));
+        }
+        session.detached_at = None;
+        session.resume_token = new_resume_token();
+
+        let missed = session.missed.take();
+        log::info!(
+            "{} resumed session {}, replaying {} messages",
+            session.username,
+            id,
+            missed.messages.len()
+        );
+
+        let resumed = ServerMessage::SessionResumed {
+            resume_token: session.resume_token.clone(),
+            missed: missed.messages.len(),
+            truncated: missed.truncated,
+        };
+        let uuid = session.uuid.clone();
+
+        self.send_to(id, resumed);
+        for message in missed.messages {
+            self.send_to(id, message);
+        }
+
+        // Whispers are stored rather than buffered while every session of a player is detached
+        ctx.notify(FlushWhispers { uuid });
+    }
+
+    /// Unregisters a session for good, it leaves its rooms and its player may go offline
+    fn remove(&mut self, id: usize) {
+        let Some(rooms) = self.sessions.get(&id).map(|s| s.rooms.clone()) else {
+            return;
+        };
+
+        for room in rooms {
+            self.leave(id, &room);
+        }
+
+        self.presence_subscribers.remove(&id);
+
+        if let Some(session) = self.sessions.remove(&id) {
+            for watcher in &self.session_watchers {
+                watcher.do_send(SessionRemoved {
+                    uuid: session.uuid.clone(),
+                    session: id,
+                });
+            }
+
+            let change = self.presence.disconnect(id, &session.uuid);
+            self.publish_presence(change);
+
+            log::info!("{} left the chat (session {})", session.username, id);
+        }
+    }
+}
+
+/// A random token that is hard to guess
+fn new_resume_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+impl Actor for ChatServer {
+    type Context = Context<Self>;
+}
+
+impl Handler<Connect> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Self::Result {
+        if let Some(token) = &msg.resume_token {
+            // Tokens only work for the player they were issued to
+            let resumable = self
+                .sessions
+                .iter()
+                .find(|(_, session)| session.uuid == msg.uuid && session.resume_token == *token)
+                .map(|(id, _)| *id);
+
+            match resumable {
+                Some(id) => {
+                    self.resume(id, msg.addr, ctx);
+                    return id;
+                }
+                None => log::info!(
+                    "{} tried to resume an unknown or expired session",
+                    msg.username
+                ),
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        log::info!("{} joined the chat as session {}", msg.username, id);
+
+        let resume_token = new_resume_token();
+        self.sessions.insert(
+            id,
+            Session {
+                addr: Some(msg.addr),
+                username: msg.username,
+                uuid: msg.uuid,
+                rooms: HashSet::new(),
+                muted_until: msg.muted_until,
+                resume_token: resume_token.clone(),
+                detached_at: None,
+                missed: RefCell::default(),
+            },
+        );
+        self.send_to(
+            id,
+            ServerMessage::SessionStarted {
+                resume_token,
+                resume_grace_seconds: self.session_settings.resume_grace_seconds,
+            },
+        );
+        self.join(id, GLOBAL_ROOM);
+
+        let session = &self.sessions[&id];
+        let change = self.presence.connect(id, &session.uuid, &session.username);
+        self.publish_presence(change);
+
+        // Whispers that arrived while the player was offline
+        ctx.notify(FlushWhispers {
+            uuid: self.sessions[&id].uuid.clone(),
+        });
+
+        id
+    }
+}
+
+impl Handler<Disconnect> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
+        let grace = Duration::from_secs(self.session_settings.resume_grace_seconds);
+
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+
+        // The session was resumed on another connection already
+        if session.addr.as_ref() != Some(&msg.addr) {
+            return;
+        }
+
+        if !msg.resumable || grace.is_zero() {
+            self.remove(msg.id);
+            return;
+        }
+
+        log::info!(
+            "Connection of {} dropped, keeping session {} for {:?}",
+            session.username,
+            msg.id,
+            grace
+        );
+
+        let detached_at = Instant::now();
+        session.addr = None;
+        session.detached_at = Some(detached_at);
+
+        ctx.run_later(grace, move |act, _| {
+            let expired = act
+                .sessions
+                .get(&msg.id)
+                .is_some_and(|session| session.detached_at == Some(detached_at));
+            if expired {
+                log::info!("Session {} was not resumed in time", msg.id);
+                act.remove(msg.id);
+            }
+        });
+    }
+}
+
+impl Handler<ClientChat> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientChat, _: &mut Context<Self>) {
+        let Some(sender) = self.sessions.get(&msg.id) else {
+            log::warn!("Chat message from unknown session {}", msg.id);
+            return;
+        };
+
+        if !self.is_member(msg.id, &msg.room) {
+            self.reject(msg.id, format!("You are not in room {}", msg.room));
+            return;
+        }
+
+        let Some(text) = self.screen(msg.id, msg.text) else {
+            return;
+        };
+
+        let timestamp = chrono::Utc::now();
+
+        // Stored in the background, relaying the message doesn't wait for the database
+        let db = self.db.clone();
+        let (room, uuid, username, stored_text) = (
+            msg.room.clone(),
+            sender.uuid.clone(),
+            sender.username.clone(),
+            text.clone(),
+        );
+        actix::spawn(async move {
+            if let Err(e) = db
+                .insert_chat_message(&room, &uuid, &username, &stored_text, timestamp)
+                .await
+            {
+                log::error!("Failed to store a chat message in {}: {}", room, e);
+            }
+        });
+
+        // The sender gets its own message back as well, so everyone sees the same order
+        let chat = ServerMessage::Chat {
+            room: msg.room.clone(),
+            username: sender.username.clone(),
+            uuid: sender.uuid.clone(),
+            text,
+            timestamp,
+        };
+        self.send_to_room(&msg.room, chat, None);
+    }
+}
+
+impl Handler<ClientWhisper> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientWhisper, ctx: &mut Context<Self>) {
+        let Some(sender) = self.sessions.get(&msg.id) else {
+            log::warn!("Whisper from unknown session {}", msg.id);
+            return;
+        };
+
+        if sender.username == msg.to {
+            self.reject(msg.id, "You can't whisper to yourself");
+            return;
+        }
+
+        let Some(text) = self.screen(msg.id, msg.text) else {
+            return;
+        };
+
+        let mut whisper = DirectMessageRecord {
+            id: 0,
+            sender_uuid: sender.uuid.clone(),
+            sender_username: sender.username.clone(),
+            recipient_uuid: String::new(),
+            recipient_username: msg.to,
+            text,
+            sent_at: chrono::Utc::now(),
+        };
+
+        // Detached sessions don't count, a whisper buffered in one is lost if it expires
+        let recipient = self.sessions.values().find(|session| {
+            session.username == whisper.recipient_username && session.addr.is_some()
+        });
+
+        if let Some(recipient) = recipient {
+            whisper.recipient_uuid = recipient.uuid.clone();
+
+            let message = ServerMessage::from(whisper.clone());
+            self.send_to_player(&whisper.recipient_uuid, message.clone());
+            self.send_to_player(&whisper.sender_uuid, message);
+
+            let db = self.db.clone();
+            actix::spawn(async move {
+                if let Err(e) = db.insert_direct_message(&whisper, true).await {
+                    log::error!("Failed to store a whisper: {}", e);
+                }
+            });
+            return;
+        }
+
+        // Offline, detached, or no such player. The sender's sessions are collected now, they could be
+        // gone by the time the database answers.
+        let db = self.db.clone();
+        let server = ctx.address();
+        let sender_sessions = self.connections_of(&whisper.sender_uuid);
+        let sender = self.sessions[&msg.id].addr.clone();
+
+        actix::spawn(async move {
+            let uuid = match db.uuid_by_username(&whisper.recipient_username).await {
+                Ok(Some(uuid)) => uuid,
+                Ok(None) => {
+                    let error = ServerMessage::error(
+                        ErrorCode::UserNotFound,
+                        format!("There is no player called {}", whisper.recipient_username),
+                    );
+                    if let Some(sender) = sender {
+                        sender.do_send(Deliver(error));
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Failed to look up the recipient of a whisper: {}", e);
+                    return;
+                }
+            };
+            whisper.recipient_uuid = uuid;
+
+            if let Err(e) = db.insert_direct_message(&whisper, false).await {
+                log::error!("Failed to store a whisper: {}", e);
+                return;
+            }
+
+            let message = ServerMessage::from(whisper.clone());
+            for session in sender_sessions {
+                session.do_send(Deliver(message.clone()));
+            }
+
+            // The recipient may have connected while the whisper was being stored
+            server.do_send(FlushWhispers {
+                uuid: whisper.recipient_uuid,
+            });
+        });
+    }
+}
+
+impl Handler<FlushWhispers> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: FlushWhispers, _: &mut Context<Self>) {
+        let sessions = self.connections_of(&msg.uuid);
+
+        // Stays stored until a session of the player is connected
+        if sessions.is_empty() {
+            return;
+        }
+
+        let db = self.db.clone();
+        actix::spawn(async move {
+            let whispers = match db.take_undelivered_direct_messages(&msg.uuid).await {
+                Ok(whispers) => whispers,
+                Err(e) => {
+                    log::error!("Failed to load the stored whispers of {}: {}", msg.uuid, e);
+                    return;
+                }
+            };
+
+            for whisper in whispers {
+                let message = ServerMessage::from(whisper);
+                for session in &sessions {
+                    session.do_send(Deliver(message.clone()));
+                }
+            }
+        });
+    }
+}
+
+impl Handler<JoinRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: JoinRoom, _: &mut Context<Self>) {
+        match RoomKind::of(&msg.room) {
+            Some(kind) if kind.is_client_managed() => {
+                self.rooms
+                    .entry(msg.room.clone())
+                    .or_insert_with(|| Room::new(kind));
+                self.join(msg.id, &msg.room);
+            }
+            Some(_) => self.reject(msg.id, format!("Room {} can't be joined", msg.room)),
+            None => self.reject(msg.id, format!("{} is not a valid room name", msg.room)),
+        }
+    }
+}
+
+impl Handler<LeaveRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaveRoom, _: &mut Context<Self>) {
+        match RoomKind::of(&msg.room) {
+            Some(kind) if kind.is_client_managed() => self.leave(msg.id, &msg.room),
+            _ => self.reject(msg.id, format!("Room {} can't be left", msg.room)),
+        }
+    }
+}
+
+impl Handler<ListMembers> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ListMembers, _: &mut Context<Self>) {
+        if !self.is_member(msg.id, &msg.room) {
+            self.reject(msg.id, format!("You are not in room {}", msg.room));
+            return;
+        }
+
+        self.send_to(
+            msg.id,
+            ServerMessage::RoomMembers {
+                members: self.members(&msg.room),
+                room: msg.room,
+            },
+        );
+    }
+}
+
+impl Handler<OpenRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: OpenRoom, _: &mut Context<Self>) {
+        let kind = match RoomKind::of(&msg.room) {
+            Some(kind @ (RoomKind::Match | RoomKind::Spectators | RoomKind::Lobby)) => kind,
+            _ => {
+                log::error!(
+                    "Refusing to open {}, it is not a match, spectator or lobby room",
+                    msg.room
+                );
+                return;
+            }
+        };
+
+        self.rooms
+            .entry(msg.room.clone())
+            .or_insert_with(|| Room::new(kind));
+
+        for uuid in msg.uuids {
+            for id in self.sessions_of(&uuid) {
+                self.join(id, &msg.room);
+            }
+        }
+    }
+}
+
+impl Handler<AddToRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddToRoom, _: &mut Context<Self>) {
+        for id in self.sessions_of(&msg.uuid) {
+            self.join(id, &msg.room);
+        }
+    }
+}
+
+impl Handler<RemoveFromRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveFromRoom, _: &mut Context<Self>) {
+        if msg.room == GLOBAL_ROOM {
+            log::error!("Refusing to remove {} from the global room", msg.uuid);
+            return;
+        }
+
+        for id in self.sessions_of(&msg.uuid) {
+            self.leave(id, &msg.room);
+        }
+    }
+}
+
+impl Handler<InRoom> for ChatServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: InRoom, _: &mut Context<Self>) -> Self::Result {
+        self.sessions_of(&msg.uuid)
+            .into_iter()
+            .any(|id| self.is_member(id, &msg.room))
+    }
+}
+
+impl Handler<Mute> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Mute, _: &mut Context<Self>) {
+        for id in self.sessions_of(&msg.uuid) {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.muted_until = msg.until;
+            }
+            self.send_to(id, ServerMessage::Muted { until: msg.until });
+        }
+    }
+}
+
+impl Handler<Kick> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Kick, _: &mut Context<Self>) -> Self::Result {
+        let sessions = self.sessions_of(&msg.uuid);
+
+        // The sessions close themselves on `Kicked` and disconnect from here like any other,
+        // detached sessions are not kept around for a client that isn't welcome
+        for id in &sessions {
+            if self.sessions[id].addr.is_none() {
+                self.remove(*id);
+                continue;
+            }
+
+            self.send_to(
+                *id,
+                ServerMessage::Kicked {
+                    reason: msg.reason.clone(),
+                },
+            );
+        }
+
+        sessions.len()
+    }
+}
+
+impl Handler<CloseRoom> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseRoom, _: &mut Context<Self>) {
+        if msg.room == GLOBAL_ROOM {
+            log::error!("Refusing to close the global room");
+            return;
+        }
+
+        let Some(room) = self.rooms.remove(&msg.room) else {
+            return;
+        };
+
+        for id in room.members {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.rooms.remove(&msg.room);
+            }
+            self.send_to(
+                id,
+                ServerMessage::RoomClosed {
+                    room: msg.room.clone(),
+                },
+            );
+        }
+    }
+}
+
+impl Handler<SetActivity> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetActivity, _: &mut Context<Self>) {
+        let Some(session) = self.sessions.get(&msg.id) else {
+            return;
+        };
+
+        let change = self
+            .presence
+            .set_activity(msg.id, &session.uuid, msg.activity);
+        self.publish_presence(change);
+    }
+}
+
+impl Handler<SubscribePresence> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribePresence, _: &mut Context<Self>) {
+        if !self.sessions.contains_key(&msg.id) {
+            return;
+        }
+
+        self.presence_subscribers.insert(msg.id);
+        self.send_to(
+            msg.id,
+            ServerMessage::Presence {
+                players: self.presence.all(),
+            },
+        );
+    }
+}
+
+impl Handler<UnsubscribePresence> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: UnsubscribePresence, _: &mut Context<Self>) {
+        self.presence_subscribers.remove(&msg.id);
+    }
+}
+
+impl Handler<GetPresence> for ChatServer {
+    type Result = MessageResult<GetPresence>;
+
+    fn handle(&mut self, msg: GetPresence, _: &mut Context<Self>) -> Self::Result {
+        let players = match msg.uuids {
+            Some(uuids) => uuids
+                .iter()
+                .filter_map(|uuid| self.presence.get(uuid))
+                .collect(),
+            None => self.presence.all(),
+        };
+
+        MessageResult(players)
+    }
+}
+
+impl Handler<SetPlayerActivity> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetPlayerActivity, _: &mut Context<Self>) {
+        let change = self.presence.set_server_activity(&msg.uuid, msg.activity);
+        self.publish_presence(change);
+    }
+}
+
+impl Handler<WatchPresence> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: WatchPresence, _: &mut Context<Self>) {
+        self.presence_watchers.push(msg.0);
+    }
+}
+
+impl Handler<WatchSessions> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: WatchSessions, _: &mut Context<Self>) {
+        self.session_watchers.push(msg.0);
+    }
+}
+
+impl Handler<SendToPlayers> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendToPlayers, _: &mut Context<Self>) {
+        for uuid in &msg.uuids {
+            self.send_to_player(uuid, msg.message.clone());
+        }
+    }
+}
+
+impl Handler<SendToSessions> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendToSessions, _: &mut Context<Self>) {
+        for id in msg.ids {
+            self.send_to(id, msg.message.clone());
+        }
+    }
+}
+
+impl Handler<SendToPlayersExcept> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendToPlayersExcept, _: &mut Context<Self>) {
+        for uuid in &msg.uuids {
+            for id in self.sessions_of(uuid) {
+                if !msg.except.contains(&id) {
+                    self.send_to(id, msg.message.clone());
+                }
+            }
+        }
+    }
+}
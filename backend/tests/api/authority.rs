@@ -0,0 +1,106 @@
+use crate::general::spawn_app;
+use serde_json::{json, Value};
+use service::types::Authority;
+
+#[actix_web::test]
+async fn users_all_requires_a_jwt() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/users/all", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    let body: Value = response.json().await.expect("Error body is not json");
+    assert_eq!(body["error"], "Unauthorized");
+}
+
+#[actix_web::test]
+async fn users_all_is_forbidden_for_regular_users() {
+    let app = spawn_app().await;
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+    let (authorization, _) = app.login().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/users/all", &app.address))
+        .header("authorization", authorization)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 403);
+
+    let body: Value = response.json().await.expect("Error body is not json");
+    assert_eq!(body["error"], "Forbidden");
+}
+
+#[actix_web::test]
+async fn admins_can_promote_users() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_named_user("admin")
+        .await
+        .expect("Failed to create admin");
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    app.db_client
+        .set_authority("admin", Authority::Admin)
+        .await
+        .expect("Failed to promote admin");
+    let (admin, _) = app.login_as("admin").await;
+
+    let response = client
+        .post(format!("{}/users/authority", &app.address))
+        .header("authorization", &admin)
+        .json(&json!({"username": "test", "authority": "moderator"}))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+
+    let (authorization, _) = app.login().await;
+
+    let response = client
+        .get(format!("{}/players/player", &app.address))
+        .header("authorization", authorization)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let body: Value = response.json().await.expect("Player info is not json");
+    assert_eq!(body["authority"], "moderator");
+}
+
+#[actix_web::test]
+async fn unknown_authorities_are_rejected() {
+    let app = spawn_app().await;
+
+    app.new_named_user("admin")
+        .await
+        .expect("Failed to create admin");
+    app.db_client
+        .set_authority("admin", Authority::Admin)
+        .await
+        .expect("Failed to promote admin");
+    let (admin, _) = app.login_as("admin").await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/users/authority", &app.address))
+        .header("authorization", &admin)
+        .json(&json!({"username": "admin", "authority": "emperor"}))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 400);
+}
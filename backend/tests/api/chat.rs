@@ -0,0 +1,53 @@
+use crate::general::{next_of_type, send_json, spawn_app};
+use serde_json::json;
+use service::websocket::protocol::PROTOCOL_VERSION;
+
+#[actix_web::test]
+async fn chat_messages_are_broadcast_to_every_player() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": "hello: bob"}),
+    )
+    .await;
+
+    for socket in [&mut alice, &mut bob] {
+        let chat = next_of_type(socket, "chat").await;
+
+        assert_eq!(chat["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(chat["room"], "global");
+        assert_eq!(chat["username"], "alice");
+        assert_eq!(chat["text"], "hello: bob");
+        assert!(chat["timestamp"].as_str().is_some());
+    }
+}
+
+#[actix_web::test]
+async fn players_are_notified_when_someone_joins_and_leaves() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    let joined = next_of_type(&mut alice, "joined").await;
+    assert_eq!(joined["username"], "bob");
+    assert_eq!(joined["room"], "global");
+
+    bob.close(None)
+        .await
+        .expect("Failed to close the websocket");
+
+    let left = next_of_type(&mut alice, "left").await;
+    assert_eq!(left["username"], "bob");
+    assert_eq!(left["room"], "global");
+}
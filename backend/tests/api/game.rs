@@ -0,0 +1,395 @@
+use service::game::boss::{Attack, Boss, Phase};
+use service::game::collision::{
+    circle_rect_collision, rect_rect_collision, Circle, Position, Rect,
+};
+use service::game::entity::{Alien, AlienKind, Bullet, Movement, Player, PlayerInput};
+use service::game::input::{InputQueue, InputRejection, SequencedInput, MAX_INPUT_BURST};
+use service::game::level::Spawn;
+use service::game::timestep::FixedTimestep;
+use service::game::world::{Outcome, World, PLAYER_SPAWN};
+use service::game::{FIELD_WIDTH, MAX_BULLETS};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn firing() -> PlayerInput {
+    PlayerInput {
+        fire: true,
+        ..PlayerInput::default()
+    }
+}
+
+fn sequenced(sequence: u32, input: PlayerInput) -> SequencedInput {
+    SequencedInput {
+        sequence,
+        client_tick: u64::from(sequence),
+        input,
+    }
+}
+
+#[test]
+fn rect_rect_collision_counts_touching_edges() {
+    let a = Rect::new(Position::new(0.0, 0.0), 20.0, 20.0);
+
+    assert!(rect_rect_collision(
+        a,
+        Rect::new(Position::new(10.0, 10.0), 5.0, 10.0)
+    ));
+    assert!(rect_rect_collision(
+        a,
+        Rect::new(Position::new(20.0, 20.0), 5.0, 10.0)
+    ));
+    assert!(!rect_rect_collision(
+        a,
+        Rect::new(Position::new(21.0, 0.0), 5.0, 10.0)
+    ));
+}
+
+#[test]
+fn circle_rect_collision_measures_from_the_circle_center() {
+    // The position of a circle is the corner of its bounding box, its center is at (10, 10)
+    let circle = Circle::new(Position::new(0.0, 0.0), 10.0);
+
+    assert!(circle_rect_collision(
+        circle,
+        Rect::new(Position::new(8.0, 8.0), 5.0, 10.0)
+    ));
+    assert!(!circle_rect_collision(
+        circle,
+        Rect::new(Position::new(30.0, 30.0), 5.0, 10.0)
+    ));
+}
+
+#[test]
+fn fixed_timestep_carries_over_partial_ticks() {
+    let mut timestep = FixedTimestep::new(60, 5);
+    let tick = timestep.tick();
+
+    assert_eq!(timestep.advance(tick / 2), 0);
+    assert_eq!(timestep.advance(tick / 2), 1);
+    assert_eq!(timestep.advance(tick * 3), 3);
+
+    // Far behind, the rest is dropped rather than caught up with
+    assert_eq!(timestep.advance(Duration::from_secs(10)), 5);
+    assert!(timestep.advance(Duration::ZERO) <= 1);
+}
+
+#[test]
+fn players_move_with_their_input_and_stay_on_the_field() {
+    let mut world = World::new();
+    world.add_player("alice");
+
+    world.set_input(
+        "alice",
+        PlayerInput {
+            left: true,
+            up: true,
+            ..PlayerInput::default()
+        },
+    );
+    world.step();
+
+    let player = world.player("alice").unwrap();
+    assert_eq!(
+        player.position,
+        Position::new(
+            PLAYER_SPAWN.x - Player::SPEED,
+            PLAYER_SPAWN.y - Player::SPEED
+        )
+    );
+
+    world.set_input(
+        "alice",
+        PlayerInput {
+            right: true,
+            ..PlayerInput::default()
+        },
+    );
+    for _ in 0..2000 {
+        world.step();
+    }
+    assert_eq!(
+        world.player("alice").unwrap().position.x,
+        FIELD_WIDTH - Player::WIDTH
+    );
+}
+
+#[test]
+fn players_fire_at_their_fire_rate_up_to_their_bullet_limit() {
+    let mut world = World::new();
+    let id = world.add_player("alice");
+    world.players[0].max_bullets = 3;
+    world.set_input("alice", firing());
+
+    for _ in 0..11 {
+        world.step();
+    }
+    // Ticks 0, 5 and 10
+    assert_eq!(world.bullets.len(), 3);
+    assert!(world
+        .bullets
+        .iter()
+        .all(|bullet| bullet.shooter == id && bullet.from_player));
+
+    for _ in 0..5 {
+        world.step();
+    }
+    assert_eq!(world.bullets.len(), 3);
+}
+
+#[test]
+fn bullets_on_the_field_are_capped() {
+    let mut world = World::new();
+    for i in 0..MAX_BULLETS + 10 {
+        world.add_player(&i.to_string());
+        world.set_input(&i.to_string(), firing());
+    }
+
+    world.step();
+    assert_eq!(world.bullets.len(), MAX_BULLETS);
+}
+
+#[test]
+fn bullets_leave_the_field() {
+    let mut world = World::new();
+    world.add_player("alice");
+    world.set_input("alice", firing());
+    world.step();
+    world.set_input("alice", PlayerInput::default());
+
+    let ticks = (PLAYER_SPAWN.y / Bullet::PLAYER_SPEED) as usize + 1;
+    for _ in 0..ticks {
+        world.step();
+    }
+    assert!(world.bullets.is_empty());
+}
+
+#[test]
+fn aliens_sweep_and_move_down_at_the_edges() {
+    let mut world = World::new();
+    world.spawn_alien(AlienKind::Alien, Position::new(1200.0, 100.0), 1.0);
+
+    world.step();
+    assert_eq!(world.aliens[0].position, Position::new(1230.0, 100.0));
+
+    // At the edge, the next tick moves it down a row and then it turns around
+    world.step();
+    assert_eq!(world.aliens[0].position, Position::new(1260.0, 100.0));
+    world.step();
+    assert_eq!(world.aliens[0].position, Position::new(1260.0, 130.0));
+    world.step();
+    assert_eq!(world.aliens[0].position, Position::new(1230.0, 130.0));
+}
+
+#[test]
+fn player_bullets_destroy_aliens_and_score() {
+    let mut world = World::new();
+    world.add_player("alice");
+    // Sits right above the player and doesn't move
+    world.spawn_alien(
+        AlienKind::Alien,
+        Position::new(PLAYER_SPAWN.x - 10.0, PLAYER_SPAWN.y - 100.0),
+        0.0,
+    );
+    world.set_input("alice", firing());
+
+    for _ in 0..20 {
+        world.step();
+    }
+
+    assert!(world.aliens.is_empty());
+    assert_eq!(
+        world.player("alice").unwrap().score,
+        AlienKind::Alien.points()
+    );
+    assert_eq!(world.outcome(), Some(Outcome::Victory));
+}
+
+#[test]
+fn slow_straight_shooting_aliens_shoot_players_down() {
+    let mut world = World::new();
+    world.add_player("alice");
+    world.spawn_alien(
+        AlienKind::SlowStraightShootingAlien,
+        Position::new(PLAYER_SPAWN.x, 0.0),
+        0.0,
+    );
+
+    world.step();
+    assert_eq!(world.bullets.len(), 1);
+    assert!(!world.bullets[0].from_player);
+
+    for _ in 0..20 {
+        world.step();
+    }
+
+    assert!(!world.player("alice").unwrap().alive);
+    assert_eq!(world.outcome(), Some(Outcome::Defeat));
+}
+
+#[test]
+fn bosses_change_their_attack_and_look_with_their_phase() {
+    let boss = Boss {
+        name: "The Rock".to_string(),
+        phases: vec![
+            Phase {
+                health: 4,
+                attack: Attack::Straight,
+                fire_rate: Some(10),
+                fire_chance: 1.0,
+                bullet_speed: 20.0,
+                speed: 0.0,
+                movement: Movement::Strafe,
+            },
+            Phase {
+                health: 2,
+                attack: Attack::Volley {
+                    bullets: 3,
+                    spacing: 30.0,
+                },
+                fire_rate: Some(10),
+                fire_chance: 1.0,
+                bullet_speed: 40.0,
+                speed: 0.5,
+                movement: Movement::Sweep,
+            },
+        ],
+    };
+    let mut world = World::new();
+    world.spawn(&Spawn {
+        at: 0,
+        kind: AlienKind::SlowStraightShootingAlien,
+        position: Position::new(400.0, 0.0),
+        speed: 0.0,
+        movement: Movement::Strafe,
+        boss: Some(Arc::new(boss)),
+    });
+
+    world.step();
+    assert_eq!(world.bullets.len(), 1);
+    assert_eq!(world.bullets[0].velocity, 20.0);
+
+    let health = world.aliens[0].boss_health().unwrap();
+    assert_eq!(
+        (
+            health.health,
+            health.max_health,
+            health.phase,
+            health.phases
+        ),
+        (4, 4, 1, 2)
+    );
+    assert!(!world.aliens[0].damaged);
+
+    // The second phase moves and shoots differently, and looks damaged
+    assert!(!world.aliens[0].take_damage());
+    assert!(!world.aliens[0].damaged);
+    assert!(!world.aliens[0].take_damage());
+    let alien = &world.aliens[0];
+    assert!(alien.damaged);
+    assert_eq!((alien.speed, alien.movement), (0.5, Movement::Sweep));
+    assert_eq!(alien.boss_health().unwrap().phase, 2);
+
+    world.bullets.clear();
+    while world.bullets.is_empty() {
+        world.step();
+    }
+    let x = world.aliens[0].position.x;
+    let mut volley: Vec<f64> = world
+        .bullets
+        .iter()
+        .map(|bullet| bullet.position.x)
+        .collect();
+    volley.sort_by(f64::total_cmp);
+    assert_eq!(volley, vec![x - 30.0, x, x + 30.0]);
+    assert!(world.bullets.iter().all(|bullet| bullet.velocity == 40.0));
+
+    // Aliens that aren't bosses look damaged once half their health is gone
+    let mut alien = Alien::new(
+        1,
+        AlienKind::SlowStraightShootingAlien,
+        Position::new(0.0, 0.0),
+        1.0,
+    );
+    assert!(alien.boss_health().is_none());
+    for _ in 0..AlienKind::SlowStraightShootingAlien.health() / 2 - 1 {
+        alien.take_damage();
+    }
+    assert!(!alien.damaged);
+    alien.take_damage();
+    assert!(alien.damaged);
+}
+
+#[test]
+fn input_queues_refuse_stale_inputs_and_bursts() {
+    let mut queue = InputQueue::new();
+
+    for sequence in 1..=MAX_INPUT_BURST {
+        assert_eq!(queue.push(sequenced(sequence, firing()), 0), Ok(()));
+    }
+    assert_eq!(
+        queue.push(sequenced(MAX_INPUT_BURST + 1, firing()), 0),
+        Err(InputRejection::TooFast)
+    );
+
+    // Every tick makes room for one more
+    assert_eq!(
+        queue.push(sequenced(MAX_INPUT_BURST + 1, firing()), 1),
+        Ok(())
+    );
+    assert_eq!(
+        queue.push(sequenced(MAX_INPUT_BURST + 2, firing()), 1),
+        Err(InputRejection::TooFast)
+    );
+
+    assert_eq!(
+        queue.push(sequenced(3, firing()), 10),
+        Err(InputRejection::Stale)
+    );
+    let rewound = SequencedInput {
+        client_tick: 0,
+        ..sequenced(MAX_INPUT_BURST + 2, firing())
+    };
+    assert_eq!(queue.push(rewound, 10), Err(InputRejection::Stale));
+
+    assert_eq!(queue.len(), MAX_INPUT_BURST as usize + 1);
+    assert_eq!(queue.pop().map(|input| input.sequence), Some(1));
+}
+
+#[test]
+fn worlds_apply_one_queued_input_per_tick() {
+    let mut world = World::new();
+    world.add_player("alice");
+    let right = PlayerInput {
+        right: true,
+        ..PlayerInput::default()
+    };
+
+    for sequence in 1..=3 {
+        let input = if sequence < 3 {
+            right
+        } else {
+            PlayerInput::default()
+        };
+        assert_eq!(
+            world.queue_input("alice", sequenced(sequence, input)),
+            Some(Ok(()))
+        );
+    }
+    assert!(world.queue_input("bob", sequenced(1, right)).is_none());
+
+    let start = world.player("alice").unwrap().position.x;
+    for expected in 1..=3 {
+        world.step();
+        assert_eq!(world.player("alice").unwrap().last_input, Some(expected));
+    }
+    assert_eq!(
+        world.player("alice").unwrap().position.x,
+        start + 2.0 * Player::SPEED
+    );
+
+    // Without a new input the last one stays in effect
+    world.step();
+    let alice = world.player("alice").unwrap();
+    assert_eq!(alice.last_input, Some(3));
+    assert_eq!(alice.position.x, start + 2.0 * Player::SPEED);
+}
@@ -1,25 +1,45 @@
+use actix::Addr;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
 use service::types::User;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use uuid::Uuid;
 
 use service::application::Application;
-use service::configuration::{get_settings, Settings};
+use service::configuration::{get_settings, FilterMode, Settings};
 
 use service::database::db::DatabaseClient;
+use service::matches::manager::MatchManager;
+use service::websocket::protocol::PROTOCOL_VERSION;
+use service::websocket::server::ChatServer;
+
+pub type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 pub struct TestApp {
     pub address: String,
     pub db_client: DatabaseClient,
+    pub chat_server: Addr<ChatServer>,
+    pub match_manager: Addr<MatchManager>,
 }
 
 impl TestApp {
     pub async fn new_test_user(&self) -> Result<User, sqlx::Error> {
+        self.new_named_user("test").await
+    }
+
+    /// Creates a user whose username, password and email are all derived from `username`
+    pub async fn new_named_user(&self, username: &str) -> Result<User, sqlx::Error> {
         let user = User {
             id: None,
-            email: "test@test.com".to_string(),
-            username: "test".to_string(),
-            password: "test".to_string(),
+            email: format!("{}@test.com", username),
+            username: username.to_string(),
+            password: username.to_string(),
             authority: None,
             creation_date: None,
             uuid: None,
@@ -29,13 +49,116 @@ impl TestApp {
 
         Ok(user)
     }
+
+    /// The url of the websocket endpoint, without any token
+    pub fn websocket_address(&self) -> String {
+        format!("{}/ws", self.address.replacen("http", "ws", 1))
+    }
+
+    /// Logs in as `username` and opens a websocket session, nothing is read from it yet
+    pub async fn open_websocket(&self, username: &str) -> WebSocket {
+        self.open_websocket_at(username, self.websocket_address())
+            .await
+    }
+
+    /// Like `open_websocket`, but asks to resume the session `resume_token` belongs to
+    pub async fn resume_websocket(&self, username: &str, resume_token: &str) -> WebSocket {
+        let address = format!("{}?resume={}", self.websocket_address(), resume_token);
+        self.open_websocket_at(username, address).await
+    }
+
+    async fn open_websocket_at(&self, username: &str, address: String) -> WebSocket {
+        let (authorization, _) = self.login_as(username).await;
+
+        let mut request = address
+            .into_client_request()
+            .expect("Invalid websocket request");
+        request
+            .headers_mut()
+            .insert("authorization", authorization.parse().unwrap());
+
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("Failed to connect");
+
+        socket
+    }
+
+    /// Logs in as `username` and opens a websocket session.
+    ///
+    /// The welcome message, the session token and the member list and backlog of the global room
+    /// are consumed.
+    pub async fn connect_websocket(&self, username: &str) -> WebSocket {
+        let mut socket = self.open_websocket(username).await;
+
+        let welcome = next_json(&mut socket).await;
+        assert_eq!(welcome["type"], "welcome");
+
+        let session = next_json(&mut socket).await;
+        assert_eq!(session["type"], "session_started");
+
+        let members = next_json(&mut socket).await;
+        assert_eq!(members["type"], "room_members");
+        assert_eq!(members["room"], "global");
+
+        let backlog = next_of_type(&mut socket, "chat_history").await;
+        assert_eq!(backlog["room"], "global");
+
+        socket
+    }
+
+    /// Logs in as the test user, returns the `Authorization` header value and the refresh token
+    pub async fn login(&self) -> (String, String) {
+        self.login_as("test").await
+    }
+
+    /// Logs in as a user created by `new_named_user`
+    pub async fn login_as(&self, username: &str) -> (String, String) {
+        let mut map = HashMap::new();
+        map.insert("username", username);
+        map.insert("password", username);
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/auth/login", &self.address))
+            .json(&map)
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert!(response.status().is_success());
+
+        let authorization = response
+            .headers()
+            .get("authorization")
+            .expect("Login did not return an authorization header")
+            .to_str()
+            .expect("could not parse the header to a string")
+            .to_string();
+
+        let body: serde_json::Value = response.json().await.expect("Login body is not json");
+        let refresh_token = body["refresh_token"]
+            .as_str()
+            .expect("Login did not return a refresh token")
+            .to_string();
+
+        (authorization, refresh_token)
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, but `configure` can change the settings before the app is built
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     let settings = {
         let mut c = get_settings().expect("Failed to get settings");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.chat.filtered_words = vec!["heck".to_string()];
+        c.chat.filter_mode = FilterMode::Mask;
+        c.matches.replays = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        configure(&mut c);
         c
     };
 
@@ -46,6 +169,8 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application");
 
     let address = format!("http://127.0.0.1:{}", app.port());
+    let chat_server = app.chat_server();
+    let match_manager = app.match_manager();
 
     tokio::spawn(app.start());
 
@@ -56,6 +181,8 @@ pub async fn spawn_app() -> TestApp {
         db_client: DatabaseClient {
             pool: PgPoolOptions::new().connect_lazy_with(settings.database.with_db()),
         },
+        chat_server,
+        match_manager,
     }
 }
 
@@ -82,3 +209,60 @@ async fn new_test_database(settings: &Settings) -> PgPool {
 
     connection_pool
 }
+
+/// Waits for the next text frame and parses it as json
+pub async fn next_json(socket: &mut WebSocket) -> Value {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Text(text))) => {
+                return serde_json::from_str(&text).expect("Server sent invalid json")
+            }
+            Some(Ok(_)) => continue,
+            other => panic!("Websocket closed unexpectedly: {:?}", other),
+        }
+    }
+}
+
+/// Sends a json value as a text frame
+pub async fn send_json(socket: &mut WebSocket, value: Value) {
+    socket
+        .send(Message::Text(value.to_string()))
+        .await
+        .expect("Failed to send message");
+}
+
+/// Stamps the protocol version on a message and sends it
+pub async fn send(socket: &mut WebSocket, message: Value) {
+    let mut message = message;
+    message["protocol_version"] = json!(PROTOCOL_VERSION);
+    send_json(socket, message).await;
+}
+
+/// Creates a match, returns the `match_joined` of the creator
+pub async fn create_match(socket: &mut WebSocket, private: bool) -> Value {
+    send(socket, json!({"type": "create_match", "private": private})).await;
+    next_of_type(socket, "match_joined").await
+}
+
+/// Queues for a match of `mode` in the `eu` region, returns the `queued` message
+pub async fn join_queue(socket: &mut WebSocket, mode: &str) -> Value {
+    send(
+        socket,
+        json!({"type": "join_queue", "mode": mode, "region": "eu"}),
+    )
+    .await;
+    let queued = next_of_type(socket, "queued").await;
+    assert_eq!(queued["mode"], mode);
+    assert_eq!(queued["region"], "eu");
+    queued
+}
+
+/// Skips messages until one of type `kind` arrives
+pub async fn next_of_type(socket: &mut WebSocket, kind: &str) -> Value {
+    loop {
+        let message = next_json(socket).await;
+        if message["type"] == kind {
+            return message;
+        }
+    }
+}
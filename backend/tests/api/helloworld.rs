@@ -1,12 +1,12 @@
 use crate::general::spawn_app;
 
-#[tokio::test]
+#[actix_web::test]
 async fn helloworld_works() {
     let app = spawn_app().await;
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/helloworld", &app.address))
+        .get(format!("{}/helloworld", &app.address))
         .send()
         .await
         .expect("Failed to execute request");
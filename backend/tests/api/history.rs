@@ -0,0 +1,182 @@
+use crate::general::{next_of_type, send_json, spawn_app, TestApp};
+use chrono::{TimeDelta, Utc};
+use serde_json::{json, Value};
+use service::types::Authority;
+use service::websocket::protocol::PROTOCOL_VERSION;
+
+async fn get_history(
+    app: &TestApp,
+    username: &str,
+    room: &str,
+    query: &[(&str, &str)],
+) -> reqwest::Response {
+    let (authorization, _) = app.login_as(username).await;
+
+    reqwest::Client::new()
+        .get(format!("{}/chat/{}/history", app.address, room))
+        .header("Authorization", authorization)
+        .query(query)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn texts(body: &Value) -> Vec<&str> {
+    body["messages"]
+        .as_array()
+        .expect("No messages in the body")
+        .iter()
+        .map(|message| message["text"].as_str().unwrap())
+        .collect()
+}
+
+/// Stores `count` messages in `room`, one minute apart and ending a minute ago
+async fn seed_messages(app: &TestApp, room: &str, count: i64) {
+    for i in 0..count {
+        let sent_at = Utc::now() - TimeDelta::try_minutes(count - i).unwrap();
+        app.db_client
+            .insert_chat_message(
+                room,
+                "some-uuid",
+                "seeder",
+                &format!("message {}", i),
+                sent_at,
+            )
+            .await
+            .expect("Failed to insert a chat message");
+    }
+}
+
+#[actix_web::test]
+async fn joining_sends_the_latest_messages_of_the_room() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let backlog = next_of_type(&mut alice, "chat_history").await;
+    assert_eq!(backlog["room"], "global");
+    assert!(backlog["messages"].as_array().unwrap().is_empty());
+
+    for text in ["first", "second", "third"] {
+        send_json(
+            &mut alice,
+            json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": text}),
+        )
+        .await;
+        next_of_type(&mut alice, "chat").await;
+    }
+
+    // Messages are stored in the background, wait until they all made it to the database
+    let mut stored = 0;
+    for _ in 0..50 {
+        let body: Value = get_history(&app, "alice", "global", &[])
+            .await
+            .json()
+            .await
+            .unwrap();
+        stored = texts(&body).len();
+        if stored == 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(stored, 3);
+
+    let mut bob = app.open_websocket("bob").await;
+    let backlog = next_of_type(&mut bob, "chat_history").await;
+
+    assert_eq!(backlog["room"], "global");
+    assert_eq!(texts(&backlog), vec!["first", "second", "third"]);
+    assert_eq!(backlog["messages"][0]["username"], "alice");
+}
+
+#[actix_web::test]
+async fn history_pages_backwards_with_before() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+
+    seed_messages(&app, "global", 5).await;
+
+    let response = get_history(&app, "alice", "global", &[("limit", "2")]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let page: Value = response.json().await.unwrap();
+    assert_eq!(texts(&page), vec!["message 3", "message 4"]);
+
+    let oldest = page["messages"][0]["sent_at"].as_str().unwrap().to_string();
+    let page: Value = get_history(
+        &app,
+        "alice",
+        "global",
+        &[("limit", "2"), ("before", &oldest)],
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(texts(&page), vec!["message 1", "message 2"]);
+
+    let oldest = page["messages"][0]["sent_at"].as_str().unwrap().to_string();
+    let page: Value = get_history(
+        &app,
+        "alice",
+        "global",
+        &[("limit", "2"), ("before", &oldest)],
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(texts(&page), vec!["message 0"]);
+}
+
+#[actix_web::test]
+async fn history_of_other_rooms_is_for_members_and_moderators() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+    app.new_named_user("mod").await.unwrap();
+    app.db_client
+        .set_authority("mod", Authority::Moderator)
+        .await
+        .unwrap();
+
+    seed_messages(&app, "party:secret", 2).await;
+
+    let mut alice = app.connect_websocket("alice").await;
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "join_room", "room": "party:secret"}),
+    )
+    .await;
+    next_of_type(&mut alice, "room_members").await;
+
+    let response = get_history(&app, "alice", "party:secret", &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = get_history(&app, "bob", "party:secret", &[]).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = get_history(&app, "mod", "party:secret", &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(texts(&body).len(), 2);
+
+    let response = get_history(&app, "bob", "nonsense", &[]).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn history_requires_a_jwt() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/chat/global/history", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,460 @@
+use service::application::Application;
+use service::configuration::get_settings;
+use service::game::boss::{Attack, Phase};
+use service::game::collision::Position;
+use service::game::director::{Director, StageStarted};
+use service::game::entity::{Alien, AlienKind, Movement};
+use service::game::level::{self, Level, LevelError};
+use service::game::world::{Outcome, World};
+use service::game::{FIELD_WIDTH, TICK_RATE};
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn parse(text: &str) -> Result<Level, LevelError> {
+    level::parse(Path::new("test.toml"), text)
+}
+
+fn positions(level: &Level, stage: usize) -> Vec<(f64, f64)> {
+    level.stages[stage]
+        .spawns
+        .iter()
+        .map(|spawn| (spawn.position.x, spawn.position.y))
+        .collect()
+}
+
+/// Destroys every alien on the field
+fn clear(world: &mut World) {
+    for alien in &mut world.aliens {
+        alien.health = 0;
+    }
+}
+
+#[test]
+fn the_shipped_levels_are_valid() {
+    let levels = level::load(Path::new("levels")).expect("The shipped levels are broken");
+
+    assert_eq!(levels[0].name, "First contact");
+    let opening = &levels[0].stages[0];
+    assert_eq!(opening.delay, 0);
+    assert_eq!(opening.spawns.len(), 8);
+    assert!(opening.spawns.iter().all(|spawn| spawn.at == 0));
+    assert!(levels.iter().all(|level| level.stages.last().unwrap().boss));
+}
+
+#[test]
+fn formations_lay_out_their_aliens_at_their_time() {
+    let level = parse(
+        r#"
+        name = "Formations"
+
+        [[waves]]
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 100, y = 50 }
+        formation = { shape = "row", count = 3, spacing = 40 }
+        interval = 0.5
+
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 600, y = 50 }
+        formation = { shape = "column", count = 2, spacing = 30 }
+        delay = 0.25
+
+        [[waves]]
+        delay = 1.5
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 100, y = 100 }
+        formation = { shape = "grid", rows = 2, columns = 2, spacing_x = 50, spacing_y = 20 }
+
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 400, y = 100 }
+        formation = { shape = "wedge", count = 2, spacing_x = 10, spacing_y = 10 }
+        movement = "strafe"
+
+        [boss]
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        health = 3
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(level.stages.len(), 3);
+    let tick_rate = u64::from(TICK_RATE);
+
+    let first = &level.stages[0];
+    let times: Vec<u64> = first.spawns.iter().map(|spawn| spawn.at).collect();
+    assert_eq!(
+        times,
+        vec![0, tick_rate / 4, tick_rate / 4, tick_rate / 2, tick_rate]
+    );
+    assert_eq!(
+        positions(&level, 0),
+        vec![
+            (100.0, 50.0),
+            (600.0, 50.0),
+            (600.0, 80.0),
+            (140.0, 50.0),
+            (180.0, 50.0)
+        ]
+    );
+
+    let second = &level.stages[1];
+    assert_eq!(second.delay, tick_rate * 3 / 2);
+    assert_eq!(
+        positions(&level, 1),
+        vec![
+            (100.0, 100.0),
+            (150.0, 100.0),
+            (100.0, 120.0),
+            (150.0, 120.0),
+            // The wedge, both arms top down, then the tip
+            (400.0, 100.0),
+            (440.0, 100.0),
+            (410.0, 110.0),
+            (430.0, 110.0),
+            (420.0, 120.0),
+        ]
+    );
+    assert!(second.spawns[4..]
+        .iter()
+        .all(|spawn| spawn.movement == Movement::Strafe));
+
+    let boss = &level.stages[2];
+    assert!(boss.boss);
+    assert_eq!(boss.spawns[0].boss.as_ref().unwrap().max_health(), 3);
+    assert_eq!(boss.spawns[0].speed, 1.0);
+}
+
+#[test]
+fn broken_levels_are_rejected() {
+    let group = r#"kind = "alien"
+        origin = { x = 100, y = 100 }"#;
+
+    let unparseable = [
+        ("unknown field", "name = \"a\"\nwaves = []\nbosss = 1".to_string()),
+        (
+            "unknown kind",
+            "name = \"a\"\n[[waves]]\n[[waves.groups]]\nkind = \"ufo\"\norigin = { x = 1, y = 1 }"
+                .to_string(),
+        ),
+        (
+            "unknown formation",
+            format!("name = \"a\"\n[[waves]]\n[[waves.groups]]\n{group}\nformation = {{ shape = \"ring\" }}"),
+        ),
+        ("no name", "[[waves]]\ngroups = []".to_string()),
+    ];
+    for (case, text) in unparseable {
+        assert!(
+            matches!(parse(&text), Err(LevelError::Parse { .. })),
+            "{case} was accepted"
+        );
+    }
+
+    let invalid = [
+        ("empty name", format!("name = \" \"\n[[waves]]\n[[waves.groups]]\n{group}")),
+        ("nothing to play", "name = \"a\"".to_string()),
+        ("empty wave", "name = \"a\"\n[[waves]]\ngroups = []".to_string()),
+        (
+            "negative delay",
+            format!("name = \"a\"\n[[waves]]\ndelay = -1\n[[waves.groups]]\n{group}"),
+        ),
+        (
+            "zero speed",
+            format!("name = \"a\"\n[[waves]]\n[[waves.groups]]\n{group}\nspeed = 0"),
+        ),
+        (
+            "empty formation",
+            format!("name = \"a\"\n[[waves]]\n[[waves.groups]]\n{group}\nformation = {{ shape = \"row\", count = 0, spacing = 10 }}"),
+        ),
+        (
+            "formation off the field",
+            format!("name = \"a\"\n[[waves]]\n[[waves.groups]]\n{group}\nformation = {{ shape = \"row\", count = 30, spacing = 60 }}"),
+        ),
+        (
+            "boss off the field",
+            "name = \"a\"\n[boss]\nkind = \"slow_straight_shooting_alien\"\norigin = { x = 1100, y = 0 }"
+                .to_string(),
+        ),
+        (
+            "boss without health",
+            "name = \"a\"\n[boss]\nkind = \"alien\"\norigin = { x = 0, y = 0 }\nhealth = 0"
+                .to_string(),
+        ),
+    ];
+    let boss = "name = \"a\"\n[boss]\nkind = \"slow_straight_shooting_alien\"\norigin = { x = 0, y = 0 }\nhealth = 10";
+    let invalid_bosses = [
+        ("first phase below full health", "[[boss.phases]]\nhealth = 9"),
+        (
+            "phases out of order",
+            "[[boss.phases]]\nhealth = 10\n[[boss.phases]]\nhealth = 4\n[[boss.phases]]\nhealth = 6",
+        ),
+        (
+            "phase at zero health",
+            "[[boss.phases]]\nhealth = 10\n[[boss.phases]]\nhealth = 0",
+        ),
+        (
+            "empty volley",
+            "[[boss.phases]]\nhealth = 10\nattack = { pattern = \"volley\", bullets = 0, spacing = 10 }",
+        ),
+        ("no fire rate", "[[boss.phases]]\nhealth = 10\nfire_rate = 0"),
+    ];
+    let invalid = invalid
+        .into_iter()
+        .chain(invalid_bosses.map(|(case, phases)| (case, format!("{boss}\n{phases}"))));
+
+    for (case, text) in invalid {
+        assert!(
+            matches!(parse(&text), Err(LevelError::Invalid { .. })),
+            "{case} was accepted"
+        );
+    }
+}
+
+#[test]
+fn bosses_are_built_from_their_phases() {
+    let level = parse(
+        r#"
+        name = "Boss rush"
+
+        [boss]
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        speed = 0.1
+        movement = "strafe"
+        health = 30
+
+        [[boss.phases]]
+        health = 30
+
+        [[boss.phases]]
+        health = 12
+        attack = { pattern = "volley", bullets = 3, spacing = 40 }
+        fire_rate = 0.25
+        bullet_speed = 50
+        speed = 0.3
+        movement = "sweep"
+        "#,
+    )
+    .unwrap();
+
+    let boss = level.stages[0].spawns[0].boss.clone().unwrap();
+    assert_eq!(boss.name, "Boss rush");
+    assert_eq!(boss.max_health(), 30);
+
+    // Phases shoot like the kind and move like the boss unless they say otherwise
+    let kind = AlienKind::SlowStraightShootingAlien;
+    assert_eq!(
+        boss.phases,
+        vec![
+            Phase {
+                health: 30,
+                attack: Attack::Straight,
+                fire_rate: kind.fire_rate(),
+                fire_chance: 1.0,
+                bullet_speed: kind.bullet_speed(),
+                speed: 0.1,
+                movement: Movement::Strafe,
+            },
+            Phase {
+                health: 12,
+                attack: Attack::Volley {
+                    bullets: 3,
+                    spacing: 40.0,
+                },
+                fire_rate: Some(u64::from(TICK_RATE) / 4),
+                fire_chance: 1.0,
+                bullet_speed: 50.0,
+                speed: 0.3,
+                movement: Movement::Sweep,
+            },
+        ]
+    );
+    assert_eq!(boss.phase_at(30), 0);
+    assert_eq!(boss.phase_at(13), 0);
+    assert_eq!(boss.phase_at(12), 1);
+    assert_eq!(boss.phase_at(1), 1);
+
+    // Without phases a boss fights like its kind for its whole health
+    let level = parse(
+        r#"
+        name = "Plain"
+
+        [boss]
+        name = "Rocky"
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        "#,
+    )
+    .unwrap();
+    let boss = level.stages[0].spawns[0].boss.clone().unwrap();
+    assert_eq!(boss.name, "Rocky");
+    assert_eq!(boss.phases.len(), 1);
+    assert_eq!(boss.max_health(), kind.health());
+}
+
+#[test]
+fn levels_are_loaded_in_file_name_order() {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    assert!(matches!(
+        level::load(&directory),
+        Err(LevelError::NoLevels(_))
+    ));
+
+    let level = |name: &str| {
+        format!("name = \"{name}\"\n[boss]\nkind = \"alien\"\norigin = {{ x = 0, y = 0 }}")
+    };
+    std::fs::write(directory.join("02-second.toml"), level("second")).unwrap();
+    std::fs::write(directory.join("01-first.toml"), level("first")).unwrap();
+    std::fs::write(directory.join("notes.txt"), "not a level").unwrap();
+
+    let levels = level::load(&directory).unwrap();
+    let names: Vec<&str> = levels.iter().map(|level| level.name.as_str()).collect();
+    assert_eq!(names, vec!["first", "second"]);
+
+    // One broken file fails the whole set
+    std::fs::write(directory.join("03-broken.toml"), "name = ").unwrap();
+    assert!(matches!(
+        level::load(&directory),
+        Err(LevelError::Parse { path, .. }) if path.ends_with("03-broken.toml")
+    ));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[actix_web::test]
+async fn the_server_does_not_start_with_broken_levels() {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    std::fs::write(directory.join("01-broken.toml"), "name = \"a\"").unwrap();
+
+    let mut settings = get_settings().unwrap();
+    settings.application.port = 0;
+    settings.matches.levels = directory.clone();
+
+    let error = Application::build(settings)
+        .await
+        .err()
+        .expect("The server started with a broken level");
+    assert!(error.to_string().contains("neither waves nor a boss"));
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn the_director_plays_stages_and_levels_in_order() {
+    let first = parse(
+        r#"
+        name = "First"
+
+        [[waves]]
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 100, y = 100 }
+        formation = { shape = "row", count = 2, spacing = 50 }
+        interval = 0.5
+
+        [boss]
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        health = 3
+        delay = 1
+        "#,
+    )
+    .unwrap();
+    let second = parse(
+        r#"
+        name = "Second"
+
+        [[waves]]
+        [[waves.groups]]
+        kind = "alien"
+        origin = { x = 100, y = 100 }
+        "#,
+    )
+    .unwrap();
+
+    let tick_rate = u64::from(TICK_RATE);
+    let mut world = World::new();
+    world.add_player("player");
+    let mut director = Director::new(Arc::from(vec![first, second]));
+
+    let started = director.step(&mut world);
+    assert_eq!(
+        started,
+        Some(StageStarted {
+            level: 1,
+            name: "First".to_string(),
+            wave: 1,
+            boss: false,
+        })
+    );
+    assert_eq!(world.aliens.len(), 1);
+
+    // The second alien of the row comes after the interval, the wave isn't over before that
+    while world.tick < tick_rate / 2 {
+        clear(&mut world);
+        world.step();
+        assert_eq!(director.step(&mut world), None);
+        assert_eq!(director.outcome(&world), None);
+    }
+    assert_eq!(world.aliens.len(), 1);
+
+    // The boss comes its delay after the wave was cleared
+    clear(&mut world);
+    world.step();
+    let cleared = world.tick;
+    assert_eq!(director.step(&mut world), None);
+    while world.tick + 1 < cleared + tick_rate {
+        world.step();
+        assert_eq!(director.step(&mut world), None);
+        assert!(world.aliens.is_empty());
+        assert_eq!(director.outcome(&world), None);
+    }
+
+    world.step();
+    let started = director.step(&mut world).unwrap();
+    assert_eq!((started.level, started.wave, started.boss), (1, 2, true));
+    assert_eq!(world.aliens[0].kind, AlienKind::SlowStraightShootingAlien);
+    assert_eq!(world.aliens[0].health, 3);
+
+    clear(&mut world);
+    world.step();
+    assert_eq!(director.step(&mut world), None);
+    world.step();
+    let started = director.step(&mut world).unwrap();
+    assert_eq!((started.level, started.name.as_str()), (2, "Second"));
+
+    // Only clearing the last level wins the game
+    clear(&mut world);
+    world.step();
+    assert_eq!(director.outcome(&world), None);
+    director.step(&mut world);
+    assert!(director.is_finished());
+    assert_eq!(director.outcome(&world), Some(Outcome::Victory));
+}
+
+#[test]
+fn strafing_aliens_turn_around_without_coming_closer() {
+    let mut sweeping = Alien::new(
+        0,
+        AlienKind::Alien,
+        Position::new(FIELD_WIDTH - 30.0, 100.0),
+        1.0,
+    );
+    let mut strafing = sweeping.clone();
+    strafing.movement = Movement::Strafe;
+
+    for _ in 0..200 {
+        sweeping.update();
+        strafing.update();
+    }
+
+    assert!(sweeping.position.y > 100.0);
+    assert_eq!(strafing.position.y, 100.0);
+    assert!((0.0..FIELD_WIDTH).contains(&strafing.position.x));
+}
@@ -0,0 +1,123 @@
+use crate::general::spawn_app;
+use std::collections::HashMap;
+
+async fn verify_jwt_status(address: &str, authorization: &str) -> u16 {
+    reqwest::Client::new()
+        .get(format!("{}/auth/verify_jwt", address))
+        .header("authorization", authorization)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .status()
+        .as_u16()
+}
+
+#[actix_web::test]
+async fn logout_revokes_the_access_token() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    let (authorization, refresh_token) = app.login().await;
+    assert_eq!(verify_jwt_status(&app.address, &authorization).await, 200);
+
+    let mut map = HashMap::new();
+    map.insert("refresh_token", refresh_token.clone());
+
+    let response = client
+        .post(format!("{}/auth/logout", &app.address))
+        .header("authorization", &authorization)
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+    assert_eq!(verify_jwt_status(&app.address, &authorization).await, 401);
+
+    // The refresh token that was sent along is revoked too
+    let response = client
+        .post(format!("{}/auth/refresh", &app.address))
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn logout_only_revokes_the_current_session() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    let (first, _) = app.login().await;
+    let (second, _) = app.login().await;
+
+    let response = client
+        .post(format!("{}/auth/logout", &app.address))
+        .header("authorization", &first)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+    assert_eq!(verify_jwt_status(&app.address, &first).await, 401);
+    assert_eq!(verify_jwt_status(&app.address, &second).await, 200);
+}
+
+#[actix_web::test]
+async fn logout_all_revokes_every_session() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    let (first, _) = app.login().await;
+    let (second, refresh_token) = app.login().await;
+
+    let response = client
+        .post(format!("{}/auth/logout_all", &app.address))
+        .header("authorization", &first)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+    assert_eq!(verify_jwt_status(&app.address, &first).await, 401);
+    assert_eq!(verify_jwt_status(&app.address, &second).await, 401);
+
+    let mut map = HashMap::new();
+    map.insert("refresh_token", refresh_token);
+
+    let response = client
+        .post(format!("{}/auth/refresh", &app.address))
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn logout_requires_a_valid_jwt() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/auth/logout", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
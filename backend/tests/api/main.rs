@@ -1,5 +1,26 @@
+mod authority;
+mod chat;
+mod game;
 mod general;
 mod helloworld;
+mod history;
+mod levels;
 mod login;
+mod logout;
+mod matches;
+mod matchmaking;
+mod moderation;
+mod presence;
+mod protocol;
+mod rate_limit;
+mod ratings;
+mod refresh;
+mod replays;
+mod resume;
+mod rooms;
 mod signup;
+mod snapshots;
+mod spectators;
 mod verify_jwt;
+mod websocket;
+mod whisper;
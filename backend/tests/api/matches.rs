@@ -0,0 +1,525 @@
+use crate::general::{
+    create_match, next_json, next_of_type, send, spawn_app, spawn_app_with, TestApp, WebSocket,
+};
+use futures_util::SinkExt;
+use serde_json::{json, Value};
+use service::game::snapshot::{WorldDelta, BANDWIDTH_BUDGET};
+use service::game::world::World;
+use service::matches::manager::{ListMatches, MatchSummary};
+use service::websocket::server::GetPresence;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+/// Sends the input of client tick `sequence`
+async fn hold(socket: &mut WebSocket, sequence: u32, input: Value) {
+    send(
+        socket,
+        json!({"type": "input", "sequence": sequence, "client_tick": sequence, "input": input}),
+    )
+    .await;
+}
+
+/// Skips snapshots until one satisfies `condition`
+async fn snapshot_where(socket: &mut WebSocket, condition: impl Fn(&Value) -> bool) -> Value {
+    loop {
+        let snapshot = next_of_type(socket, "snapshot").await;
+        if condition(&snapshot) {
+            return snapshot;
+        }
+    }
+}
+
+fn player<'a>(snapshot: &'a Value, uuid: &Value) -> &'a Value {
+    snapshot["world"]["players"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|player| &player["uuid"] == uuid)
+        .expect("The player is not in the snapshot")
+}
+
+async fn running_matches(app: &TestApp) -> Vec<MatchSummary> {
+    app.match_manager.send(ListMatches).await.unwrap()
+}
+
+/// Polls the match manager until `expected` matches are running
+async fn wait_for_matches(app: &TestApp, expected: usize) -> Vec<MatchSummary> {
+    for _ in 0..50 {
+        let matches = running_matches(app).await;
+        if matches.len() == expected {
+            return matches;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Expected {} running matches", expected);
+}
+
+#[actix_web::test]
+async fn players_create_and_join_matches_and_receive_snapshots() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    let joined = create_match(&mut alice, false).await;
+    assert_eq!(joined["username"], "alice");
+    let match_id = joined["match_id"].clone();
+    let alice_uuid = joined["uuid"].clone();
+
+    send(
+        &mut bob,
+        json!({"type": "join_match", "match_id": match_id}),
+    )
+    .await;
+    let joined = next_of_type(&mut alice, "match_joined").await;
+    assert_eq!(joined["username"], "bob");
+    let bob_uuid = joined["uuid"].clone();
+    assert_eq!(
+        next_of_type(&mut bob, "match_joined").await["uuid"],
+        bob_uuid
+    );
+
+    for socket in [&mut alice, &mut bob] {
+        let snapshot = snapshot_where(socket, |s| {
+            s["world"]["players"].as_array().unwrap().len() == 2
+        })
+        .await;
+        assert_eq!(snapshot["match_id"], match_id);
+        assert!(!snapshot["world"]["aliens"].as_array().unwrap().is_empty());
+    }
+
+    let matches = running_matches(&app).await;
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].players.len(), 2);
+
+    // The server knows both are playing
+    let presence = app
+        .chat_server
+        .send(GetPresence {
+            uuids: Some(vec![alice_uuid.as_str().unwrap().to_string()]),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&presence[0]).unwrap()["activity"],
+        "in_match"
+    );
+
+    // The match has its own chat room
+    let room = format!("match:{}", match_id.as_str().unwrap());
+    send(
+        &mut bob,
+        json!({"type": "chat", "room": room, "text": "gl"}),
+    )
+    .await;
+    let message = next_of_type(&mut alice, "chat").await;
+    assert_eq!(message["room"], room);
+
+    // Nobody can be in two matches
+    send(&mut alice, json!({"type": "create_match"})).await;
+    assert_eq!(next_of_type(&mut alice, "error").await["code"], "rejected");
+}
+
+#[actix_web::test]
+async fn inputs_move_the_players_ship() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    let uuid = create_match(&mut alice, false).await["uuid"].clone();
+    let start = next_of_type(&mut alice, "snapshot").await;
+    let x = player(&start, &uuid)["position"]["x"].as_f64().unwrap();
+
+    hold(&mut alice, 1, json!({"right": true})).await;
+    let moved = snapshot_where(&mut alice, |s| {
+        player(s, &uuid)["position"]["x"].as_f64().unwrap() > x + 5.0
+    })
+    .await;
+    assert!(moved["world"]["tick"].as_u64() > start["world"]["tick"].as_u64());
+
+    // Letting go stops the ship
+    hold(&mut alice, 2, json!({})).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let first = next_of_type(&mut alice, "snapshot").await;
+    let second = snapshot_where(&mut alice, |s| s["world"]["tick"] != first["world"]["tick"]).await;
+    assert_eq!(
+        player(&first, &uuid)["position"],
+        player(&second, &uuid)["position"]
+    );
+}
+
+#[actix_web::test]
+async fn matches_are_torn_down_once_empty() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    let match_id = create_match(&mut alice, false).await["match_id"].clone();
+    send(
+        &mut bob,
+        json!({"type": "join_match", "match_id": match_id}),
+    )
+    .await;
+    next_of_type(&mut bob, "match_joined").await;
+
+    send(&mut alice, json!({"type": "leave_match"})).await;
+    let left = next_of_type(&mut bob, "match_left").await;
+    assert_eq!(left["username"], "alice");
+    assert_eq!(wait_for_matches(&app, 1).await[0].players.len(), 1);
+
+    // Closing the connection takes bob out as well, which empties the match
+    bob.send(Message::Close(None)).await.unwrap();
+    wait_for_matches(&app, 0).await;
+
+    send(
+        &mut alice,
+        json!({"type": "join_match", "match_id": match_id}),
+    )
+    .await;
+    assert_eq!(
+        next_of_type(&mut alice, "error").await["code"],
+        "match_not_found"
+    );
+
+    send(&mut alice, json!({"type": "leave_match"})).await;
+    assert_eq!(next_of_type(&mut alice, "error").await["code"], "rejected");
+}
+
+#[actix_web::test]
+async fn full_matches_turn_players_away() {
+    let app = spawn_app_with(|settings| settings.matches.max_players = 1).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    let match_id = create_match(&mut alice, false).await["match_id"].clone();
+    send(
+        &mut bob,
+        json!({"type": "join_match", "match_id": match_id}),
+    )
+    .await;
+    assert_eq!(next_of_type(&mut bob, "error").await["code"], "rejected");
+}
+
+#[actix_web::test]
+async fn concurrent_matches_are_isolated() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    let alice_match = create_match(&mut alice, false).await;
+    let bob_match = create_match(&mut bob, false).await;
+    assert_ne!(alice_match["match_id"], bob_match["match_id"]);
+    assert_eq!(wait_for_matches(&app, 2).await.len(), 2);
+
+    hold(&mut alice, 1, json!({"left": true})).await;
+    snapshot_where(&mut alice, |s| {
+        player(s, &alice_match["uuid"])["position"]["x"]
+            .as_f64()
+            .unwrap()
+            < 630.0
+    })
+    .await;
+
+    // Bob only ever sees his own match, where nothing moved his ship
+    let snapshot = next_of_type(&mut bob, "snapshot").await;
+    assert_eq!(snapshot["match_id"], bob_match["match_id"]);
+    let players = snapshot["world"]["players"].as_array().unwrap();
+    assert_eq!(players.len(), 1);
+    assert_eq!(players[0]["position"]["x"], 640.0);
+}
+
+#[actix_web::test]
+async fn snapshots_acknowledge_the_last_applied_input() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    let uuid = create_match(&mut alice, false).await["uuid"].clone();
+    let start = next_of_type(&mut alice, "snapshot").await;
+    assert_eq!(player(&start, &uuid)["last_input"], Value::Null);
+
+    for sequence in 1..=5 {
+        hold(&mut alice, sequence, json!({"right": true})).await;
+    }
+    let acknowledged = snapshot_where(&mut alice, |s| player(s, &uuid)["last_input"] == 5).await;
+
+    // Every input held the key for exactly one tick, and it stays held afterwards
+    let x = player(&acknowledged, &uuid)["position"]["x"]
+        .as_f64()
+        .unwrap();
+    let ticks =
+        acknowledged["world"]["tick"].as_u64().unwrap() - start["world"]["tick"].as_u64().unwrap();
+    assert!(x >= 645.0 && x <= 640.0 + ticks as f64);
+
+    // A replayed input is ignored
+    hold(&mut alice, 3, json!({"left": true})).await;
+    let later = snapshot_where(&mut alice, |s| {
+        s["world"]["tick"].as_u64() > acknowledged["world"]["tick"].as_u64()
+    })
+    .await;
+    assert_eq!(player(&later, &uuid)["last_input"], 5);
+    assert!(player(&later, &uuid)["position"]["x"].as_f64().unwrap() > x);
+}
+
+#[actix_web::test]
+async fn extra_inputs_do_not_speed_up_the_ship() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    let uuid = create_match(&mut alice, false).await["uuid"].clone();
+    let start = next_of_type(&mut alice, "snapshot").await;
+
+    // Far more inputs than ticks, as a speed hack would send them
+    for sequence in 1..=20 {
+        hold(&mut alice, sequence, json!({"right": true})).await;
+    }
+
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rate_limited");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let snapshot = next_of_type(&mut alice, "snapshot").await;
+    let ticks =
+        snapshot["world"]["tick"].as_u64().unwrap() - start["world"]["tick"].as_u64().unwrap();
+    let x = player(&snapshot, &uuid)["position"]["x"].as_f64().unwrap();
+    assert!(x - 640.0 <= ticks as f64);
+
+    // The inputs beyond the burst were dropped rather than queued
+    let acknowledged = player(&snapshot, &uuid)["last_input"].as_u64().unwrap();
+    assert!(acknowledged < 20);
+}
+
+async fn acknowledge(socket: &mut WebSocket, tick: &Value) {
+    send(socket, json!({"type": "ack_snapshot", "tick": tick})).await;
+}
+
+/// Reads the next snapshot or delta
+async fn next_snapshot(socket: &mut WebSocket) -> Value {
+    loop {
+        let message = next_json(socket).await;
+        if message["type"] == "snapshot" || message["type"] == "snapshot_delta" {
+            return message;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn acknowledged_snapshots_are_followed_by_deltas() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    create_match(&mut alice, false).await;
+    let full = next_of_type(&mut alice, "snapshot").await;
+    let mut world: World = serde_json::from_value(full["world"].clone()).unwrap();
+    acknowledge(&mut alice, &full["world"]["tick"]).await;
+
+    let delta = next_of_type(&mut alice, "snapshot_delta").await;
+    assert_eq!(delta["delta"]["base_tick"], full["world"]["tick"]);
+
+    // Applying every delta and acknowledging the result keeps the client in sync
+    let delta: WorldDelta = serde_json::from_value(delta["delta"].clone()).unwrap();
+    world = delta.apply(&world).unwrap();
+    acknowledge(&mut alice, &json!(world.tick)).await;
+    let next = loop {
+        let message = next_of_type(&mut alice, "snapshot_delta").await;
+        if message["delta"]["base_tick"].as_u64() == Some(world.tick) {
+            break message;
+        }
+    };
+    let delta: WorldDelta = serde_json::from_value(next["delta"].clone()).unwrap();
+    assert!(delta.apply(&world).is_some());
+
+    // A client that stops acknowledging gets a full snapshot once its last one is too old
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let message = loop {
+        let message = next_snapshot(&mut alice).await;
+        let stale = message["type"] == "snapshot_delta"
+            && message["delta"]["base_tick"].as_u64() == Some(world.tick);
+        if !stale {
+            break message;
+        }
+    };
+    assert_eq!(message["type"], "snapshot");
+}
+
+/// Reads snapshots until a delta against `base` arrives, returns the world it leads to
+async fn next_delta_against(socket: &mut WebSocket, base: &World) -> World {
+    loop {
+        let message = next_of_type(socket, "snapshot_delta").await;
+        if message["delta"]["base_tick"].as_u64() == Some(base.tick) {
+            let delta: WorldDelta = serde_json::from_value(message["delta"].clone()).unwrap();
+            return delta
+                .apply(base)
+                .expect("Delta against a snapshot that wasn't acknowledged");
+        }
+    }
+}
+
+#[actix_web::test]
+async fn every_session_of_a_player_gets_snapshots_it_can_apply() {
+    // Slow enough for the first tab's snapshot to stay diffable while the second one connects
+    let app = spawn_app_with(|settings| settings.matches.tick_rate = 5).await;
+    app.new_named_user("alice").await.unwrap();
+    let mut first = app.connect_websocket("alice").await;
+
+    create_match(&mut first, false).await;
+    let full = next_of_type(&mut first, "snapshot").await;
+    let world: World = serde_json::from_value(full["world"].clone()).unwrap();
+    acknowledge(&mut first, &full["world"]["tick"]).await;
+    let world = next_delta_against(&mut first, &world).await;
+    acknowledge(&mut first, &json!(world.tick)).await;
+
+    // A second tab of the same player has none of the snapshots the first one acknowledged
+    let mut second = app.connect_websocket("alice").await;
+    let message = next_snapshot(&mut second).await;
+    assert_eq!(message["type"], "snapshot");
+    let second_world: World = serde_json::from_value(message["world"].clone()).unwrap();
+    acknowledge(&mut second, &json!(second_world.tick)).await;
+    next_delta_against(&mut second, &second_world).await;
+
+    // The first tab keeps getting deltas against what it acknowledged itself
+    next_delta_against(&mut first, &world).await;
+}
+
+#[actix_web::test]
+async fn a_player_receives_snapshots_within_the_bandwidth_budget() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    create_match(&mut alice, false).await;
+    let full = next_of_type(&mut alice, "snapshot").await;
+    let mut world: World = serde_json::from_value(full["world"].clone()).unwrap();
+    acknowledge(&mut alice, &full["world"]["tick"]).await;
+
+    let mut bytes = 0;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        let message = next_snapshot(&mut alice).await;
+        bytes += message.to_string().len();
+
+        world = match message["type"].as_str() {
+            Some("snapshot_delta") => {
+                let delta: WorldDelta = serde_json::from_value(message["delta"].clone()).unwrap();
+                delta
+                    .apply(&world)
+                    .expect("Delta against a snapshot that wasn't acknowledged")
+            }
+            _ => serde_json::from_value(message["world"].clone()).unwrap(),
+        };
+        acknowledge(&mut alice, &json!(world.tick)).await;
+    }
+
+    assert!(bytes <= BANDWIDTH_BUDGET, "{} bytes in a second", bytes);
+}
+
+#[actix_web::test]
+async fn matches_play_the_levels_and_announce_their_waves() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    let match_id = create_match(&mut alice, false).await["match_id"].clone();
+    let started = next_of_type(&mut alice, "wave_started").await;
+    assert_eq!(started["match_id"], match_id);
+    assert_eq!(started["level"], 1);
+    assert_eq!(started["name"], "First contact");
+    assert_eq!(started["wave"], 1);
+    assert_eq!(started["boss"], false);
+
+    // The opening wave of the first level
+    let snapshot = snapshot_where(&mut alice, |s| {
+        !s["world"]["aliens"].as_array().unwrap().is_empty()
+    })
+    .await;
+    let aliens = snapshot["world"]["aliens"].as_array().unwrap();
+    assert_eq!(aliens.len(), 8);
+    assert!(aliens.iter().all(|alien| alien["movement"] == "sweep"));
+}
+
+#[actix_web::test]
+async fn boss_health_bars_are_broadcast_through_the_fight() {
+    let levels = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&levels).unwrap();
+    // Right above the spawn point, its own bullets miss the player
+    std::fs::write(
+        levels.join("01-boss.toml"),
+        r#"
+        name = "Boss"
+
+        [boss]
+        name = "Target"
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        speed = 0.001
+        health = 3
+
+        [[boss.phases]]
+        health = 3
+
+        [[boss.phases]]
+        health = 1
+        "#,
+    )
+    .unwrap();
+
+    let directory = levels.clone();
+    let app = spawn_app_with(move |settings| settings.matches.levels = directory).await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    create_match(&mut alice, false).await;
+
+    let appeared = next_of_type(&mut alice, "boss_health").await;
+    assert_eq!(appeared["name"], "Target");
+    assert_eq!(
+        (
+            &appeared["health"],
+            &appeared["max_health"],
+            &appeared["phase"],
+            &appeared["phases"]
+        ),
+        (&json!(3), &json!(3), &json!(1), &json!(2))
+    );
+
+    hold(&mut alice, 1, json!({"fire": true})).await;
+    let mut bars = Vec::new();
+    loop {
+        let bar = next_of_type(&mut alice, "boss_health").await;
+        bars.push((bar["health"].clone(), bar["phase"].clone()));
+        if bar["health"] == 0 {
+            break;
+        }
+        if bar["phase"] == 2 {
+            let snapshot = next_snapshot(&mut alice).await;
+            assert_eq!(snapshot["world"]["aliens"][0]["damaged"], true);
+        }
+    }
+    assert_eq!(
+        bars,
+        vec![
+            (json!(2), json!(1)),
+            (json!(1), json!(2)),
+            (json!(0), json!(2))
+        ]
+    );
+    assert_eq!(
+        next_of_type(&mut alice, "match_ended").await["outcome"],
+        "victory"
+    );
+
+    std::fs::remove_dir_all(&levels).unwrap();
+}
@@ -0,0 +1,306 @@
+use crate::general::{join_queue, next_of_type, send, spawn_app_with, TestApp, WebSocket};
+use serde_json::json;
+use service::configuration::{MatchmakingSettings, Settings};
+use service::matches::manager::ListMatches;
+use service::matches::matchmaking::{GameMode, Matchmaker, Ticket};
+use service::matches::MatchError;
+use std::time::{Duration, Instant};
+
+fn matchmaker() -> Matchmaker {
+    Matchmaker::new(MatchmakingSettings {
+        regions: vec!["eu".to_string(), "na".to_string()],
+        coop_players: 2,
+        initial_tolerance: 50.0,
+        tolerance_per_second: 10.0,
+        max_tolerance: 400.0,
+        accept_timeout_seconds: 15,
+        search_interval_ms: 1000,
+    })
+}
+
+fn ticket(uuid: &str, mode: GameMode, region: &str, rating: f64, queued_at: Instant) -> Ticket {
+    Ticket {
+        uuid: uuid.to_string(),
+        username: uuid.to_string(),
+        mode,
+        region: region.to_string(),
+        rating,
+        queued_at,
+    }
+}
+
+fn seconds(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+#[test]
+fn the_tolerance_widens_until_players_are_paired() {
+    let start = Instant::now();
+    let mut matchmaker = matchmaker();
+    for (uuid, rating) in [("alice", 1500.0), ("bob", 1700.0)] {
+        matchmaker
+            .enqueue(ticket(uuid, GameMode::Versus, "eu", rating, start))
+            .unwrap();
+    }
+
+    assert!(matchmaker.find_matches(start).is_empty());
+    assert!(matchmaker.find_matches(start + seconds(10)).is_empty());
+
+    let found = matchmaker.find_matches(start + seconds(15));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].uuids(), vec!["alice", "bob"]);
+    assert_eq!(found[0].expires_at, start + seconds(30));
+    assert_eq!(matchmaker.queued(), 0);
+    assert!(matchmaker.is_waiting("bob"));
+
+    let waiting = ticket("carol", GameMode::Versus, "eu", 1500.0, start);
+    assert_eq!(matchmaker.tolerance(&waiting, start + seconds(3600)), 400.0);
+    assert_eq!(
+        matchmaker.enqueue(ticket("carol", GameMode::Versus, "mars", 1500.0, start)),
+        Err(MatchError::UnknownRegion("mars".to_string()))
+    );
+}
+
+#[test]
+fn players_are_paired_with_the_closest_rating_of_their_mode_and_region() {
+    let start = Instant::now();
+    let mut matchmaker = matchmaker();
+    let tickets = [
+        ticket("alice", GameMode::Versus, "eu", 1500.0, start),
+        ticket("carol", GameMode::Versus, "na", 1500.0, start),
+        ticket("dave", GameMode::Coop, "eu", 1500.0, start),
+        ticket("erin", GameMode::Versus, "eu", 1540.0, start),
+        ticket("bob", GameMode::Versus, "eu", 1510.0, start + seconds(1)),
+    ];
+    for ticket in tickets {
+        matchmaker.enqueue(ticket).unwrap();
+    }
+
+    let found = matchmaker.find_matches(start + seconds(1));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].uuids(), vec!["alice", "bob"]);
+    assert_eq!(found[0].mode, GameMode::Versus);
+    assert_eq!(matchmaker.queued(), 3);
+
+    // Queueing again keeps the place, for another mode it starts over
+    matchmaker
+        .enqueue(ticket(
+            "dave",
+            GameMode::Coop,
+            "eu",
+            1500.0,
+            start + seconds(5),
+        ))
+        .unwrap();
+    assert_eq!(matchmaker.ticket("dave").unwrap().queued_at, start);
+    matchmaker
+        .enqueue(ticket(
+            "erin",
+            GameMode::Coop,
+            "eu",
+            1540.0,
+            start + seconds(5),
+        ))
+        .unwrap();
+    assert_eq!(
+        matchmaker.ticket("erin").unwrap().queued_at,
+        start + seconds(5)
+    );
+
+    let found = matchmaker.find_matches(start + seconds(5));
+    assert_eq!(found[0].uuids(), vec!["dave", "erin"]);
+    assert_eq!(found[0].mode, GameMode::Coop);
+}
+
+#[test]
+fn found_matches_are_accepted_declined_or_time_out() {
+    let start = Instant::now();
+    let mut matchmaker = matchmaker();
+    for (i, uuid) in ["alice", "bob", "carol", "dave"].into_iter().enumerate() {
+        matchmaker
+            .enqueue(ticket(
+                uuid,
+                GameMode::Versus,
+                "eu",
+                1500.0,
+                start + seconds(i as u64),
+            ))
+            .unwrap();
+    }
+
+    let now = start + seconds(3);
+    let found = matchmaker.find_matches(now);
+    assert_eq!(found.len(), 2);
+    let (first, second) = (&found[0].match_id, &found[1].match_id);
+
+    assert_eq!(
+        matchmaker.accept("alice", second),
+        Err(MatchError::NotFound(second.clone()))
+    );
+    assert_eq!(matchmaker.accept("alice", first), Ok(None));
+    let accepted = matchmaker.accept("bob", first).unwrap().unwrap();
+    assert_eq!(accepted.match_id, *first);
+    assert!(!matchmaker.is_waiting("alice"));
+
+    // Carol accepted and keeps the place in the queue, dave didn't answer and is out
+    matchmaker.accept("carol", second).unwrap();
+    assert!(matchmaker.expire(now + seconds(14)).is_empty());
+    let cancelled = matchmaker.expire(now + seconds(15));
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].requeued, vec!["carol"]);
+    assert_eq!(cancelled[0].dropped, vec!["dave"]);
+    assert_eq!(
+        matchmaker.ticket("carol").unwrap().queued_at,
+        start + seconds(2)
+    );
+    assert!(!matchmaker.is_waiting("dave"));
+
+    matchmaker
+        .enqueue(ticket("erin", GameMode::Versus, "eu", 1500.0, now))
+        .unwrap();
+    let found = matchmaker.find_matches(now + seconds(15));
+    let cancelled = matchmaker.decline("erin", &found[0].match_id).unwrap();
+    assert_eq!(cancelled.requeued, vec!["carol"]);
+    assert_eq!(cancelled.dropped, vec!["erin"]);
+
+    assert_eq!(matchmaker.leave("carol"), Ok(None));
+    assert_eq!(matchmaker.leave("carol"), Err(MatchError::NotQueued));
+}
+
+async fn answer(socket: &mut WebSocket, match_id: &str, accept: bool) {
+    let kind = if accept {
+        "accept_match"
+    } else {
+        "decline_match"
+    };
+    send(socket, json!({"type": kind, "match_id": match_id})).await;
+}
+
+async fn spawn_app_searching(configure: impl FnOnce(&mut Settings)) -> TestApp {
+    spawn_app_with(|settings| {
+        settings.matchmaking.search_interval_ms = 50;
+        configure(settings);
+    })
+    .await
+}
+
+#[actix_web::test]
+async fn players_found_by_matchmaking_play_together() {
+    let app = spawn_app_searching(|_| {}).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    join_queue(&mut alice, "versus").await;
+    join_queue(&mut bob, "versus").await;
+
+    let found = next_of_type(&mut alice, "match_found").await;
+    assert_eq!(next_of_type(&mut bob, "match_found").await, found);
+    let match_id = found["match_id"].as_str().unwrap().to_string();
+    assert_eq!(found["lobby"], format!("lobby:{match_id}"));
+    assert_eq!(found["players"][0]["username"], "alice");
+    assert_eq!(found["players"][1]["username"], "bob");
+    assert_eq!(found["timeout_seconds"], 15);
+
+    // The lobby is for the two of them to talk until the match starts
+    let members = next_of_type(&mut bob, "room_members").await;
+    assert_eq!(members["room"], found["lobby"]);
+    assert_eq!(members["members"].as_array().unwrap().len(), 2);
+
+    // Players with a match to accept can't start one of their own
+    send(&mut alice, json!({"type": "create_match"})).await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    answer(&mut alice, &match_id, true).await;
+    let accepted = next_of_type(&mut bob, "match_accepted").await;
+    assert_eq!(accepted["uuid"], found["players"][0]["uuid"]);
+    answer(&mut bob, &match_id, true).await;
+
+    for socket in [&mut alice, &mut bob] {
+        let joined = next_of_type(socket, "match_joined").await;
+        assert_eq!(joined["match_id"], match_id.as_str());
+    }
+
+    let matches = app.match_manager.send(ListMatches).await.unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].match_id, match_id);
+    assert_eq!(matches[0].players.len(), 2);
+}
+
+#[actix_web::test]
+async fn the_queue_survives_a_reconnect() {
+    let app = spawn_app_searching(|_| {}).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let session = next_of_type(&mut alice, "session_started").await;
+    let token = session["resume_token"].as_str().unwrap().to_string();
+    join_queue(&mut alice, "versus").await;
+
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Alice keeps waiting while the connection is gone, and gets the match found meanwhile
+    let mut bob = app.connect_websocket("bob").await;
+    join_queue(&mut bob, "versus").await;
+    let found = next_of_type(&mut bob, "match_found").await;
+
+    let mut alice = app.resume_websocket("alice", &token).await;
+    next_of_type(&mut alice, "session_resumed").await;
+    assert_eq!(next_of_type(&mut alice, "match_found").await, found);
+
+    // Bob declines, alice goes back to waiting and asking again confirms it
+    let match_id = found["match_id"].as_str().unwrap();
+    answer(&mut bob, match_id, false).await;
+    let cancelled = next_of_type(&mut alice, "match_cancelled").await;
+    assert_eq!(cancelled["match_id"], match_id);
+    assert_eq!(cancelled["requeued"], true);
+    assert_eq!(
+        next_of_type(&mut bob, "match_cancelled").await["requeued"],
+        false
+    );
+    join_queue(&mut alice, "versus").await;
+
+    send(&mut alice, json!({"type": "leave_queue"})).await;
+    next_of_type(&mut alice, "left_queue").await;
+    send(&mut alice, json!({"type": "leave_queue"})).await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["message"], "You are not waiting for a match");
+}
+
+#[actix_web::test]
+async fn unanswered_matches_time_out() {
+    let app = spawn_app_searching(|settings| settings.matchmaking.accept_timeout_seconds = 1).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    join_queue(&mut alice, "versus").await;
+    join_queue(&mut bob, "versus").await;
+    let found = next_of_type(&mut alice, "match_found").await;
+    answer(&mut alice, found["match_id"].as_str().unwrap(), true).await;
+
+    let started = Instant::now();
+    let cancelled = next_of_type(&mut alice, "match_cancelled").await;
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(cancelled["requeued"], true);
+    assert_eq!(
+        next_of_type(&mut bob, "match_cancelled").await["requeued"],
+        false
+    );
+
+    // Bob is out of the queue and free to play alone
+    send(&mut bob, json!({"type": "create_match"})).await;
+    next_of_type(&mut bob, "match_joined").await;
+    send(
+        &mut bob,
+        json!({"type": "join_queue", "mode": "versus", "region": "eu"}),
+    )
+    .await;
+    let error = next_of_type(&mut bob, "error").await;
+    assert_eq!(error["message"], "You are already in a match");
+}
@@ -0,0 +1,304 @@
+use crate::general::{next_of_type, send_json, spawn_app, TestApp, WebSocket};
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use service::configuration::{ChatSettings, FilterMode};
+use service::types::Authority;
+use service::websocket::filter::{Filtered, WordFilter};
+use service::websocket::protocol::PROTOCOL_VERSION;
+use std::collections::HashMap;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Creates a moderator called `mod` and returns its `Authorization` header value
+async fn moderator(app: &TestApp) -> String {
+    app.new_named_user("mod").await.unwrap();
+    app.db_client
+        .set_authority("mod", Authority::Moderator)
+        .await
+        .unwrap();
+
+    app.login_as("mod").await.0
+}
+
+async fn moderate(
+    app: &TestApp,
+    authorization: &str,
+    action: &str,
+    body: Value,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/moderation/{}", app.address, action))
+        .header("Authorization", authorization)
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn chat(socket: &mut WebSocket, text: &str) {
+    send_json(
+        socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": text}),
+    )
+    .await;
+}
+
+#[test]
+fn word_filter_masks_whole_words_ignoring_case() {
+    let mut settings = ChatSettings {
+        filtered_words: vec!["Heck".to_string()],
+        ..ChatSettings::default()
+    };
+
+    let filter = WordFilter::new(&settings);
+    assert_eq!(
+        filter.apply("what the HECK, heck!"),
+        Filtered::Masked("what the ****, ****!".to_string())
+    );
+    assert_eq!(
+        filter.apply("checkers anyone?"),
+        Filtered::Clean("checkers anyone?".to_string())
+    );
+
+    settings.filter_mode = FilterMode::Reject;
+    let filter = WordFilter::new(&settings);
+    assert_eq!(filter.apply("oh heck"), Filtered::Rejected);
+}
+
+#[actix_web::test]
+async fn filtered_words_are_masked_and_audited() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    chat(&mut alice, "what the heck").await;
+
+    let message = next_of_type(&mut alice, "chat").await;
+    assert_eq!(message["text"], "what the ****");
+
+    // The audit entry is written in the background
+    let mut log = Value::Null;
+    for _ in 0..50 {
+        log = reqwest::Client::new()
+            .get(format!("{}/moderation/audit", app.address))
+            .header("Authorization", &authorization)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if !log.as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    assert_eq!(log[0]["action"], "filter");
+    assert_eq!(log[0]["target_username"], "alice");
+    assert_eq!(log[0]["reason"], "what the heck");
+    assert!(log[0]["moderator_uuid"].is_null());
+}
+
+#[actix_web::test]
+async fn muted_players_can_not_chat_until_unmuted() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+
+    let response = moderate(
+        &app,
+        &authorization,
+        "mute",
+        json!({"username": "alice", "duration_seconds": 600, "reason": "spam"}),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let muted = next_of_type(&mut alice, "muted").await;
+    assert!(muted["until"].is_string());
+
+    chat(&mut alice, "let me talk").await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "muted");
+
+    // Reconnecting doesn't help
+    let mut alice = app.connect_websocket("alice").await;
+    chat(&mut alice, "let me talk").await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "muted");
+
+    let response = moderate(&app, &authorization, "unmute", json!({"username": "alice"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    next_of_type(&mut alice, "muted").await;
+
+    chat(&mut alice, "thanks").await;
+    let message = next_of_type(&mut alice, "chat").await;
+    assert_eq!(message["text"], "thanks");
+}
+
+#[actix_web::test]
+async fn mute_requires_a_duration() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let response = moderate(&app, &authorization, "mute", json!({"username": "alice"})).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn kicked_players_are_disconnected() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+
+    let response = moderate(
+        &app,
+        &authorization,
+        "kick",
+        json!({"username": "alice", "reason": "afk"}),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["sessions"], 1);
+
+    let kicked = next_of_type(&mut alice, "kicked").await;
+    assert_eq!(kicked["reason"], "afk");
+
+    loop {
+        match alice.next().await {
+            Some(Ok(Message::Close(_))) | None => break,
+            Some(Ok(_)) => continue,
+            Some(Err(_)) => break,
+        }
+    }
+}
+
+#[actix_web::test]
+async fn banned_players_can_not_log_in_until_unbanned() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let (alice_token, _) = app.login_as("alice").await;
+    let mut alice = app.connect_websocket("alice").await;
+
+    let response = moderate(&app, &authorization, "ban", json!({"username": "alice"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    next_of_type(&mut alice, "kicked").await;
+
+    let mut credentials = HashMap::new();
+    credentials.insert("username", "alice");
+    credentials.insert("password", "alice");
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/auth/login", app.address))
+        .json(&credentials)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Tokens from before the ban are revoked
+    let response = reqwest::Client::new()
+        .get(format!("{}/auth/verify_jwt", app.address))
+        .header("Authorization", alice_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = moderate(&app, &authorization, "unban", json!({"username": "alice"})).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.login_as("alice").await;
+}
+
+#[actix_web::test]
+async fn moderation_is_limited_to_lower_authorities() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("other_mod").await.unwrap();
+    app.db_client
+        .set_authority("other_mod", Authority::Moderator)
+        .await
+        .unwrap();
+
+    let response = moderate(
+        &app,
+        &authorization,
+        "kick",
+        json!({"username": "other_mod"}),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = moderate(&app, &authorization, "kick", json!({"username": "nobody"})).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let (alice_token, _) = app.login_as("alice").await;
+    let response = moderate(&app, &alice_token, "ban", json!({"username": "mod"})).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/moderation/audit", app.address))
+        .header("Authorization", alice_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[actix_web::test]
+async fn moderation_actions_are_audited() {
+    let app = spawn_app().await;
+    let authorization = moderator(&app).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    moderate(
+        &app,
+        &authorization,
+        "mute",
+        json!({"username": "alice", "duration_seconds": 60, "reason": "caps"}),
+    )
+    .await;
+    moderate(&app, &authorization, "kick", json!({"username": "bob"})).await;
+
+    let log: Value = reqwest::Client::new()
+        .get(format!("{}/moderation/audit", app.address))
+        .header("Authorization", &authorization)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(log[0]["action"], "kick");
+    assert_eq!(log[0]["target_username"], "bob");
+    assert_eq!(log[0]["moderator_username"], "mod");
+    assert_eq!(log[1]["action"], "mute");
+    assert_eq!(log[1]["reason"], "caps");
+    assert!(log[1]["expires_at"].is_string());
+
+    let log: Value = reqwest::Client::new()
+        .get(format!("{}/moderation/audit", app.address))
+        .header("Authorization", &authorization)
+        .query(&[("username", "alice")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(log.as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,135 @@
+use crate::general::{next_of_type, send_json, spawn_app, WebSocket};
+use serde_json::{json, Value};
+use service::websocket::presence::{Activity, PresenceRegistry};
+use service::websocket::protocol::{GameState, PROTOCOL_VERSION};
+
+async fn subscribe(socket: &mut WebSocket) -> Value {
+    send_json(
+        socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "subscribe_presence"}),
+    )
+    .await;
+
+    next_of_type(socket, "presence").await
+}
+
+async fn report_state(socket: &mut WebSocket, state: &str) {
+    send_json(
+        socket,
+        json!({
+            "protocol_version": PROTOCOL_VERSION,
+            "type": "game_event",
+            "event": {"kind": "state_changed", "state": state}
+        }),
+    )
+    .await;
+}
+
+#[test]
+fn game_states_map_to_activities() {
+    assert_eq!(Activity::from(GameState::Menu), Activity::Menu);
+    assert_eq!(Activity::from(GameState::Run), Activity::InMatch);
+    assert_eq!(Activity::from(GameState::Pause), Activity::InMatch);
+}
+
+#[test]
+fn players_show_the_busiest_of_their_sessions() {
+    let mut presence = PresenceRegistry::new();
+
+    let change = presence.connect(0, "uuid", "alice").unwrap();
+    assert_eq!(change.activity, Activity::Online);
+
+    // A second session that is only online doesn't change what others see
+    assert!(presence.connect(1, "uuid", "alice").is_none());
+
+    let change = presence.set_activity(1, "uuid", Activity::InMatch).unwrap();
+    assert_eq!(change.activity, Activity::InMatch);
+    assert!(presence.set_activity(0, "uuid", Activity::Menu).is_none());
+
+    let change = presence.disconnect(1, "uuid").unwrap();
+    assert_eq!(change.activity, Activity::Menu);
+
+    let change = presence.disconnect(0, "uuid").unwrap();
+    assert_eq!(change.activity, Activity::Offline);
+    assert!(presence.get("uuid").is_none());
+    assert!(presence.all().is_empty());
+}
+
+#[actix_web::test]
+async fn subscribers_are_told_about_presence_changes() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let snapshot = subscribe(&mut alice).await;
+    assert_eq!(snapshot["players"][0]["username"], "alice");
+    assert_eq!(snapshot["players"][0]["activity"], "online");
+
+    let mut bob = app.connect_websocket("bob").await;
+    let change = next_of_type(&mut alice, "presence_changed").await;
+    assert_eq!(change["username"], "bob");
+    assert_eq!(change["activity"], "online");
+
+    report_state(&mut bob, "menu").await;
+    let change = next_of_type(&mut alice, "presence_changed").await;
+    assert_eq!(change["activity"], "menu");
+
+    report_state(&mut bob, "run").await;
+    let change = next_of_type(&mut alice, "presence_changed").await;
+    assert_eq!(change["activity"], "in_match");
+
+    drop(bob);
+    let change = next_of_type(&mut alice, "presence_changed").await;
+    assert_eq!(change["username"], "bob");
+    assert_eq!(change["activity"], "offline");
+}
+
+#[actix_web::test]
+async fn presence_can_be_requested_over_http() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+    let alice_uuid = app
+        .db_client
+        .uuid_by_username("alice")
+        .await
+        .unwrap()
+        .unwrap();
+    let bob_uuid = app
+        .db_client
+        .uuid_by_username("bob")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    report_state(&mut alice, "pause").await;
+
+    // The state change is handled in the background, subscribing afterwards orders it
+    let snapshot = subscribe(&mut alice).await;
+    assert_eq!(snapshot["players"][0]["activity"], "in_match");
+
+    let (authorization, _) = app.login_as("bob").await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/presence", app.address))
+        .header("Authorization", &authorization)
+        .query(&[("uuids", format!("{},{}", alice_uuid, bob_uuid))])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: Value = response.json().await.unwrap();
+    let players = body["players"].as_array().unwrap();
+    assert_eq!(players.len(), 1);
+    assert_eq!(players[0]["uuid"], alice_uuid.as_str());
+    assert_eq!(players[0]["activity"], "in_match");
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/presence", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
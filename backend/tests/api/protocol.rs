@@ -0,0 +1,411 @@
+use crate::general::{next_json, send_json, spawn_app, TestApp, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use service::game::boss::BossHealth;
+use service::game::collision::Position;
+use service::game::director::StageStarted;
+use service::game::entity::{AlienKind, PlayerInput};
+use service::game::snapshot::WorldDelta;
+use service::game::world::{Outcome, World};
+use service::matches::matchmaking::GameMode;
+use service::matches::playback::PlaybackSpeed;
+use service::websocket::protocol::{
+    self, ClientMessage, Encoding, Envelope, GameEvent, GameState, Member, ProtocolError,
+    ServerMessage, PROTOCOL_VERSION,
+};
+use std::fmt::Debug;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Encodes and decodes a message in both formats, they have to agree with each other and lose
+/// nothing on the way
+fn assert_round_trips<T>(message: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let text = protocol::encode_json(message).unwrap();
+    let bytes = protocol::encode_binary(message).unwrap();
+
+    let from_json: T = protocol::decode_json(&text).unwrap();
+    let from_binary: T = protocol::decode_binary(&bytes).unwrap();
+
+    assert_eq!(from_json, from_binary);
+    assert_eq!(protocol::encode_json(&from_binary).unwrap(), text);
+}
+
+fn played_world(ticks: u64) -> World {
+    let mut world = World::new();
+    world.add_player("alice");
+    world.spawn_alien(AlienKind::Alien, Position::new(100.0, 100.0), 0.5);
+    world.spawn_alien(
+        AlienKind::SlowStraightShootingAlien,
+        Position::new(500.0, 0.0),
+        0.5,
+    );
+    world.set_input(
+        "alice",
+        PlayerInput {
+            left: true,
+            fire: true,
+            ..PlayerInput::default()
+        },
+    );
+    for _ in 0..ticks {
+        world.step();
+    }
+    world
+}
+
+/// Opens a session offering `protocols` next to the JWT, returns the protocol the server picked
+async fn open_with_protocols(
+    app: &TestApp,
+    username: &str,
+    protocols: &str,
+) -> (WebSocket, Option<String>) {
+    let (authorization, _) = app.login_as(username).await;
+    let jwt = authorization.trim_start_matches("Bearer ");
+
+    let mut request = app.websocket_address().into_client_request().unwrap();
+    request.headers_mut().insert(
+        "sec-websocket-protocol",
+        format!("bearer.{}, {}", jwt, protocols).parse().unwrap(),
+    );
+
+    let (socket, response) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("Failed to connect");
+    let protocol = response
+        .headers()
+        .get("sec-websocket-protocol")
+        .map(|protocol| protocol.to_str().unwrap().to_string());
+
+    (socket, protocol)
+}
+
+async fn next_binary(socket: &mut WebSocket) -> ServerMessage {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Binary(bytes))) => return protocol::decode_binary(&bytes).unwrap(),
+            Some(Ok(_)) => continue,
+            other => panic!("Websocket closed unexpectedly: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn client_messages_round_trip_through_the_envelope() {
+    let messages = vec![
+        ClientMessage::Chat {
+            room: "global".to_string(),
+            text: "a: message: with colons".to_string(),
+        },
+        ClientMessage::JoinRoom {
+            room: "party:raid".to_string(),
+        },
+        ClientMessage::Ping { nonce: Some(42) },
+        ClientMessage::GameEvent {
+            event: GameEvent::StateChanged {
+                state: GameState::Pause,
+            },
+        },
+    ];
+
+    for message in messages {
+        let text = serde_json::to_string(&Envelope::new(message.clone())).unwrap();
+        assert_eq!(protocol::decode(&text).unwrap(), message);
+    }
+}
+
+#[actix_web::test]
+async fn ping_is_answered_with_pong() {
+    let app = spawn_app().await;
+    app.new_test_user().await.unwrap();
+    let mut socket = app.connect_websocket("test").await;
+
+    send_json(
+        &mut socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "ping", "nonce": 7}),
+    )
+    .await;
+
+    let pong = next_json(&mut socket).await;
+    assert_eq!(pong["type"], "pong");
+    assert_eq!(pong["nonce"], 7);
+}
+
+#[actix_web::test]
+async fn malformed_messages_get_an_error_reply() {
+    let app = spawn_app().await;
+    app.new_test_user().await.unwrap();
+    let mut socket = app.connect_websocket("test").await;
+
+    let frames = vec![
+        Message::Text("hello: world".to_string()),
+        Message::Text(json!({"protocol_version": PROTOCOL_VERSION, "type": "dance"}).to_string()),
+        Message::Text(json!({"type": "chat", "text": "no version"}).to_string()),
+        Message::Binary(vec![1, 2, 3]),
+    ];
+
+    for frame in frames {
+        socket.send(frame).await.expect("Failed to send frame");
+
+        let error = next_json(&mut socket).await;
+        assert_eq!(error["type"], "error");
+        assert_eq!(error["code"], "malformed_message");
+    }
+}
+
+#[actix_web::test]
+async fn other_protocol_versions_are_rejected() {
+    let app = spawn_app().await;
+    app.new_test_user().await.unwrap();
+    let mut socket = app.connect_websocket("test").await;
+
+    send_json(
+        &mut socket,
+        json!({"protocol_version": PROTOCOL_VERSION + 1, "type": "chat", "text": "hi"}),
+    )
+    .await;
+
+    let error = next_json(&mut socket).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["code"], "unsupported_version");
+}
+
+#[test]
+fn game_messages_are_the_same_in_both_encodings() {
+    let base = played_world(20);
+    let world = played_world(40);
+
+    assert_round_trips(&ServerMessage::MatchJoined {
+        match_id: "match".to_string(),
+        uuid: "alice".to_string(),
+        username: "alice".to_string(),
+    });
+    assert_round_trips(&ServerMessage::Snapshot {
+        match_id: "match".to_string(),
+        world: world.clone(),
+    });
+    assert_round_trips(&ServerMessage::SnapshotDelta {
+        match_id: "match".to_string(),
+        delta: WorldDelta::between(&base, &world),
+    });
+    assert_round_trips(&ServerMessage::WaveStarted {
+        match_id: "match".to_string(),
+        stage: StageStarted {
+            level: 1,
+            name: "First contact".to_string(),
+            wave: 2,
+            boss: true,
+        },
+    });
+    assert_round_trips(&ServerMessage::BossHealth {
+        match_id: "match".to_string(),
+        health: BossHealth {
+            id: 3,
+            name: "The Rock".to_string(),
+            health: 12,
+            max_health: 20,
+            phase: 2,
+            phases: 3,
+        },
+    });
+    assert_round_trips(&ServerMessage::MatchEnded {
+        match_id: "match".to_string(),
+        outcome: Outcome::Defeat,
+    });
+
+    assert_round_trips(&ClientMessage::CreateMatch { private: true });
+    assert_round_trips(&ClientMessage::JoinMatch {
+        match_id: "match".to_string(),
+    });
+    assert_round_trips(&ClientMessage::Input {
+        sequence: 12,
+        client_tick: 400,
+        input: PlayerInput {
+            up: true,
+            fire: true,
+            ..PlayerInput::default()
+        },
+    });
+    assert_round_trips(&ClientMessage::AckSnapshot { tick: 40 });
+    assert_round_trips(&ClientMessage::SpectateMatch {
+        match_id: "match".to_string(),
+    });
+    assert_round_trips(&ServerMessage::Spectating {
+        match_id: "match".to_string(),
+        room: "spectators:match".to_string(),
+        delay_seconds: 2,
+    });
+    assert_round_trips(&ServerMessage::StoppedSpectating {
+        match_id: "match".to_string(),
+    });
+    assert_round_trips(&ClientMessage::WatchReplay {
+        match_id: "match".to_string(),
+    });
+    assert_round_trips(&ClientMessage::SetPlaybackSpeed {
+        speed: PlaybackSpeed::Quadruple,
+    });
+    assert_round_trips(&ClientMessage::SeekReplay { tick: 900 });
+    assert_round_trips(&ServerMessage::Playback {
+        match_id: "match".to_string(),
+        tick: 900,
+        final_tick: 3600,
+        speed: PlaybackSpeed::Paused,
+    });
+
+    // Chat and matchmaking can be encoded either way too, they just aren't sent in binary frames
+    assert_round_trips(&ClientMessage::Chat {
+        room: "global".to_string(),
+        text: "gg".to_string(),
+    });
+    assert_round_trips(&ClientMessage::JoinQueue {
+        mode: GameMode::Versus,
+        region: "eu".to_string(),
+    });
+    assert_round_trips(&ClientMessage::AcceptMatch {
+        match_id: "match".to_string(),
+    });
+    assert_round_trips(&ServerMessage::MatchFound {
+        match_id: "match".to_string(),
+        mode: GameMode::Coop,
+        region: "eu".to_string(),
+        lobby: "lobby:match".to_string(),
+        players: vec![Member {
+            username: "alice".to_string(),
+            uuid: "alice-uuid".to_string(),
+        }],
+        timeout_seconds: 15,
+    });
+    assert_round_trips(&ServerMessage::MatchCancelled {
+        match_id: "match".to_string(),
+        requeued: true,
+    });
+}
+
+#[test]
+fn binary_frames_are_smaller() {
+    let message = ServerMessage::Snapshot {
+        match_id: "match".to_string(),
+        world: played_world(40),
+    };
+
+    let text = protocol::encode_json(&message).unwrap();
+    let bytes = protocol::encode_binary(&message).unwrap();
+    assert!(bytes.len() < text.len());
+}
+
+#[test]
+fn binary_frames_are_checked_like_text_frames() {
+    let other_version = rmp_serde::to_vec_named(&Envelope {
+        protocol_version: PROTOCOL_VERSION + 1,
+        message: ClientMessage::CreateMatch { private: false },
+    })
+    .unwrap();
+    assert!(matches!(
+        protocol::decode_frame(&other_version),
+        Err(ProtocolError::UnsupportedVersion(_))
+    ));
+
+    assert!(matches!(
+        protocol::decode_frame(&[0xc1]),
+        Err(ProtocolError::MalformedBinary(_))
+    ));
+
+    let chat = protocol::encode_binary(&ClientMessage::Chat {
+        room: "global".to_string(),
+        text: "hi".to_string(),
+    })
+    .unwrap();
+    assert!(matches!(
+        protocol::decode_frame(&chat),
+        Err(ProtocolError::NotGameTraffic)
+    ));
+}
+
+#[test]
+fn the_first_offered_subprotocol_picks_the_encoding() {
+    assert_eq!(
+        Encoding::negotiate("bearer.x, starblazers.msgpack, starblazers"),
+        Some(Encoding::MessagePack)
+    );
+    assert_eq!(
+        Encoding::negotiate("starblazers,starblazers.msgpack"),
+        Some(Encoding::Json)
+    );
+    assert_eq!(Encoding::negotiate("bearer.x, graphql"), None);
+}
+
+#[actix_web::test]
+async fn binary_sessions_get_game_traffic_in_binary_frames() {
+    let app = spawn_app().await;
+    app.new_test_user().await.unwrap();
+
+    let (mut socket, protocol) = open_with_protocols(&app, "test", "starblazers.msgpack").await;
+    assert_eq!(protocol.as_deref(), Some("starblazers.msgpack"));
+
+    // Everything that isn't about the match is still JSON
+    assert_eq!(next_json(&mut socket).await["type"], "welcome");
+
+    let create = protocol::encode_binary(&ClientMessage::CreateMatch { private: false }).unwrap();
+    socket.send(Message::Binary(create)).await.unwrap();
+
+    let ServerMessage::MatchJoined { match_id, .. } = next_binary(&mut socket).await else {
+        panic!("Expected the match to be joined first");
+    };
+    let snapshot = loop {
+        if let ServerMessage::Snapshot { match_id, world } = next_binary(&mut socket).await {
+            break (match_id, world);
+        }
+    };
+    assert_eq!(snapshot.0, match_id);
+    assert_eq!(snapshot.1.players.len(), 1);
+
+    // Chat has to stay JSON
+    let chat = protocol::encode_binary(&ClientMessage::Chat {
+        room: "global".to_string(),
+        text: "hi".to_string(),
+    })
+    .unwrap();
+    socket.send(Message::Binary(chat)).await.unwrap();
+    let error = loop {
+        let message = next_json(&mut socket).await;
+        if message["type"] == "error" {
+            break message;
+        }
+    };
+    assert_eq!(error["code"], "malformed_message");
+}
+
+#[actix_web::test]
+async fn json_sessions_stay_json() {
+    let app = spawn_app().await;
+    app.new_test_user().await.unwrap();
+
+    let (mut socket, protocol) = open_with_protocols(&app, "test", "starblazers").await;
+    assert_eq!(protocol.as_deref(), Some("starblazers"));
+
+    send_json(
+        &mut socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "create_match"}),
+    )
+    .await;
+    loop {
+        let message = next_json(&mut socket).await;
+        if message["type"] == "snapshot" {
+            break;
+        }
+    }
+
+    let leave = protocol::encode_binary(&ClientMessage::LeaveMatch).unwrap();
+    socket.send(Message::Binary(leave)).await.unwrap();
+    let error = loop {
+        let message = next_json(&mut socket).await;
+        if message["type"] == "error" {
+            break message;
+        }
+    };
+    assert_eq!(error["code"], "malformed_message");
+}
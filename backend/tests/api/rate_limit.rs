@@ -0,0 +1,192 @@
+use crate::general::{next_json, next_of_type, send_json, spawn_app_with, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use service::configuration::RateLimitSettings;
+use service::websocket::protocol::PROTOCOL_VERSION;
+use service::websocket::rate_limit::{FloodGuard, RateLimiter, TokenBucket, Verdict};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+fn strict_settings() -> RateLimitSettings {
+    RateLimitSettings {
+        session_burst: 3.0,
+        session_rate: 0.001,
+        account_burst: 100.0,
+        account_rate: 100.0,
+        warnings: 2,
+        throttle_seconds: 60,
+        max_strikes: 5,
+        ..RateLimitSettings::default()
+    }
+}
+
+async fn ping(socket: &mut WebSocket) {
+    send_json(
+        socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "ping", "nonce": null}),
+    )
+    .await;
+}
+
+/// Reads until the server closes the connection
+async fn wait_for_close(socket: &mut WebSocket) {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return,
+            Some(Ok(_)) => continue,
+        }
+    }
+}
+
+#[test]
+fn token_bucket_refills_over_time() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(2.0, 1.0, start);
+
+    assert!(bucket.try_take(start));
+    assert!(bucket.try_take(start));
+    assert!(!bucket.try_take(start));
+
+    let later = start + Duration::from_millis(1500);
+    assert!(bucket.try_take(later));
+    assert!(!bucket.try_take(later));
+
+    assert!(bucket.is_full(later + Duration::from_secs(10)));
+}
+
+#[test]
+fn flood_guard_warns_then_drops_then_disconnects() {
+    let limiter = Arc::new(RateLimiter::new(strict_settings()));
+    let mut guard = FloodGuard::new(limiter, "uuid");
+    let now = Instant::now();
+
+    let verdicts: Vec<_> = (0..9).map(|_| guard.check(now)).collect();
+
+    assert_eq!(
+        verdicts,
+        vec![
+            Verdict::Allow,
+            Verdict::Allow,
+            Verdict::Allow,
+            Verdict::Warn,
+            Verdict::Warn,
+            Verdict::Drop { first: true },
+            Verdict::Drop { first: false },
+            Verdict::Drop { first: false },
+            Verdict::Disconnect,
+        ]
+    );
+}
+
+#[test]
+fn account_limit_is_shared_between_sessions() {
+    let limiter = Arc::new(RateLimiter::new(RateLimitSettings {
+        session_burst: 10.0,
+        account_burst: 3.0,
+        account_rate: 0.001,
+        ..strict_settings()
+    }));
+    let mut desktop = FloodGuard::new(limiter.clone(), "uuid");
+    let mut laptop = FloodGuard::new(limiter.clone(), "uuid");
+    let mut someone_else = FloodGuard::new(limiter, "other");
+    let now = Instant::now();
+
+    assert_eq!(desktop.check(now), Verdict::Allow);
+    assert_eq!(laptop.check(now), Verdict::Allow);
+    assert_eq!(desktop.check(now), Verdict::Allow);
+    assert_eq!(laptop.check(now), Verdict::Warn);
+    assert_eq!(someone_else.check(now), Verdict::Allow);
+}
+
+#[test]
+fn strikes_are_forgiven_after_a_quiet_period() {
+    let limiter = Arc::new(RateLimiter::new(RateLimitSettings {
+        session_rate: 1.0,
+        strike_decay_seconds: 5,
+        ..strict_settings()
+    }));
+    let mut guard = FloodGuard::new(limiter, "uuid");
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        guard.check(now);
+    }
+    assert_eq!(guard.check(now), Verdict::Warn);
+    assert_eq!(guard.check(now), Verdict::Warn);
+
+    let later = now + Duration::from_secs(10);
+    for _ in 0..3 {
+        assert_eq!(guard.check(later), Verdict::Allow);
+    }
+    assert_eq!(guard.check(later), Verdict::Warn);
+}
+
+#[test]
+fn inputs_have_a_bucket_of_their_own() {
+    let limiter = Arc::new(RateLimiter::new(RateLimitSettings {
+        input_burst: 5.0,
+        input_rate: 0.001,
+        ..strict_settings()
+    }));
+    let mut guard = FloodGuard::new(limiter, "uuid");
+    let now = Instant::now();
+
+    for _ in 0..5 {
+        assert_eq!(guard.check_input(now), Verdict::Allow);
+    }
+    assert_eq!(guard.check_input(now), Verdict::Warn);
+
+    // Chat and the like are unaffected
+    assert_eq!(guard.check(now), Verdict::Allow);
+}
+
+#[actix_web::test]
+async fn flooding_clients_are_warned_and_disconnected() {
+    let app = spawn_app_with(|settings| settings.rate_limit = strict_settings()).await;
+    app.new_named_user("alice").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+
+    for _ in 0..3 {
+        ping(&mut alice).await;
+        assert_eq!(next_of_type(&mut alice, "pong").await["type"], "pong");
+    }
+
+    ping(&mut alice).await;
+    let warning = next_json(&mut alice).await;
+    assert_eq!(warning["code"], "rate_limited");
+
+    for _ in 0..10 {
+        ping(&mut alice).await;
+    }
+
+    wait_for_close(&mut alice).await;
+}
+
+#[actix_web::test]
+async fn oversized_frames_and_messages_are_refused() {
+    let app = spawn_app_with(|settings| {
+        settings.rate_limit.max_frame_size = 1024;
+        settings.rate_limit.max_message_length = 10;
+    })
+    .await;
+    app.new_named_user("alice").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": "this is more than ten characters"}),
+    )
+    .await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    alice
+        .send(Message::Binary(vec![0; 4096]))
+        .await
+        .expect("Failed to send frame");
+
+    wait_for_close(&mut alice).await;
+}
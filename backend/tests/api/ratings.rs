@@ -0,0 +1,357 @@
+use crate::general::{
+    join_queue, next_of_type, send, spawn_app, spawn_app_with, TestApp, WebSocket,
+};
+use serde_json::{json, Value};
+use service::game::world::Outcome;
+use service::matches::matchmaking::GameMode;
+use service::matches::rating::{rate_match, Game, Rating, Standing, DEFAULT_RATING};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn rating(rating: f64, deviation: f64) -> Rating {
+    Rating {
+        rating,
+        deviation,
+        volatility: 0.06,
+    }
+}
+
+fn standing(uuid: &str, score: Option<u64>) -> Standing {
+    Standing {
+        uuid: uuid.to_string(),
+        score,
+        left: None,
+    }
+}
+
+#[test]
+fn ratings_follow_the_glicko2_example() {
+    let player = rating(1500.0, 200.0);
+    let games = [
+        Game {
+            opponent: rating(1400.0, 30.0),
+            score: 1.0,
+        },
+        Game {
+            opponent: rating(1550.0, 100.0),
+            score: 0.0,
+        },
+        Game {
+            opponent: rating(1700.0, 300.0),
+            score: 0.0,
+        },
+    ];
+
+    let updated = player.update(&games);
+    assert!((updated.rating - 1464.06).abs() < 0.01, "{:?}", updated);
+    assert!((updated.deviation - 151.52).abs() < 0.01, "{:?}", updated);
+    assert!(
+        (updated.volatility - 0.05999).abs() < 0.00001,
+        "{:?}",
+        updated
+    );
+
+    // Without games the rating only gets less certain, up to where new players start
+    let idle = player.update(&[]);
+    assert_eq!(idle.rating, 1500.0);
+    assert!((idle.deviation - 200.27).abs() < 0.01, "{:?}", idle);
+    assert_eq!(Rating::default().update(&[]), Rating::default());
+}
+
+#[test]
+fn versus_is_won_by_the_higher_score_and_co_op_against_the_levels() {
+    let standings = [
+        standing("alice", Some(300)),
+        standing("bob", Some(100)),
+        standing("carol", None),
+    ];
+
+    let versus = rate_match(
+        GameMode::Versus,
+        Outcome::Victory,
+        &standings,
+        &HashMap::new(),
+    );
+    assert!(versus["alice"].rating > DEFAULT_RATING);
+    assert!((versus["bob"].rating - DEFAULT_RATING).abs() < 0.01);
+    assert!(versus["carol"].rating < DEFAULT_RATING);
+    assert!(versus.values().all(|rating| rating.deviation < 350.0));
+
+    // An upset moves ratings further than the expected result
+    let ratings = HashMap::from([
+        ("alice".to_string(), rating(1400.0, 100.0)),
+        ("bob".to_string(), rating(1600.0, 100.0)),
+    ]);
+    let upset = rate_match(GameMode::Versus, Outcome::Defeat, &standings[..2], &ratings);
+    let swapped = HashMap::from([
+        ("alice".to_string(), rating(1600.0, 100.0)),
+        ("bob".to_string(), rating(1400.0, 100.0)),
+    ]);
+    let expected = rate_match(GameMode::Versus, Outcome::Defeat, &standings[..2], &swapped);
+    assert!(upset["alice"].rating - 1400.0 > expected["alice"].rating - 1600.0);
+
+    // Everyone that stayed wins or loses together, leaving is a loss
+    let coop = rate_match(
+        GameMode::Coop,
+        Outcome::Victory,
+        &standings,
+        &HashMap::new(),
+    );
+    assert_eq!(coop["alice"], coop["bob"]);
+    assert!(coop["alice"].rating > DEFAULT_RATING);
+    assert!(coop["carol"].rating < DEFAULT_RATING);
+    let lost = rate_match(GameMode::Coop, Outcome::Defeat, &standings, &HashMap::new());
+    assert_eq!(lost["alice"], lost["carol"]);
+
+    // Of the players that left, the ones that stayed longer win
+    let left = [
+        Standing {
+            left: Some(1),
+            ..standing("alice", None)
+        },
+        Standing {
+            left: Some(0),
+            ..standing("bob", None)
+        },
+    ];
+    let abandoned = rate_match(GameMode::Versus, Outcome::Defeat, &left, &HashMap::new());
+    assert!(abandoned["alice"].rating > DEFAULT_RATING);
+    assert!(abandoned["bob"].rating < DEFAULT_RATING);
+}
+
+#[actix_web::test]
+async fn matches_are_rated_once_in_a_single_transaction() {
+    let app = spawn_app().await;
+    let standings = [standing("alice", Some(300)), standing("bob", Some(100))];
+
+    let rated = app
+        .db_client
+        .rate_match("first", GameMode::Versus, Outcome::Victory, &standings)
+        .await
+        .unwrap();
+
+    let ratings = app.db_client.ratings("alice").await.unwrap();
+    assert_eq!(ratings.len(), 1);
+    assert_eq!(ratings[0].mode, GameMode::Versus);
+    assert_eq!(ratings[0].rating, rated["alice"].rating);
+    assert_eq!(ratings[0].games_played, 1);
+    assert_eq!(
+        app.db_client.rating("bob", GameMode::Versus).await.unwrap(),
+        rated["bob"]
+    );
+    assert_eq!(
+        app.db_client.rating("bob", GameMode::Coop).await.unwrap(),
+        Rating::default()
+    );
+
+    // Rating the same match again fails as a whole
+    assert!(app
+        .db_client
+        .rate_match("first", GameMode::Versus, Outcome::Victory, &standings)
+        .await
+        .is_err());
+    assert_eq!(
+        app.db_client
+            .rating("alice", GameMode::Versus)
+            .await
+            .unwrap(),
+        rated["alice"]
+    );
+
+    let rerated = app
+        .db_client
+        .rate_match("second", GameMode::Versus, Outcome::Victory, &standings)
+        .await
+        .unwrap();
+    let history = app
+        .db_client
+        .rating_history("alice", GameMode::Versus, 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|entry| entry.match_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["second", "first"]
+    );
+    assert_eq!(history[0].rating, rerated["alice"].rating);
+    assert!((history[1].rating_change - (rated["alice"].rating - DEFAULT_RATING)).abs() < 1e-9);
+    assert!(history[0].rating_change > 0.0);
+}
+
+async fn player_details(app: &TestApp, username: &str) -> Value {
+    let (authorization, _) = app.login_as(username).await;
+    reqwest::Client::new()
+        .get(format!("{}/players/player", &app.address))
+        .header("authorization", authorization)
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .expect("Player info is not json")
+}
+
+#[actix_web::test]
+async fn versus_matches_found_by_matchmaking_are_rated_when_they_end() {
+    let levels = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&levels).unwrap();
+    // A boss right above the spawn point that goes down with the first hit
+    std::fs::write(
+        levels.join("01-boss.toml"),
+        r#"
+        name = "Boss"
+
+        [boss]
+        name = "Target"
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        speed = 0.001
+        health = 1
+        "#,
+    )
+    .unwrap();
+
+    let directory = levels.clone();
+    let app = spawn_app_with(move |settings| {
+        settings.matches.levels = directory;
+        settings.matchmaking.search_interval_ms = 50;
+    })
+    .await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let details = player_details(&app, "alice").await;
+    assert_eq!(details["username"], "alice");
+    assert_eq!(details["ratings"], json!([]));
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+    assert_eq!(
+        join_queue(&mut alice, "versus").await["rating"],
+        DEFAULT_RATING
+    );
+    join_queue(&mut bob, "versus").await;
+
+    let found = next_of_type(&mut alice, "match_found").await;
+    for socket in [&mut alice, &mut bob] {
+        send(
+            socket,
+            json!({"type": "accept_match", "match_id": found["match_id"]}),
+        )
+        .await;
+    }
+    for socket in [&mut alice, &mut bob] {
+        next_of_type(socket, "match_joined").await;
+    }
+
+    // Only alice shoots, and scores
+    send(
+        &mut alice,
+        json!({"type": "input", "sequence": 1, "client_tick": 1, "input": {"fire": true}}),
+    )
+    .await;
+    assert_eq!(
+        next_of_type(&mut alice, "match_ended").await["outcome"],
+        "victory"
+    );
+
+    let mut ratings = json!([]);
+    for _ in 0..50 {
+        ratings = player_details(&app, "alice").await["ratings"].clone();
+        if ratings != json!([]) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(ratings[0]["mode"], "versus");
+    assert_eq!(ratings[0]["games_played"], 1);
+    let rating = ratings[0]["rating"].as_f64().unwrap();
+    assert!(rating > DEFAULT_RATING);
+    assert!(ratings[0]["deviation"].as_f64().unwrap() < 350.0);
+
+    let bob_rating = player_details(&app, "bob").await["ratings"][0]["rating"]
+        .as_f64()
+        .unwrap();
+    assert!(bob_rating < DEFAULT_RATING);
+
+    // The next time alice is matched by the new rating
+    assert_eq!(join_queue(&mut alice, "versus").await["rating"], rating);
+
+    std::fs::remove_dir_all(&levels).unwrap();
+}
+
+/// Queues alice and bob for `mode`, has them accept the match that was found and returns their
+/// sockets once they are in it
+async fn found_match(app: &TestApp, mode: &str) -> (WebSocket, WebSocket) {
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+    for socket in [&mut alice, &mut bob] {
+        join_queue(socket, mode).await;
+    }
+
+    let found = next_of_type(&mut alice, "match_found").await;
+    for socket in [&mut alice, &mut bob] {
+        send(
+            socket,
+            json!({"type": "accept_match", "match_id": found["match_id"]}),
+        )
+        .await;
+    }
+    for socket in [&mut alice, &mut bob] {
+        next_of_type(socket, "match_joined").await;
+    }
+
+    (alice, bob)
+}
+
+/// Polls the details of a player until they have a rating in `mode`, returns it
+async fn rating_in(app: &TestApp, username: &str, mode: &str) -> f64 {
+    for _ in 0..50 {
+        let ratings = player_details(app, username).await["ratings"].clone();
+        let rating = ratings
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|rating| rating["mode"] == mode)
+            .cloned();
+        if let Some(rating) = rating {
+            assert_eq!(rating["games_played"], 1);
+            return rating["rating"].as_f64().unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{} was never rated in {}", username, mode);
+}
+
+#[actix_web::test]
+async fn abandoned_matches_are_rated_as_a_defeat() {
+    let app = spawn_app_with(|settings| {
+        settings.matchmaking.search_interval_ms = 50;
+        settings.matchmaking.coop_players = 2;
+    })
+    .await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    // Everyone quits before the match has an outcome
+    let (mut alice, mut bob) = found_match(&app, "coop").await;
+    for socket in [&mut alice, &mut bob] {
+        send(socket, json!({"type": "leave_match"})).await;
+    }
+    assert!(rating_in(&app, "alice", "coop").await < DEFAULT_RATING);
+    assert!(rating_in(&app, "bob", "coop").await < DEFAULT_RATING);
+
+    // In versus the first to quit loses to the one that stayed longer
+    let (mut alice, mut bob) = found_match(&app, "versus").await;
+    send(&mut alice, json!({"type": "leave_match"})).await;
+    assert_eq!(
+        next_of_type(&mut bob, "match_left").await["username"],
+        "alice"
+    );
+    send(&mut bob, json!({"type": "leave_match"})).await;
+    assert!(rating_in(&app, "alice", "versus").await < DEFAULT_RATING);
+    assert!(rating_in(&app, "bob", "versus").await > DEFAULT_RATING);
+}
@@ -0,0 +1,85 @@
+use crate::general::spawn_app;
+use serde_json::Value;
+use std::collections::HashMap;
+
+async fn send_refresh(
+    address: &str,
+    client: &reqwest::Client,
+    refresh_token: &str,
+) -> reqwest::Response {
+    let mut map = HashMap::new();
+    map.insert("refresh_token", refresh_token);
+
+    client
+        .post(format!("{}/auth/refresh", address))
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[actix_web::test]
+async fn refresh_returns_a_new_token_pair() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    let (_, refresh_token) = app.login().await;
+
+    let response = send_refresh(&app.address, &client, &refresh_token).await;
+
+    assert!(response.status().is_success());
+    assert!(response
+        .headers()
+        .get("authorization")
+        .expect("No authorization header")
+        .to_str()
+        .expect("could not parse the header to a string")
+        .starts_with("Bearer "));
+
+    let body: Value = response.json().await.expect("Refresh body is not json");
+    let rotated = body["refresh_token"]
+        .as_str()
+        .expect("Refresh did not return a refresh token");
+
+    assert_ne!(rotated, refresh_token);
+}
+
+#[actix_web::test]
+async fn refresh_rejects_unknown_tokens() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = send_refresh(&app.address, &client, "not-a-refresh-token").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[actix_web::test]
+async fn reusing_a_refresh_token_revokes_the_family() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+
+    let (_, refresh_token) = app.login().await;
+
+    let response = send_refresh(&app.address, &client, &refresh_token).await;
+    assert!(response.status().is_success());
+
+    let body: Value = response.json().await.expect("Refresh body is not json");
+    let rotated = body["refresh_token"].as_str().unwrap().to_string();
+
+    // The original token has already been used, replaying it is treated as theft
+    let response = send_refresh(&app.address, &client, &refresh_token).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // ...which also invalidates the token the legitimate client received
+    let response = send_refresh(&app.address, &client, &rotated).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
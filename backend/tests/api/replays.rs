@@ -0,0 +1,476 @@
+use crate::general::{next_of_type, send_json, spawn_app, spawn_app_with, TestApp, WebSocket};
+use serde_json::{json, Value};
+use service::game::director::Director;
+use service::game::entity::PlayerInput;
+use service::game::input::SequencedInput;
+use service::game::level::{self, Level};
+use service::game::world::World;
+use service::matches::playback::{Timeline, KEYFRAME_INTERVAL};
+use service::matches::replay::{Recorder, Replay, ReplayError, ReplayHeader};
+use service::types::Authority;
+use service::websocket::protocol::PROTOCOL_VERSION;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A boss that only shoots some of the time, so the seed decides the game
+fn moody_boss() -> Vec<Level> {
+    let text = r#"
+        name = "Moody"
+
+        [boss]
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        speed = 0.5
+
+        [[boss.phases]]
+        health = 20
+        fire_rate = 0.1
+        fire_chance = 0.5
+        bullet_speed = 5
+    "#;
+    vec![level::parse(Path::new("moody.toml"), text).unwrap()]
+}
+
+fn header(seed: u64) -> ReplayHeader {
+    ReplayHeader {
+        match_id: "match".to_string(),
+        seed,
+        level: "moody".to_string(),
+        protocol_version: PROTOCOL_VERSION,
+    }
+}
+
+fn keys(tick: u64) -> PlayerInput {
+    PlayerInput {
+        left: tick % 90 < 45,
+        right: tick % 90 >= 45,
+        fire: tick.is_multiple_of(3),
+        ..PlayerInput::default()
+    }
+}
+
+/// Plays a match the way `GameMatch` does and records it. Alice sends her inputs in bursts like
+/// a client on a bad connection, Bob drops in for a while.
+fn play(seed: u64, ticks: u64) -> Replay {
+    let mut world = World::with_seed(seed);
+    let mut director = Director::new(Arc::from(moody_boss()));
+    let mut recorder = Recorder::new(header(seed));
+
+    world.add_player("alice");
+    recorder.join(&world, "alice");
+
+    let mut sequence = 0;
+    while world.tick < ticks {
+        if world.tick == 50 {
+            world.add_player("bob");
+            recorder.join(&world, "bob");
+        }
+        if world.tick == 150 {
+            world.remove_player("bob");
+            recorder.leave(&world, "bob");
+        }
+
+        if world.tick.is_multiple_of(4) {
+            for _ in 0..4 {
+                sequence += 1;
+                let input = SequencedInput {
+                    sequence,
+                    client_tick: u64::from(sequence),
+                    input: keys(u64::from(sequence)),
+                };
+                world.queue_input("alice", input);
+            }
+        }
+        if (50..150).contains(&world.tick) {
+            let input = SequencedInput {
+                sequence: world.tick as u32,
+                client_tick: world.tick,
+                input: keys(world.tick + 7),
+            };
+            world.queue_input("bob", input);
+        }
+
+        director.step(&mut world);
+        recorder.inputs(&world);
+        world.step();
+    }
+
+    recorder.finish(&world)
+}
+
+#[test]
+fn seeded_worlds_play_the_same_game() {
+    let first = play(7, 300);
+    let again = play(7, 300);
+    let other = play(8, 300);
+
+    assert_eq!(first, again);
+    assert_ne!(first.final_hash, other.final_hash);
+}
+
+#[test]
+fn recorded_matches_replay_to_the_same_state() {
+    let levels = moody_boss();
+    let replay = play(42, 300);
+    assert_eq!(replay.final_tick, 300);
+
+    let decoded = Replay::decode(&replay.encode().unwrap()).unwrap();
+    assert_eq!(decoded, replay);
+    assert_eq!(decoded.run(&levels).unwrap(), replay.final_hash);
+
+    // A single different key press ends somewhere else
+    let mut tampered = replay.clone();
+    let input = tampered
+        .ticks
+        .iter_mut()
+        .find_map(|tick| tick.inputs.first_mut())
+        .unwrap();
+    input.keys ^= 0b100;
+    assert!(matches!(
+        tampered.run(&levels),
+        Err(ReplayError::HashMismatch { .. })
+    ));
+
+    let mut seeded = replay.clone();
+    seeded.header.seed += 1;
+    assert!(matches!(
+        seeded.run(&levels),
+        Err(ReplayError::HashMismatch { .. })
+    ));
+
+    let mut unknown = replay.clone();
+    unknown.header.level = "missing".to_string();
+    assert!(matches!(
+        unknown.run(&levels),
+        Err(ReplayError::UnknownLevel(_))
+    ));
+
+    let mut outdated = replay;
+    outdated.header.protocol_version += 1;
+    assert!(matches!(
+        outdated.run(&levels),
+        Err(ReplayError::UnsupportedVersion(_))
+    ));
+
+    assert!(matches!(
+        Replay::decode(b"not a replay"),
+        Err(ReplayError::NotAReplay)
+    ));
+}
+
+#[test]
+fn replays_are_compact() {
+    let replay = play(1, 3600);
+    let bytes = replay.encode().unwrap().len();
+
+    // A minute of a player sending an input every tick
+    assert!(bytes < 12 * 3600, "{} bytes", bytes);
+}
+
+#[actix_web::test]
+async fn every_match_saves_a_replay_that_plays_back() {
+    let replays = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let directory = replays.clone();
+    let app = spawn_app_with(move |settings| settings.matches.replays = directory).await;
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    send_json(
+        &mut alice,
+        json!({"type": "create_match", "protocol_version": PROTOCOL_VERSION}),
+    )
+    .await;
+    let match_id = next_of_type(&mut alice, "match_joined").await["match_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    for sequence in 1..=30 {
+        send_json(
+            &mut alice,
+            json!({
+                "type": "input",
+                "protocol_version": PROTOCOL_VERSION,
+                "sequence": sequence,
+                "client_tick": sequence,
+                "input": {"right": sequence % 2 == 0, "fire": true},
+            }),
+        )
+        .await;
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    send_json(
+        &mut alice,
+        json!({"type": "leave_match", "protocol_version": PROTOCOL_VERSION}),
+    )
+    .await;
+    next_of_type(&mut alice, "match_left").await;
+
+    let path = replays.join(format!("{match_id}.replay"));
+    let mut bytes = None;
+    for _ in 0..50 {
+        if let Ok(read) = std::fs::read(&path) {
+            bytes = Some(read);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let replay = Replay::decode(&bytes.expect("The match saved no replay")).unwrap();
+
+    assert_eq!(replay.header.match_id, match_id);
+    assert_eq!(replay.header.level, "01-first-contact");
+    assert_eq!(replay.header.protocol_version, PROTOCOL_VERSION);
+    assert!(replay.final_tick > 0);
+
+    let levels = level::load(Path::new("levels")).unwrap();
+    assert_eq!(replay.run(&levels).unwrap(), replay.final_hash);
+
+    std::fs::remove_dir_all(&replays).unwrap();
+}
+
+#[test]
+fn seeking_lands_where_playing_got_to() {
+    let levels = moody_boss();
+    let replay = play(3, 1000);
+
+    let mut timeline = Timeline::new(replay.clone(), &levels).unwrap();
+    let mut hashes = vec![timeline.world().state_hash()];
+    while timeline.step().unwrap() {
+        hashes.push(timeline.world().state_hash());
+    }
+    assert_eq!(hashes.len() as u64, replay.final_tick + 1);
+    assert_eq!(hashes.last(), Some(&replay.final_hash));
+    assert_eq!(
+        timeline.keyframes() as u64,
+        replay.final_tick / KEYFRAME_INTERVAL + 1
+    );
+
+    // Backwards, forwards and onto the keyframes themselves
+    for tick in [0, 10, 700, 299, 300, 301, 999, 1000] {
+        timeline.seek(tick);
+        timeline.catch_up(u32::MAX).unwrap();
+        assert_eq!(timeline.tick(), tick);
+        assert_eq!(timeline.world().state_hash(), hashes[tick as usize]);
+    }
+    timeline.seek(5000);
+    timeline.catch_up(u32::MAX).unwrap();
+    assert!(timeline.is_finished());
+
+    // Seeking ahead of what was played keeps the keyframes on the way
+    let mut fresh = Timeline::new(replay, &levels).unwrap();
+    fresh.seek(650);
+    fresh.catch_up(u32::MAX).unwrap();
+    assert_eq!(fresh.keyframes(), 3);
+    assert_eq!(fresh.world().state_hash(), hashes[650]);
+
+    // A seek only simulates as many ticks as it is allowed to at a time
+    fresh.seek(0);
+    assert!(!fresh.is_seeking());
+    fresh.seek(900);
+    assert_eq!(fresh.tick(), 600);
+    let mut calls = 0;
+    while fresh.is_seeking() {
+        fresh.catch_up(40).unwrap();
+        calls += 1;
+    }
+    assert_eq!(calls, 8);
+    assert_eq!(fresh.world().state_hash(), hashes[900]);
+}
+
+/// Plays a match as `username` for `duration` and leaves it, returns the match id and the
+/// player's uuid
+async fn play_match(
+    app: &TestApp,
+    username: &str,
+    private: bool,
+    duration: Duration,
+) -> (String, String) {
+    let mut socket = app.connect_websocket(username).await;
+    send_json(
+        &mut socket,
+        json!({"type": "create_match", "protocol_version": PROTOCOL_VERSION, "private": private}),
+    )
+    .await;
+    let joined = next_of_type(&mut socket, "match_joined").await;
+
+    tokio::time::sleep(duration).await;
+    send_json(
+        &mut socket,
+        json!({"type": "leave_match", "protocol_version": PROTOCOL_VERSION}),
+    )
+    .await;
+    next_of_type(&mut socket, "match_left").await;
+
+    (
+        joined["match_id"].as_str().unwrap().to_string(),
+        joined["uuid"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn get_replays(app: &TestApp, username: &str, path: &str) -> reqwest::Response {
+    let (authorization, _) = app.login_as(username).await;
+
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .header("Authorization", authorization)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Ids of the replays `username` gets from GET /replays
+async fn listed(app: &TestApp, username: &str) -> Vec<String> {
+    let body: Value = get_replays(app, username, "/replays")
+        .await
+        .json()
+        .await
+        .unwrap();
+    body["replays"]
+        .as_array()
+        .expect("No replays in the body")
+        .iter()
+        .map(|replay| replay["match_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Waits until the replays of `count` matches are stored, the matches store them as they stop
+async fn await_replays(app: &TestApp, moderator: &str, count: usize) {
+    for _ in 0..50 {
+        if listed(app, moderator).await.len() >= count {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The replays were not stored");
+}
+
+#[actix_web::test]
+async fn private_replays_are_only_listed_for_their_players() {
+    let app = spawn_app().await;
+    for username in ["alice", "bob", "carol", "mod"] {
+        app.new_named_user(username).await.unwrap();
+    }
+    app.db_client
+        .set_authority("mod", Authority::Moderator)
+        .await
+        .unwrap();
+
+    let (public, _) = play_match(&app, "alice", false, Duration::ZERO).await;
+    let (private, bob) = play_match(&app, "bob", true, Duration::ZERO).await;
+    await_replays(&app, "mod", 2).await;
+
+    assert_eq!(listed(&app, "carol").await, vec![public.clone()]);
+    assert_eq!(
+        listed(&app, "bob").await,
+        vec![private.clone(), public.clone()]
+    );
+    assert_eq!(listed(&app, "mod").await, vec![private.clone(), public]);
+
+    let response = get_replays(&app, "carol", &format!("/replays/{private}")).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = get_replays(&app, "bob", &format!("/replays/{private}")).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let replay: Value = response.json().await.unwrap();
+    assert_eq!(replay["players"], json!([bob]));
+    assert_eq!(replay["public"], false);
+    assert_eq!(replay["level"], "01-first-contact");
+    assert_eq!(replay["outcome"], Value::Null);
+
+    let response = get_replays(&app, "bob", "/replays/missing").await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/replays", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+/// Skips messages until a `playback` one that `matches`
+async fn next_playback(socket: &mut WebSocket, matches: impl Fn(&Value) -> bool) -> Value {
+    loop {
+        let playback = next_of_type(socket, "playback").await;
+        if matches(&playback) {
+            return playback;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn replays_are_played_back_over_the_websocket() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("carol").await.unwrap();
+
+    let (match_id, _) = play_match(&app, "alice", true, Duration::from_millis(1500)).await;
+    for _ in 0..50 {
+        if !listed(&app, "alice").await.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let mut alice = app.connect_websocket("alice").await;
+    let watch =
+        json!({"type": "watch_replay", "protocol_version": PROTOCOL_VERSION, "match_id": match_id});
+    send_json(&mut alice, watch.clone()).await;
+
+    let playback = next_of_type(&mut alice, "playback").await;
+    assert_eq!(playback["match_id"], match_id.as_str());
+    assert_eq!(playback["tick"], 0);
+    assert_eq!(playback["speed"], "1x");
+    let final_tick = playback["final_tick"].as_u64().unwrap();
+    assert!(final_tick > 60);
+    let snapshot = next_of_type(&mut alice, "snapshot").await;
+    assert_eq!(snapshot["match_id"], match_id.as_str());
+
+    let speed = |speed: &str| json!({"type": "set_playback_speed", "protocol_version": PROTOCOL_VERSION, "speed": speed});
+    let seek = |tick: u64| json!({"type": "seek_replay", "protocol_version": PROTOCOL_VERSION, "tick": tick});
+
+    send_json(&mut alice, speed("paused")).await;
+    next_playback(&mut alice, |playback| playback["speed"] == "paused").await;
+
+    send_json(&mut alice, seek(5)).await;
+    let playback = next_playback(&mut alice, |playback| playback["tick"] == 5).await;
+    assert_eq!(playback["speed"], "paused");
+    loop {
+        let snapshot = next_of_type(&mut alice, "snapshot").await;
+        if snapshot["world"]["tick"] == 5 {
+            break;
+        }
+    }
+
+    // Past the end is the end, and a replay that is over stays paused
+    send_json(&mut alice, seek(u64::MAX)).await;
+    next_playback(&mut alice, |playback| playback["tick"] == final_tick).await;
+    send_json(&mut alice, speed("4x")).await;
+    let playback = next_playback(&mut alice, |_| true).await;
+    assert_eq!(playback["speed"], "paused");
+
+    // Rewound it plays to the end by itself
+    send_json(&mut alice, seek(0)).await;
+    next_playback(&mut alice, |playback| playback["tick"] == 0).await;
+    send_json(&mut alice, speed("4x")).await;
+    next_playback(&mut alice, |playback| playback["speed"] == "4x").await;
+    let playback = next_playback(&mut alice, |playback| playback["tick"] == final_tick).await;
+    assert_eq!(playback["speed"], "paused");
+
+    send_json(
+        &mut alice,
+        json!({"type": "stop_replay", "protocol_version": PROTOCOL_VERSION}),
+    )
+    .await;
+    send_json(&mut alice, seek(0)).await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    // The match was private
+    let mut carol = app.connect_websocket("carol").await;
+    send_json(&mut carol, watch).await;
+    let error = next_of_type(&mut carol, "error").await;
+    assert_eq!(error["code"], "replay_not_found");
+}
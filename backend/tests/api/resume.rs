@@ -0,0 +1,149 @@
+use crate::general::{next_json, next_of_type, send_json, spawn_app, spawn_app_with, WebSocket};
+use serde_json::{json, Value};
+use service::websocket::protocol::PROTOCOL_VERSION;
+use std::time::Duration;
+
+/// Reads the welcome and the session message of a freshly opened websocket
+async fn session_of(socket: &mut WebSocket) -> Value {
+    assert_eq!(next_json(socket).await["type"], "welcome");
+    next_json(socket).await
+}
+
+async fn chat(socket: &mut WebSocket, room: &str, text: &str) {
+    send_json(
+        socket,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "room": room, "text": text}),
+    )
+    .await;
+}
+
+/// Drops a connection without a close frame and gives the server a moment to notice
+async fn drop_connection(socket: WebSocket) {
+    drop(socket);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+}
+
+#[actix_web::test]
+async fn dropped_sessions_are_resumed_with_what_they_missed() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let session = session_of(&mut alice).await;
+    assert_eq!(session["type"], "session_started");
+    let token = session["resume_token"].as_str().unwrap().to_string();
+
+    let mut bob = app.connect_websocket("bob").await;
+    for socket in [&mut alice, &mut bob] {
+        send_json(
+            socket,
+            json!({"protocol_version": PROTOCOL_VERSION, "type": "join_room", "room": "party:raid"}),
+        )
+        .await;
+        next_of_type(socket, "room_members").await;
+    }
+
+    drop_connection(alice).await;
+    chat(&mut bob, "party:raid", "still there?").await;
+    next_of_type(&mut bob, "chat").await;
+
+    let mut alice = app.resume_websocket("alice", &token).await;
+    let session = session_of(&mut alice).await;
+    assert_eq!(session["type"], "session_resumed");
+    assert_eq!(session["truncated"], false);
+    assert_ne!(session["resume_token"], token.as_str());
+
+    // Still in the party, and the message sent while alice was gone is replayed
+    let message = next_of_type(&mut alice, "chat").await;
+    assert_eq!(message["room"], "party:raid");
+    assert_eq!(message["text"], "still there?");
+
+    chat(&mut alice, "party:raid", "back").await;
+    let message = next_of_type(&mut bob, "chat").await;
+    assert_eq!(message["text"], "back");
+
+    // Tokens are single use
+    let mut again = app.resume_websocket("alice", &token).await;
+    assert_eq!(session_of(&mut again).await["type"], "session_started");
+}
+
+#[actix_web::test]
+async fn the_replay_buffer_is_bounded() {
+    let app = spawn_app_with(|settings| settings.session.resume_buffer_length = 2).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let token = session_of(&mut alice).await["resume_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let mut bob = app.connect_websocket("bob").await;
+
+    drop_connection(alice).await;
+    for text in ["one", "two", "three", "four"] {
+        chat(&mut bob, "global", text).await;
+    }
+    next_of_type(&mut bob, "chat").await;
+
+    let mut alice = app.resume_websocket("alice", &token).await;
+    let session = session_of(&mut alice).await;
+    assert_eq!(session["missed"], 2);
+    assert_eq!(session["truncated"], true);
+
+    assert_eq!(next_of_type(&mut alice, "chat").await["text"], "three");
+    assert_eq!(next_of_type(&mut alice, "chat").await["text"], "four");
+}
+
+#[actix_web::test]
+async fn sessions_expire_after_the_grace_window() {
+    let app = spawn_app_with(|settings| settings.session.resume_grace_seconds = 1).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let token = session_of(&mut alice).await["resume_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let mut bob = app.connect_websocket("bob").await;
+    let left = next_of_type(&mut bob, "left");
+
+    drop_connection(alice).await;
+
+    // Alice only leaves once the grace window is over
+    let left = tokio::time::timeout(Duration::from_secs(5), left)
+        .await
+        .expect("Alice never left");
+    assert_eq!(left["username"], "alice");
+
+    let mut alice = app.resume_websocket("alice", &token).await;
+    assert_eq!(session_of(&mut alice).await["type"], "session_started");
+}
+
+#[actix_web::test]
+async fn closed_sessions_can_not_be_resumed() {
+    let app = spawn_app().await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.open_websocket("alice").await;
+    let token = session_of(&mut alice).await["resume_token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let mut bob = app.connect_websocket("bob").await;
+
+    alice.close(None).await.unwrap();
+    let left = next_of_type(&mut bob, "left").await;
+    assert_eq!(left["username"], "alice");
+
+    let mut alice = app.resume_websocket("alice", &token).await;
+    assert_eq!(session_of(&mut alice).await["type"], "session_started");
+
+    // Someone else's token doesn't work either
+    let mut bob_again = app.resume_websocket("bob", &token).await;
+    assert_eq!(session_of(&mut bob_again).await["type"], "session_started");
+}
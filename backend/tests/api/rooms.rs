@@ -0,0 +1,188 @@
+use crate::general::{next_of_type, send_json, spawn_app, TestApp};
+use serde_json::json;
+use service::types::LoginMethod;
+use service::websocket::protocol::PROTOCOL_VERSION;
+use service::websocket::server::{CloseRoom, OpenRoom};
+
+async fn uuid_of(app: &TestApp, username: &str) -> String {
+    app.db_client
+        .get_details_by_login_method(&LoginMethod::Username(username.to_string()))
+        .await
+        .expect("User does not exist")
+        .uuid
+}
+
+#[actix_web::test]
+async fn party_chat_only_reaches_the_party() {
+    let app = spawn_app().await;
+
+    for username in ["alice", "bob", "carol"] {
+        app.new_named_user(username).await.unwrap();
+    }
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+    let mut carol = app.connect_websocket("carol").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "join_room", "room": "party:raid"}),
+    )
+    .await;
+    let members = next_of_type(&mut alice, "room_members").await;
+    assert_eq!(members["room"], "party:raid");
+    assert_eq!(members["members"].as_array().unwrap().len(), 1);
+
+    send_json(
+        &mut bob,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "join_room", "room": "party:raid"}),
+    )
+    .await;
+    let members = next_of_type(&mut bob, "room_members").await;
+    assert_eq!(members["members"].as_array().unwrap().len(), 2);
+
+    // Alice still has bob's join in the global room queued up
+    let joined = loop {
+        let joined = next_of_type(&mut alice, "joined").await;
+        if joined["room"] == "party:raid" {
+            break joined;
+        }
+    };
+    assert_eq!(joined["username"], "bob");
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "room": "party:raid", "text": "pssst"}),
+    )
+    .await;
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": "hi all"}),
+    )
+    .await;
+
+    for socket in [&mut alice, &mut bob] {
+        let chat = next_of_type(socket, "chat").await;
+        assert_eq!(chat["room"], "party:raid");
+        assert_eq!(chat["text"], "pssst");
+    }
+
+    // Carol is not in the party, the first chat she sees is the global one
+    let chat = next_of_type(&mut carol, "chat").await;
+    assert_eq!(chat["room"], "global");
+    assert_eq!(chat["text"], "hi all");
+
+    send_json(
+        &mut bob,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "leave_room", "room": "party:raid"}),
+    )
+    .await;
+    let left = next_of_type(&mut alice, "left").await;
+    assert_eq!(left["room"], "party:raid");
+    assert_eq!(left["username"], "bob");
+}
+
+#[actix_web::test]
+async fn chatting_in_a_room_you_are_not_in_is_rejected() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "room": "party:raid", "text": "hello?"}),
+    )
+    .await;
+
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+}
+
+#[actix_web::test]
+async fn clients_can_not_join_server_managed_or_invalid_rooms() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    for room in [
+        "match:1234",
+        "spectators:1234",
+        "lobby:abc",
+        "party:",
+        "party:no spaces",
+        "nonsense",
+    ] {
+        send_json(
+            &mut alice,
+            json!({"protocol_version": PROTOCOL_VERSION, "type": "join_room", "room": room}),
+        )
+        .await;
+
+        let error = next_of_type(&mut alice, "error").await;
+        assert_eq!(
+            error["code"], "rejected",
+            "joining {} was not rejected",
+            room
+        );
+    }
+}
+
+#[actix_web::test]
+async fn match_rooms_are_opened_and_closed_by_the_server() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+
+    app.chat_server
+        .send(OpenRoom {
+            room: "match:1".to_string(),
+            uuids: vec![uuid_of(&app, "alice").await, uuid_of(&app, "bob").await],
+        })
+        .await
+        .expect("Chat server is not running");
+
+    for socket in [&mut alice, &mut bob] {
+        let members = loop {
+            let members = next_of_type(socket, "room_members").await;
+            if members["room"] == "match:1" {
+                break members;
+            }
+        };
+        assert!(!members["members"].as_array().unwrap().is_empty());
+    }
+
+    send_json(
+        &mut bob,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "room": "match:1", "text": "gg"}),
+    )
+    .await;
+    let chat = next_of_type(&mut alice, "chat").await;
+    assert_eq!(chat["room"], "match:1");
+    assert_eq!(chat["username"], "bob");
+
+    send_json(
+        &mut bob,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "leave_room", "room": "match:1"}),
+    )
+    .await;
+    let error = next_of_type(&mut bob, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    app.chat_server
+        .send(CloseRoom {
+            room: "match:1".to_string(),
+        })
+        .await
+        .expect("Chat server is not running");
+
+    for socket in [&mut alice, &mut bob] {
+        let closed = next_of_type(socket, "room_closed").await;
+        assert_eq!(closed["room"], "match:1");
+    }
+}
@@ -0,0 +1,188 @@
+use serde_json::Value;
+use service::game::collision::Position;
+use service::game::entity::{AlienKind, PlayerInput};
+use service::game::snapshot::{WorldDelta, BANDWIDTH_BUDGET};
+use service::game::world::World;
+use service::game::TICK_RATE;
+use service::websocket::protocol::{encode, encode_binary, ServerMessage};
+
+/// Snapshots per second, the default `MatchSettings::tick_rate`
+const SNAPSHOT_RATE: u32 = 30;
+
+/// A full match: four players weaving and shooting as fast as they can, a wave of aliens and a
+/// boss shooting back
+fn busy_world() -> World {
+    let mut world = World::new();
+    for i in 0..4 {
+        world.add_player(&format!("player-{}", i));
+    }
+    for player in &mut world.players {
+        player.max_bullets = 12;
+    }
+    for i in 0..16 {
+        world.spawn_alien(
+            AlienKind::Alien,
+            Position::new(60.0 * i as f64, 100.0 + 30.0 * (i % 3) as f64),
+            0.05,
+        );
+    }
+    world.spawn_alien(
+        AlienKind::SlowStraightShootingAlien,
+        Position::new(500.0, 0.0),
+        0.01,
+    );
+    world
+}
+
+fn steer(world: &mut World) {
+    let tick = world.tick;
+    for (i, player) in world.players.iter_mut().enumerate() {
+        let phase = (tick / 40 + i as u64) % 4;
+        player.input = PlayerInput {
+            up: phase == 0,
+            left: phase == 1,
+            down: phase == 2,
+            right: phase == 3,
+            fire: true,
+        };
+    }
+}
+
+/// The snapshots a match sends during `seconds`
+fn snapshots(seconds: u32) -> Vec<World> {
+    let mut world = busy_world();
+    let steps_per_snapshot = TICK_RATE / SNAPSHOT_RATE;
+
+    (0..seconds * SNAPSHOT_RATE)
+        .map(|_| {
+            for _ in 0..steps_per_snapshot {
+                steer(&mut world);
+                world.step();
+            }
+            world.clone()
+        })
+        .collect()
+}
+
+fn json(world: &World) -> Value {
+    serde_json::to_value(world).unwrap()
+}
+
+fn encoded_len(message: ServerMessage) -> usize {
+    encode(&message).unwrap().len()
+}
+
+fn binary_len(message: ServerMessage) -> usize {
+    encode_binary(&message).unwrap().len()
+}
+
+#[test]
+fn deltas_rebuild_the_snapshot_they_were_made_for() {
+    let snapshots = snapshots(3);
+
+    for gap in [1, 2, 10, 31] {
+        for pair in snapshots.windows(gap + 1) {
+            let (base, world) = (&pair[0], &pair[gap]);
+            let delta = WorldDelta::between(base, world);
+
+            let rebuilt = delta.apply(base).expect("The delta was made against base");
+            assert_eq!(json(&rebuilt), json(world), "gap of {} snapshots", gap);
+        }
+    }
+}
+
+#[test]
+fn deltas_only_apply_to_their_base() {
+    let snapshots = snapshots(1);
+    let delta = WorldDelta::between(&snapshots[3], &snapshots[5]);
+
+    assert!(delta.apply(&snapshots[4]).is_none());
+    assert!(delta.apply(&snapshots[3]).is_some());
+}
+
+#[test]
+fn deltas_leave_out_what_clients_can_predict() {
+    let snapshots = snapshots(2);
+
+    for pair in snapshots.windows(2) {
+        let delta = WorldDelta::between(&pair[0], &pair[1]);
+
+        // Bullets fly straight and aliens sweep, neither is sent while that's all they do
+        assert!(delta.bullets.changed.is_empty());
+        assert!(delta
+            .aliens
+            .changed
+            .iter()
+            .all(|alien| alien.position.is_none()));
+    }
+
+    let still = World::new();
+    assert!(WorldDelta::between(&still, &still).players.is_empty());
+}
+
+#[test]
+fn a_busy_match_stays_within_the_bandwidth_budget() {
+    let snapshots = snapshots(4);
+    let match_id = "00000000-0000-0000-0000-000000000000".to_string();
+
+    // Skip the first second, the opening has few bullets in flight
+    let second = &snapshots[SNAPSHOT_RATE as usize - 1..];
+    let bullets = second
+        .iter()
+        .map(|world| world.bullets.len())
+        .max()
+        .unwrap();
+    assert!(bullets >= 30, "Only {} bullets in flight", bullets);
+
+    let seconds = (second.len() - 1) as f64 / SNAPSHOT_RATE as f64;
+    let deltas: usize = second
+        .windows(2)
+        .map(|pair| {
+            encoded_len(ServerMessage::SnapshotDelta {
+                match_id: match_id.clone(),
+                delta: WorldDelta::between(&pair[0], &pair[1]),
+            })
+        })
+        .sum();
+    let full: usize = second[1..]
+        .iter()
+        .map(|world| {
+            encoded_len(ServerMessage::Snapshot {
+                match_id: match_id.clone(),
+                world: world.clone(),
+            })
+        })
+        .sum();
+
+    let binary: usize = second
+        .windows(2)
+        .map(|pair| {
+            binary_len(ServerMessage::SnapshotDelta {
+                match_id: match_id.clone(),
+                delta: WorldDelta::between(&pair[0], &pair[1]),
+            })
+        })
+        .sum();
+
+    let deltas_per_second = (deltas as f64 / seconds) as usize;
+    let binary_per_second = (binary as f64 / seconds) as usize;
+    let full_per_second = (full as f64 / seconds) as usize;
+    assert!(
+        deltas_per_second <= BANDWIDTH_BUDGET,
+        "{} bytes per second is over the budget of {}",
+        deltas_per_second,
+        BANDWIDTH_BUDGET
+    );
+    assert!(
+        deltas_per_second * 2 < full_per_second,
+        "deltas take {} bytes per second against {} for full snapshots",
+        deltas_per_second,
+        full_per_second
+    );
+    assert!(
+        binary_per_second < deltas_per_second,
+        "MessagePack deltas take {} bytes per second against {} for JSON",
+        binary_per_second,
+        deltas_per_second
+    );
+}
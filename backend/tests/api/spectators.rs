@@ -0,0 +1,272 @@
+use crate::general::{
+    create_match, next_json, next_of_type, send, spawn_app_with, TestApp, WebSocket,
+};
+use serde_json::json;
+use service::matches::manager::{ListMatches, MatchSummary};
+use service::types::Authority;
+use service::websocket::server::GetPresence;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+async fn spectate(socket: &mut WebSocket, match_id: &str) {
+    send(
+        socket,
+        json!({"type": "spectate_match", "match_id": match_id}),
+    )
+    .await;
+}
+
+async fn running_match(app: &TestApp) -> MatchSummary {
+    app.match_manager.send(ListMatches).await.unwrap().remove(0)
+}
+
+#[actix_web::test]
+async fn spectators_watch_matches_but_can_not_play() {
+    let app = spawn_app_with(|settings| settings.matches.spectator_delay_seconds = 0).await;
+    for username in ["alice", "carol", "dave"] {
+        app.new_named_user(username).await.unwrap();
+    }
+    let mut alice = app.connect_websocket("alice").await;
+    let mut carol = app.connect_websocket("carol").await;
+    let mut dave = app.connect_websocket("dave").await;
+
+    let joined = create_match(&mut alice, false).await;
+    let match_id = joined["match_id"].as_str().unwrap();
+    let alice_uuid = joined["uuid"].as_str().unwrap();
+    spectate(&mut carol, match_id).await;
+    let spectating = next_of_type(&mut carol, "spectating").await;
+    assert_eq!(spectating["match_id"], match_id);
+    assert_eq!(spectating["delay_seconds"], 0);
+    let room = spectating["room"].as_str().unwrap().to_string();
+    assert_eq!(room, format!("spectators:{match_id}"));
+
+    // The same stream as the players, deltas once a snapshot was acknowledged
+    let snapshot = next_of_type(&mut carol, "snapshot").await;
+    assert_eq!(snapshot["match_id"], match_id);
+    assert_eq!(
+        snapshot["world"]["players"],
+        json!([snapshot["world"]["players"][0]])
+    );
+    assert_eq!(snapshot["world"]["players"][0]["uuid"], alice_uuid);
+    send(
+        &mut carol,
+        json!({"type": "ack_snapshot", "tick": snapshot["world"]["tick"]}),
+    )
+    .await;
+    let delta = next_of_type(&mut carol, "snapshot_delta").await;
+    assert_eq!(delta["delta"]["base_tick"], snapshot["world"]["tick"]);
+
+    let running = running_match(&app).await;
+    assert_eq!(running.players, vec![alice_uuid.to_string()]);
+    assert_eq!(running.spectators.len(), 1);
+    let carol_uuid = running.spectators[0].clone();
+
+    let presence = app
+        .chat_server
+        .send(GetPresence {
+            uuids: Some(vec![carol_uuid.clone()]),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&presence[0]).unwrap()["activity"],
+        "spectating"
+    );
+
+    // Inputs of spectators go nowhere
+    send(
+        &mut carol,
+        json!({"type": "input", "sequence": 1, "client_tick": 1, "input": {"fire": true}}),
+    )
+    .await;
+    let error = next_of_type(&mut carol, "error").await;
+    assert_eq!(error["code"], "rejected");
+    assert_eq!(
+        running_match(&app).await.players,
+        vec![alice_uuid.to_string()]
+    );
+
+    // Spectators chat among themselves, the players can't read along
+    spectate(&mut dave, match_id).await;
+    next_of_type(&mut dave, "spectating").await;
+    send(
+        &mut carol,
+        json!({"type": "chat", "room": room, "text": "nice dodge"}),
+    )
+    .await;
+    let chat = next_of_type(&mut dave, "chat").await;
+    assert_eq!(chat["room"], room.as_str());
+    assert_eq!(chat["text"], "nice dodge");
+
+    send(
+        &mut alice,
+        json!({"type": "chat", "room": room, "text": "hello?"}),
+    )
+    .await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    send(&mut carol, json!({"type": "leave_match"})).await;
+    let stopped = next_of_type(&mut carol, "stopped_spectating").await;
+    assert_eq!(stopped["match_id"], match_id);
+    assert_eq!(running_match(&app).await.spectators.len(), 1);
+
+    // Spectators are let go when the match is torn down
+    send(&mut alice, json!({"type": "leave_match"})).await;
+    let stopped = next_of_type(&mut dave, "stopped_spectating").await;
+    assert_eq!(stopped["match_id"], match_id);
+}
+
+#[actix_web::test]
+async fn spectators_are_capped_and_kept_out_of_private_matches() {
+    let app = spawn_app_with(|settings| settings.matches.max_spectators = 1).await;
+    for username in ["alice", "bob", "carol", "dave", "mod"] {
+        app.new_named_user(username).await.unwrap();
+    }
+    app.db_client
+        .set_authority("mod", Authority::Moderator)
+        .await
+        .unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob = app.connect_websocket("bob").await;
+    let mut carol = app.connect_websocket("carol").await;
+    let mut dave = app.connect_websocket("dave").await;
+    let mut moderator = app.connect_websocket("mod").await;
+
+    let public = create_match(&mut alice, false).await["match_id"].clone();
+    let private = create_match(&mut bob, true).await["match_id"].clone();
+    let (public, private) = (public.as_str().unwrap(), private.as_str().unwrap());
+
+    spectate(&mut carol, public).await;
+    next_of_type(&mut carol, "spectating").await;
+    spectate(&mut dave, public).await;
+    let error = next_of_type(&mut dave, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    spectate(&mut dave, private).await;
+    let error = next_of_type(&mut dave, "error").await;
+    assert_eq!(error["code"], "match_not_found");
+
+    spectate(&mut moderator, private).await;
+    let spectating = next_of_type(&mut moderator, "spectating").await;
+    assert_eq!(spectating["match_id"], private);
+
+    // Players don't spectate on the side
+    spectate(&mut alice, private).await;
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+
+    // A spectator that joins the match plays in it instead
+    send(
+        &mut carol,
+        json!({"type": "join_match", "match_id": public}),
+    )
+    .await;
+    next_of_type(&mut carol, "stopped_spectating").await;
+    next_of_type(&mut carol, "match_joined").await;
+    spectate(&mut dave, public).await;
+    next_of_type(&mut dave, "spectating").await;
+}
+
+#[actix_web::test]
+async fn spectators_see_the_match_late() {
+    let app = spawn_app_with(|settings| settings.matches.spectator_delay_seconds = 1).await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("carol").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    let mut carol = app.connect_websocket("carol").await;
+
+    let match_id = create_match(&mut alice, false).await["match_id"].clone();
+    let match_id = match_id.as_str().unwrap();
+    let started = Instant::now();
+    spectate(&mut carol, match_id).await;
+    assert_eq!(
+        next_of_type(&mut carol, "spectating").await["delay_seconds"],
+        1
+    );
+
+    // The match can't be further along than the time it has been running
+    for _ in 0..10 {
+        let snapshot = next_of_type(&mut carol, "snapshot").await;
+        let tick = snapshot["world"]["tick"].as_u64().unwrap();
+        let elapsed = started.elapsed().as_millis() as u64;
+        assert!(
+            tick + 60 <= elapsed * 60 / 1000 + 5,
+            "saw tick {} after {}ms",
+            tick,
+            elapsed
+        );
+    }
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[actix_web::test]
+async fn spectators_see_the_rest_of_the_match_before_it_ends() {
+    let levels = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&levels).unwrap();
+    // A boss right above the spawn point that goes down with the first hit
+    std::fs::write(
+        levels.join("01-boss.toml"),
+        r#"
+        name = "Boss"
+
+        [boss]
+        name = "Target"
+        kind = "slow_straight_shooting_alien"
+        origin = { x = 500, y = 40 }
+        speed = 0.001
+        health = 1
+        "#,
+    )
+    .unwrap();
+
+    let directory = levels.clone();
+    let app = spawn_app_with(move |settings| {
+        settings.matches.levels = directory;
+        settings.matches.spectator_delay_seconds = 1;
+    })
+    .await;
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("carol").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+    let mut carol = app.connect_websocket("carol").await;
+
+    let match_id = create_match(&mut alice, false).await["match_id"].clone();
+    spectate(&mut carol, match_id.as_str().unwrap()).await;
+    next_of_type(&mut carol, "spectating").await;
+
+    send(
+        &mut alice,
+        json!({"type": "input", "sequence": 1, "client_tick": 1, "input": {"fire": true}}),
+    )
+    .await;
+    let mut last_tick = None;
+    loop {
+        let message = next_json(&mut alice).await;
+        match message["type"].as_str().unwrap() {
+            "snapshot" => last_tick = message["world"]["tick"].as_u64(),
+            "match_ended" => break,
+            _ => {}
+        }
+    }
+    let ended = Instant::now();
+
+    // Carol is still a second behind and sees the match up to its last tick first
+    let mut seen = None;
+    loop {
+        let message = next_json(&mut carol).await;
+        match message["type"].as_str().unwrap() {
+            "snapshot" => seen = message["world"]["tick"].as_u64(),
+            "match_ended" => break,
+            _ => {}
+        }
+    }
+    assert_eq!(seen, last_tick);
+    assert!(ended.elapsed() >= Duration::from_millis(900));
+    assert_eq!(
+        next_of_type(&mut carol, "stopped_spectating").await["match_id"],
+        match_id
+    );
+
+    std::fs::remove_dir_all(&levels).unwrap();
+}
@@ -1,7 +1,7 @@
 use crate::general::spawn_app;
 use service::{claims::Claims, types::LoginMethod};
 
-#[tokio::test]
+#[actix_web::test]
 async fn verify_jwt_works() {
     let app = spawn_app().await;
 
@@ -21,7 +21,7 @@ async fn verify_jwt_works() {
     let client = reqwest::Client::new();
 
     let response = client
-        .get(&format!("{}/auth/verify_jwt", &app.address))
+        .get(format!("{}/auth/verify_jwt", &app.address))
         .header("authorization", jwt)
         .send()
         .await
@@ -0,0 +1,102 @@
+use crate::general::{next_json, spawn_app};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Error;
+
+#[actix_web::test]
+async fn websocket_requires_a_jwt() {
+    let app = spawn_app().await;
+
+    let result = tokio_tungstenite::connect_async(app.websocket_address()).await;
+
+    match result {
+        Err(Error::Http(response)) => assert_eq!(response.status().as_u16(), 401),
+        other => panic!("Expected the handshake to be rejected, got {:?}", other),
+    }
+}
+
+#[actix_web::test]
+async fn websocket_welcomes_the_player_with_the_query_token() {
+    let app = spawn_app().await;
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+    let (authorization, _) = app.login().await;
+    let jwt = authorization.trim_start_matches("Bearer ");
+
+    let url = format!("{}?token={}", app.websocket_address(), jwt);
+    let (mut socket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .expect("Failed to connect");
+
+    let welcome = next_json(&mut socket).await;
+
+    assert_eq!(welcome["type"], "welcome");
+    assert_eq!(welcome["username"], "test");
+    assert_eq!(welcome["authority"], "user");
+    assert!(welcome["uuid"]
+        .as_str()
+        .is_some_and(|uuid| !uuid.is_empty()));
+}
+
+#[actix_web::test]
+async fn websocket_accepts_the_jwt_as_a_subprotocol() {
+    let app = spawn_app().await;
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+    let (authorization, _) = app.login().await;
+    let jwt = authorization.trim_start_matches("Bearer ");
+
+    let mut request = app
+        .websocket_address()
+        .into_client_request()
+        .expect("Invalid websocket request");
+    request.headers_mut().insert(
+        "sec-websocket-protocol",
+        format!("starblazers, bearer.{}", jwt).parse().unwrap(),
+    );
+
+    let (mut socket, response) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("Failed to connect");
+
+    assert_eq!(
+        response.headers().get("sec-websocket-protocol").unwrap(),
+        "starblazers"
+    );
+
+    let welcome = next_json(&mut socket).await;
+    assert_eq!(welcome["username"], "test");
+}
+
+#[actix_web::test]
+async fn websocket_rejects_revoked_tokens() {
+    let app = spawn_app().await;
+
+    app.new_test_user()
+        .await
+        .expect("Failed to create test user");
+    let (authorization, _) = app.login().await;
+
+    reqwest::Client::new()
+        .post(format!("{}/auth/logout", &app.address))
+        .header("authorization", &authorization)
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let mut request = app
+        .websocket_address()
+        .into_client_request()
+        .expect("Invalid websocket request");
+    request
+        .headers_mut()
+        .insert("authorization", authorization.parse().unwrap());
+
+    match tokio_tungstenite::connect_async(request).await {
+        Err(Error::Http(response)) => assert_eq!(response.status().as_u16(), 401),
+        other => panic!("Expected the handshake to be rejected, got {:?}", other),
+    }
+}
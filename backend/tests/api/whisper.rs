@@ -0,0 +1,136 @@
+use crate::general::{next_of_type, send_json, spawn_app, spawn_app_with};
+use serde_json::json;
+use service::websocket::protocol::PROTOCOL_VERSION;
+use std::time::Duration;
+
+#[actix_web::test]
+async fn whispers_reach_every_session_of_the_recipient_and_nobody_else() {
+    let app = spawn_app().await;
+
+    for username in ["alice", "bob", "carol"] {
+        app.new_named_user(username).await.unwrap();
+    }
+
+    let mut alice = app.connect_websocket("alice").await;
+    let mut bob_desktop = app.connect_websocket("bob").await;
+    let mut bob_laptop = app.connect_websocket("bob").await;
+    let mut carol = app.connect_websocket("carol").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "whisper", "to": "bob", "text": "psst"}),
+    )
+    .await;
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "chat", "text": "hi all"}),
+    )
+    .await;
+
+    for socket in [&mut alice, &mut bob_desktop, &mut bob_laptop] {
+        let whisper = next_of_type(socket, "whisper").await;
+        assert_eq!(whisper["from"]["username"], "alice");
+        assert_eq!(whisper["to"]["username"], "bob");
+        assert_eq!(whisper["text"], "psst");
+    }
+
+    // Carol's next message after the whisper was sent is the global chat, not the whisper
+    let message = loop {
+        let message = next_of_type(&mut carol, "chat").await;
+        if message["text"] == "hi all" {
+            break message;
+        }
+    };
+    assert_eq!(message["room"], "global");
+}
+
+#[actix_web::test]
+async fn whispers_to_offline_players_are_delivered_on_connect() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "whisper", "to": "bob", "text": "are you there?"}),
+    )
+    .await;
+
+    // The sender gets the whisper back once it is stored
+    let echo = next_of_type(&mut alice, "whisper").await;
+    assert_eq!(echo["to"]["username"], "bob");
+
+    let mut bob = app.open_websocket("bob").await;
+    let whisper = next_of_type(&mut bob, "whisper").await;
+    assert_eq!(whisper["from"]["username"], "alice");
+    assert_eq!(whisper["text"], "are you there?");
+
+    let bob_uuid = whisper["to"]["uuid"].as_str().unwrap();
+    let undelivered = app
+        .db_client
+        .take_undelivered_direct_messages(bob_uuid)
+        .await
+        .unwrap();
+    assert!(undelivered.is_empty());
+}
+
+#[actix_web::test]
+async fn whispering_an_unknown_player_is_an_error() {
+    let app = spawn_app().await;
+
+    app.new_named_user("alice").await.unwrap();
+    let mut alice = app.connect_websocket("alice").await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "whisper", "to": "nobody", "text": "hello?"}),
+    )
+    .await;
+
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "user_not_found");
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "whisper", "to": "alice", "text": "me"}),
+    )
+    .await;
+
+    let error = next_of_type(&mut alice, "error").await;
+    assert_eq!(error["code"], "rejected");
+}
+
+#[actix_web::test]
+async fn whispers_to_detached_sessions_outlive_the_grace_window() {
+    let app = spawn_app_with(|settings| settings.session.resume_grace_seconds = 1).await;
+
+    app.new_named_user("alice").await.unwrap();
+    app.new_named_user("bob").await.unwrap();
+
+    let mut alice = app.connect_websocket("alice").await;
+    let bob = app.connect_websocket("bob").await;
+
+    // Bob's connection drops without a goodbye, his session waits to be resumed
+    drop(bob);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    send_json(
+        &mut alice,
+        json!({"protocol_version": PROTOCOL_VERSION, "type": "whisper", "to": "bob", "text": "still there?"}),
+    )
+    .await;
+    next_of_type(&mut alice, "whisper").await;
+
+    // The session expires, the whisper is waiting for bob's next connection
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let mut bob = app.open_websocket("bob").await;
+    let whisper = tokio::time::timeout(Duration::from_secs(5), next_of_type(&mut bob, "whisper"))
+        .await
+        .expect("The whisper was lost");
+    assert_eq!(whisper["from"]["username"], "alice");
+    assert_eq!(whisper["text"], "still there?");
+}
@@ -4,12 +4,15 @@ import { ChatInput } from './chatinput';
 import { ChatMessage } from './chatmessage';
 import { WebSocketManager } from '../websocketmanager';
 import type { DevConsole } from '$lib/dev_console';
+import { parseServerMessage } from '../protocol';
 
 export class ChatBox {
 	chatLog: ChatLog;
 	chatInput: ChatInput;
 	websocket: WebSocketManager;
 	readonly user: User;
+	// Index of the first websocket message that has not been handled yet
+	private received: number = 0;
 
 	constructor(user: User, websocket: WebSocketManager) {
 		this.user = user;
@@ -19,12 +22,15 @@ export class ChatBox {
 	}
 
 	handleSendMessage(text: string): void {
-		// Logic to send message to the server
-		// For now, just add it to the chat history
-		const userMessage = new ChatMessage(this.user, text);
-		this.chatLog.addMessage(userMessage);
+		// `/w <username> <text>` whispers to a single player
+		const whisper = text.match(/^\/w\s+(\S+)\s+(.+)$/);
+		if (whisper !== null) {
+			this.websocket.sendMessage({ type: 'whisper', to: whisper[1], text: whisper[2] });
+			return;
+		}
 
-		this.websocket.sendMessage(text);
+		// The server sends our own message back, it's added to the chat history then
+		this.websocket.sendMessage({ type: 'chat', text: text });
 	}
 
 	getChatInputElement(): HTMLInputElement | null {
@@ -38,25 +44,76 @@ export class ChatBox {
 	receiveMessage() {
 		const websocketMessages = this.websocket.getMessages();
 
-		if (this.chatLog.messages.length != websocketMessages.length) {
-			const message = websocketMessages[websocketMessages.length - 1];
-			if (message == undefined) {
-				return;
-			}
+		while (this.received < websocketMessages.length) {
+			const message = parseServerMessage(websocketMessages[this.received]);
+			this.received += 1;
 
-			if (this.user.username == '' && message.includes('YOUR ID IS:')) {
-				this.user.username = message.split(': ')[1];
-				this.websocket.messages.pop();
-				return;
+			if (message === null) {
+				continue;
 			}
 
-			const split = message.split(':');
-			const u = split[0];
-			const t = split[1];
-
-			const user = new User(u);
-			const msg = new ChatMessage(user, t);
-			this.chatLog.addMessage(msg);
+			switch (message.type) {
+				case 'welcome':
+					this.user.username = message.username;
+					this.user.uuid = message.uuid;
+					break;
+				case 'chat':
+					this.chatLog.addMessage(
+						new ChatMessage(new User(message.username, message.uuid), message.text)
+					);
+					break;
+				case 'whisper':
+					if (message.from.uuid === this.user.uuid) {
+						this.chatLog.addMessage(
+							new ChatMessage(this.user, `(to ${message.to.username}) ${message.text}`)
+						);
+					} else {
+						this.chatLog.addMessage(
+							new ChatMessage(
+								new User(message.from.username, message.from.uuid),
+								`(whisper) ${message.text}`
+							)
+						);
+					}
+					break;
+				case 'chat_history':
+					if (message.room === 'global') {
+						for (const record of message.messages) {
+							this.chatLog.addMessage(
+								new ChatMessage(new User(record.username, record.uuid), record.text)
+							);
+						}
+					}
+					break;
+				case 'joined':
+					this.chatLog.addMessage(new ChatMessage(new User('SERVER'), `${message.username} joined`));
+					break;
+				case 'left':
+					this.chatLog.addMessage(new ChatMessage(new User('SERVER'), `${message.username} left`));
+					break;
+				case 'muted':
+					this.chatLog.addMessage(
+						new ChatMessage(
+							new User('SERVER'),
+							message.until === null ? 'You are no longer muted' : `You are muted until ${message.until}`
+						)
+					);
+					break;
+				case 'kicked':
+					this.chatLog.addMessage(
+						new ChatMessage(new User('SERVER'), `You were kicked: ${message.reason ?? 'no reason given'}`)
+					);
+					break;
+				case 'system':
+					this.chatLog.addMessage(new ChatMessage(new User('SERVER'), message.text));
+					break;
+				case 'error':
+					if (message.code === 'user_not_found' || message.code === 'muted') {
+						this.chatLog.addMessage(new ChatMessage(new User('SERVER'), message.message));
+					}
+					console.warn('Server rejected a message:', message.code, message.message);
+					break;
+			}
 		}
 	}
 
@@ -17,6 +17,13 @@ import DebugManager from '$lib/system/debug_manager';
 import { EntityManager } from '$lib/system/entities/entity_manager';
 import { InputHandler } from '$lib/system/input_handler';
 import { EntityIndex, MenuFactory, MenuIndex } from '$lib/entity/entity_index';
+import type { GameStateName } from '$lib/protocol';
+
+const GAME_STATE_NAMES: Record<GameState, GameStateName> = {
+	[GameState.MENU]: 'menu',
+	[GameState.RUN]: 'run',
+	[GameState.PAUSE]: 'pause'
+};
 
 // eslint-disable-next-line @typescript-eslint/no-explicit-any
 const cartesian = (...a: any) => a.reduce((a, b) => a.flatMap((d) => b.map((e) => [d, e].flat())));
@@ -164,6 +171,13 @@ export class SpaceInvadersGame {
 	}
 
 	setGameState(state: GameState): void {
+		if (state !== this.state) {
+			// The server uses this for the player's presence
+			this.websocket.sendMessage({
+				type: 'game_event',
+				event: { kind: 'state_changed', state: GAME_STATE_NAMES[state] }
+			});
+		}
 		this.state = state;
 	}
 
@@ -0,0 +1,35 @@
+import type { ClientMessage, PlayerInput } from '$lib/protocol';
+
+export type PendingInput = { sequence: number; clientTick: number; input: PlayerInput };
+
+/**
+ * Inputs the local player sent that the server hasn't applied yet.
+ *
+ * The ship moves on every key press right away. Snapshots acknowledge the last input the server
+ * applied (`last_input`), the client puts its ship where the snapshot has it and replays the
+ * inputs returned by `acknowledge` on top.
+ */
+export class InputHistory {
+	private sequence = 0;
+	private pending: PendingInput[] = [];
+
+	/** Records the input of a client tick, returns the message to send */
+	record(clientTick: number, input: PlayerInput): ClientMessage {
+		this.sequence += 1;
+		this.pending.push({ sequence: this.sequence, clientTick, input });
+		return { type: 'input', sequence: this.sequence, client_tick: clientTick, input };
+	}
+
+	/** Forgets the inputs up to `lastInput`, returns the ones still to replay */
+	acknowledge(lastInput: number | null): PendingInput[] {
+		if (lastInput !== null) {
+			this.pending = this.pending.filter((pending) => pending.sequence > lastInput);
+		}
+		return this.pending;
+	}
+
+	reset(): void {
+		this.sequence = 0;
+		this.pending = [];
+	}
+}
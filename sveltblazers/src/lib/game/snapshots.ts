@@ -0,0 +1,69 @@
+import type { AlienState, BulletState, EntityDelta, WorldDelta, WorldSnapshot } from '$lib/protocol';
+
+const FIELD_WIDTH = 1280;
+const ALIEN_ROW_HEIGHT = 30;
+const ALIEN_RADIUS = { alien: 10, slow_straight_shooting_alien: 136 };
+
+/** Where a bullet is `ticks` later, they fly in a straight line */
+function predictBullet(bullet: BulletState, ticks: number): BulletState {
+	const position = { x: bullet.position.x, y: bullet.position.y + bullet.velocity * ticks };
+	return { ...bullet, position };
+}
+
+/** Where an alien is `ticks` later, the same movement as `Alien::update` on the server */
+function predictAlien(alien: AlienState, ticks: number): AlienState {
+	const next = { ...alien, position: { ...alien.position } };
+	const width = 2 * ALIEN_RADIUS[alien.kind];
+
+	for (let i = 0; i < ticks; i++) {
+		if (next.move_down) {
+			next.position.y += ALIEN_ROW_HEIGHT;
+			next.x_velocity *= -1;
+			next.move_down = false;
+		} else {
+			next.position.x += next.speed * next.x_velocity;
+		}
+
+		const atEdge =
+			(next.position.x <= 0 && next.x_velocity < 0) ||
+			(next.position.x + width >= FIELD_WIDTH && next.x_velocity > 0);
+		if (atEdge && next.movement === 'strafe') {
+			next.x_velocity *= -1;
+		} else {
+			next.move_down = atEdge;
+		}
+	}
+	return next;
+}
+
+function applyEntities<E extends { id: number }, C extends { id: number }>(
+	base: E[],
+	delta: EntityDelta<E, C>,
+	predict: (entity: E) => E
+): E[] {
+	const despawned = new Set(delta.despawned ?? []);
+	const changes = new Map((delta.changed ?? []).map((change) => [change.id, change]));
+
+	return base
+		.filter((entity) => !despawned.has(entity.id))
+		.map((entity) => ({ ...predict(entity), ...changes.get(entity.id) }))
+		.concat(delta.spawned ?? []);
+}
+
+/**
+ * The snapshot a delta describes, null if `base` is not the snapshot it was made against. The
+ * client acknowledges every snapshot it ends up with, see `ack_snapshot`.
+ */
+export function applyDelta(base: WorldSnapshot, delta: WorldDelta): WorldSnapshot | null {
+	if (base.tick !== delta.base_tick) {
+		return null;
+	}
+	const ticks = delta.tick - delta.base_tick;
+
+	return {
+		tick: delta.tick,
+		players: applyEntities(base.players, delta.players, (player) => player),
+		aliens: applyEntities(base.aliens, delta.aliens, (alien) => predictAlien(alien, ticks)),
+		bullets: applyEntities(base.bullets, delta.bullets, (bullet) => predictBullet(bullet, ticks))
+	};
+}
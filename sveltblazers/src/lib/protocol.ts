@@ -0,0 +1,206 @@
+/**
+ * Websocket protocol shared with the backend, see `backend/src/lib/websocket/protocol.rs`.
+ *
+ * Every frame is a JSON object with a `protocol_version` and a `type` tag. Clients that offer the
+ * `starblazers.msgpack` subprotocol get match traffic as MessagePack in binary frames instead,
+ * this client sticks to JSON.
+ */
+export const PROTOCOL_VERSION = 1;
+
+export type GameStateName = 'menu' | 'run' | 'pause';
+
+export type GameEvent = { kind: 'state_changed'; state: GameStateName };
+
+export type Member = { username: string; uuid: string };
+
+export type Activity = 'offline' | 'online' | 'menu' | 'spectating' | 'in_match';
+
+export type PlayerPresence = { uuid: string; username: string; activity: Activity };
+
+export type PlayerInput = {
+	up?: boolean;
+	down?: boolean;
+	left?: boolean;
+	right?: boolean;
+	fire?: boolean;
+};
+
+export type Position = { x: number; y: number };
+
+export type PlayerState = {
+	id: number;
+	uuid: string;
+	position: Position;
+	alive: boolean;
+	score: number;
+	fire_rate: number;
+	max_bullets: number;
+	last_input: number | null;
+};
+
+export type AlienState = {
+	id: number;
+	kind: 'alien' | 'slow_straight_shooting_alien';
+	position: Position;
+	health: number;
+	max_health: number;
+	damaged: boolean;
+	movement: 'sweep' | 'strafe';
+	speed: number;
+	x_velocity: number;
+	move_down: boolean;
+};
+
+export type BulletState = {
+	id: number;
+	shooter: number;
+	position: Position;
+	from_player: boolean;
+	velocity: number;
+	active: boolean;
+};
+
+export type WorldSnapshot = {
+	tick: number;
+	players: PlayerState[];
+	aliens: AlienState[];
+	bullets: BulletState[];
+};
+
+export type EntityDelta<E, C> = { spawned?: E[]; changed?: C[]; despawned?: number[] };
+
+export type PlayerChange = { id: number } & Partial<Omit<PlayerState, 'id' | 'uuid'>>;
+export type AlienChange = { id: number } & Partial<Omit<AlienState, 'id' | 'kind'>>;
+export type BulletChange = { id: number } & Partial<Pick<BulletState, 'position' | 'velocity'>>;
+
+export type WorldDelta = {
+	base_tick: number;
+	tick: number;
+	players: EntityDelta<PlayerState, PlayerChange>;
+	aliens: EntityDelta<AlienState, AlienChange>;
+	bullets: EntityDelta<BulletState, BulletChange>;
+};
+
+export type ChatMessageRecord = {
+	id: number;
+	room: string;
+	uuid: string;
+	username: string;
+	text: string;
+	sent_at: string;
+};
+
+export type PlaybackSpeed = 'paused' | '1x' | '2x' | '4x';
+
+export type GameMode = 'coop' | 'versus';
+
+export type ClientMessage =
+	| { type: 'chat'; room?: string; text: string }
+	| { type: 'whisper'; to: string; text: string }
+	| { type: 'join_room'; room: string }
+	| { type: 'leave_room'; room: string }
+	| { type: 'list_members'; room: string }
+	| { type: 'subscribe_presence' }
+	| { type: 'unsubscribe_presence' }
+	| { type: 'create_match'; private?: boolean }
+	| { type: 'join_match'; match_id: string }
+	| { type: 'spectate_match'; match_id: string }
+	| { type: 'leave_match' }
+	| { type: 'join_queue'; mode: GameMode; region: string }
+	| { type: 'leave_queue' }
+	| { type: 'accept_match'; match_id: string }
+	| { type: 'decline_match'; match_id: string }
+	| { type: 'input'; sequence: number; client_tick: number; input: PlayerInput }
+	| { type: 'ack_snapshot'; tick: number }
+	| { type: 'watch_replay'; match_id: string }
+	| { type: 'set_playback_speed'; speed: PlaybackSpeed }
+	| { type: 'seek_replay'; tick: number }
+	| { type: 'stop_replay' }
+	| { type: 'ping'; nonce: number | null }
+	| { type: 'game_event'; event: GameEvent };
+
+export type ServerMessage =
+	| { type: 'welcome'; username: string; uuid: string; authority: string }
+	| { type: 'session_started'; resume_token: string; resume_grace_seconds: number }
+	| { type: 'session_resumed'; resume_token: string; missed: number; truncated: boolean }
+	| { type: 'chat'; room: string; username: string; uuid: string; text: string; timestamp: string }
+	| { type: 'whisper'; from: Member; to: Member; text: string; timestamp: string }
+	| { type: 'joined'; room: string; username: string; uuid: string; timestamp: string }
+	| { type: 'left'; room: string; username: string; uuid: string; timestamp: string }
+	| { type: 'room_members'; room: string; members: Member[] }
+	| { type: 'chat_history'; room: string; messages: ChatMessageRecord[] }
+	| { type: 'room_closed'; room: string }
+	| { type: 'presence'; players: PlayerPresence[] }
+	| ({ type: 'presence_changed' } & PlayerPresence)
+	| { type: 'queued'; mode: GameMode; region: string; rating: number }
+	| { type: 'left_queue' }
+	| {
+			type: 'match_found';
+			match_id: string;
+			mode: GameMode;
+			region: string;
+			lobby: string;
+			players: Member[];
+			timeout_seconds: number;
+	  }
+	| { type: 'match_accepted'; match_id: string; uuid: string }
+	| { type: 'match_cancelled'; match_id: string; requeued: boolean }
+	| { type: 'match_joined'; match_id: string; uuid: string; username: string }
+	| { type: 'match_left'; match_id: string; uuid: string; username: string }
+	| { type: 'snapshot'; match_id: string; world: WorldSnapshot }
+	| { type: 'snapshot_delta'; match_id: string; delta: WorldDelta }
+	| {
+			type: 'wave_started';
+			match_id: string;
+			level: number;
+			name: string;
+			wave: number;
+			boss: boolean;
+	  }
+	| {
+			type: 'boss_health';
+			match_id: string;
+			id: number;
+			name: string;
+			health: number;
+			max_health: number;
+			phase: number;
+			phases: number;
+	  }
+	| { type: 'spectating'; match_id: string; room: string; delay_seconds: number }
+	| { type: 'stopped_spectating'; match_id: string }
+	| { type: 'match_ended'; match_id: string; outcome: 'victory' | 'defeat' }
+	| {
+			type: 'playback';
+			match_id: string;
+			tick: number;
+			final_tick: number;
+			speed: PlaybackSpeed;
+	  }
+	| { type: 'muted'; until: string | null }
+	| { type: 'kicked'; reason: string | null }
+	| { type: 'system'; text: string; timestamp: string }
+	| { type: 'error'; code: string; message: string }
+	| { type: 'pong'; nonce: number | null }
+	| { type: 'game_event'; uuid: string; event: GameEvent };
+
+export function encodeClientMessage(message: ClientMessage): string {
+	return JSON.stringify({ protocol_version: PROTOCOL_VERSION, ...message });
+}
+
+/**
+ * Returns null for frames that are not valid JSON or from another protocol version.
+ */
+export function parseServerMessage(data: string): ServerMessage | null {
+	try {
+		const parsed = JSON.parse(data);
+		if (parsed.protocol_version !== PROTOCOL_VERSION) {
+			console.warn('Server speaks protocol version', parsed.protocol_version);
+			return null;
+		}
+		return parsed as ServerMessage;
+	} catch {
+		console.error('Received a malformed message from the server', data);
+		return null;
+	}
+}
@@ -2,13 +2,9 @@ export class User {
 	uuid: string;
 	username: string;
 
-	constructor(username: string) {
-		this.uuid = this.generateUUID();
+	constructor(username: string, uuid: string = '') {
+		// The real uuid is filled in by the server's welcome message
+		this.uuid = uuid;
 		this.username = username;
 	}
-
-	private generateUUID(): string {
-		// Implementation to generate a unique identifier
-		return 'uuid-12345'; // Placeholder, use a proper UUID generation method
-	}
 }
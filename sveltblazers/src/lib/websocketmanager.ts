@@ -1,6 +1,17 @@
+import { get } from 'svelte/store';
+import { jwtStore } from '../store/auth';
+import { encodeClientMessage, parseServerMessage, type ClientMessage } from './protocol';
+
+/** Delay before reconnecting after the connection dropped */
+const RECONNECT_DELAY_MS = 1000;
+
 export class WebSocketManager {
 	private url: string;
 	private ws: WebSocket | null = null;
+	// Token to get the same session back after the connection dropped, and until when it works
+	private resumeToken: string | null = null;
+	private resumeGraceMs = 0;
+	private resumableUntil = 0;
 	public messages: string[];
 
 	constructor() {
@@ -13,19 +24,40 @@ export class WebSocketManager {
 	}
 
 	connect() {
-		this.ws = new WebSocket(this.url);
+		// Browsers can't set an Authorization header on the upgrade request, so the jwt is sent
+		// as a subprotocol next to the one the server actually selects.
+		const jwt = get(jwtStore).replace('Bearer ', '');
+		const resume =
+			this.resumeToken && Date.now() < this.resumableUntil ? `?resume=${this.resumeToken}` : '';
+		this.ws = new WebSocket(this.url + resume, ['starblazers', `bearer.${jwt}`]);
 
 		this.ws.onopen = () => {
 			console.log('WebSocket connection established');
 		};
 
 		this.ws.onmessage = (event) => {
+			const message = parseServerMessage(event.data);
+			if (message?.type === 'session_started') {
+				this.resumeToken = message.resume_token;
+				this.resumeGraceMs = message.resume_grace_seconds * 1000;
+			} else if (message?.type === 'session_resumed') {
+				this.resumeToken = message.resume_token;
+			}
+
 			// create callback?
 			this.messages.push(event.data);
 		};
 
 		this.ws.onclose = (event) => {
 			console.log('WebSocket connection closed', event.code, event.reason);
+
+			// 1006: the connection dropped without a close frame, try to get the session back
+			if (event.code === 1006 && this.resumeToken) {
+				if (Date.now() >= this.resumableUntil) {
+					this.resumableUntil = Date.now() + this.resumeGraceMs;
+				}
+				setTimeout(() => this.connect(), RECONNECT_DELAY_MS);
+			}
 		};
 
 		this.ws.onerror = (error) => {
@@ -33,16 +65,16 @@ export class WebSocketManager {
 		};
 	}
 
-	sendMessage(message: string) {
+	sendMessage(message: ClientMessage) {
 		if (this.ws && this.ws.readyState === WebSocket.OPEN) {
-			this.messages.push(message);
-			this.ws.send(message);
+			this.ws.send(encodeClientMessage(message));
 		} else {
 			console.error('WebSocket is not connected');
 		}
 	}
 
 	close() {
+		this.resumeToken = null;
 		if (this.ws) {
 			this.ws.close();
 		}